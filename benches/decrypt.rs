@@ -0,0 +1,110 @@
+//! What decrypting an advertisement costs, next to what handing it to a
+//! `spawn_blocking` pool would (the `--decode-workers` idea in the README).
+//!
+//! ```text
+//! cargo bench --bench decrypt
+//! ```
+//!
+//! Replays encrypted BTHome and MiBeacon frames through the same
+//! `decrypt_service_data` and `handle_service_data` calls the scanner
+//! makes, one at a time like advertisements arrive.
+
+use mitempr::crypto::{self, BindKey};
+use mitempr::decoder::{self, BindKeys};
+use std::collections::HashMap;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+use uuid::{Uuid, uuid};
+
+const BTHOME: Uuid = uuid!("0000fcd2-0000-1000-8000-00805f9b34fb");
+const MIJIA: Uuid = uuid!("0000fe95-0000-1000-8000-00805f9b34fb");
+const FRAMES: usize = 1000;
+const ROUNDS: usize = 50;
+
+type Frame = (HashMap<Uuid, Vec<u8>>, [u8; 6]);
+
+/// The example from the BTHome encryption docs: 25.06 °C, 50.55 %.
+fn bthome() -> (Frame, BindKeys) {
+    let mac = [0x54, 0x48, 0xE6, 0x8F, 0x80, 0xA5];
+    let key: BindKey = "231d39c1d7cc1ab1aee224cd096db932".parse().unwrap();
+    let payload = vec![
+        0x41, 0xA4, 0x72, 0x66, 0xC9, 0x5F, 0x73, 0x00, 0x11, 0x22, 0x33, 0x78, 0x23, 0x72, 0x14,
+    ];
+    (
+        (HashMap::from([(BTHOME, payload)]), mac),
+        BindKeys::from([(mac, key)]),
+    )
+}
+
+/// A LYWSD03MMC MiBeacon v5 frame with MAC: 21.5 °C / 45.0 %.
+fn mibeacon() -> (Frame, BindKeys) {
+    let mac = [0xA4, 0xC1, 0x38, 0x12, 0x34, 0x56];
+    let key = BindKey([0x42; 16]);
+    let header = [0x58, 0x58, 0x5B, 0x05, 0x07];
+    let ext_counter = [0x01, 0x00, 0x00];
+    let mut nonce: Vec<u8> = mac.iter().rev().copied().collect();
+    nonce.extend(&header[2..]);
+    nonce.extend(ext_counter);
+    let objects = [0x0D, 0x10, 0x04, 0xD7, 0x00, 0xC2, 0x01];
+    let (ciphertext, mic) = crypto::ccm_encrypt(&key, &nonce, &[0x11], &objects, 4);
+
+    let mut payload = header.to_vec();
+    payload.extend(mac.iter().rev());
+    payload.extend(ciphertext);
+    payload.extend(ext_counter);
+    payload.extend(mic);
+    (
+        (HashMap::from([(MIJIA, payload)]), mac),
+        BindKeys::from([(mac, key)]),
+    )
+}
+
+/// Decrypts and decodes like the scanner does with every advertisement.
+fn decode(frame: &Frame, keys: &BindKeys) -> f32 {
+    let (data, mac) = frame;
+    let decrypted = decoder::decrypt_service_data(data, *mac, keys)
+        .unwrap()
+        .unwrap();
+    decoder::handle_service_data(&decrypted)
+        .unwrap()
+        .temperature
+        .unwrap()
+}
+
+/// The fastest of `ROUNDS` runs of `FRAMES` calls, per call.
+fn time(mut f: impl FnMut()) -> Duration {
+    (0..ROUNDS)
+        .map(|_| {
+            let start = Instant::now();
+            for _ in 0..FRAMES {
+                f();
+            }
+            start.elapsed() / FRAMES as u32
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(2)
+        .build()
+        .unwrap();
+
+    for (name, (frame, keys)) in [("BTHome", bthome()), ("MiBeacon v5", mibeacon())] {
+        let inline = time(|| {
+            black_box(decode(black_box(&frame), &keys));
+        });
+        // The same work handed to the blocking pool and awaited, as a
+        // --decode-workers pool would
+        let pooled = time(|| {
+            let (frame, keys) = (frame.clone(), keys.clone());
+            let handle = runtime.spawn_blocking(move || decode(&frame, &keys));
+            black_box(runtime.block_on(handle).unwrap());
+        });
+        println!(
+            "{name:12} decrypt+decode {:>8.2?}/frame   via spawn_blocking {:>8.2?}/frame",
+            inline, pooled
+        );
+    }
+}
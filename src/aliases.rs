@@ -0,0 +1,160 @@
+use crate::icons::{Icon, status, warning};
+use bluer::Address;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+/// Friendly device names from `--alias` and `--aliases`, a JSON object
+/// mapping addresses to names: `{"A4:C1:38:00:00:01": "Bedroom"}`.
+#[derive(Clone)]
+pub struct Aliases {
+    path: Option<PathBuf>,
+    /// From `--alias`, which wins over the file
+    fixed: Arc<HashMap<Address, String>>,
+    names: Arc<RwLock<HashMap<Address, String>>>,
+}
+
+impl Aliases {
+    pub fn load(path: Option<PathBuf>, fixed: HashMap<Address, String>) -> Result<Self, String> {
+        let names = match &path {
+            Some(path) => read(path)?,
+            None => HashMap::new(),
+        };
+        Ok(Self {
+            path,
+            fixed: Arc::new(fixed),
+            names: Arc::new(RwLock::new(names)),
+        })
+    }
+
+    pub fn get(&self, addr: Address) -> Option<String> {
+        self.fixed
+            .get(&addr)
+            .or(self.names.read().unwrap().get(&addr))
+            .cloned()
+    }
+
+    pub fn len(&self) -> usize {
+        let names = self.names.read().unwrap();
+        self.fixed.len() + names.keys().filter(|a| !self.fixed.contains_key(a)).count()
+    }
+
+    /// Whether there's a file that [`Aliases::reload`] reads.
+    pub fn has_file(&self) -> bool {
+        self.path.is_some()
+    }
+
+    /// Read the file again; on error the previous aliases stay in use.
+    pub fn reload(&self) -> Result<usize, String> {
+        let Some(path) = &self.path else {
+            return Ok(self.len());
+        };
+        let names = read(path)?;
+        *self.names.write().unwrap() = names;
+        Ok(self.len())
+    }
+}
+
+fn read(path: &PathBuf) -> Result<HashMap<Address, String>, String> {
+    let json = std::fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    parse(&json).map_err(|e| format!("{}: {e}", path.display()))
+}
+
+fn parse(json: &str) -> Result<HashMap<Address, String>, String> {
+    let entries: HashMap<String, String> = serde_json::from_str(json).map_err(|e| e.to_string())?;
+    entries
+        .into_iter()
+        .map(|(addr, name)| {
+            let addr = addr
+                .parse()
+                .map_err(|_| format!("invalid address {addr:?}"))?;
+            Ok((addr, name))
+        })
+        .collect()
+}
+
+/// Reload `aliases` whenever the process gets SIGHUP.
+pub async fn reload_on_hangup(aliases: Aliases) {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let Ok(mut hangups) = signal(SignalKind::hangup()) else {
+        warning!(
+            "{} Cannot listen for SIGHUP, aliases won't reload",
+            Icon::Warn
+        );
+        return;
+    };
+    while hangups.recv().await.is_some() {
+        match aliases.reload() {
+            Ok(count) => status!("{} Reloaded {count} aliases", Icon::Ok),
+            Err(e) => warning!("{} Keeping previous aliases: {e}", Icon::Warn),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let aliases =
+            parse(r#"{"A4:C1:38:00:00:01": "Bedroom", "a4:c1:38:00:00:02": "Attic"}"#).unwrap();
+
+        assert_eq!(
+            aliases[&Address::new([0xA4, 0xC1, 0x38, 0x00, 0x00, 0x01])],
+            "Bedroom"
+        );
+        assert_eq!(
+            aliases[&Address::new([0xA4, 0xC1, 0x38, 0x00, 0x00, 0x02])],
+            "Attic"
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            parse(r#"{"bedroom": "Bedroom"}"#),
+            Err(r#"invalid address "bedroom""#.to_string())
+        );
+        assert!(parse(r#"["A4:C1:38:00:00:01"]"#).is_err());
+    }
+
+    #[test]
+    fn test_reload_keeps_previous_on_error() {
+        let path =
+            std::env::temp_dir().join(format!("mitempr-aliases-{}.json", std::process::id()));
+        let addr = Address::new([0xA4, 0xC1, 0x38, 0x00, 0x00, 0x01]);
+        std::fs::write(&path, r#"{"A4:C1:38:00:00:01": "Bedroom"}"#).unwrap();
+        let aliases = Aliases::load(Some(path.clone()), HashMap::new()).unwrap();
+
+        std::fs::write(&path, r#"{"A4:C1:38:00:00:01": "Guest room"}"#).unwrap();
+        assert_eq!(aliases.reload(), Ok(1));
+        assert_eq!(aliases.get(addr).as_deref(), Some("Guest room"));
+
+        std::fs::write(&path, "{").unwrap();
+        assert!(aliases.reload().is_err());
+        assert_eq!(aliases.get(addr).as_deref(), Some("Guest room"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_alias_option_wins_over_file() {
+        let path =
+            std::env::temp_dir().join(format!("mitempr-aliases-fixed-{}.json", std::process::id()));
+        let bedroom = Address::new([0xA4, 0xC1, 0x38, 0x00, 0x00, 0x01]);
+        let attic = Address::new([0xA4, 0xC1, 0x38, 0x00, 0x00, 0x02]);
+        std::fs::write(
+            &path,
+            r#"{"A4:C1:38:00:00:01": "Bedroom", "A4:C1:38:00:00:02": "Attic"}"#,
+        )
+        .unwrap();
+        let fixed = HashMap::from([(bedroom, "Kid's room".to_string())]);
+        let aliases = Aliases::load(Some(path.clone()), fixed).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(aliases.get(bedroom).as_deref(), Some("Kid's room"));
+        assert_eq!(aliases.get(attic).as_deref(), Some("Attic"));
+        assert_eq!(aliases.len(), 2);
+    }
+}
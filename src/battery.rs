@@ -0,0 +1,212 @@
+use crate::decoder::Mac;
+use std::collections::HashSet;
+use std::str::FromStr;
+
+/// Voltage → percent discharge curve for a battery type.
+///
+/// Curves are `(volts, percent)` points sorted by falling voltage; values
+/// between points are interpolated linearly and values outside are clamped.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Chemistry {
+    /// CR2032 coin cell (the default)
+    Cr2032,
+    /// A single alkaline AA/AAA cell
+    Alkaline,
+    /// A single Li-ion/LiPo cell
+    Liion,
+    /// User-supplied curve
+    Custom(Vec<(f32, u8)>),
+}
+
+const CR2032_CURVE: &[(f32, u8)] = &[
+    (3.0, 100),
+    (2.9, 80),
+    (2.8, 60),
+    (2.7, 40),
+    (2.5, 20),
+    (2.0, 0),
+];
+
+const ALKALINE_CURVE: &[(f32, u8)] = &[
+    (1.6, 100),
+    (1.5, 90),
+    (1.4, 70),
+    (1.3, 45),
+    (1.2, 20),
+    (1.1, 8),
+    (1.0, 0),
+];
+
+const LIION_CURVE: &[(f32, u8)] = &[
+    (4.2, 100),
+    (4.1, 90),
+    (4.0, 80),
+    (3.9, 60),
+    (3.8, 40),
+    (3.7, 20),
+    (3.6, 10),
+    (3.5, 5),
+    (3.3, 0),
+];
+
+impl Chemistry {
+    fn curve(&self) -> &[(f32, u8)] {
+        match self {
+            Chemistry::Cr2032 => CR2032_CURVE,
+            Chemistry::Alkaline => ALKALINE_CURVE,
+            Chemistry::Liion => LIION_CURVE,
+            Chemistry::Custom(points) => points,
+        }
+    }
+
+    /// Estimated remaining capacity for a battery `voltage`.
+    pub fn percent(&self, voltage: f32) -> u8 {
+        let curve = self.curve();
+        let (first, last) = (curve[0], curve[curve.len() - 1]);
+        if voltage >= first.0 {
+            return first.1;
+        }
+        if voltage <= last.0 {
+            return last.1;
+        }
+
+        for pair in curve.windows(2) {
+            let ((v_hi, p_hi), (v_lo, p_lo)) = (pair[0], pair[1]);
+            if voltage >= v_lo {
+                let fraction = (voltage - v_lo) / (v_hi - v_lo);
+                return (p_lo as f32 + fraction * (p_hi as f32 - p_lo as f32)).round() as u8;
+            }
+        }
+        last.1
+    }
+}
+
+/// Remembers which devices were reported as low on battery, so each is
+/// reported once until its level recovers (a new battery).
+pub struct LowBattery {
+    threshold: u8,
+    warned: HashSet<Mac>,
+}
+
+impl LowBattery {
+    pub fn new(threshold: u8) -> Self {
+        Self {
+            threshold,
+            warned: HashSet::new(),
+        }
+    }
+
+    pub fn threshold(&self) -> u8 {
+        self.threshold
+    }
+
+    /// Whether `percent` is news: at or below the threshold for the first
+    /// time since the device was last above it.
+    pub fn observe(&mut self, addr: Mac, percent: u8) -> bool {
+        if percent > self.threshold {
+            self.warned.remove(&addr);
+            return false;
+        }
+        self.warned.insert(addr)
+    }
+}
+
+impl FromStr for Chemistry {
+    type Err = String;
+
+    /// `cr2032`, `alkaline`, `liion`, or a custom table like
+    /// `3.0:100,2.8:50,2.2:0` (volts:percent).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "cr2032" => return Ok(Chemistry::Cr2032),
+            "alkaline" => return Ok(Chemistry::Alkaline),
+            "liion" => return Ok(Chemistry::Liion),
+            _ => {}
+        }
+
+        let mut points = s
+            .split(',')
+            .map(|point| {
+                let (volts, percent) = point
+                    .split_once(':')
+                    .ok_or_else(|| format!("expected volts:percent, got {point:?}"))?;
+                let volts: f32 = volts
+                    .trim()
+                    .parse()
+                    .map_err(|e| format!("invalid voltage {volts:?}: {e}"))?;
+                let percent: u8 = percent
+                    .trim()
+                    .parse()
+                    .ok()
+                    .filter(|p| *p <= 100)
+                    .ok_or_else(|| format!("invalid percentage {percent:?}"))?;
+                Ok((volts, percent))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        if points.len() < 2 {
+            return Err(format!(
+                "unknown chemistry {s:?} (expected cr2032, alkaline, liion or a volts:percent table with at least two points)"
+            ));
+        }
+        points.sort_by(|a, b| b.0.total_cmp(&a.0));
+        Ok(Chemistry::Custom(points))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cr2032_points() {
+        let c = Chemistry::Cr2032;
+        assert_eq!(c.percent(3.3), 100);
+        assert_eq!(c.percent(3.0), 100);
+        assert_eq!(c.percent(2.8), 60);
+        assert_eq!(c.percent(2.75), 50);
+        assert_eq!(c.percent(2.0), 0);
+        assert_eq!(c.percent(1.5), 0);
+    }
+
+    #[test]
+    fn test_alkaline_points() {
+        let c = Chemistry::Alkaline;
+        assert_eq!(c.percent(1.6), 100);
+        assert_eq!(c.percent(1.4), 70);
+        assert_eq!(c.percent(1.2), 20);
+        assert_eq!(c.percent(0.9), 0);
+    }
+
+    #[test]
+    fn test_liion_points() {
+        let c = Chemistry::Liion;
+        assert_eq!(c.percent(4.2), 100);
+        assert_eq!(c.percent(3.9), 60);
+        assert_eq!(c.percent(3.85), 50);
+        assert_eq!(c.percent(3.3), 0);
+    }
+
+    #[test]
+    fn test_low_battery_warns_once_until_recovered() {
+        let addr = [0xA4, 0xC1, 0x38, 0x00, 0x00, 0x01];
+        let mut low = LowBattery::new(15);
+
+        assert!(!low.observe(addr, 16));
+        assert!(low.observe(addr, 15));
+        assert!(!low.observe(addr, 12));
+        assert!(!low.observe(addr, 100));
+        assert!(low.observe(addr, 9));
+    }
+
+    #[test]
+    fn test_parse_custom_table() {
+        let c: Chemistry = "2.2:0, 3.0:100,2.8:50".parse().unwrap();
+        assert_eq!(c, Chemistry::Custom(vec![(3.0, 100), (2.8, 50), (2.2, 0)]));
+        assert_eq!(c.percent(2.9), 75);
+
+        assert_eq!("LiIon".parse::<Chemistry>().unwrap(), Chemistry::Liion);
+        assert!("nimh".parse::<Chemistry>().is_err());
+        assert!("3.0:150,2.0:0".parse::<Chemistry>().is_err());
+    }
+}
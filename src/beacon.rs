@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+use std::fmt;
+use uuid::Uuid;
+
+/// Apple's Bluetooth SIG company identifier, used by iBeacon.
+const APPLE_COMPANY_ID: u16 = 0x004C;
+const IBEACON_PREFIX: [u8; 2] = [0x02, 0x15];
+const EDDYSTONE_SERVICE_UUID: Uuid = Uuid::from_u128(0x0000FEAA_0000_1000_8000_00805F9B34FB);
+const EDDYSTONE_UID_FRAME: u8 = 0x00;
+
+/// A presence beacon, for inventory rather than sensor data.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Beacon {
+    IBeacon {
+        uuid: Uuid,
+        major: u16,
+        minor: u16,
+        /// Calibrated RSSI at 1 m
+        tx_power: i8,
+    },
+    EddystoneUid {
+        namespace: [u8; 10],
+        instance: [u8; 6],
+        /// Calibrated RSSI at 0 m
+        tx_power: i8,
+    },
+}
+
+impl fmt::Display for Beacon {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Beacon::IBeacon {
+                uuid,
+                major,
+                minor,
+                tx_power,
+            } => write!(
+                f,
+                "iBeacon {uuid} major={major} minor={minor} tx_power={tx_power}"
+            ),
+            Beacon::EddystoneUid {
+                namespace,
+                instance,
+                tx_power,
+            } => write!(
+                f,
+                "Eddystone-UID namespace={} instance={} tx_power={tx_power}",
+                hex::encode(namespace),
+                hex::encode(instance)
+            ),
+        }
+    }
+}
+
+/// Find a beacon in manufacturer or service data.
+pub fn decode_beacon(
+    manufacturer_data: &HashMap<u16, Vec<u8>>,
+    service_data: &HashMap<Uuid, Vec<u8>>,
+) -> Option<Beacon> {
+    manufacturer_data
+        .get(&APPLE_COMPANY_ID)
+        .and_then(|data| decode_ibeacon(data))
+        .or_else(|| {
+            service_data
+                .get(&EDDYSTONE_SERVICE_UUID)
+                .and_then(|data| decode_eddystone_uid(data))
+        })
+}
+
+// Layout after the company ID: 02 15 <uuid:16> <major:2 BE> <minor:2 BE> <tx:1>
+fn decode_ibeacon(data: &[u8]) -> Option<Beacon> {
+    if data.len() != 23 || data[..2] != IBEACON_PREFIX {
+        return None;
+    }
+    Some(Beacon::IBeacon {
+        uuid: Uuid::from_slice(&data[2..18]).ok()?,
+        major: u16::from_be_bytes([data[18], data[19]]),
+        minor: u16::from_be_bytes([data[20], data[21]]),
+        tx_power: data[22] as i8,
+    })
+}
+
+// Layout: 00 <tx:1> <namespace:10> <instance:6> [<reserved:2>]
+fn decode_eddystone_uid(data: &[u8]) -> Option<Beacon> {
+    if !(data.len() == 18 || data.len() == 20) || data[0] != EDDYSTONE_UID_FRAME {
+        return None;
+    }
+    Some(Beacon::EddystoneUid {
+        tx_power: data[1] as i8,
+        namespace: data[2..12].try_into().ok()?,
+        instance: data[12..18].try_into().ok()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::uuid;
+
+    #[test]
+    fn test_ibeacon() {
+        let mut data = vec![0x02, 0x15];
+        data.extend_from_slice(uuid!("e2c56db5-dffb-48d2-b060-d0f5a71096e0").as_bytes());
+        data.extend_from_slice(&[0x00, 0x01, 0x01, 0x02, 0xC5]);
+        let manufacturer = HashMap::from([(APPLE_COMPANY_ID, data)]);
+
+        assert_eq!(
+            decode_beacon(&manufacturer, &HashMap::new()),
+            Some(Beacon::IBeacon {
+                uuid: uuid!("e2c56db5-dffb-48d2-b060-d0f5a71096e0"),
+                major: 1,
+                minor: 258,
+                tx_power: -59,
+            })
+        );
+    }
+
+    #[test]
+    fn test_eddystone_uid() {
+        let data = vec![
+            0x00, 0xE7, 0xED, 0xD1, 0xEB, 0xEA, 0xC0, 0x4E, 0x5D, 0xEF, 0xA0, 0x17, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x01, 0x00, 0x00,
+        ];
+        let service = HashMap::from([(EDDYSTONE_SERVICE_UUID, data)]);
+        let beacon = decode_beacon(&HashMap::new(), &service).unwrap();
+
+        assert_eq!(
+            beacon.to_string(),
+            "Eddystone-UID namespace=edd1ebeac04e5defa017 instance=000000000001 tx_power=-25"
+        );
+    }
+
+    #[test]
+    fn test_other_apple_and_eddystone_frames_are_ignored() {
+        // Apple "Nearby" message and an Eddystone-URL frame
+        let manufacturer = HashMap::from([(APPLE_COMPANY_ID, vec![0x10, 0x05, 0x01, 0x18])]);
+        let service = HashMap::from([(EDDYSTONE_SERVICE_UUID, vec![0x10, 0xE7, 0x03, 0x67])]);
+
+        assert_eq!(decode_beacon(&manufacturer, &service), None);
+    }
+}
@@ -0,0 +1,116 @@
+use crate::decoder::{Flag, SensorData};
+use std::str::FromStr;
+
+/// Offsets from `--calibrate`, added to a device's decoded values.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Calibration {
+    /// °C
+    pub temperature: f32,
+    /// Percentage points
+    pub humidity: f32,
+}
+
+impl Calibration {
+    /// Add the offsets to the values `data` has. Humidity stays within
+    /// 0–100 %.
+    pub fn apply(&self, data: &mut SensorData) {
+        let mut calibrated = false;
+        if let Some(temperature) = &mut data.temperature {
+            *temperature = round(*temperature + self.temperature);
+            calibrated = true;
+        }
+        if let Some(humidity) = &mut data.humidity {
+            *humidity = round((*humidity + self.humidity).clamp(0.0, 100.0));
+            calibrated = true;
+        }
+        if calibrated && *self != Calibration::default() {
+            data.flags.insert(Flag::Calibrated);
+        }
+    }
+}
+
+impl FromStr for Calibration {
+    type Err = String;
+
+    /// `<temperature offset>[,<humidity offset>]`, e.g. `-0.7,2.5`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (temperature, humidity) = s.split_once(',').unwrap_or((s, "0"));
+        let offset = |value: &str| {
+            value
+                .trim()
+                .parse::<f32>()
+                .ok()
+                .filter(|v| v.is_finite())
+                .ok_or_else(|| format!("invalid offset {value:?}"))
+        };
+        Ok(Self {
+            temperature: offset(temperature)?,
+            humidity: offset(humidity)?,
+        })
+    }
+}
+
+/// Two decimals, the sensors' resolution, instead of float noise.
+fn round(value: f32) -> f32 {
+    (value * 100.0).round() / 100.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            "-0.7, 2.5".parse(),
+            Ok(Calibration {
+                temperature: -0.7,
+                humidity: 2.5
+            })
+        );
+        assert_eq!(
+            "0.5".parse(),
+            Ok(Calibration {
+                temperature: 0.5,
+                humidity: 0.0
+            })
+        );
+        assert!("warm".parse::<Calibration>().is_err());
+        assert!("0.5,".parse::<Calibration>().is_err());
+    }
+
+    #[test]
+    fn test_apply_is_additive_and_bounded() {
+        let calibration = Calibration {
+            temperature: -0.7,
+            humidity: 3.0,
+        };
+        let mut data = SensorData {
+            temperature: Some(22.9),
+            humidity: Some(98.5),
+            battery: Some(87),
+            ..Default::default()
+        };
+
+        calibration.apply(&mut data);
+        assert_eq!(data.temperature, Some(22.2));
+        assert_eq!(data.humidity, Some(100.0));
+        assert_eq!(data.battery, Some(87));
+        assert!(data.flags.contains(&Flag::Calibrated));
+    }
+
+    #[test]
+    fn test_nothing_to_calibrate() {
+        let mut data = SensorData {
+            battery: Some(87),
+            ..Default::default()
+        };
+
+        Calibration {
+            temperature: 1.0,
+            humidity: 1.0,
+        }
+        .apply(&mut data);
+        assert!(data.flags.is_empty());
+    }
+}
@@ -0,0 +1,80 @@
+use std::time::{Instant, SystemTime};
+
+/// Source of the current time, so time-dependent logic can be tested
+/// without sleeping.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+
+    /// Wall-clock time, for timestamps that leave the process.
+    fn system_now(&self) -> SystemTime;
+}
+
+/// The real clock.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A clock that only moves when told to. Clones share the same time.
+///
+/// Its wall-clock time starts at 2024-05-01T12:34:56Z.
+#[cfg(test)]
+#[derive(Clone)]
+pub struct MockClock {
+    now: std::sync::Arc<std::sync::Mutex<(Instant, SystemTime)>>,
+}
+
+#[cfg(test)]
+impl MockClock {
+    pub fn new() -> Self {
+        Self {
+            now: std::sync::Arc::new(std::sync::Mutex::new((
+                Instant::now(),
+                std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_714_566_896),
+            ))),
+        }
+    }
+
+    pub fn advance(&self, by: std::time::Duration) {
+        let mut now = self.now.lock().unwrap();
+        now.0 += by;
+        now.1 += by;
+    }
+}
+
+#[cfg(test)]
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.now.lock().unwrap().0
+    }
+
+    fn system_now(&self) -> SystemTime {
+        self.now.lock().unwrap().1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_mock_clock_moves_only_when_advanced() {
+        let clock = MockClock::new();
+        let shared = clock.clone();
+        let t0 = clock.now();
+        let wall = clock.system_now();
+
+        assert_eq!(clock.now(), t0);
+        shared.advance(Duration::from_secs(90));
+        assert_eq!(clock.now(), t0 + Duration::from_secs(90));
+        assert_eq!(clock.system_now(), wall + Duration::from_secs(90));
+    }
+}
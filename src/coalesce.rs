@@ -0,0 +1,83 @@
+use crate::decoder::SensorData;
+use bluer::Address;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Merges partial readings from the same device that arrive within a
+/// window, for devices that split temperature and humidity across
+/// separate advertisements.
+pub struct Coalescer {
+    window: Duration,
+    pending: HashMap<Address, Pending>,
+}
+
+struct Pending {
+    data: SensorData,
+    first_seen: Instant,
+}
+
+impl Coalescer {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Add a reading; returns the readings that are ready to emit.
+    ///
+    /// A merged reading is ready once it has both temperature and humidity.
+    /// A pending reading whose window already ran out is emitted on its own
+    /// before the new one starts a fresh window.
+    pub fn push(&mut self, addr: Address, data: SensorData, now: Instant) -> Vec<SensorData> {
+        let mut ready = Vec::new();
+
+        let pending = match self.pending.remove(&addr) {
+            Some(pending) if now.duration_since(pending.first_seen) > self.window => {
+                ready.push(pending.data);
+                Pending {
+                    data,
+                    first_seen: now,
+                }
+            }
+            Some(mut pending) => {
+                pending.data.merge_from(data);
+                pending
+            }
+            None => Pending {
+                data,
+                first_seen: now,
+            },
+        };
+
+        if pending.data.temperature.is_some() && pending.data.humidity.is_some() {
+            ready.push(pending.data);
+        } else {
+            self.pending.insert(addr, pending);
+        }
+        ready
+    }
+
+    /// Remove and return every pending reading.
+    pub fn drain(&mut self) -> Vec<(Address, SensorData)> {
+        self.pending
+            .drain()
+            .map(|(addr, p)| (addr, p.data))
+            .collect()
+    }
+
+    /// Remove and return the readings whose window ran out by `now`.
+    pub fn flush_expired(&mut self, now: Instant) -> Vec<(Address, SensorData)> {
+        let expired: Vec<Address> = self
+            .pending
+            .iter()
+            .filter(|(_, p)| now.duration_since(p.first_seen) >= self.window)
+            .map(|(addr, _)| *addr)
+            .collect();
+
+        expired
+            .into_iter()
+            .filter_map(|addr| self.pending.remove(&addr).map(|p| (addr, p.data)))
+            .collect()
+    }
+}
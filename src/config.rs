@@ -0,0 +1,726 @@
+//! Options from a TOML file (`--config`).
+//!
+//! Top-level keys are the long command line options, with `-` or `_`:
+//!
+//! ```toml
+//! watchdog = 120
+//! mqtt_broker = "localhost:1883"
+//! deny = ["A4:C1:38:00:00:09"]
+//!
+//! [[device]]
+//! address = "A4:C1:38:00:00:01"
+//! alias = "Bedroom"
+//! bindkey = "231d39c1d7cc1ab1aee224cd096db932"
+//! calibrate = "-0.7,2.5"
+//!
+//! [adapters.hci1]
+//! scan = "passive"
+//! min_rssi = -75
+//! ```
+//!
+//! The file becomes command line arguments, so clap validates both the
+//! same way. Options the command line gives replace the file's; the
+//! per-device and per-adapter settings of its tables merge with the command
+//! line's. Only the part of TOML such a file needs is understood: key/value
+//! pairs with strings, numbers, booleans and arrays, `[[device]]` tables
+//! and `[adapters.<name>]` tables. Errors name the line or the key
+//! (`device[1].calibrate`).
+
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Number, Value};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub struct Config {
+    /// `[[device]]` tables
+    device: Vec<Device>,
+    /// `[adapters.<name>]` tables by adapter name
+    adapters: BTreeMap<String, AdapterTable>,
+    /// Everything else: command line options
+    options: BTreeMap<String, Value>,
+}
+
+/// What's configured for one device; each field is the per-device command
+/// line option of the same name.
+#[derive(Debug)]
+struct Device {
+    address: String,
+    alias: Option<String>,
+    bindkey: Option<String>,
+    calibrate: Option<Value>,
+    battery_chemistry: Option<String>,
+}
+
+/// What's configured for one adapter; each field is the per-adapter
+/// command line option `--adapter-<field>`.
+#[derive(Debug)]
+struct AdapterTable {
+    scan: Option<String>,
+    duplicate_data: Option<bool>,
+    min_rssi: Option<Value>,
+    uuids: Option<Vec<String>>,
+}
+
+impl Device {
+    fn from_table(mut table: Map<String, Value>, path: &str) -> Result<Self, String> {
+        let device = Self {
+            address: take(&mut table, path, "address")?
+                .ok_or_else(|| format!("{path}: address is missing"))?,
+            alias: take(&mut table, path, "alias")?,
+            bindkey: take(&mut table, path, "bindkey")?,
+            calibrate: take(&mut table, path, "calibrate")?,
+            battery_chemistry: take(&mut table, path, "battery_chemistry")?,
+        };
+        no_other_keys(&table, path)?;
+        Ok(device)
+    }
+}
+
+impl AdapterTable {
+    fn from_table(mut table: Map<String, Value>, path: &str) -> Result<Self, String> {
+        let adapter = Self {
+            scan: take(&mut table, path, "scan")?,
+            duplicate_data: take(&mut table, path, "duplicate_data")?,
+            min_rssi: take(&mut table, path, "min_rssi")?,
+            uuids: take(&mut table, path, "uuids")?,
+        };
+        no_other_keys(&table, path)?;
+        Ok(adapter)
+    }
+}
+
+/// `key` of the table at `path`, removed and deserialized; errors name the
+/// key's path (`device[1].calibrate`) as serde alone can't.
+fn take<T: DeserializeOwned>(
+    table: &mut Map<String, Value>,
+    path: &str,
+    key: &str,
+) -> Result<Option<T>, String> {
+    let path = match path {
+        "" => key.to_string(),
+        path => format!("{path}.{key}"),
+    };
+    table
+        .remove(key)
+        .map(|value| serde_json::from_value(value).map_err(|e| format!("{path}: {e}")))
+        .transpose()
+}
+
+/// What's left of the table at `path` once its keys are taken.
+fn no_other_keys(table: &Map<String, Value>, path: &str) -> Result<(), String> {
+    match table.keys().next() {
+        Some(key) => Err(format!("{path}: unknown key {key:?}")),
+        None => Ok(()),
+    }
+}
+
+/// What a config file becomes.
+#[derive(Debug, Default, PartialEq)]
+pub struct FileArgs {
+    /// `--<option>[=<value>]` for the top-level keys
+    pub options: Vec<String>,
+    /// `--<option>=<device or adapter>=<value>` from the tables
+    pub tables: Vec<String>,
+}
+
+impl FileArgs {
+    pub fn iter(&self) -> impl Iterator<Item = &String> {
+        self.options.iter().chain(&self.tables)
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Self::parse(&text)
+    }
+
+    fn parse(text: &str) -> Result<Self, String> {
+        let mut root = Parser::new(text).document()?;
+        let device = take::<Vec<Map<String, Value>>>(&mut root, "", "device")?
+            .unwrap_or_default()
+            .into_iter()
+            .enumerate()
+            .map(|(i, table)| Device::from_table(table, &format!("device[{i}]")))
+            .collect::<Result<_, _>>()?;
+        let adapters = take::<BTreeMap<String, Map<String, Value>>>(&mut root, "", "adapters")?
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(name, table)| {
+                let adapter = AdapterTable::from_table(table, &format!("adapters.{name}"))?;
+                Ok((name, adapter))
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Self {
+            device,
+            adapters,
+            options: root.into_iter().collect(),
+        })
+    }
+
+    /// The file as `--<option>=<value>` arguments. `known` are the long
+    /// options there are; anything else is an error.
+    pub fn args(&self, known: &[&str]) -> Result<FileArgs, String> {
+        let mut args = Vec::new();
+        for (key, value) in &self.options {
+            let option = key.replace('_', "-");
+            if option == "config" || !known.contains(&option.as_str()) {
+                return Err(format!("unknown option {key:?}"));
+            }
+            let values = match value {
+                Value::Array(values) => values.iter().collect(),
+                value => vec![value],
+            };
+            for value in values {
+                match value {
+                    Value::Bool(true) => args.push(format!("--{option}")),
+                    Value::Bool(false) => {}
+                    value => args.push(format!("--{option}={}", scalar(key, value)?)),
+                }
+            }
+        }
+        let options = std::mem::take(&mut args);
+        for (i, device) in self.device.iter().enumerate() {
+            let addr = &device.address;
+            let mut per_device = |option: &str, value: String| {
+                args.push(format!("--{option}={addr}={value}"));
+            };
+            if let Some(alias) = &device.alias {
+                per_device("alias", alias.clone());
+            }
+            if let Some(bindkey) = &device.bindkey {
+                per_device("bindkey", bindkey.clone());
+            }
+            if let Some(calibrate) = &device.calibrate {
+                per_device(
+                    "calibrate",
+                    scalar(&format!("device[{i}].calibrate"), calibrate)?,
+                );
+            }
+            if let Some(chemistry) = &device.battery_chemistry {
+                per_device("battery-chemistry", chemistry.clone());
+            }
+        }
+        for (name, adapter) in &self.adapters {
+            let mut per_adapter = |option: &str, value: String| {
+                args.push(format!("--adapter-{option}={name}={value}"));
+            };
+            if let Some(scan) = &adapter.scan {
+                per_adapter("scan", scan.clone());
+            }
+            if let Some(duplicate_data) = adapter.duplicate_data {
+                per_adapter("duplicate-data", duplicate_data.to_string());
+            }
+            if let Some(min_rssi) = &adapter.min_rssi {
+                per_adapter(
+                    "min-rssi",
+                    scalar(&format!("adapters.{name}.min_rssi"), min_rssi)?,
+                );
+            }
+            for uuid in adapter.uuids.iter().flatten() {
+                per_adapter("uuid", uuid.clone());
+            }
+        }
+        Ok(FileArgs {
+            options,
+            tables: args,
+        })
+    }
+}
+
+/// A string or number as an argument value; `path` names the key.
+fn scalar(path: &str, value: &Value) -> Result<String, String> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Number(n) => Ok(n.to_string()),
+        _ => Err(format!("{path}: expected a string or number")),
+    }
+}
+
+/// The path given to `option` (e.g. `--config`) among the raw command line
+/// arguments.
+pub fn path(args: &[OsString], option: &str) -> Option<PathBuf> {
+    let mut args = args.iter().skip(1).map(|arg| arg.to_string_lossy());
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        }
+        if arg == option {
+            return args.next().map(|path| PathBuf::from(path.as_ref()));
+        }
+        if let Some(path) = arg
+            .strip_prefix(option)
+            .and_then(|rest| rest.strip_prefix('='))
+        {
+            return Some(PathBuf::from(path));
+        }
+    }
+    None
+}
+
+/// A table header the following keys belong to.
+enum Table {
+    /// `[[name]]`: the last table of the array
+    Array(String),
+    /// `[outer.inner]`
+    Named(String, String),
+}
+
+/// Recursive descent over the TOML subset, building JSON values so serde
+/// can take it from there.
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(text: &'a str) -> Self {
+        Self { text, pos: 0 }
+    }
+
+    fn document(&mut self) -> Result<Map<String, Value>, String> {
+        let mut root = Map::new();
+        // The `[[table]]` or `[table.name]` keys go into, if any
+        let mut table: Option<Table> = None;
+        loop {
+            self.skip_blank();
+            if self.rest().is_empty() {
+                return Ok(root);
+            }
+            if self.eat("[[") {
+                let name = self.key()?;
+                self.skip_spaces();
+                if !self.eat("]]") {
+                    return Err(self.error("expected ]]"));
+                }
+                match root
+                    .entry(name.clone())
+                    .or_insert_with(|| Value::Array(Vec::new()))
+                {
+                    Value::Array(tables) => tables.push(Value::Object(Map::new())),
+                    _ => return Err(self.error(&format!("{name:?} is already a value"))),
+                }
+                table = Some(Table::Array(name));
+            } else if self.eat("[") {
+                let outer = self.key()?;
+                self.skip_spaces();
+                if outer != "adapters" || !self.eat(".") {
+                    return Err(
+                        self.error("only [[device]] and [adapters.<name>] tables are supported")
+                    );
+                }
+                let inner = self.key()?;
+                self.skip_spaces();
+                if !self.eat("]") {
+                    return Err(self.error("expected ]"));
+                }
+                let tables = root
+                    .entry(outer.clone())
+                    .or_insert_with(|| Value::Object(Map::new()));
+                let Value::Object(tables) = tables else {
+                    return Err(self.error(&format!("{outer:?} is already a value")));
+                };
+                if tables
+                    .insert(inner.clone(), Value::Object(Map::new()))
+                    .is_some()
+                {
+                    return Err(self.error(&format!("[{outer}.{inner}] is defined twice")));
+                }
+                table = Some(Table::Named(outer, inner));
+            } else {
+                let key = self.key()?;
+                self.skip_spaces();
+                if !self.eat("=") {
+                    return Err(self.error("expected ="));
+                }
+                self.skip_spaces();
+                let value = self.value()?;
+                let target = match &table {
+                    Some(Table::Array(name)) => root[name.as_str()]
+                        .as_array_mut()
+                        .and_then(|tables| tables.last_mut())
+                        .and_then(Value::as_object_mut)
+                        .expect("a [[table]] was opened"),
+                    Some(Table::Named(outer, inner)) => root[outer.as_str()][inner.as_str()]
+                        .as_object_mut()
+                        .expect("a [table.name] was opened"),
+                    None => &mut root,
+                };
+                if target.insert(key.clone(), value).is_some() {
+                    return Err(self.error(&format!("{key:?} is set twice")));
+                }
+            }
+            self.end_of_line()?;
+        }
+    }
+
+    fn key(&mut self) -> Result<String, String> {
+        self.skip_spaces();
+        match self.peek() {
+            Some('"') => self.basic_string(),
+            Some('\'') => self.literal_string(),
+            _ => {
+                let key = self.take_while(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+                if key.is_empty() {
+                    return Err(self.error("expected a key"));
+                }
+                Ok(key.to_string())
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        match self.peek() {
+            Some('"') => self.basic_string().map(Value::String),
+            Some('\'') => self.literal_string().map(Value::String),
+            Some('[') => self.array(),
+            _ => {
+                let token = self.take_while(|c| c.is_ascii_alphanumeric() || "+-._".contains(c));
+                match token {
+                    "true" => Ok(Value::Bool(true)),
+                    "false" => Ok(Value::Bool(false)),
+                    _ => number(token).ok_or_else(|| self.error("expected a value")),
+                }
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, String> {
+        self.eat("[");
+        let mut values = Vec::new();
+        loop {
+            self.skip_blank();
+            if self.eat("]") {
+                return Ok(Value::Array(values));
+            }
+            values.push(self.value()?);
+            self.skip_blank();
+            if !self.eat(",") && !self.rest().starts_with(']') {
+                return Err(self.error("expected , or ]"));
+            }
+        }
+    }
+
+    fn basic_string(&mut self) -> Result<String, String> {
+        self.eat("\"");
+        let mut s = String::new();
+        let mut chars = self.rest().char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.pos += i + 1;
+                    return Ok(s);
+                }
+                '\n' => break,
+                '\\' => match chars.next().map(|(_, c)| c) {
+                    Some('n') => s.push('\n'),
+                    Some('t') => s.push('\t'),
+                    Some('"') => s.push('"'),
+                    Some('\\') => s.push('\\'),
+                    _ => {
+                        self.pos += i;
+                        return Err(self.error("unsupported escape"));
+                    }
+                },
+                c => s.push(c),
+            }
+        }
+        Err(self.error("unterminated string"))
+    }
+
+    fn literal_string(&mut self) -> Result<String, String> {
+        self.eat("'");
+        match self.rest().find(['\'', '\n']) {
+            Some(end) if self.rest()[end..].starts_with('\'') => {
+                let s = self.rest()[..end].to_string();
+                self.pos += end + 1;
+                Ok(s)
+            }
+            _ => Err(self.error("unterminated string")),
+        }
+    }
+
+    /// Only spaces and a comment may follow a key/value pair or header.
+    fn end_of_line(&mut self) -> Result<(), String> {
+        self.skip_spaces();
+        if self.rest().starts_with('#') {
+            self.take_while(|c| c != '\n');
+        }
+        if self.rest().is_empty() || self.eat("\n") || self.eat("\r\n") {
+            Ok(())
+        } else {
+            Err(self.error("expected the end of the line"))
+        }
+    }
+
+    /// Whitespace including newlines, and comments.
+    fn skip_blank(&mut self) {
+        loop {
+            self.take_while(char::is_whitespace);
+            if !self.rest().starts_with('#') {
+                return;
+            }
+            self.take_while(|c| c != '\n');
+        }
+    }
+
+    fn skip_spaces(&mut self) {
+        self.take_while(|c| c == ' ' || c == '\t');
+    }
+
+    fn take_while(&mut self, f: impl Fn(char) -> bool) -> &'a str {
+        let rest = &self.text[self.pos..];
+        let len = rest.find(|c| !f(c)).unwrap_or(rest.len());
+        self.pos += len;
+        &rest[..len]
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        let found = self.rest().starts_with(token);
+        if found {
+            self.pos += token.len();
+        }
+        found
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.text[self.pos..]
+    }
+
+    fn error(&self, message: &str) -> String {
+        let line = self.text[..self.pos].matches('\n').count() + 1;
+        format!("line {line}: {message}")
+    }
+}
+
+/// A TOML integer or float, `_` separators allowed.
+fn number(token: &str) -> Option<Value> {
+    let digits = token.replace('_', "");
+    if let Ok(n) = digits.parse::<i64>() {
+        return Some(Value::Number(n.into()));
+    }
+    // Infinity and NaN aren't numbers to serde_json either
+    Number::from_f64(digits.parse().ok()?).map(Value::Number)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KNOWN: &[&str] = &[
+        "watchdog",
+        "deny",
+        "derive",
+        "rates",
+        "mqtt-broker",
+        "min-rssi",
+        "alias",
+        "bindkey",
+        "calibrate",
+        "battery-chemistry",
+        "adapter",
+    ];
+
+    fn args(text: &str) -> Result<Vec<String>, String> {
+        let args = Config::parse(text)?.args(KNOWN)?;
+        Ok(args.iter().cloned().collect())
+    }
+
+    #[test]
+    fn test_options_become_arguments() {
+        let text = r#"
+# Living room Pi
+watchdog = 120
+min-rssi = -90   # far away sensors are noise
+mqtt_broker = "localhost:1883"
+deny = [
+    "A4:C1:38:00:00:09",
+    'A4:C1:38:00:00:0A',  # neighbour
+]
+derive = true
+rates = false
+"#;
+        assert_eq!(
+            args(text).unwrap(),
+            [
+                "--deny=A4:C1:38:00:00:09",
+                "--deny=A4:C1:38:00:00:0A",
+                "--derive",
+                "--min-rssi=-90",
+                "--mqtt-broker=localhost:1883",
+                "--watchdog=120",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_device_tables() {
+        let text = r#"
+[[device]]
+address = "A4:C1:38:00:00:01"
+alias = "Kid's \"room\""
+bindkey = "231d39c1d7cc1ab1aee224cd096db932"
+calibrate = "-0.7,2.5"
+
+[[device]]
+address = "A4:C1:38:00:00:02"
+calibrate = -0.5
+battery_chemistry = "alkaline"
+"#;
+        assert_eq!(
+            args(text).unwrap(),
+            [
+                "--alias=A4:C1:38:00:00:01=Kid's \"room\"",
+                "--bindkey=A4:C1:38:00:00:01=231d39c1d7cc1ab1aee224cd096db932",
+                "--calibrate=A4:C1:38:00:00:01=-0.7,2.5",
+                "--calibrate=A4:C1:38:00:00:02=-0.5",
+                "--battery-chemistry=A4:C1:38:00:00:02=alkaline",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_adapter_option_next_to_adapter_tables() {
+        assert_eq!(args("adapter = 'hci1'").unwrap(), ["--adapter=hci1"]);
+        assert_eq!(
+            args("adapter = ['hci0', 'hci1']\n[adapters.hci1]\nscan = 'passive'").unwrap(),
+            [
+                "--adapter=hci0",
+                "--adapter=hci1",
+                "--adapter-scan=hci1=passive"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_adapter_tables() {
+        let text = r#"
+watchdog = 120
+
+[adapters.hci0]
+duplicate_data = true
+
+[adapters."hci1"]
+scan = "passive"
+min_rssi = -75
+uuids = ["fcd2", "known"]
+"#;
+        assert_eq!(
+            args(text).unwrap(),
+            [
+                "--watchdog=120",
+                "--adapter-duplicate-data=hci0=true",
+                "--adapter-scan=hci1=passive",
+                "--adapter-min-rssi=hci1=-75",
+                "--adapter-uuid=hci1=fcd2",
+                "--adapter-uuid=hci1=known",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            args("watchdgo = 1"),
+            Err("unknown option \"watchdgo\"".into())
+        );
+        assert_eq!(
+            args("config = 'x.toml'"),
+            Err("unknown option \"config\"".into())
+        );
+        assert_eq!(
+            args("watchdog = 1\nwatchdog = 2"),
+            Err("line 2: \"watchdog\" is set twice".into())
+        );
+        assert_eq!(
+            args("[mqtt]\nbroker = 'x'"),
+            Err("line 1: only [[device]] and [adapters.<name>] tables are supported".into())
+        );
+        assert_eq!(
+            args("[adapters.hci0]\nscan = 'passive'\n[adapters.hci0]"),
+            Err("line 3: [adapters.hci0] is defined twice".into())
+        );
+        assert_eq!(
+            args("[adapters.hci0]\nscan_type = 'passive'"),
+            Err("adapters.hci0: unknown key \"scan_type\"".into())
+        );
+        assert_eq!(
+            args("watchdog = \"120"),
+            Err("line 1: unterminated string".into())
+        );
+        assert_eq!(
+            args("watchdog = 120 120"),
+            Err("line 1: expected the end of the line".into())
+        );
+        assert_eq!(
+            args("watchdog = sixty"),
+            Err("line 1: expected a value".into())
+        );
+        assert_eq!(
+            args("[[device]]\nalias = 'no address'"),
+            Err("device[0]: address is missing".into())
+        );
+        assert_eq!(
+            args("[[device]]\naddress = 'A4:C1:38:00:00:01'\nname = 'x'"),
+            Err("device[0]: unknown key \"name\"".into())
+        );
+    }
+
+    #[test]
+    fn test_type_errors_name_the_key() {
+        assert_eq!(
+            args("device = \"x\""),
+            Err("device: invalid type: string \"x\", expected a sequence".into())
+        );
+        assert_eq!(
+            args("[[device]]\naddress = 'a'\n[[device]]\naddress = 'b'\nalias = 1"),
+            Err("device[1].alias: invalid type: integer `1`, expected a string".into())
+        );
+        assert_eq!(
+            args("[[device]]\naddress = 'a'\ncalibrate = [1]"),
+            Err("device[0].calibrate: expected a string or number".into())
+        );
+        assert_eq!(
+            args("[adapters.hci0]\nduplicate_data = 'yes'"),
+            Err(
+                "adapters.hci0.duplicate_data: invalid type: string \"yes\", expected a boolean"
+                    .into()
+            )
+        );
+        assert_eq!(
+            args("adapters = 1"),
+            Err("adapters: invalid type: integer `1`, expected a map".into())
+        );
+        assert_eq!(
+            args("deny = [['A4:C1:38:00:00:09']]"),
+            Err("deny: expected a string or number".into())
+        );
+    }
+
+    #[test]
+    fn test_path() {
+        let args = |args: &[&str]| {
+            path(
+                &args.iter().map(OsString::from).collect::<Vec<_>>(),
+                "--config",
+            )
+        };
+
+        assert_eq!(
+            args(&["mitempr", "--derive", "--config", "a.toml"]),
+            Some(PathBuf::from("a.toml"))
+        );
+        assert_eq!(
+            args(&["mitempr", "--config=b.toml"]),
+            Some(PathBuf::from("b.toml"))
+        );
+        assert_eq!(args(&["mitempr", "--derive"]), None);
+        assert_eq!(args(&["mitempr", "--config-dir=x"]), None);
+    }
+}
@@ -0,0 +1,289 @@
+//! AES-128-CCM (RFC 3610), just what encrypted sensor advertisements need.
+//! Only the AES forward cipher is used, so there is no decryption half.
+
+use std::str::FromStr;
+
+/// 16-byte AES key of an encrypting sensor, from `--bindkey`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BindKey(pub [u8; 16]);
+
+impl FromStr for BindKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let bytes = hex::decode(s.trim()).map_err(|e| format!("bind key {s:?}: {e}"))?;
+        let key = bytes
+            .try_into()
+            .map_err(|bytes: Vec<u8>| format!("bind key must be 16 bytes, got {}", bytes.len()))?;
+        Ok(BindKey(key))
+    }
+}
+
+/// Decrypt and authenticate `ciphertext` with its `mic` (tag). `None` if
+/// the tag doesn't match, i.e. wrong key, nonce or corrupted data.
+pub fn ccm_decrypt(
+    key: &BindKey,
+    nonce: &[u8],
+    aad: &[u8],
+    ciphertext: &[u8],
+    mic: &[u8],
+) -> Option<Vec<u8>> {
+    let aes = Aes128::new(&key.0);
+    let plaintext = ctr(&aes, nonce, ciphertext);
+    let tag = cbc_mac(&aes, nonce, aad, &plaintext, mic.len());
+    // Constant time compare, so timing reveals nothing about the tag
+    let diff = tag.iter().zip(mic).fold(0, |acc, (a, b)| acc | (a ^ b));
+    (diff == 0).then_some(plaintext)
+}
+
+/// Encrypt `plaintext`, returning the ciphertext and a `mic_len` byte tag,
+/// e.g. to build encrypted test frames.
+pub fn ccm_encrypt(
+    key: &BindKey,
+    nonce: &[u8],
+    aad: &[u8],
+    plaintext: &[u8],
+    mic_len: usize,
+) -> (Vec<u8>, Vec<u8>) {
+    let aes = Aes128::new(&key.0);
+    let tag = cbc_mac(&aes, nonce, aad, plaintext, mic_len);
+    (ctr(&aes, nonce, plaintext), tag)
+}
+
+/// Counter block `i`: flags (L - 1), nonce, big-endian counter.
+fn counter_block(nonce: &[u8], i: u16) -> [u8; 16] {
+    let mut block = [0; 16];
+    block[0] = (14 - nonce.len()) as u8;
+    block[1..=nonce.len()].copy_from_slice(nonce);
+    block[14..].copy_from_slice(&i.to_be_bytes());
+    block
+}
+
+/// The tag encrypted with counter block 0 is the MIC; the data uses
+/// blocks 1.. and is XORed with the key stream either way.
+fn ctr(aes: &Aes128, nonce: &[u8], data: &[u8]) -> Vec<u8> {
+    data.chunks(16)
+        .enumerate()
+        .flat_map(|(i, chunk)| {
+            let stream = aes.encrypt(counter_block(nonce, i as u16 + 1));
+            chunk
+                .iter()
+                .zip(stream)
+                .map(|(b, s)| b ^ s)
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+fn cbc_mac(aes: &Aes128, nonce: &[u8], aad: &[u8], plaintext: &[u8], mic_len: usize) -> Vec<u8> {
+    let length_size = 15 - nonce.len();
+    let mut b0 = [0; 16];
+    b0[0] = (u8::from(!aad.is_empty()) << 6)
+        | ((((mic_len - 2) / 2) as u8) << 3)
+        | (length_size - 1) as u8;
+    b0[1..=nonce.len()].copy_from_slice(nonce);
+    b0[14..].copy_from_slice(&(plaintext.len() as u16).to_be_bytes());
+
+    let mut input = Vec::new();
+    if !aad.is_empty() {
+        input.extend((aad.len() as u16).to_be_bytes());
+        input.extend(aad);
+        input.resize(input.len().div_ceil(16) * 16, 0);
+    }
+    input.extend(plaintext);
+    input.resize(input.len().div_ceil(16) * 16, 0);
+
+    let mut x = aes.encrypt(b0);
+    for block in input.chunks(16) {
+        for (x, b) in x.iter_mut().zip(block) {
+            *x ^= b;
+        }
+        x = aes.encrypt(x);
+    }
+
+    let s0 = aes.encrypt(counter_block(nonce, 0));
+    x.iter().zip(s0).take(mic_len).map(|(x, s)| x ^ s).collect()
+}
+
+/// AES-128 block cipher, encryption direction only.
+struct Aes128 {
+    round_keys: [[u8; 16]; 11],
+}
+
+impl Aes128 {
+    fn new(key: &[u8; 16]) -> Self {
+        let mut words = [[0u8; 4]; 44];
+        for (i, word) in key.chunks(4).enumerate() {
+            words[i].copy_from_slice(word);
+        }
+        let mut rcon = 1u8;
+        for i in 4..44 {
+            let mut temp = words[i - 1];
+            if i % 4 == 0 {
+                temp.rotate_left(1);
+                temp = temp.map(|b| SBOX[b as usize]);
+                temp[0] ^= rcon;
+                rcon = xtime(rcon);
+            }
+            for j in 0..4 {
+                words[i][j] = words[i - 4][j] ^ temp[j];
+            }
+        }
+
+        let mut round_keys = [[0; 16]; 11];
+        for (round, key) in round_keys.iter_mut().enumerate() {
+            for (j, word) in words[round * 4..round * 4 + 4].iter().enumerate() {
+                key[j * 4..j * 4 + 4].copy_from_slice(word);
+            }
+        }
+        Self { round_keys }
+    }
+
+    fn encrypt(&self, mut state: [u8; 16]) -> [u8; 16] {
+        add_round_key(&mut state, &self.round_keys[0]);
+        for round in 1..11 {
+            for b in &mut state {
+                *b = SBOX[*b as usize];
+            }
+            shift_rows(&mut state);
+            if round < 10 {
+                mix_columns(&mut state);
+            }
+            add_round_key(&mut state, &self.round_keys[round]);
+        }
+        state
+    }
+}
+
+fn add_round_key(state: &mut [u8; 16], key: &[u8; 16]) {
+    for (s, k) in state.iter_mut().zip(key) {
+        *s ^= k;
+    }
+}
+
+/// The state is column-major: byte `r + 4c` is row `r`, column `c`.
+fn shift_rows(state: &mut [u8; 16]) {
+    let old = *state;
+    for row in 1..4 {
+        for col in 0..4 {
+            state[row + 4 * col] = old[row + 4 * ((col + row) % 4)];
+        }
+    }
+}
+
+fn mix_columns(state: &mut [u8; 16]) {
+    for col in state.chunks_mut(4) {
+        let [a, b, c, d] = [col[0], col[1], col[2], col[3]];
+        let all = a ^ b ^ c ^ d;
+        col[0] ^= all ^ xtime(a ^ b);
+        col[1] ^= all ^ xtime(b ^ c);
+        col[2] ^= all ^ xtime(c ^ d);
+        col[3] ^= all ^ xtime(d ^ a);
+    }
+}
+
+/// Multiplication by x in GF(2^8).
+fn xtime(b: u8) -> u8 {
+    (b << 1) ^ if b & 0x80 != 0 { 0x1B } else { 0 }
+}
+
+#[rustfmt::skip]
+const SBOX: [u8; 256] = [
+    0x63, 0x7C, 0x77, 0x7B, 0xF2, 0x6B, 0x6F, 0xC5, 0x30, 0x01, 0x67, 0x2B, 0xFE, 0xD7, 0xAB, 0x76,
+    0xCA, 0x82, 0xC9, 0x7D, 0xFA, 0x59, 0x47, 0xF0, 0xAD, 0xD4, 0xA2, 0xAF, 0x9C, 0xA4, 0x72, 0xC0,
+    0xB7, 0xFD, 0x93, 0x26, 0x36, 0x3F, 0xF7, 0xCC, 0x34, 0xA5, 0xE5, 0xF1, 0x71, 0xD8, 0x31, 0x15,
+    0x04, 0xC7, 0x23, 0xC3, 0x18, 0x96, 0x05, 0x9A, 0x07, 0x12, 0x80, 0xE2, 0xEB, 0x27, 0xB2, 0x75,
+    0x09, 0x83, 0x2C, 0x1A, 0x1B, 0x6E, 0x5A, 0xA0, 0x52, 0x3B, 0xD6, 0xB3, 0x29, 0xE3, 0x2F, 0x84,
+    0x53, 0xD1, 0x00, 0xED, 0x20, 0xFC, 0xB1, 0x5B, 0x6A, 0xCB, 0xBE, 0x39, 0x4A, 0x4C, 0x58, 0xCF,
+    0xD0, 0xEF, 0xAA, 0xFB, 0x43, 0x4D, 0x33, 0x85, 0x45, 0xF9, 0x02, 0x7F, 0x50, 0x3C, 0x9F, 0xA8,
+    0x51, 0xA3, 0x40, 0x8F, 0x92, 0x9D, 0x38, 0xF5, 0xBC, 0xB6, 0xDA, 0x21, 0x10, 0xFF, 0xF3, 0xD2,
+    0xCD, 0x0C, 0x13, 0xEC, 0x5F, 0x97, 0x44, 0x17, 0xC4, 0xA7, 0x7E, 0x3D, 0x64, 0x5D, 0x19, 0x73,
+    0x60, 0x81, 0x4F, 0xDC, 0x22, 0x2A, 0x90, 0x88, 0x46, 0xEE, 0xB8, 0x14, 0xDE, 0x5E, 0x0B, 0xDB,
+    0xE0, 0x32, 0x3A, 0x0A, 0x49, 0x06, 0x24, 0x5C, 0xC2, 0xD3, 0xAC, 0x62, 0x91, 0x95, 0xE4, 0x79,
+    0xE7, 0xC8, 0x37, 0x6D, 0x8D, 0xD5, 0x4E, 0xA9, 0x6C, 0x56, 0xF4, 0xEA, 0x65, 0x7A, 0xAE, 0x08,
+    0xBA, 0x78, 0x25, 0x2E, 0x1C, 0xA6, 0xB4, 0xC6, 0xE8, 0xDD, 0x74, 0x1F, 0x4B, 0xBD, 0x8B, 0x8A,
+    0x70, 0x3E, 0xB5, 0x66, 0x48, 0x03, 0xF6, 0x0E, 0x61, 0x35, 0x57, 0xB9, 0x86, 0xC1, 0x1D, 0x9E,
+    0xE1, 0xF8, 0x98, 0x11, 0x69, 0xD9, 0x8E, 0x94, 0x9B, 0x1E, 0x87, 0xE9, 0xCE, 0x55, 0x28, 0xDF,
+    0x8C, 0xA1, 0x89, 0x0D, 0xBF, 0xE6, 0x42, 0x68, 0x41, 0x99, 0x2D, 0x0F, 0xB0, 0x54, 0xBB, 0x16,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(s: &str) -> Vec<u8> {
+        hex::decode(s.replace(' ', "")).unwrap()
+    }
+
+    #[test]
+    fn test_aes128_fips197() {
+        let aes = Aes128::new(
+            &bytes("000102030405060708090a0b0c0d0e0f")
+                .try_into()
+                .unwrap(),
+        );
+        let out = aes.encrypt(
+            bytes("00112233445566778899aabbccddeeff")
+                .try_into()
+                .unwrap(),
+        );
+        assert_eq!(out.to_vec(), bytes("69c4e0d86a7b0430d8cdb78070b4c55a"));
+    }
+
+    #[test]
+    fn test_ccm_rfc3610_packet_vector_1() {
+        let key: BindKey = "c0c1c2c3c4c5c6c7c8c9cacbcccdcecf".parse().unwrap();
+        let nonce = bytes("00000003020100a0a1a2a3a4a5");
+        let aad = bytes("0001020304050607");
+        let plaintext = bytes("08090a0b0c0d0e0f101112131415161718191a1b1c1d1e");
+
+        let (ciphertext, mic) = ccm_encrypt(&key, &nonce, &aad, &plaintext, 8);
+        assert_eq!(
+            ciphertext,
+            bytes("588c979a61c663d2f066d0c2c0f989806d5f6b61dac384")
+        );
+        assert_eq!(mic, bytes("17e8d12cfdf926e0"));
+        assert_eq!(
+            ccm_decrypt(&key, &nonce, &aad, &ciphertext, &mic),
+            Some(plaintext)
+        );
+    }
+
+    #[test]
+    fn test_ccm_bthome_spec_example() {
+        // The encryption example in the BTHome v2 docs: MAC 54:48:E6:8F:80:A5,
+        // UUID 0xFCD2, device info 0x41, counter 0x00112233
+        let key: BindKey = "231d39c1d7cc1ab1aee224cd096db932".parse().unwrap();
+        let nonce = bytes("5448e68f80a5 d2fc 41 00112233");
+        // 25.06 °C, 50.55 %
+        let plaintext = bytes("02ca09 03bf13");
+
+        let (ciphertext, mic) = ccm_encrypt(&key, &nonce, &[], &plaintext, 4);
+        assert_eq!(ciphertext, bytes("a47266c95f73"));
+        assert_eq!(mic, bytes("78237214"));
+        assert_eq!(
+            ccm_decrypt(&key, &nonce, &[], &ciphertext, &mic),
+            Some(plaintext)
+        );
+    }
+
+    #[test]
+    fn test_ccm_rejects_wrong_tag() {
+        let key = BindKey([7; 16]);
+        let nonce = [1; 13];
+        let (ciphertext, mut mic) = ccm_encrypt(&key, &nonce, &[], b"22.9", 4);
+        mic[0] ^= 1;
+        assert_eq!(ccm_decrypt(&key, &nonce, &[], &ciphertext, &mic), None);
+    }
+
+    #[test]
+    fn test_parse_bind_key() {
+        assert!(
+            "231d39c1d7cc1ab1aee224cd096db932"
+                .parse::<BindKey>()
+                .is_ok()
+        );
+        assert!("231d39c1d7cc1ab1".parse::<BindKey>().is_err());
+        assert!("not hex".parse::<BindKey>().is_err());
+    }
+}
@@ -0,0 +1,144 @@
+use crate::decoder::SensorData;
+use crate::export::{Exporter, Reading};
+use crate::icons::{Icon, warning};
+use crate::template;
+use crate::units::Units;
+use bluer::Address;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+const HEADER: &str =
+    "timestamp_iso8601,address,name,rssi,temperature,humidity,battery,voltage,flags";
+
+/// Appends one row per reading to a CSV file (`--csv`).
+///
+/// Rows are flushed as they are written, so a crash loses at most the
+/// reading being handled.
+pub struct CsvExporter {
+    writer: Mutex<BufWriter<File>>,
+    units: Units,
+}
+
+impl CsvExporter {
+    /// Open `path` for appending; the header is only written to a new
+    /// (or empty) file. Temperatures are written in `units`.
+    pub fn open(path: &Path, units: Units) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let mut writer = BufWriter::new(file);
+        if writer.get_ref().metadata()?.len() == 0 {
+            writeln!(writer, "{HEADER}")?;
+            writer.flush()?;
+        }
+        Ok(Self {
+            writer: Mutex::new(writer),
+            units,
+        })
+    }
+}
+
+/// One CSV row; absent values are blank cells.
+fn row(addr: Address, data: &SensorData, time: SystemTime) -> String {
+    fn cell<T: ToString>(value: Option<T>) -> String {
+        value.map(|v| v.to_string()).unwrap_or_default()
+    }
+    [
+        template::rfc3339(time),
+        addr.to_string(),
+        data.name.as_deref().map(quote).unwrap_or_default(),
+        cell(data.rssi),
+        cell(data.temperature),
+        cell(data.humidity),
+        cell(data.battery),
+        cell(data.voltage),
+        quote(&data.flag_list()),
+    ]
+    .join(",")
+}
+
+/// Quote a field if it contains a separator, quote or line break.
+fn quote(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+impl Exporter for CsvExporter {
+    fn export(&self, reading: &Reading) {
+        let mut writer = self.writer.lock().unwrap();
+        let data = self.units.convert(&reading.data);
+        let written = writeln!(
+            writer,
+            "{}",
+            row(reading.address, &data, reading.received_at)
+        )
+        .and_then(|()| writer.flush());
+        if let Err(e) = written {
+            warning!("{} Cannot write CSV row: {e}", Icon::Warn);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::Flag;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    const ADDR: Address = Address::new([0xA4, 0xC1, 0x38, 0x00, 0x00, 0x01]);
+
+    #[test]
+    fn test_row_leaves_absent_values_blank() {
+        let data = SensorData {
+            temperature: Some(22.9),
+            battery: Some(0),
+            name: Some("Kitchen, north".into()),
+            ..Default::default()
+        };
+        let time = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        assert_eq!(
+            row(ADDR, &data, time),
+            "2023-11-14T22:13:20Z,A4:C1:38:00:00:01,\"Kitchen, north\",,22.9,,0,,"
+        );
+    }
+
+    #[test]
+    fn test_flags_are_one_cell() {
+        let data = SensorData {
+            battery: Some(50),
+            flags: [Flag::Estimated, Flag::Cached].into(),
+            ..Default::default()
+        };
+
+        assert!(row(ADDR, &data, UNIX_EPOCH).ends_with(",50,,\"estimated,cached\""));
+    }
+
+    #[test]
+    fn test_header_only_in_new_file() {
+        let path = std::env::temp_dir().join(format!("mitempr-{}.csv", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let data = SensorData {
+            humidity: Some(45.5),
+            ..Default::default()
+        };
+
+        CsvExporter::open(&path, Units::Metric)
+            .unwrap()
+            .export(&Reading::new(ADDR, data.clone()));
+        CsvExporter::open(&path, Units::Metric)
+            .unwrap()
+            .export(&Reading::new(ADDR, data));
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<_> = contents.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], HEADER);
+        assert!(lines[2].ends_with(",A4:C1:38:00:00:01,,,,45.5,,,"));
+    }
+}
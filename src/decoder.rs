@@ -1,7 +1,16 @@
-use std::collections::HashMap;
+use crate::battery::Chemistry;
+use crate::crypto::{self, BindKey};
+use crate::derived::Derived;
+use crate::rate::Rates;
+use crate::rf::RfContext;
+use crate::smooth::Smoothed;
+use serde::{Serialize, Serializer};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::ops::RangeInclusive;
 use uuid::Uuid;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum BlePacketType {
     Mijia,  // 0xFE95
     BTHome, // 0xFCD2
@@ -10,269 +19,1344 @@ pub enum BlePacketType {
 }
 
 // --- SensorData Struct (from your working code) ---
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
 pub struct SensorData {
     pub temperature: Option<f32>,
     pub humidity: Option<f32>,
     pub battery: Option<u8>,
     pub voltage: Option<f32>,
+    /// Everything beyond the core fields, keyed by name with the unit
+    /// as suffix (e.g. `energy_kwh`)
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub measurements: BTreeMap<&'static str, f64>,
+    /// Computed from the measurements with `--derive`, never measured
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub derived: Option<Derived>,
+    /// Change per minute since an earlier reading, with `--rates`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rates: Option<Rates>,
+    /// Moving averages over the device's last readings, with `--smooth`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub smoothed: Option<Smoothed>,
+    /// Radio environment at reception time, with `--rf-context`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rf_context: Option<RfContext>,
+    /// MiBeacon header of Mijia frames, kept even if the object isn't decoded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mijia: Option<MijiaHeader>,
+    /// Button presses and dimmer turns of BTHome devices, which happened
+    /// at the advertisement rather than being a state
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub events: Vec<BtHomeEvent>,
+    /// Sensor model the advertisement identifies, see [`identify_model`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<&'static str>,
+    /// Why the frame was only partially decoded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    /// MAC the sensor embeds in its payload (PVVX, Mijia), which stays the
+    /// same when the BLE address rotates
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_optional_mac"
+    )]
+    pub device_mac: Option<[u8; 6]>,
+    /// BLE address the reading arrived from, when `--identity` exports it
+    /// under `device_mac` instead
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ble_address: Option<String>,
+    /// How far to trust the values, see [`Flag`]
+    #[serde(skip_serializing_if = "BTreeSet::is_empty")]
+    pub flags: BTreeSet<Flag>,
+    /// BTHome packet ID, a counter that restarts when the device reboots
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub packet_id: Option<u8>,
+    /// Device name (inventory, advertised name or BlueZ alias)
+    pub name: Option<String>,
+    /// Signal strength (dBm) of the advertisement
+    pub rssi: Option<i16>,
+    /// Approximate distance estimated from the RSSI, with `--tx-power`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub distance_m: Option<f32>,
+    /// Bluetooth adapter the reading was taken from, when scanning with
+    /// several
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub adapter: Option<String>,
+    /// Undecoded service data as `<uuid>:<hex>`, with `--passthrough-unknown`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub raw: Vec<String>,
+}
+
+/// Marks readings that aren't plain measurements.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Flag {
+    /// A value was estimated, e.g. the battery percentage from the voltage
+    Estimated,
+    /// Part of the payload wasn't understood
+    Partial,
+    /// A physically impossible value was dropped
+    OutOfRangeClamped,
+    /// The payload was encrypted and decrypted with a `--bindkey`
+    Decrypted,
+    /// Temperature or humidity include a `--calibrate` offset
+    Calibrated,
+    /// Moving averages were added with `--smooth`
+    Smoothed,
+    /// BlueZ had the data from its cache: it came without an RSSI, so the
+    /// device wasn't heard just now
+    Cached,
+}
+
+impl fmt::Display for Flag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Flag::Estimated => "estimated",
+            Flag::Partial => "partial",
+            Flag::OutOfRangeClamped => "out_of_range_clamped",
+            Flag::Decrypted => "decrypted",
+            Flag::Calibrated => "calibrated",
+            Flag::Smoothed => "smoothed",
+            Flag::Cached => "cached",
+        })
+    }
+}
+
+/// A BTHome event object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum BtHomeEvent {
+    /// Object 0x3A. `button` counts from 0 in the order the device lists
+    /// its buttons
+    Button { button: u8, press: ButtonPress },
+    /// Object 0x3C, turned by `steps`: positive clockwise (right),
+    /// negative counter-clockwise (left)
+    Dimmer { steps: i16 },
+}
+
+/// How a BTHome button was pressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ButtonPress {
+    Press,
+    DoublePress,
+    TriplePress,
+    LongPress,
+    LongDoublePress,
+    LongTriplePress,
+    HoldPress,
+}
+
+impl ButtonPress {
+    /// The press of a BTHome button event value; `None` for 0x00 (this
+    /// button wasn't pressed) and values the spec doesn't define.
+    fn from_bthome(value: u8) -> Option<Self> {
+        Some(match value {
+            0x01 => ButtonPress::Press,
+            0x02 => ButtonPress::DoublePress,
+            0x03 => ButtonPress::TriplePress,
+            0x04 => ButtonPress::LongPress,
+            0x05 => ButtonPress::LongDoublePress,
+            0x06 => ButtonPress::LongTriplePress,
+            0x80 => ButtonPress::HoldPress,
+            _ => return None,
+        })
+    }
 }
+
+/// Provenance fields from the MiBeacon frame header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct MijiaHeader {
+    pub product_id: u16,
+    pub frame_counter: u8,
+    /// Sender MAC in display order (the frame carries it reversed), if
+    /// the frame includes it
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_optional_mac"
+    )]
+    pub mac: Option<[u8; 6]>,
+}
+
+/// A device address in display order, as in `A4:C1:38:00:00:01`.
+pub type Mac = [u8; 6];
+
+/// `A4:C1:38:00:00:01`, the way BlueZ shows addresses.
+pub fn format_mac(mac: &Mac) -> String {
+    mac.iter()
+        .map(|b| format!("{b:02X}"))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// MACs serialize as `A4:C1:38:00:00:01` rather than a byte array.
+fn serialize_mac<S: Serializer>(mac: &Mac, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format_mac(mac))
+}
+
+fn serialize_optional_mac<S: Serializer>(
+    mac: &Option<[u8; 6]>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match mac {
+        Some(mac) => serialize_mac(mac, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+impl SensorData {
+    /// The flags as `estimated,smoothed`, empty without any.
+    pub fn flag_list(&self) -> String {
+        self.flags
+            .iter()
+            .map(Flag::to_string)
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// Fill in fields from a newer reading. Fields the newer reading has
+    /// win; fields it lacks keep their current value.
+    pub fn merge_from(&mut self, newer: SensorData) {
+        self.temperature = newer.temperature.or(self.temperature);
+        self.humidity = newer.humidity.or(self.humidity);
+        self.battery = newer.battery.or(self.battery);
+        self.voltage = newer.voltage.or(self.voltage);
+        self.measurements.extend(newer.measurements);
+        self.mijia = newer.mijia.or(self.mijia);
+        self.model = newer.model.or(self.model);
+        self.events.extend(newer.events);
+        self.note = newer.note.or(self.note.take());
+        self.device_mac = newer.device_mac.or(self.device_mac);
+        self.ble_address = newer.ble_address.or(self.ble_address.take());
+        self.name = newer.name.or(self.name.take());
+        self.rssi = newer.rssi.or(self.rssi);
+        self.distance_m = newer.distance_m.or(self.distance_m);
+        self.adapter = newer.adapter.or(self.adapter.take());
+        self.packet_id = newer.packet_id.or(self.packet_id);
+        self.flags.extend(newer.flags);
+        self.raw.extend(newer.raw);
+    }
+}
+
 // --- Constants ---
 // Define the custom UUIDs used by Xiaomi/BTHome/PVVX devices
 const MIJIA_SERVICE_UUID: Uuid = Uuid::from_u128(0x0000FE95_0000_1000_8000_00805F9B34FB);
 const BTHOME_SERVICE_UUID: Uuid = Uuid::from_u128(0x0000FCD2_0000_1000_8000_00805F9B34FB);
 const PVVX_SERVICE_UUID: Uuid = Uuid::from_u128(0x0000181A_0000_1000_8000_00805F9B34FB);
-const BTHOME_V2_PREAMBLE: [u8; 4] = [0x16, 0xd2, 0xfc, 0x40];
 
-// Function to check the Service Data keys and return the classification
-fn get_packet_type(service_data: &HashMap<Uuid, Vec<u8>>) -> (BlePacketType, Option<&Vec<u8>>) {
-    if let Some(data) = service_data.get(&MIJIA_SERVICE_UUID) {
-        return (BlePacketType::Mijia, Some(data));
+/// The standard service data UUID of each format, in lookup order.
+const SERVICES: [(Uuid, BlePacketType); 3] = [
+    (MIJIA_SERVICE_UUID, BlePacketType::Mijia),
+    (BTHOME_SERVICE_UUID, BlePacketType::BTHome),
+    (PVVX_SERVICE_UUID, BlePacketType::Pvvx),
+];
+
+/// Which service data UUID carries which format: the standard UUIDs, then
+/// any added with [`ServiceMap::add`] for firmware that advertises a format
+/// under a custom UUID (`--extra-uuid`). The default has the standard ones
+/// only, which is what the free `handle_*` functions use.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ServiceMap {
+    extra: Vec<(Uuid, BlePacketType)>,
+}
+
+impl ServiceMap {
+    /// Also decode service data under `uuid` as `format`.
+    pub fn add(&mut self, uuid: Uuid, format: BlePacketType) {
+        self.extra.push((uuid, format));
     }
-    if let Some(data) = service_data.get(&BTHOME_SERVICE_UUID) {
-        return (BlePacketType::BTHome, Some(data));
+
+    /// Every mapping, in lookup order.
+    pub fn entries(&self) -> impl Iterator<Item = (Uuid, BlePacketType)> + '_ {
+        SERVICES.iter().chain(&self.extra).copied()
     }
-    if let Some(data) = service_data.get(&PVVX_SERVICE_UUID) {
-        return (BlePacketType::Pvvx, Some(data));
+
+    /// Every UUID that's decoded, e.g. to match advertisements without
+    /// decoding them.
+    pub fn uuids(&self) -> impl Iterator<Item = Uuid> + '_ {
+        self.entries().map(|(uuid, _)| uuid)
+    }
+
+    /// The first mapped service in `service_data`, with its payload.
+    fn lookup<'a>(
+        &self,
+        service_data: &'a HashMap<Uuid, Vec<u8>>,
+    ) -> Option<(Uuid, BlePacketType, &'a Vec<u8>)> {
+        self.entries()
+            .find_map(|(uuid, format)| Some((uuid, format, service_data.get(&uuid)?)))
+    }
+
+    /// The format of an advertisement's service data.
+    pub fn packet_type(&self, service_data: &HashMap<Uuid, Vec<u8>>) -> BlePacketType {
+        self.lookup(service_data)
+            .map_or(BlePacketType::Other, |(_, format, _)| format)
+    }
+
+    /// [`handle_service_data`] with this map.
+    pub fn handle_service_data(
+        &self,
+        data: &HashMap<Uuid, Vec<u8>>,
+    ) -> Result<SensorData, DecodeError> {
+        let Some((_, packet_type, bytes)) = self.lookup(data) else {
+            return Err(DecodeError::UnknownService(data.keys().copied().collect()));
+        };
+
+        let decoded = match packet_type {
+            BlePacketType::Mijia => decode_mijia(bytes)?,
+            BlePacketType::BTHome => decode_bthome(bytes)?,
+            BlePacketType::Pvvx => decode_pvvx(bytes)?,
+            BlePacketType::Other => unreachable!("Other never carries a payload"),
+        };
+        let mut data = lenient(decoded, bytes.len());
+        data.model = identify_model(packet_type, bytes);
+        Ok(data)
     }
-    (BlePacketType::Other, None)
 }
 
-/// Decode or print service data from BLE advertisements.
-///
-/// This function is intentionally crate-agnostic: it doesn't depend on `bluer`
-/// or any Bluetooth stack, only on standard Rust types.
-pub fn handle_service_data(data: &HashMap<Uuid, Vec<u8>>) -> Option<SensorData> {
-    let (packet_type, payload) = get_packet_type(data);
+/// Bluetooth SIG company ID of Xiaomi, some of whose devices carry their
+/// MiBeacon frame in manufacturer data instead of service data.
+pub const XIAOMI_COMPANY_ID: u16 = 0x038F;
+/// Version in the top three bits of the BTHome device info byte
+const BTHOME_VERSION: u8 = 2;
+/// Device info bit of BTHome v2 payloads that are AES-CCM encrypted
+const BTHOME_ENCRYPTED: u8 = 0x01;
+// MiBeacon frame control bits
+const MIBEACON_ENCRYPTED: u16 = 0x0008;
+const MIBEACON_HAS_MAC: u16 = 0x0010;
+const MIBEACON_HAS_CAPABILITY: u16 = 0x0020;
+const MIBEACON_HAS_OBJECT: u16 = 0x0040;
+/// Capability bit announcing two more I/O capability bytes
+const MIBEACON_CAPABILITY_IO: u8 = 0x20;
 
-    match packet_type {
-        BlePacketType::Mijia => {
-            if let Some(bytes) = payload {
-                match decode_mijia(bytes) {
-                    Ok(decoded) => {
-                        //println!("  🔍 Decoded Mijia data: {:?}", decoded);
-                        return Some(decoded);
-                    }
-                    Err(e) => {
-                        println!("  ⚠️  Could not decode Mijia payload: {}", e);
-                    }
-                }
+/// Bind keys by device MAC (display order), from `--bindkey`.
+pub type BindKeys = HashMap<[u8; 6], BindKey>;
+
+/// Every key decoders put into [`SensorData::measurements`].
+pub const MEASUREMENT_NAMES: &[&str] = &[
+    "count",
+    "energy_kwh",
+    "power_w",
+    "dew_point_c",
+    "moisture_percent",
+    "illuminance_lux",
+    "conductivity_us_cm",
+    "formaldehyde_mg_m3",
+    "consumable_percent",
+    "pressure_hpa",
+    "mass_kg",
+    "mass_lb",
+    "pm2_5_ug_m3",
+    "pm10_ug_m3",
+    "co2_ppm",
+    "tvoc_ug_m3",
+    "rotation_deg",
+    "distance_mm",
+    "distance_m",
+    "duration_s",
+    "current_a",
+    "speed_m_s",
+    "uv_index",
+    "volume_l",
+    "volume_ml",
+    "volume_flow_rate_m3_h",
+    "gas_m3",
+    "water_l",
+    "timestamp",
+    "acceleration_m_s2",
+    "gyroscope_deg_s",
+    "volume_storage_l",
+    "generic_boolean",
+    "power_on",
+    "opening",
+    "battery_low",
+    "battery_charging",
+    "carbon_monoxide",
+    "cold",
+    "connectivity",
+    "door",
+    "garage_door",
+    "gas_detected",
+    "heat",
+    "light",
+    "lock",
+    "moisture_detected",
+    "motion",
+    "moving",
+    "occupancy",
+    "plug",
+    "presence",
+    "problem",
+    "running",
+    "safety",
+    "smoke",
+    "sound",
+    "tamper",
+    "vibration",
+    "window",
+    "reed_switch",
+    "gpio_trigger_output",
+    "temperature_trigger",
+    "humidity_trigger",
+];
+
+/// BTHome v2 objects that go into [`SensorData::measurements`] as is:
+/// (object ID, bytes, signed, divisor, name). Binary sensors are 0 or 1.
+#[rustfmt::skip]
+const BTHOME_MEASUREMENTS: &[(u8, usize, bool, f64, &str)] = &[
+    (0x04, 3, false, 100.0, "pressure_hpa"),
+    (0x05, 3, false, 100.0, "illuminance_lux"),
+    (0x06, 2, false, 100.0, "mass_kg"),
+    (0x07, 2, false, 100.0, "mass_lb"),
+    (0x08, 2, true, 100.0, "dew_point_c"),
+    (0x09, 1, false, 1.0, "count"),
+    (0x0A, 3, false, 1000.0, "energy_kwh"),
+    (0x0B, 3, false, 100.0, "power_w"),
+    (0x0D, 2, false, 1.0, "pm2_5_ug_m3"),
+    (0x0E, 2, false, 1.0, "pm10_ug_m3"),
+    (0x0F, 1, false, 1.0, "generic_boolean"),
+    (0x10, 1, false, 1.0, "power_on"),
+    (0x11, 1, false, 1.0, "opening"),
+    (0x12, 2, false, 1.0, "co2_ppm"),
+    (0x13, 2, false, 1.0, "tvoc_ug_m3"),
+    (0x15, 1, false, 1.0, "battery_low"),
+    (0x16, 1, false, 1.0, "battery_charging"),
+    (0x17, 1, false, 1.0, "carbon_monoxide"),
+    (0x18, 1, false, 1.0, "cold"),
+    (0x19, 1, false, 1.0, "connectivity"),
+    (0x1A, 1, false, 1.0, "door"),
+    (0x1B, 1, false, 1.0, "garage_door"),
+    (0x1C, 1, false, 1.0, "gas_detected"),
+    (0x1D, 1, false, 1.0, "heat"),
+    (0x1E, 1, false, 1.0, "light"),
+    (0x1F, 1, false, 1.0, "lock"),
+    (0x20, 1, false, 1.0, "moisture_detected"),
+    (0x21, 1, false, 1.0, "motion"),
+    (0x22, 1, false, 1.0, "moving"),
+    (0x23, 1, false, 1.0, "occupancy"),
+    (0x24, 1, false, 1.0, "plug"),
+    (0x25, 1, false, 1.0, "presence"),
+    (0x26, 1, false, 1.0, "problem"),
+    (0x27, 1, false, 1.0, "running"),
+    (0x28, 1, false, 1.0, "safety"),
+    (0x29, 1, false, 1.0, "smoke"),
+    (0x2A, 1, false, 1.0, "sound"),
+    (0x2B, 1, false, 1.0, "tamper"),
+    (0x2C, 1, false, 1.0, "vibration"),
+    (0x2D, 1, false, 1.0, "window"),
+    (0x3D, 2, false, 1.0, "count"),
+    (0x3E, 4, false, 1.0, "count"),
+    (0x3F, 2, true, 10.0, "rotation_deg"),
+    (0x40, 2, false, 1.0, "distance_mm"),
+    (0x41, 2, false, 10.0, "distance_m"),
+    (0x42, 3, false, 1000.0, "duration_s"),
+    (0x43, 2, false, 1000.0, "current_a"),
+    (0x44, 2, false, 100.0, "speed_m_s"),
+    (0x46, 1, false, 10.0, "uv_index"),
+    (0x47, 2, false, 10.0, "volume_l"),
+    (0x48, 2, false, 1.0, "volume_ml"),
+    (0x49, 2, false, 1000.0, "volume_flow_rate_m3_h"),
+    (0x4B, 3, false, 1000.0, "gas_m3"),
+    (0x4C, 4, false, 1000.0, "gas_m3"),
+    (0x4D, 4, false, 1000.0, "energy_kwh"),
+    (0x4E, 4, false, 1000.0, "volume_l"),
+    (0x4F, 4, false, 1000.0, "water_l"),
+    (0x50, 4, false, 1.0, "timestamp"),
+    (0x51, 2, false, 1000.0, "acceleration_m_s2"),
+    (0x52, 2, false, 1000.0, "gyroscope_deg_s"),
+    (0x55, 4, false, 1000.0, "volume_storage_l"),
+    (0x56, 2, false, 1.0, "conductivity_us_cm"),
+];
+
+// Physically possible values; anything outside comes from a corrupt frame
+// for these sensors (their operating range, coin cell voltages)
+const TEMPERATURE_RANGE: RangeInclusive<f32> = -40.0..=85.0;
+const HUMIDITY_RANGE: RangeInclusive<f32> = 0.0..=100.0;
+const VOLTAGE_RANGE: RangeInclusive<f32> = 0.0..=4.0;
+const MOISTURE_RANGE: RangeInclusive<f32> = 0.0..=100.0;
+const BATTERY_MAX: u8 = 100;
+
+/// Why a payload couldn't be decoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// None of the service data UUIDs belongs to a known format.
+    UnknownService(Vec<Uuid>),
+    /// None of the manufacturer data company IDs has a known format.
+    UnknownManufacturer(Vec<u16>),
+    /// The payload ends before the `need` bytes its header calls for.
+    TooShort { got: usize, need: usize },
+    /// A BTHome or MiBeacon version this decoder doesn't support.
+    UnsupportedVersion(u8),
+    /// The payload is encrypted and has to be decrypted first.
+    Encrypted,
+    /// Encrypted, but there is no bind key for the device.
+    NoBindKey,
+    /// The payload doesn't authenticate with the device's bind key.
+    DecryptFailed,
+    /// A MiBeacon frame that carries no object.
+    NoObject,
+    /// A known MiBeacon object with too short a value.
+    TruncatedObject(u16),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnknownService(uuids) => {
+                write!(f, "no decoder for service data {:?}", uuids)
+            }
+            DecodeError::UnknownManufacturer(ids) => {
+                write!(f, "no decoder for manufacturer data {:04X?}", ids)
+            }
+            DecodeError::TooShort { got, need } => {
+                write!(f, "too short: {} bytes, need {}", got, need)
+            }
+            DecodeError::UnsupportedVersion(version) => {
+                write!(f, "version {} is not supported", version)
+            }
+            DecodeError::Encrypted => write!(f, "encrypted (needs a --bindkey)"),
+            DecodeError::NoBindKey => write!(f, "encrypted, but no --bindkey for the device"),
+            DecodeError::DecryptFailed => write!(f, "doesn't decrypt with the bind key"),
+            DecodeError::NoObject => write!(f, "frame without object"),
+            DecodeError::TruncatedObject(object) => {
+                write!(f, "incomplete object 0x{:04X}", object)
             }
         }
+    }
+}
 
-        BlePacketType::BTHome => {
-            if let Some(bytes) = payload {
-                if let Some(decoded) = decode_bthome(bytes) {
-                    //println!("  🔍 Decoded BTHome data: {:?}", decoded);
-                    return Some(decoded);
-                } else {
-                    println!("  ⚠️  Could not decode BTHome payload");
-                }
+impl std::error::Error for DecodeError {}
+
+/// Why an advertisement was rejected in `--strict` mode.
+#[derive(Debug)]
+pub enum StrictError {
+    /// None of the service data UUIDs belongs to a known format.
+    UnknownService(Vec<Uuid>),
+    /// The format was recognized but the payload could not be decoded.
+    Undecodable {
+        format: BlePacketType,
+        reason: DecodeError,
+    },
+    /// A BTHome or MiBeacon object ID the decoder doesn't know.
+    UnknownObject { format: BlePacketType, object: u16 },
+    /// The decoder stopped before the end of the payload.
+    TrailingBytes {
+        format: BlePacketType,
+        consumed: usize,
+        len: usize,
+    },
+}
+
+impl fmt::Display for StrictError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StrictError::UnknownService(uuids) => {
+                write!(f, "no decoder for service data {:?}", uuids)
+            }
+            StrictError::Undecodable { format, reason } => {
+                write!(f, "{:?} payload undecodable: {}", format, reason)
             }
+            StrictError::UnknownObject { format, object } => {
+                write!(
+                    f,
+                    "{:?} payload has unknown object 0x{:02X}",
+                    format, object
+                )
+            }
+            StrictError::TrailingBytes {
+                format,
+                consumed,
+                len,
+            } => write!(
+                f,
+                "{:?} payload has {} trailing bytes (decoded {} of {})",
+                format,
+                len - consumed,
+                consumed,
+                len
+            ),
         }
+    }
+}
 
-        BlePacketType::Pvvx => {
-            if let Some(bytes) = payload {
-                if let Some(decoded) = decode_pvvx(bytes) {
-                    //println!("  🔍 Decoded PVVX data: {:?}", decoded);
-                    return Some(decoded);
-                } else {
-                    println!("  ⚠️  Could not decode PVVX payload");
-                }
+/// Output of a format decoder plus the bookkeeping `--strict` needs
+/// to tell whether the whole payload was understood.
+#[derive(Debug)]
+pub struct Decoded {
+    pub data: SensorData,
+    /// Bytes of the payload that were understood
+    pub consumed: usize,
+    /// The object ID decoding stopped at, if it was unknown
+    pub unknown_object: Option<u16>,
+}
+
+/// The format of an advertisement's service data, by the standard UUIDs.
+pub fn packet_type(service_data: &HashMap<Uuid, Vec<u8>>) -> BlePacketType {
+    ServiceMap::default().packet_type(service_data)
+}
+
+/// The service data UUID a format is advertised under, if it has one.
+pub fn service_uuid(packet_type: BlePacketType) -> Option<Uuid> {
+    match packet_type {
+        BlePacketType::Mijia => Some(MIJIA_SERVICE_UUID),
+        BlePacketType::BTHome => Some(BTHOME_SERVICE_UUID),
+        BlePacketType::Pvvx => Some(PVVX_SERVICE_UUID),
+        BlePacketType::Other => None,
+    }
+}
+
+/// Decode service data from BLE advertisements.
+///
+/// This function is intentionally crate-agnostic: it doesn't depend on `bluer`
+/// or any Bluetooth stack, only on standard Rust types. Nothing is printed;
+/// the caller decides how to report errors. Only the standard UUIDs are
+/// looked up; [`ServiceMap::handle_service_data`] takes custom ones too.
+pub fn handle_service_data(data: &HashMap<Uuid, Vec<u8>>) -> Result<SensorData, DecodeError> {
+    ServiceMap::default().handle_service_data(data)
+}
+
+/// The format of an advertisement's manufacturer data.
+pub fn manufacturer_packet_type(manufacturer_data: &HashMap<u16, Vec<u8>>) -> BlePacketType {
+    if manufacturer_data.contains_key(&XIAOMI_COMPANY_ID) {
+        BlePacketType::Mijia
+    } else {
+        BlePacketType::Other
+    }
+}
+
+/// Decode the manufacturer data of company `id`.
+pub fn decode_manufacturer(id: u16, data: &[u8]) -> Result<SensorData, DecodeError> {
+    let decoded = match id {
+        XIAOMI_COMPANY_ID => decode_mijia(data)?,
+        _ => return Err(DecodeError::UnknownManufacturer(vec![id])),
+    };
+    let mut decoded = lenient(decoded, data.len());
+    decoded.model = identify_model(BlePacketType::Mijia, data);
+    Ok(decoded)
+}
+
+/// Decode manufacturer data from BLE advertisements, like
+/// [`handle_service_data`] does service data.
+pub fn handle_manufacturer_data(data: &HashMap<u16, Vec<u8>>) -> Result<SensorData, DecodeError> {
+    match data.get(&XIAOMI_COMPANY_ID) {
+        Some(bytes) => decode_manufacturer(XIAOMI_COMPANY_ID, bytes),
+        None => {
+            let mut ids: Vec<u16> = data.keys().copied().collect();
+            ids.sort();
+            Err(DecodeError::UnknownManufacturer(ids))
+        }
+    }
+}
+
+/// Decrypt encrypted payloads in `data` from the device with `mac`.
+///
+/// `Ok(None)` if nothing is encrypted, otherwise a copy of `data` with the
+/// payload replaced by what an unencrypted device would send. Errors if
+/// there is no key for the device or the payload doesn't authenticate.
+/// MiBeacon frames that carry a MAC are looked up by that one instead.
+pub fn decrypt_service_data(
+    data: &HashMap<Uuid, Vec<u8>>,
+    mac: [u8; 6],
+    keys: &BindKeys,
+) -> Result<Option<HashMap<Uuid, Vec<u8>>>, DecodeError> {
+    ServiceMap::default().decrypt_service_data(data, mac, keys)
+}
+
+impl ServiceMap {
+    /// [`decrypt_service_data`] with this map.
+    pub fn decrypt_service_data(
+        &self,
+        data: &HashMap<Uuid, Vec<u8>>,
+        mac: [u8; 6],
+        keys: &BindKeys,
+    ) -> Result<Option<HashMap<Uuid, Vec<u8>>>, DecodeError> {
+        let (uuid, plaintext) = match self.lookup(data) {
+            Some((uuid, BlePacketType::Mijia, payload)) if mibeacon_encrypted(payload) => {
+                let mac = mibeacon_mac(payload).unwrap_or(mac);
+                let key = keys.get(&mac).ok_or(DecodeError::NoBindKey)?;
+                (uuid, decrypt_mibeacon(payload, mac, key)?)
+            }
+            Some((uuid, BlePacketType::BTHome, payload))
+                if payload
+                    .first()
+                    .is_some_and(|info| info & BTHOME_ENCRYPTED != 0) =>
+            {
+                let key = keys.get(&mac).ok_or(DecodeError::NoBindKey)?;
+                (uuid, decrypt_bthome(payload, mac, key)?)
             }
+            _ => return Ok(None),
+        };
+
+        let mut decrypted = data.clone();
+        decrypted.insert(uuid, plaintext);
+        Ok(Some(decrypted))
+    }
+}
+
+fn mibeacon_frame_control(payload: &[u8]) -> Option<u16> {
+    Some(u16::from_le_bytes([*payload.first()?, *payload.get(1)?]))
+}
+
+fn mibeacon_encrypted(payload: &[u8]) -> bool {
+    mibeacon_frame_control(payload).is_some_and(|fc| fc & MIBEACON_ENCRYPTED != 0)
+}
+
+/// Sender MAC in display order, if the frame carries one.
+fn mibeacon_mac(payload: &[u8]) -> Option<[u8; 6]> {
+    if mibeacon_frame_control(payload)? & MIBEACON_HAS_MAC == 0 {
+        return None;
+    }
+    let mut mac: [u8; 6] = payload.get(5..11)?.try_into().ok()?;
+    mac.reverse();
+    Some(mac)
+}
+
+/// Decrypt a MiBeacon v4/v5 frame,
+/// `[frame control: 2][product: 2][counter][MAC: 6]?[capability]?[ciphertext][ext counter: 3][MIC: 4]`,
+/// into the plaintext layout `decode_mijia` reads: frame control, product,
+/// counter, MAC, objects.
+fn decrypt_mibeacon(payload: &[u8], mac: [u8; 6], key: &BindKey) -> Result<Vec<u8>, DecodeError> {
+    let fc = mibeacon_frame_control(payload).ok_or(DecodeError::TooShort {
+        got: payload.len(),
+        need: 2,
+    })?;
+    let version = (fc >> 12) as u8;
+    if version < 4 {
+        return Err(DecodeError::UnsupportedVersion(version));
+    }
+    let mut start = 5;
+    if fc & MIBEACON_HAS_MAC != 0 {
+        start += 6;
+    }
+    if fc & MIBEACON_HAS_CAPABILITY != 0 {
+        if payload
+            .get(start)
+            .is_some_and(|c| c & MIBEACON_CAPABILITY_IO != 0)
+        {
+            start += 2;
         }
+        start += 1;
+    }
+    if payload.len() < start + 8 {
+        return Err(DecodeError::TooShort {
+            got: payload.len(),
+            need: start + 8,
+        });
+    }
+    let (ciphertext, tail) = payload[start..].split_at(payload.len() - start - 7);
+    let (ext_counter, mic) = tail.split_at(3);
 
-        BlePacketType::Other => {
-            println!("  -> Unknown BLE packet");
+    // The nonce has the MAC in frame (reversed) order
+    let mut nonce: Vec<u8> = mac.iter().rev().copied().collect();
+    nonce.extend(&payload[2..5]);
+    nonce.extend(ext_counter);
+    let plaintext = crypto::ccm_decrypt(key, &nonce, &[0x11], ciphertext, mic)
+        .ok_or(DecodeError::DecryptFailed)?;
+
+    let fc = (fc & !(MIBEACON_ENCRYPTED | MIBEACON_HAS_CAPABILITY)) | MIBEACON_HAS_MAC;
+    let mut decrypted = fc.to_le_bytes().to_vec();
+    decrypted.extend(&payload[2..5]);
+    decrypted.extend(mac.iter().rev());
+    decrypted.extend(plaintext);
+    Ok(decrypted)
+}
+
+/// `[device info][ciphertext][counter: 4][MIC: 4]` to `[device info][objects]`.
+fn decrypt_bthome(payload: &[u8], mac: [u8; 6], key: &BindKey) -> Result<Vec<u8>, DecodeError> {
+    if payload.len() < 9 {
+        return Err(DecodeError::TooShort {
+            got: payload.len(),
+            need: 9,
+        });
+    }
+    let info = payload[0];
+    let (ciphertext, tail) = payload[1..].split_at(payload.len() - 9);
+    let (counter, mic) = tail.split_at(4);
+
+    let mut nonce = mac.to_vec();
+    nonce.extend([0xD2, 0xFC, info]);
+    nonce.extend(counter);
+    let plaintext =
+        crypto::ccm_decrypt(key, &nonce, &[], ciphertext, mic).ok_or(DecodeError::DecryptFailed)?;
+
+    let mut decrypted = vec![info & !BTHOME_ENCRYPTED];
+    decrypted.extend(plaintext);
+    Ok(decrypted)
+}
+
+/// Like [`handle_service_data`], but every advertisement that isn't fully
+/// understood is an error: unknown services, undecodable payloads, unknown
+/// object IDs and trailing bytes the decoder didn't consume.
+///
+/// Nothing is printed; the caller decides how loud to be.
+pub fn handle_service_data_strict(
+    data: &HashMap<Uuid, Vec<u8>>,
+) -> Result<SensorData, StrictError> {
+    ServiceMap::default().handle_service_data_strict(data)
+}
+
+impl ServiceMap {
+    /// [`handle_service_data_strict`] with this map.
+    pub fn handle_service_data_strict(
+        &self,
+        data: &HashMap<Uuid, Vec<u8>>,
+    ) -> Result<SensorData, StrictError> {
+        let Some((_, packet_type, bytes)) = self.lookup(data) else {
+            return Err(StrictError::UnknownService(data.keys().copied().collect()));
+        };
+
+        let decoded = match packet_type {
+            BlePacketType::Mijia => decode_mijia(bytes),
+            BlePacketType::BTHome => decode_bthome(bytes),
+            BlePacketType::Pvvx => decode_pvvx(bytes),
+            BlePacketType::Other => unreachable!("Other never carries a payload"),
+        }
+        .map_err(|reason| StrictError::Undecodable {
+            format: packet_type,
+            reason,
+        })?;
+
+        if let Some(object) = decoded.unknown_object {
+            return Err(StrictError::UnknownObject {
+                format: packet_type,
+                object,
+            });
         }
+        if decoded.consumed < bytes.len() {
+            return Err(StrictError::TrailingBytes {
+                format: packet_type,
+                consumed: decoded.consumed,
+                len: bytes.len(),
+            });
+        }
+
+        let mut data = decoded.data;
+        estimate_battery(&mut data);
+        data.model = identify_model(packet_type, bytes);
+        Ok(data)
     }
+}
 
-    None
+/// MiBeacon product IDs of the sensors known to use them.
+const MIJIA_MODELS: [(u16, &str); 8] = [
+    (0x0098, "HHCCJCY01"),
+    (0x01AA, "LYWSDCGQ"),
+    (0x02DF, "JQJCY01YM"),
+    (0x0347, "CGG1"),
+    (0x0387, "MHO-C401"),
+    (0x045B, "LYWSD02"),
+    (0x055B, "LYWSD03MMC"),
+    (0x0576, "CGD1"),
+];
+
+/// The sensor model a payload of `packet_type` comes from, as far as it
+/// tells: MiBeacon frames carry a product ID. The PVVX format is the same
+/// on every model its firmware runs on and BTHome names no model, so they
+/// (like unlisted product IDs) give `None` rather than a guess.
+pub fn identify_model(packet_type: BlePacketType, payload: &[u8]) -> Option<&'static str> {
+    match (packet_type, payload) {
+        (BlePacketType::Mijia, [_, _, low, high, ..]) => {
+            let product_id = u16::from_le_bytes([*low, *high]);
+            MIJIA_MODELS
+                .iter()
+                .find(|(id, _)| *id == product_id)
+                .map(|(_, model)| *model)
+        }
+        _ => None,
+    }
 }
 
-// --- BTHome Decoder ---
-fn decode_bthome(payload: &Vec<u8>) -> Option<SensorData> {
-    // 1. Create the full data array by prepending the preamble
-    let mut all_data = Vec::new();
-    all_data.extend_from_slice(&BTHOME_V2_PREAMBLE);
-    all_data.extend_from_slice(payload); // payload is the [40, 00, 73, 0C, ...]
+/// Fill in the battery level of voltage-only readings from the CR2032
+/// curve (what nearly all of these sensors run on), flagged as estimated.
+fn estimate_battery(data: &mut SensorData) {
+    if let (None, Some(voltage)) = (data.battery, data.voltage) {
+        data.battery = Some(Chemistry::Cr2032.percent(voltage));
+        data.flags.insert(Flag::Estimated);
+    }
+}
 
-    // 2. The working decoder expects the full array but is sliced to skip the first 4 bytes
-    let data = &all_data[4..];
+/// `Some(value)` if it lies in `range`, `None` for physically impossible
+/// values, which are flagged.
+fn plausible(value: f32, range: RangeInclusive<f32>, flags: &mut BTreeSet<Flag>) -> Option<f32> {
+    let value = range.contains(&value).then_some(value);
+    if value.is_none() {
+        flags.insert(Flag::OutOfRangeClamped);
+    }
+    value
+}
 
-    let mut result = SensorData {
-        temperature: None,
-        humidity: None,
-        battery: None,
-        voltage: None,
-    };
+fn plausible_battery(percent: u8, flags: &mut BTreeSet<Flag>) -> Option<u8> {
+    let percent = (percent <= BATTERY_MAX).then_some(percent);
+    if percent.is_none() {
+        flags.insert(Flag::OutOfRangeClamped);
+    }
+    percent
+}
+
+/// The lenient view of a decode: whatever was understood, flagged as
+/// partial if something wasn't.
+fn lenient(decoded: Decoded, len: usize) -> SensorData {
+    let mut data = decoded.data;
+    if decoded.unknown_object.is_some() || decoded.consumed < len {
+        data.flags.insert(Flag::Partial);
+    }
+    estimate_battery(&mut data);
+    data
+}
+
+// --- BTHome Decoder ---
+/// `[device info][object ID][value]...`; the device info byte holds the
+/// version (bits 5-7), the trigger-based bit (2) and the encryption bit (0).
+pub fn decode_bthome(payload: &[u8]) -> Result<Decoded, DecodeError> {
+    let info = *payload
+        .first()
+        .ok_or(DecodeError::TooShort { got: 0, need: 1 })?;
+    if info >> 5 != BTHOME_VERSION {
+        return Err(DecodeError::UnsupportedVersion(info >> 5));
+    }
+    // Ciphertext would decode to garbage; see decrypt_service_data
+    if info & BTHOME_ENCRYPTED != 0 {
+        return Err(DecodeError::Encrypted);
+    }
+    // Trigger-based devices (buttons, door sensors) advertise on events
+    // rather than regularly, which changes nothing about the objects
+
+    let mut result = SensorData::default();
 
-    let mut i = 1; // Skip first byte (00) - This is the Packet ID in the [40, 00] header
-    while i < data.len() {
-        if i + 1 >= data.len() {
+    let mut unknown_object = None;
+    // Every button object counts, pressed or not, so indexes stay stable
+    let mut buttons = 0u8;
+    let mut i = 1; // Objects start after the device info byte
+    while i < payload.len() {
+        if i + 1 >= payload.len() {
             break;
         }
 
-        match data[i] {
+        match payload[i] {
+            0x00 => {
+                // Packet ID (1 byte), not a measurement
+                result.packet_id = Some(payload[i + 1]);
+                i += 2;
+            }
             0x01 => {
                 // Battery (%) (1 byte)
-                if i + 1 >= data.len() {
+                if i + 1 >= payload.len() {
                     break;
                 }
-                result.battery = Some(data[i + 1]);
+                result.battery = plausible_battery(payload[i + 1], &mut result.flags);
                 i += 2;
             }
             0x02 => {
                 // Temperature (2 bytes, factor 0.01)
-                if i + 2 >= data.len() {
+                if i + 2 >= payload.len() {
                     break;
                 }
-                let temp_raw = i16::from_le_bytes([data[i + 1], data[i + 2]]);
-                result.temperature = Some(temp_raw as f32 / 100.0);
+                let temp_raw = i16::from_le_bytes([payload[i + 1], payload[i + 2]]);
+                result.temperature = plausible(
+                    temp_raw as f32 / 100.0,
+                    TEMPERATURE_RANGE,
+                    &mut result.flags,
+                );
                 i += 3;
             }
             0x03 => {
                 // Humidity (2 bytes, factor 0.01)
-                if i + 2 >= data.len() {
+                if i + 2 >= payload.len() {
                     break;
                 }
-                let hum_raw = u16::from_le_bytes([data[i + 1], data[i + 2]]);
-                result.humidity = Some(hum_raw as f32 / 100.0);
+                let hum_raw = u16::from_le_bytes([payload[i + 1], payload[i + 2]]);
+                result.humidity =
+                    plausible(hum_raw as f32 / 100.0, HUMIDITY_RANGE, &mut result.flags);
                 i += 3;
             }
             0x0C => {
                 // Voltage (2 bytes, factor 0.001)
-                if i + 2 >= data.len() {
+                if i + 2 >= payload.len() {
                     break;
                 }
-                let voltage_raw = u16::from_le_bytes([data[i + 1], data[i + 2]]);
-                result.voltage = Some(voltage_raw as f32 / 1000.0);
+                let voltage_raw = u16::from_le_bytes([payload[i + 1], payload[i + 2]]);
+                result.voltage = plausible(
+                    voltage_raw as f32 / 1000.0,
+                    VOLTAGE_RANGE,
+                    &mut result.flags,
+                );
                 i += 3;
             }
-            _ => {
-                //println!("  ⚠️  Unknown type 0x{:02x} at position {}", data[i], i);
-                i += 2; // Try to skip an assumed Type + 1 byte value to continue
+            0x45 => {
+                // Temperature (sint16, factor 0.1)
+                if i + 2 >= payload.len() {
+                    break;
+                }
+                let temp_raw = i16::from_le_bytes([payload[i + 1], payload[i + 2]]);
+                result.temperature =
+                    plausible(temp_raw as f32 / 10.0, TEMPERATURE_RANGE, &mut result.flags);
+                i += 3;
+            }
+            0x2E => {
+                // Humidity (uint8, %)
+                result.humidity =
+                    plausible(payload[i + 1] as f32, HUMIDITY_RANGE, &mut result.flags);
+                i += 2;
+            }
+            0x4A => {
+                // Voltage (uint16, factor 0.1)
+                if i + 2 >= payload.len() {
+                    break;
+                }
+                let voltage_raw = u16::from_le_bytes([payload[i + 1], payload[i + 2]]);
+                result.voltage =
+                    plausible(voltage_raw as f32 / 10.0, VOLTAGE_RANGE, &mut result.flags);
+                i += 3;
+            }
+            0x3A => {
+                // Button event (uint8)
+                if let Some(press) = ButtonPress::from_bthome(payload[i + 1]) {
+                    result.events.push(BtHomeEvent::Button {
+                        button: buttons,
+                        press,
+                    });
+                }
+                buttons = buttons.saturating_add(1);
+                i += 2;
+            }
+            0x3C => {
+                // Dimmer event (uint8 direction: 1 left, 2 right; uint8 steps)
+                if i + 2 >= payload.len() {
+                    break;
+                }
+                let steps = i16::from(payload[i + 2]);
+                match payload[i + 1] {
+                    0x01 => result.events.push(BtHomeEvent::Dimmer { steps: -steps }),
+                    0x02 => result.events.push(BtHomeEvent::Dimmer { steps }),
+                    _ => {}
+                }
+                i += 3;
+            }
+            0x14 | 0x2F => {
+                // Moisture (uint16 factor 0.01 % / uint8 %)
+                let width = if payload[i] == 0x14 { 2 } else { 1 };
+                let Some(raw) = read_uint_le(payload, i, width) else {
+                    break;
+                };
+                let percent = if width == 2 {
+                    raw as f64 / 100.0
+                } else {
+                    raw as f64
+                };
+                // Checked as f32 like the rest, kept as the exact f64
+                if plausible(percent as f32, MOISTURE_RANGE, &mut result.flags).is_some() {
+                    result.measurements.insert("moisture_percent", percent);
+                }
+                i += 1 + width;
+            }
+            id => {
+                // Without the object's length the rest can't be parsed
+                let Some(&(_, width, signed, divisor, name)) =
+                    BTHOME_MEASUREMENTS.iter().find(|object| object.0 == id)
+                else {
+                    unknown_object = Some(id.into());
+                    break;
+                };
+                let Some(raw) = read_uint_le(payload, i, width) else {
+                    break;
+                };
+                let value = if signed {
+                    let shift = 32 - 8 * width;
+                    ((raw << shift) as i32 >> shift) as f64
+                } else {
+                    raw as f64
+                };
+                result.measurements.insert(name, value / divisor);
+                i += 1 + width;
             }
         }
     }
 
-    Some(result)
+    Ok(Decoded {
+        data: result,
+        consumed: i.min(payload.len()),
+        unknown_object,
+    })
 }
 
+/// Little-endian unsigned value of `width` (1..=4) bytes following the
+/// object ID at `i`, or `None` if the payload is cut short.
+fn read_uint_le(data: &[u8], i: usize, width: usize) -> Option<u32> {
+    let bytes = data.get(i + 1..i + 1 + width)?;
+    Some(bytes.iter().rev().fold(0, |acc, b| (acc << 8) | *b as u32))
+}
+
+/// Bits of the PVVX flags byte that go into [`SensorData::measurements`]
+/// as 0 or 1. Bit 2 only says whether the triggers drive the GPIO pin.
+const PVVX_FLAGS: [(u8, &str); 4] = [
+    (0, "reed_switch"),
+    (1, "gpio_trigger_output"),
+    (3, "temperature_trigger"),
+    (4, "humidity_trigger"),
+];
+
+/// Length of the original atc1441 firmware format.
+const ATC1441_LENGTH: usize = 13;
+
 // --- PVVX Decoder ---
-fn decode_pvvx(payload: &Vec<u8>) -> Option<SensorData> {
+/// The PVVX custom format: `[MAC, reversed][temperature i16 × 0.01]
+/// [humidity u16 × 0.01][battery mV u16][battery %][counter][flags]`, all
+/// little-endian, 15 bytes.
+///
+/// The same UUID carries the 13 byte atc1441 format, see
+/// [`decode_atc1441`], which PVVX firmware can be set to as well.
+pub fn decode_pvvx(payload: &[u8]) -> Result<Decoded, DecodeError> {
     const MIN_LENGTH: usize = 15;
     const MAC_LENGTH: usize = 6;
 
+    if payload.len() == ATC1441_LENGTH {
+        return decode_atc1441(payload);
+    }
     if payload.len() < MIN_LENGTH {
-        // Packet too short → return None
-        return None;
+        return Err(DecodeError::TooShort {
+            got: payload.len(),
+            need: MIN_LENGTH,
+        });
     }
 
     // Slice out the data after the MAC address
     let data_slice = &payload[MAC_LENGTH..];
+    let mut flags = BTreeSet::new();
 
     // Temperature: Bytes 0 & 1 (Little-Endian, signed, factor 0.01)
-    let temperature = if data_slice.len() >= 2 {
-        let temp_raw = i16::from_le_bytes([data_slice[0], data_slice[1]]);
-        Some(temp_raw as f32 / 100.0)
-    } else {
-        None
-    };
+    let temp_raw = i16::from_le_bytes([data_slice[0], data_slice[1]]);
+    let temperature = plausible(temp_raw as f32 / 100.0, TEMPERATURE_RANGE, &mut flags);
 
-    // Humidity: Bytes 2 & 3 (Little-Endian, unsigned, factor 0.01)
-    let humidity = if data_slice.len() >= 4 {
-        let hum_raw = u16::from_le_bytes([data_slice[2], data_slice[3]]);
-        Some(hum_raw as f32 / 100.0)
-    } else {
-        None
-    };
+    // Humidity: Bytes 2 & 3 (Little-Endian, unsigned, factor 0.01). A
+    // firmware offset taking it below 0 wraps to > 655 %, which is dropped
+    let hum_raw = u16::from_le_bytes([data_slice[2], data_slice[3]]);
+    let humidity = plausible(hum_raw as f32 / 100.0, HUMIDITY_RANGE, &mut flags);
 
-    // Voltage: Bytes 4 & 5 (Little-Endian, unsigned, factor 0.001)
-    let voltage = if data_slice.len() >= 6 {
-        let volt_raw = u16::from_le_bytes([data_slice[4], data_slice[5]]);
-        Some(volt_raw as f32 / 1000.0)
-    } else {
-        None
-    };
+    // Voltage: Bytes 4 & 5 (Little-Endian, unsigned, battery mV)
+    let volt_raw = u16::from_le_bytes([data_slice[4], data_slice[5]]);
+    let voltage = plausible(volt_raw as f32 / 1000.0, VOLTAGE_RANGE, &mut flags);
 
     // Battery: Byte 6
-    let battery = if data_slice.len() >= 7 {
-        Some(data_slice[6])
-    } else {
-        None
-    };
+    let battery = plausible_battery(data_slice[6], &mut flags);
+
+    // Flags: Byte 8, the GPIO and trigger states
+    let measurements = PVVX_FLAGS
+        .iter()
+        .map(|(bit, name)| (*name, f64::from((data_slice[8] >> bit) & 1)))
+        .collect();
+
+    let mut device_mac: [u8; 6] = payload[..MAC_LENGTH].try_into().unwrap();
+    device_mac.reverse();
 
-    Some(SensorData {
-        temperature,
-        humidity,
-        battery,
-        voltage,
+    Ok(Decoded {
+        data: SensorData {
+            temperature,
+            humidity,
+            battery,
+            voltage,
+            measurements,
+            device_mac: Some(device_mac),
+            flags,
+            ..Default::default()
+        },
+        // The counter (byte 13) is part of the format, just not decoded
+        consumed: payload.len().min(MIN_LENGTH),
+        unknown_object: None,
     })
 }
 
-// --- LYWSDCGQ V3 Decoder ---
-fn decode_mijia(payload: &Vec<u8>) -> Result<SensorData, String> {
-    // The Xiaomi Manufacturer ID (0x04C0) is already stripped by bluer.
-    // The byte at index 11 is the Type Identifier byte (0x0D, 0x06, 0x0A, etc.)
-    const TYPE_IDENTIFIER_OFFSET: usize = 11;
-
-    if payload.len() <= TYPE_IDENTIFIER_OFFSET {
-        return Err(format!(
-            "LYWSDCGQ V3 packet too short: {} bytes",
-            payload.len()
-        ));
-    }
+/// The original atc1441 firmware format: `[MAC][temperature i16 × 0.1]
+/// [humidity %][battery %][battery mV u16][counter]`, all big-endian,
+/// 13 bytes. Coarser than the PVVX custom format, and the MAC is in display
+/// order.
+pub fn decode_atc1441(payload: &[u8]) -> Result<Decoded, DecodeError> {
+    let Ok(bytes) = <[u8; ATC1441_LENGTH]>::try_from(payload) else {
+        return Err(DecodeError::TooShort {
+            got: payload.len(),
+            need: ATC1441_LENGTH,
+        });
+    };
+    let mut flags = BTreeSet::new();
 
-    let type_identifier = payload[TYPE_IDENTIFIER_OFFSET];
+    let temp_raw = i16::from_be_bytes([bytes[6], bytes[7]]);
+    let temperature = plausible(temp_raw as f32 / 10.0, TEMPERATURE_RANGE, &mut flags);
+    let humidity = plausible(f32::from(bytes[8]), HUMIDITY_RANGE, &mut flags);
+    let battery = plausible_battery(bytes[9], &mut flags);
+    let volt_raw = u16::from_be_bytes([bytes[10], bytes[11]]);
+    let voltage = plausible(volt_raw as f32 / 1000.0, VOLTAGE_RANGE, &mut flags);
 
-    // Initialize all fields as None
-    let mut temperature: Option<f32> = None;
-    let mut humidity: Option<f32> = None;
-    let mut battery_percent: Option<u8> = None;
-    let voltage: Option<f32> = None; // V3 typically doesn't send voltage
+    Ok(Decoded {
+        data: SensorData {
+            temperature,
+            humidity,
+            battery,
+            voltage,
+            device_mac: Some(bytes[..6].try_into().unwrap()),
+            flags,
+            ..Default::default()
+        },
+        // The counter (byte 12) is part of the format, just not decoded
+        consumed: ATC1441_LENGTH,
+        unknown_object: None,
+    })
+}
 
-    match type_identifier {
-        // 0x0D: Combined Temperature and Humidity
-        0x0D if payload.len() >= 18 => {
-            let raw_temp_bytes: [u8; 2] = payload[14..16].try_into().unwrap_or([0, 0]);
-            temperature = Some(i16::from_le_bytes(raw_temp_bytes) as f32 / 10.0);
+// --- MiBeacon (Xiaomi) Decoder ---
+/// Object IDs `decode_mijia` understands; a known object that is too short
+/// is a corrupt frame, an unknown one is merely unsupported.
+const MIJIA_OBJECT_TYPES: [u16; 9] = [
+    0x1004, 0x1006, 0x1007, 0x1008, 0x1009, 0x100A, 0x100D, 0x1010, 0x1013,
+];
 
-            let raw_humi_bytes: [u8; 2] = payload[16..18].try_into().unwrap_or([0, 0]);
-            humidity = Some(u16::from_le_bytes(raw_humi_bytes) as f32 / 10.0);
+/// `[frame control: 2][product: 2][counter][MAC: 6]?[capability]?[I/O: 2]?`
+/// followed by `[object ID: 2][length][value]` records.
+pub fn decode_mijia(payload: &[u8]) -> Result<Decoded, DecodeError> {
+    let fc = match mibeacon_frame_control(payload) {
+        Some(fc) if payload.len() >= 5 => fc,
+        _ => {
+            return Err(DecodeError::TooShort {
+                got: payload.len(),
+                need: 5,
+            });
         }
+    };
+    if fc & MIBEACON_ENCRYPTED != 0 {
+        return Err(DecodeError::Encrypted);
+    }
 
-        // 0x04: Temperature Only
-        0x04 if payload.len() >= 16 => {
-            let raw_temp_bytes: [u8; 2] = payload[14..16].try_into().unwrap_or([0, 0]);
-            temperature = Some(i16::from_le_bytes(raw_temp_bytes) as f32 / 10.0);
+    let mut i = 5;
+    let mac = mibeacon_mac(payload);
+    if fc & MIBEACON_HAS_MAC != 0 {
+        i += 6;
+    }
+    if fc & MIBEACON_HAS_CAPABILITY != 0 {
+        let capability = *payload.get(i).ok_or(DecodeError::TooShort {
+            got: payload.len(),
+            need: i + 1,
+        })?;
+        i += 1;
+        if capability & MIBEACON_CAPABILITY_IO != 0 {
+            i += 2;
         }
+    }
+    if fc & MIBEACON_HAS_OBJECT == 0 || i >= payload.len() {
+        return Err(DecodeError::NoObject);
+    }
 
-        // 0x06: Humidity Only
-        0x06 if payload.len() >= 16 => {
-            let raw_humi_bytes: [u8; 2] = payload[14..16].try_into().unwrap_or([0, 0]);
-            humidity = Some(u16::from_le_bytes(raw_humi_bytes) as f32 / 10.0);
-        }
+    let header = MijiaHeader {
+        product_id: u16::from_le_bytes([payload[2], payload[3]]),
+        frame_counter: payload[4],
+        mac,
+    };
+    let mut result = SensorData {
+        mijia: Some(header),
+        device_mac: mac,
+        ..Default::default()
+    };
+    let mut unknown_object = None;
+    let mut decoded_objects = 0;
 
-        // 0x0A: Battery Percentage Only
-        0x0A if payload.len() >= 15 => {
-            battery_percent = Some(payload[14]);
-        }
+    while i + 3 <= payload.len() {
+        let id = u16::from_le_bytes([payload[i], payload[i + 1]]);
+        let len = payload[i + 2] as usize;
+        let Some(value) = payload.get(i + 3..i + 3 + len) else {
+            break;
+        };
 
-        _ => {
-            return Err(format!(
-                "Unrecognized or incomplete LYWSDCGQ V3 payload (Type 0x{:02X}, Length {})",
-                type_identifier,
-                payload.len()
-            ));
+        let known = match (id, value) {
+            (0x1004, &[t0, t1, ..]) => {
+                result.temperature = plausible(
+                    i16::from_le_bytes([t0, t1]) as f32 / 10.0,
+                    TEMPERATURE_RANGE,
+                    &mut result.flags,
+                );
+                true
+            }
+            (0x1006, &[h0, h1, ..]) => {
+                result.humidity = plausible(
+                    u16::from_le_bytes([h0, h1]) as f32 / 10.0,
+                    HUMIDITY_RANGE,
+                    &mut result.flags,
+                );
+                true
+            }
+            (0x100A, &[battery, ..]) => {
+                result.battery = plausible_battery(battery, &mut result.flags);
+                true
+            }
+            (0x100D, &[t0, t1, h0, h1, ..]) => {
+                result.temperature = plausible(
+                    i16::from_le_bytes([t0, t1]) as f32 / 10.0,
+                    TEMPERATURE_RANGE,
+                    &mut result.flags,
+                );
+                result.humidity = plausible(
+                    u16::from_le_bytes([h0, h1]) as f32 / 10.0,
+                    HUMIDITY_RANGE,
+                    &mut result.flags,
+                );
+                true
+            }
+            // Illuminance (3 bytes, lux)
+            (0x1007, &[l0, l1, l2, ..]) => {
+                let lux = u32::from_le_bytes([l0, l1, l2, 0]);
+                result.measurements.insert("illuminance_lux", lux as f64);
+                true
+            }
+            // Soil moisture (1 byte, %)
+            (0x1008, &[moisture, ..]) => {
+                result
+                    .measurements
+                    .insert("moisture_percent", moisture as f64);
+                true
+            }
+            // Soil conductivity (2 bytes, µS/cm)
+            (0x1009, &[c0, c1, ..]) => {
+                let raw = u16::from_le_bytes([c0, c1]);
+                result.measurements.insert("conductivity_us_cm", raw as f64);
+                true
+            }
+            // Formaldehyde (2 bytes, factor 0.01 mg/m³)
+            (0x1010, &[f0, f1, ..]) => {
+                let raw = u16::from_le_bytes([f0, f1]);
+                result
+                    .measurements
+                    .insert("formaldehyde_mg_m3", raw as f64 / 100.0);
+                true
+            }
+            // Consumable remaining (1 byte, %), e.g. a filter
+            (0x1013, &[remaining, ..]) => {
+                result
+                    .measurements
+                    .insert("consumable_percent", remaining as f64);
+                true
+            }
+            _ => false,
+        };
+
+        if !known {
+            if MIJIA_OBJECT_TYPES.contains(&id) {
+                // Known object with too short a value: a corrupt frame
+                break;
+            }
+            // Unknown object: keep the header and what came before
+            result.note = Some(format!("unrecognized object 0x{id:04X}"));
+            unknown_object = Some(id);
+            break;
         }
+        decoded_objects += 1;
+        i += 3 + len;
+    }
+
+    if decoded_objects == 0 && unknown_object.is_none() {
+        let id = payload
+            .get(i..i + 2)
+            .map_or(0, |id| u16::from_le_bytes([id[0], id[1]]));
+        return Err(DecodeError::TruncatedObject(id));
     }
 
-    Ok(SensorData {
-        temperature,
-        humidity,
-        battery: battery_percent,
-        voltage,
+    Ok(Decoded {
+        data: result,
+        consumed: i,
+        unknown_object,
     })
 }
 
@@ -293,7 +1377,34 @@ mod tests {
             ],
         );
 
-        handle_service_data(&data);
+        // Object 0x100D: temperature and humidity, both int16 × 0.1
+        let decoded = handle_service_data(&data).unwrap();
+        assert_eq!(decoded.temperature, Some(23.4));
+        assert_eq!(decoded.humidity, Some(60.9));
+        assert_eq!(decoded.battery, None);
+        assert_eq!(decoded.voltage, None);
+        assert!(decoded.flags.is_empty());
+    }
+
+    #[test]
+    fn test_mijia_manufacturer_data() {
+        let frame = vec![
+            0x50, 0x20, 0xAA, 0x01, 0xF5, 0x40, 0x71, 0xD5, 0xA8, 0x65, 0x4C, 0x0D, 0x10, 0x04,
+            0xEA, 0x00, 0x61, 0x02,
+        ];
+        let data = HashMap::from([(XIAOMI_COMPANY_ID, frame), (0x004C, vec![0x02, 0x15])]);
+
+        assert_eq!(manufacturer_packet_type(&data), BlePacketType::Mijia);
+        let decoded = handle_manufacturer_data(&data).unwrap();
+        assert_eq!(decoded.temperature, Some(23.4));
+        assert_eq!(decoded.humidity, Some(60.9));
+
+        let apple = HashMap::from([(0x004C, vec![0x02, 0x15])]);
+        assert_eq!(manufacturer_packet_type(&apple), BlePacketType::Other);
+        assert_eq!(
+            handle_manufacturer_data(&apple),
+            Err(DecodeError::UnknownManufacturer(vec![0x004C]))
+        );
     }
 
     #[test]
@@ -307,7 +1418,125 @@ mod tests {
             ],
         );
 
-        handle_service_data(&data);
+        let decoded = handle_service_data(&data).unwrap();
+        assert_eq!(decoded.temperature, Some(22.9));
+        assert_eq!(decoded.humidity, Some(64.25));
+        assert_eq!(decoded.voltage, Some(2.333));
+        assert_eq!(decoded.battery, Some(16));
+        assert!(decoded.flags.is_empty());
+        // Flags 0x05: reed switch closed, triggers drive the GPIO pin
+        assert_eq!(
+            decoded.measurements,
+            BTreeMap::from([
+                ("reed_switch", 1.0),
+                ("gpio_trigger_output", 0.0),
+                ("temperature_trigger", 0.0),
+                ("humidity_trigger", 0.0),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_pvvx_below_freezing() {
+        // A sensor in the freezer: -18.25 °C, 71.03 %, 2.951 V, 92 %, a
+        // temperature trigger and the GPIO output it drives
+        let payload = [
+            0x03, 0x7B, 0xA0, 0x38, 0xC1, 0xA4, 0xDF, 0xF8, 0xBF, 0x1B, 0x87, 0x0B, 0x5C, 0x11,
+            0x0E,
+        ];
+        let data = decode_pvvx(&payload).unwrap().data;
+
+        assert_eq!(data.temperature, Some(-18.25));
+        assert_eq!(data.humidity, Some(71.03));
+        assert_eq!(data.voltage, Some(2.951));
+        assert_eq!(data.battery, Some(92));
+        assert_eq!(data.measurements["gpio_trigger_output"], 1.0);
+        assert_eq!(data.measurements["temperature_trigger"], 1.0);
+        assert_eq!(data.measurements["reed_switch"], 0.0);
+    }
+
+    #[test]
+    fn test_atc1441_below_freezing() {
+        // -18.2 °C, 71 %, 92 %, 2.951 V, counter 0x11
+        let payload = [
+            0xA4, 0xC1, 0x38, 0xA0, 0x7B, 0x03, 0xFF, 0x4A, 0x47, 0x5C, 0x0B, 0x87, 0x11,
+        ];
+        let decoded = decode_pvvx(&payload).unwrap();
+
+        assert_eq!(decoded.consumed, payload.len());
+        assert_eq!(decoded.data.temperature, Some(-18.2));
+        assert_eq!(decoded.data.humidity, Some(71.0));
+        assert_eq!(decoded.data.battery, Some(92));
+        assert_eq!(decoded.data.voltage, Some(2.951));
+        // No flags byte in this format
+        assert!(decoded.data.measurements.is_empty());
+        assert!(matches!(
+            decode_atc1441(&payload[..12]),
+            Err(DecodeError::TooShort { got: 12, need: 13 })
+        ));
+    }
+
+    #[test]
+    fn test_merge_partial_readings() {
+        let mut reading = SensorData {
+            temperature: Some(21.0),
+            battery: Some(90),
+            flags: BTreeSet::from([Flag::Estimated]),
+            ..Default::default()
+        };
+        let newer = SensorData {
+            temperature: Some(21.5),
+            humidity: Some(48.0),
+            flags: BTreeSet::from([Flag::Partial]),
+            ..Default::default()
+        };
+
+        reading.merge_from(newer);
+        assert_eq!(
+            reading,
+            SensorData {
+                temperature: Some(21.5),
+                humidity: Some(48.0),
+                battery: Some(90),
+                flags: BTreeSet::from([Flag::Estimated, Flag::Partial]),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_identify_model() {
+        let lywsd03mmc = [0x58, 0x58, 0x5B, 0x05, 0x07];
+        assert_eq!(
+            identify_model(BlePacketType::Mijia, &lywsd03mmc),
+            Some("LYWSD03MMC")
+        );
+        // Unlisted product, too short for one, and formats without one
+        assert_eq!(
+            identify_model(BlePacketType::Mijia, &[0x50, 0x20, 0x34, 0x12]),
+            None
+        );
+        assert_eq!(
+            identify_model(BlePacketType::Mijia, &[0x50, 0x20, 0xAA]),
+            None
+        );
+        assert_eq!(identify_model(BlePacketType::Pvvx, &[0x5B; 15]), None);
+        assert_eq!(identify_model(BlePacketType::BTHome, &lywsd03mmc), None);
+    }
+
+    #[test]
+    fn test_too_short_payloads() {
+        let pvvx = [0x03, 0x7B, 0xA0, 0x38, 0xC1, 0xA4, 0xF2, 0x08];
+        assert_eq!(
+            decode_pvvx(&pvvx).err(),
+            Some(DecodeError::TooShort { got: 8, need: 15 })
+        );
+
+        let data = HashMap::from([(BTHOME_SERVICE_UUID, vec![])]);
+        assert!(matches!(
+            handle_service_data(&data),
+            Err(DecodeError::TooShort { .. })
+        ));
     }
 
     #[test]
@@ -320,6 +1549,535 @@ mod tests {
             ],
         );
 
-        handle_service_data(&data);
+        let decoded = handle_service_data_strict(&data).unwrap();
+        assert_eq!(decoded.packet_id, Some(0x12));
+        assert_eq!(decoded.battery, Some(100));
+        assert_eq!(decoded.temperature, Some(24.29));
+        assert_eq!(decoded.humidity, Some(62.85));
+    }
+
+    #[test]
+    fn test_battery_estimated_from_voltage() {
+        let estimate = |millivolts: u16| {
+            let [lo, hi] = millivolts.to_le_bytes();
+            let data = HashMap::from([(BTHOME_SERVICE_UUID, vec![0x40, 0x0C, lo, hi])]);
+            handle_service_data_strict(&data).unwrap()
+        };
+
+        assert_eq!(estimate(3100).battery, Some(100));
+        assert_eq!(estimate(2750).battery, Some(50));
+        assert_eq!(estimate(1900).battery, Some(0));
+        assert!(estimate(2750).flags.contains(&Flag::Estimated));
+
+        // A reported level is never overridden
+        let data = HashMap::from([(
+            BTHOME_SERVICE_UUID,
+            vec![0x40, 0x01, 0x64, 0x0C, 0xDC, 0x05],
+        )]);
+        let reported = handle_service_data(&data).unwrap();
+        assert_eq!(reported.battery, Some(100));
+        assert!(reported.flags.is_empty());
+    }
+
+    #[test]
+    fn test_bthome_device_info() {
+        // Trigger-based devices decode like any other
+        assert_eq!(bthome(vec![0x44, 0x01, 0x64]).battery, Some(100));
+        // BTHome v1 layout and reserved versions are not v2
+        assert_eq!(
+            decode_bthome(&[0x20, 0x01, 0x64]).unwrap_err(),
+            DecodeError::UnsupportedVersion(1)
+        );
+        assert_eq!(
+            decode_bthome(&[0x60, 0x01, 0x64]).unwrap_err(),
+            DecodeError::UnsupportedVersion(3)
+        );
+    }
+
+    #[test]
+    fn test_strict_rejects_trailing_bytes() {
+        let mut data = HashMap::new();
+        data.insert(
+            uuid!("0000181A-0000-1000-8000-00805F9B34FB"),
+            vec![
+                0x03, 0x7B, 0xA0, 0x38, 0xC1, 0xA4, 0xF2, 0x08, 0x19, 0x19, 0x1D, 0x09, 0x10, 0x4A,
+                0x05, 0xFF,
+            ],
+        );
+
+        assert!(matches!(
+            handle_service_data_strict(&data),
+            Err(StrictError::TrailingBytes {
+                consumed: 15,
+                len: 16,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_strict_rejects_unknown_bthome_object() {
+        let mut data = HashMap::new();
+        data.insert(
+            uuid!("0000fcd2-0000-1000-8000-00805f9b34fb"),
+            vec![0x40, 0x00, 0x12, 0x01, 0x64, 0x7F, 0x00],
+        );
+
+        assert!(matches!(
+            handle_service_data_strict(&data),
+            Err(StrictError::UnknownObject { object: 0x7F, .. })
+        ));
+
+        // Lenient decoding keeps the battery but flags the reading
+        let lenient = handle_service_data(&data).unwrap();
+        assert_eq!(lenient.battery, Some(100));
+        assert!(lenient.flags.contains(&Flag::Partial));
+    }
+
+    #[test]
+    fn test_bthome_encrypted() {
+        // Example from the BTHome encryption docs: 25.06 °C, 50.55 %
+        let mac = [0x54, 0x48, 0xE6, 0x8F, 0x80, 0xA5];
+        let key: BindKey = "231d39c1d7cc1ab1aee224cd096db932".parse().unwrap();
+        let encrypted = HashMap::from([(
+            BTHOME_SERVICE_UUID,
+            vec![
+                0x41, 0xA4, 0x72, 0x66, 0xC9, 0x5F, 0x73, 0x00, 0x11, 0x22, 0x33, 0x78, 0x23, 0x72,
+                0x14,
+            ],
+        )]);
+
+        // Without the key it's not decoded at all
+        assert_eq!(handle_service_data(&encrypted), Err(DecodeError::Encrypted));
+        assert_eq!(
+            decrypt_service_data(&encrypted, mac, &BindKeys::new()),
+            Err(DecodeError::NoBindKey)
+        );
+
+        let keys = BindKeys::from([(mac, key)]);
+        let decrypted = decrypt_service_data(&encrypted, mac, &keys)
+            .unwrap()
+            .unwrap();
+        // The docs' plaintext, with the encryption bit cleared
+        assert_eq!(
+            decrypted[&BTHOME_SERVICE_UUID],
+            [0x40, 0x02, 0xCA, 0x09, 0x03, 0xBF, 0x13]
+        );
+        let data = handle_service_data_strict(&decrypted).unwrap();
+        assert_eq!(data.temperature, Some(25.06));
+        assert_eq!(data.humidity, Some(50.55));
+
+        let wrong = BindKeys::from([(mac, BindKey([0; 16]))]);
+        assert_eq!(
+            decrypt_service_data(&encrypted, mac, &wrong),
+            Err(DecodeError::DecryptFailed)
+        );
+    }
+
+    #[test]
+    fn test_mibeacon_encrypted() {
+        // LYWSD03MMC (product 0x055B), MiBeacon v5 with MAC, 21.5 °C / 45.0 %
+        let mac = [0xA4, 0xC1, 0x38, 0x12, 0x34, 0x56];
+        let key = BindKey([0x42; 16]);
+        let header = [0x58, 0x58, 0x5B, 0x05, 0x07];
+        let ext_counter = [0x01, 0x00, 0x00];
+        let mut nonce: Vec<u8> = mac.iter().rev().copied().collect();
+        nonce.extend(&header[2..]);
+        nonce.extend(ext_counter);
+        let objects = [0x0D, 0x10, 0x04, 0xD7, 0x00, 0xC2, 0x01];
+        let (ciphertext, mic) = crypto::ccm_encrypt(&key, &nonce, &[0x11], &objects, 4);
+
+        let mut frame = header.to_vec();
+        frame.extend(mac.iter().rev());
+        frame.extend(ciphertext);
+        frame.extend(ext_counter);
+        frame.extend(mic);
+        let encrypted = HashMap::from([(MIJIA_SERVICE_UUID, frame)]);
+
+        assert!(handle_service_data_strict(&encrypted).is_err());
+        // The MAC comes from the frame, not the (here unrelated) BLE address
+        let keys = BindKeys::from([(mac, key)]);
+        let decrypted = decrypt_service_data(&encrypted, [0; 6], &keys)
+            .unwrap()
+            .unwrap();
+        let data = handle_service_data_strict(&decrypted).unwrap();
+        assert_eq!(data.temperature, Some(21.5));
+        assert_eq!(data.humidity, Some(45.0));
+        assert_eq!(data.mijia.unwrap().product_id, 0x055B);
+        assert_eq!(data.device_mac, Some(mac));
+    }
+
+    #[test]
+    fn test_mibeacon_encrypted_captured_frame() {
+        // Captured from an XMWSDJ04MMC (product 0x1203) with its bindkey:
+        // MiBeacon v5 without a MAC in the frame, so the key is looked up by
+        // the BLE address
+        let mac = [0x2C, 0x11, 0x65, 0x25, 0x70, 0x04];
+        let key: BindKey = "b2cf9a553d53571b5657defd582d676e".parse().unwrap();
+        let encrypted = HashMap::from([(
+            MIJIA_SERVICE_UUID,
+            vec![
+                0x48, 0x59, 0x03, 0x12, 0xA4, 0x1B, 0x77, 0x6E, 0x7C, 0x96, 0xAD, 0xD7, 0x00, 0x00,
+                0x00, 0xF2, 0xBF, 0x54, 0x5B,
+            ],
+        )]);
+
+        let keys = BindKeys::from([(mac, key)]);
+        let decrypted = decrypt_service_data(&encrypted, mac, &keys)
+            .unwrap()
+            .unwrap();
+        // Frame control, product, counter, the MAC added, then object
+        // 0x4C08: humidity as a float, 45.0 %
+        assert_eq!(
+            decrypted[&MIJIA_SERVICE_UUID],
+            [
+                0x50, 0x59, 0x03, 0x12, 0xA4, 0x04, 0x70, 0x25, 0x65, 0x11, 0x2C, 0x08, 0x4C, 0x04,
+                0x00, 0x00, 0x34, 0x42,
+            ]
+        );
+        assert_eq!(f32::from_le_bytes([0x00, 0x00, 0x34, 0x42]), 45.0);
+        let data = handle_service_data(&decrypted).unwrap();
+        let header = data.mijia.unwrap();
+        assert_eq!((header.product_id, header.frame_counter), (0x1203, 0xA4));
+        assert_eq!(data.device_mac, Some(mac));
+
+        let wrong = BindKeys::from([(mac, BindKey([0; 16]))]);
+        assert_eq!(
+            decrypt_service_data(&encrypted, mac, &wrong),
+            Err(DecodeError::DecryptFailed)
+        );
+    }
+
+    #[test]
+    fn test_extra_service_uuid() {
+        let custom = uuid!("0000fcd9-0000-1000-8000-00805f9b34fb");
+        let data = HashMap::from([(custom, vec![0x40, 0x02, 0xCA, 0x09])]);
+        assert_eq!(packet_type(&data), BlePacketType::Other);
+
+        let mut services = ServiceMap::default();
+        services.add(custom, BlePacketType::BTHome);
+        assert_eq!(services.packet_type(&data), BlePacketType::BTHome);
+        assert_eq!(
+            services.handle_service_data(&data).unwrap().temperature,
+            Some(25.06)
+        );
+        assert_eq!(services.uuids().last(), Some(custom));
+        // Nothing leaks into other maps
+        assert_eq!(packet_type(&data), BlePacketType::Other);
+    }
+
+    #[test]
+    fn test_plaintext_needs_no_decryption() {
+        let data = HashMap::from([(BTHOME_SERVICE_UUID, vec![0x40, 0x02, 0xCA, 0x09])]);
+        assert_eq!(
+            decrypt_service_data(&data, [0; 6], &BindKeys::new()),
+            Ok(None)
+        );
+    }
+
+    fn bthome(payload: Vec<u8>) -> SensorData {
+        decode_bthome(&payload).unwrap().data
+    }
+
+    #[test]
+    fn test_bthome_humidity_bounds() {
+        // 100.00 % is the last valid raw value
+        assert_eq!(bthome(vec![0x40, 0x03, 0x10, 0x27]).humidity, Some(100.0));
+        assert_eq!(bthome(vec![0x40, 0x03, 0x11, 0x27]).humidity, None);
+        assert_eq!(bthome(vec![0x40, 0x03, 0xFF, 0xFF]).humidity, None);
+        assert_eq!(bthome(vec![0x40, 0x03, 0x00, 0x00]).humidity, Some(0.0));
+    }
+
+    #[test]
+    fn test_bthome_negative_temperatures() {
+        // Freezer at -18.00 °C (raw -1800 = 0xF8F8)
+        let freezer = bthome(vec![0x40, 0x02, 0xF8, 0xF8]);
+        assert_eq!(freezer.temperature, Some(-18.0));
+        assert!(freezer.flags.is_empty());
+
+        // Just below zero: -0.50 °C (raw -50 = 0xFFCE), not 655.1 °C
+        let data = HashMap::from([(BTHOME_SERVICE_UUID, vec![0x40, 0x02, 0xCE, 0xFF])]);
+        let chilled = handle_service_data_strict(&data).unwrap();
+        assert_eq!(chilled.temperature, Some(-0.5));
+        assert!(chilled.flags.is_empty());
+    }
+
+    #[test]
+    fn test_bthome_temperature_and_battery_bounds() {
+        // -40.00 °C (raw -4000) is the coldest accepted value
+        assert_eq!(
+            bthome(vec![0x40, 0x02, 0x60, 0xF0]).temperature,
+            Some(-40.0)
+        );
+        assert_eq!(bthome(vec![0x40, 0x02, 0x5F, 0xF0]).temperature, None);
+        assert_eq!(bthome(vec![0x40, 0x02, 0xFF, 0x7F]).temperature, None);
+        assert_eq!(bthome(vec![0x40, 0x01, 0x64]).battery, Some(100));
+        assert_eq!(bthome(vec![0x40, 0x01, 0x65]).battery, None);
+    }
+
+    #[test]
+    fn test_bthome_button_and_dimmer_events() {
+        // Packet ID 5, button 0 idle, button 1 double press, dimmer 3 left
+        let payload = [
+            0x44, 0x00, 0x05, 0x3A, 0x00, 0x3A, 0x02, 0x3C, 0x01, 0x03, 0x3C, 0x02, 0x01,
+        ];
+        let decoded = decode_bthome(&payload).unwrap();
+        assert_eq!(decoded.consumed, payload.len());
+        assert_eq!(decoded.data.packet_id, Some(5));
+        assert_eq!(
+            decoded.data.events,
+            [
+                BtHomeEvent::Button {
+                    button: 1,
+                    press: ButtonPress::DoublePress
+                },
+                BtHomeEvent::Dimmer { steps: -3 },
+                BtHomeEvent::Dimmer { steps: 1 },
+            ]
+        );
+        assert_eq!(
+            serde_json::to_value(decoded.data.events[0]).unwrap(),
+            serde_json::json!({"event": "button", "button": 1, "press": "double_press"})
+        );
+    }
+
+    #[test]
+    fn test_bthome_voltage_bounds() {
+        assert_eq!(bthome(vec![0x40, 0x0C, 0xA0, 0x0F]).voltage, Some(4.0));
+        let corrupt = bthome(vec![0x40, 0x0C, 0x70, 0x17]);
+        assert_eq!(corrupt.voltage, None);
+        assert_eq!(corrupt.flags, BTreeSet::from([Flag::OutOfRangeClamped]));
+    }
+
+    #[test]
+    fn test_pvvx_implausible_fields_dropped() {
+        // 90.00 °C and 6.000 V from a corrupted frame; humidity and
+        // battery are fine and kept
+        let payload = [
+            0x03, 0x7B, 0xA0, 0x38, 0xC1, 0xA4, 0x28, 0x23, 0x19, 0x19, 0x70, 0x17, 0x50, 0x4A,
+            0x05,
+        ];
+        let data = decode_pvvx(&payload).unwrap().data;
+
+        assert_eq!(data.temperature, None);
+        assert_eq!(data.voltage, None);
+        assert_eq!(data.humidity, Some(64.25));
+        assert_eq!(data.battery, Some(80));
+        assert_eq!(data.flags, BTreeSet::from([Flag::OutOfRangeClamped]));
+    }
+
+    #[test]
+    fn test_mijia_humidity_bounds() {
+        let mut payload = vec![
+            0x50, 0x20, 0xAA, 0x01, 0xF5, 0x40, 0x71, 0xD5, 0xA8, 0x65, 0x4C, 0x06, 0x10, 0x02,
+            0xE8, 0x03,
+        ];
+        let decoded = decode_mijia(&payload).unwrap().data;
+        assert_eq!(decoded.humidity, Some(100.0));
+        assert!(decoded.flags.is_empty());
+
+        payload[14] = 0xE9;
+        let decoded = decode_mijia(&payload).unwrap().data;
+        assert_eq!(decoded.humidity, None);
+        assert_eq!(decoded.flags, BTreeSet::from([Flag::OutOfRangeClamped]));
+    }
+
+    #[test]
+    fn test_bthome_energy_between_temperature_and_battery() {
+        let data = bthome(vec![
+            0x40, 0x02, 0xCA, 0x09, // 25.06 °C
+            0x0A, 0x13, 0x8A, 0x14, // 1346.067 kWh (uint24)
+            0x01, 0x64, // 100 %
+        ]);
+
+        assert_eq!(data.temperature, Some(25.06));
+        assert_eq!(data.measurements["energy_kwh"], 1346.067);
+        assert_eq!(data.battery, Some(100));
+    }
+
+    #[test]
+    fn test_bthome_uint32_count_and_energy() {
+        let data = bthome(vec![
+            0x40, 0x3E, 0xFF, 0xFF, 0xFF, 0xFF, // count 4294967295
+            0x4D, 0x40, 0x42, 0x0F, 0x00, // 1000.000 kWh (uint32)
+            0x01, 0x32,
+        ]);
+
+        assert_eq!(data.measurements["count"], u32::MAX as f64);
+        assert_eq!(data.measurements["energy_kwh"], 1000.0);
+        assert_eq!(data.battery, Some(50));
+    }
+
+    #[test]
+    fn test_bthome_air_quality_and_binary_objects() {
+        let data = bthome(vec![
+            0x40, 0x04, 0x13, 0x8A, 0x01, // 1008.83 hPa
+            0x12, 0xE2, 0x04, // 1250 ppm CO2
+            0x0D, 0x0C, 0x00, // PM2.5 12 µg/m³
+            0x0E, 0x15, 0x00, // PM10 21 µg/m³
+            0x21, 0x01, // motion
+            0x3F, 0x0C, 0xFE, // -50.0°
+            0x45, 0x11, 0x01, // 27.3 °C (factor 0.1)
+        ]);
+
+        assert_eq!(data.measurements["pressure_hpa"], 1008.83);
+        assert_eq!(data.measurements["co2_ppm"], 1250.0);
+        assert_eq!(data.measurements["pm2_5_ug_m3"], 12.0);
+        assert_eq!(data.measurements["pm10_ug_m3"], 21.0);
+        assert_eq!(data.measurements["motion"], 1.0);
+        assert_eq!(data.measurements["rotation_deg"], -50.0);
+        assert_eq!(data.temperature, Some(27.3));
+    }
+
+    #[test]
+    fn test_bthome_unknown_object_stops_instead_of_misaligning() {
+        // 0x7F's length is unknown; guessing would read 0x02 0xCA as an object
+        let decoded = decode_bthome(&[0x40, 0x01, 0x64, 0x7F, 0x02, 0xCA, 0x09]).unwrap();
+
+        assert_eq!(decoded.data.battery, Some(100));
+        assert_eq!(decoded.data.temperature, None);
+        assert_eq!(decoded.unknown_object, Some(0x7F));
+        assert_eq!(decoded.consumed, 3);
+    }
+
+    #[test]
+    fn test_bthome_measurement_names_are_listed() {
+        for (id, _, _, _, name) in BTHOME_MEASUREMENTS {
+            assert!(MEASUREMENT_NAMES.contains(name), "0x{id:02X} {name}");
+        }
+    }
+
+    #[test]
+    fn test_bthome_truncated_uint24_stops_cleanly() {
+        let decoded = decode_bthome(&[0x40, 0x01, 0x64, 0x0A, 0x13, 0x8A]).unwrap();
+
+        assert_eq!(decoded.data.battery, Some(100));
+        assert!(decoded.data.measurements.is_empty());
+        assert_eq!(decoded.consumed, 3);
+    }
+
+    #[test]
+    fn test_mijia_formaldehyde() {
+        // JQJCY01YM formaldehyde monitor (product ID 0x02DF), object 0x1010
+        let payload = [
+            0x50, 0x20, 0xDF, 0x02, 0x2A, 0x3B, 0x4C, 0x5D, 0x6E, 0x7F, 0x8A, 0x10, 0x10, 0x02,
+            0x05, 0x00,
+        ];
+        let decoded = decode_mijia(&payload).unwrap();
+
+        assert_eq!(decoded.data.measurements["formaldehyde_mg_m3"], 0.05);
+        assert_eq!(decoded.data.temperature, None);
+        assert_eq!(decoded.consumed, payload.len());
+    }
+
+    #[test]
+    fn test_mijia_unknown_object_keeps_header() {
+        let payload = [
+            0x50, 0x20, 0xDF, 0x02, 0x2A, 0x3B, 0x4C, 0x5D, 0x6E, 0x7F, 0x8A, 0x55, 0x10, 0x02,
+            0x05, 0x00,
+        ];
+        let decoded = decode_mijia(&payload).unwrap();
+
+        assert_eq!(
+            decoded.data.mijia,
+            Some(MijiaHeader {
+                product_id: 0x02DF,
+                frame_counter: 0x2A,
+                mac: Some([0x8A, 0x7F, 0x6E, 0x5D, 0x4C, 0x3B]),
+            })
+        );
+        assert_eq!(
+            decoded.data.note.as_deref(),
+            Some("unrecognized object 0x1055")
+        );
+        assert_eq!(decoded.data.temperature, None);
+        assert!(decoded.data.measurements.is_empty());
+
+        let data = HashMap::from([(MIJIA_SERVICE_UUID, payload.to_vec())]);
+        assert!(handle_service_data(&data).is_ok());
+        assert!(matches!(
+            handle_service_data_strict(&data),
+            Err(StrictError::UnknownObject { object: 0x1055, .. })
+        ));
+    }
+
+    #[test]
+    fn test_mijia_truncated_object_is_an_error() {
+        let payload = [
+            0x50, 0x20, 0xAA, 0x01, 0xF5, 0x40, 0x71, 0xD5, 0xA8, 0x65, 0x4C, 0x0D, 0x10, 0x04,
+            0xEA, 0x00,
+        ];
+
+        assert_eq!(
+            decode_mijia(&payload).unwrap_err(),
+            DecodeError::TruncatedObject(0x100D)
+        );
+    }
+
+    #[test]
+    fn test_mijia_capability_and_several_objects() {
+        // Frame control 0x5070: v5, MAC, capability and object present;
+        // capability 0x28 announces two I/O capability bytes
+        let payload = [
+            0x70, 0x50, 0x5B, 0x05, 0x07, 0x40, 0x71, 0xD5, 0xA8, 0x65, 0x4C, 0x28, 0x01, 0x00,
+            0x04, 0x10, 0x02, 0xD7, 0x00, 0x0A, 0x10, 0x01, 0x55,
+        ];
+        let decoded = decode_mijia(&payload).unwrap();
+
+        assert_eq!(decoded.data.temperature, Some(21.5));
+        assert_eq!(decoded.data.battery, Some(85));
+        assert_eq!(decoded.consumed, payload.len());
+        assert_eq!(
+            decoded.data.device_mac,
+            Some([0x4C, 0x65, 0xA8, 0xD5, 0x71, 0x40])
+        );
+    }
+
+    #[test]
+    fn test_mijia_without_mac() {
+        // Frame control 0x3040: v3, object present, no MAC
+        let payload = [0x40, 0x30, 0x5B, 0x05, 0x07, 0x06, 0x10, 0x02, 0xC2, 0x01];
+        let decoded = decode_mijia(&payload).unwrap();
+
+        assert_eq!(decoded.data.humidity, Some(45.0));
+        assert_eq!(decoded.data.device_mac, None);
+        assert_eq!(decoded.data.mijia.unwrap().mac, None);
+    }
+
+    #[test]
+    fn test_mijia_without_object_is_an_error() {
+        let payload = [
+            0x10, 0x20, 0x5B, 0x05, 0x07, 0x40, 0x71, 0xD5, 0xA8, 0x65, 0x4C,
+        ];
+
+        assert_eq!(decode_mijia(&payload).unwrap_err(), DecodeError::NoObject);
+    }
+
+    #[test]
+    fn test_bthome_moisture_then_battery() {
+        let wide = bthome(vec![0x40, 0x14, 0x02, 0x0C, 0x01, 0x55]);
+        assert_eq!(wide.measurements["moisture_percent"], 30.74);
+        assert_eq!(wide.battery, Some(85));
+
+        let narrow = bthome(vec![0x40, 0x2F, 0x17, 0x01, 0x55]);
+        assert_eq!(narrow.measurements["moisture_percent"], 23.0);
+        assert_eq!(narrow.battery, Some(85));
+        assert!(narrow.flags.is_empty());
+    }
+
+    #[test]
+    fn test_bthome_moisture_out_of_range_is_flagged() {
+        let data = bthome(vec![0x40, 0x2F, 150, 0x01, 0x55]);
+        assert!(!data.measurements.contains_key("moisture_percent"));
+        assert!(data.flags.contains(&Flag::OutOfRangeClamped));
+        assert_eq!(data.battery, Some(85));
+    }
+
+    #[test]
+    fn test_bthome_negative_dew_point() {
+        let data = bthome(vec![0x40, 0x08, 0x2E, 0xFB, 0x01, 0x55]);
+
+        assert_eq!(data.measurements["dew_point_c"], -12.34);
+        assert_eq!(data.battery, Some(85));
     }
 }
@@ -0,0 +1,70 @@
+//! Values computed from measurements rather than measured by the sensor.
+
+// Magnus formula coefficients (Sonntag 1990), valid for -45..60 °C over water
+const MAGNUS_B: f32 = 17.62;
+const MAGNUS_C: f32 = 243.12;
+const MAGNUS_E0: f32 = 6.112; // hPa at 0 °C
+const WATER_GAS_CONSTANT: f32 = 461.5; // J/(kg·K)
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+pub struct Derived {
+    /// °C
+    pub dew_point: Option<f32>,
+    /// g/m³
+    pub absolute_humidity: f32,
+}
+
+impl Derived {
+    /// Derived values for a temperature (°C) and relative humidity (%).
+    pub fn compute(temperature: f32, humidity: f32) -> Self {
+        Derived {
+            dew_point: dew_point(temperature, humidity),
+            absolute_humidity: absolute_humidity(temperature, humidity),
+        }
+    }
+}
+
+/// Dew point in °C (Magnus formula); undefined for 0 % humidity.
+pub fn dew_point(temperature: f32, humidity: f32) -> Option<f32> {
+    if humidity <= 0.0 {
+        return None;
+    }
+    let gamma = (humidity / 100.0).ln() + MAGNUS_B * temperature / (MAGNUS_C + temperature);
+    Some(MAGNUS_C * gamma / (MAGNUS_B - gamma))
+}
+
+/// Absolute humidity in g/m³.
+pub fn absolute_humidity(temperature: f32, humidity: f32) -> f32 {
+    let saturation_hpa = MAGNUS_E0 * (MAGNUS_B * temperature / (MAGNUS_C + temperature)).exp();
+    let vapour_pa = saturation_hpa * 100.0 * humidity / 100.0;
+    vapour_pa / (WATER_GAS_CONSTANT * (temperature + 273.15)) * 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 0.1,
+            "{actual} is not close to {expected}"
+        );
+    }
+
+    #[test]
+    fn test_dew_point_reference_values() {
+        assert_close(dew_point(25.0, 50.0).unwrap(), 13.9);
+        assert_close(dew_point(20.0, 60.0).unwrap(), 12.0);
+        assert_close(dew_point(0.0, 80.0).unwrap(), -3.0);
+        assert_close(dew_point(30.0, 100.0).unwrap(), 30.0);
+        assert_eq!(dew_point(20.0, 0.0), None);
+    }
+
+    #[test]
+    fn test_absolute_humidity_reference_values() {
+        assert_close(absolute_humidity(25.0, 50.0), 11.5);
+        assert_close(absolute_humidity(20.0, 100.0), 17.3);
+        assert_close(absolute_humidity(0.0, 100.0), 4.8);
+        assert_close(absolute_humidity(20.0, 0.0), 0.0);
+    }
+}
@@ -0,0 +1,296 @@
+use crate::beacon::Beacon;
+use crate::decoder::SensorData;
+use crate::icons::{Icon, status, warning};
+use crate::template::{self, Template};
+use crate::units::Units;
+use bluer::Address;
+use clap::ValueEnum;
+use serde::Serialize;
+use std::fmt;
+use std::time::SystemTime;
+
+/// A decoded reading with where and when it came from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reading {
+    /// The device, which is the payload MAC rather than the BLE address
+    /// with `--identity`
+    pub address: Address,
+    /// When the reading was complete: on receipt, or when the coalescing
+    /// window ended
+    pub received_at: SystemTime,
+    pub data: SensorData,
+}
+
+#[cfg(test)]
+impl Reading {
+    /// A reading received just now.
+    pub fn new(address: Address, data: SensorData) -> Self {
+        Self {
+            address,
+            received_at: SystemTime::now(),
+            data,
+        }
+    }
+}
+
+/// Destination for decoded sensor readings.
+pub trait Exporter {
+    fn export(&self, reading: &Reading);
+
+    /// Beacons seen with `--beacons`; ignored unless an exporter cares.
+    fn export_beacon(&self, _addr: Address, _beacon: &Beacon) {}
+
+    /// Something that happened to a device, as opposed to a measurement.
+    fn export_event(&self, _addr: Address, _event: &DeviceEvent) {}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeviceEvent {
+    /// The BTHome packet ID restarted from a low value
+    Rebooted {
+        previous_packet_id: u8,
+        packet_id: u8,
+    },
+    /// The battery level dropped to or below `--low-battery`
+    LowBattery { percent: u8, threshold: u8 },
+    /// Nothing decoded from the device for longer than `--silent-after`
+    Silent { seconds: u64 },
+    /// More than `--stuck-threshold` new readings in a row over at least
+    /// `--stuck-window` carried the same temperature and humidity
+    Stuck { readings: u32, seconds: u64 },
+}
+
+impl fmt::Display for DeviceEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceEvent::Rebooted {
+                previous_packet_id,
+                packet_id,
+            } => write!(
+                f,
+                "device rebooted (packet ID {previous_packet_id} -> {packet_id})"
+            ),
+            DeviceEvent::LowBattery { percent, threshold } => {
+                write!(f, "battery low: {percent}% (threshold {threshold}%)")
+            }
+            DeviceEvent::Silent { seconds } => {
+                write!(f, "went silent, no data for {seconds}s")
+            }
+            DeviceEvent::Stuck { readings, seconds } => write!(
+                f,
+                "may be frozen, same values in {readings} readings over {seconds}s"
+            ),
+        }
+    }
+}
+
+/// How readings are printed to stdout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// For humans, or one `--template` line per reading
+    Text,
+    /// One JSON object per reading and line; status lines go to stderr
+    Json,
+}
+
+/// Prints readings to stdout (the default output), one `--template` line
+/// per reading if there is one.
+pub struct ConsoleExporter {
+    pub format: OutputFormat,
+    pub template: Option<Template>,
+    pub units: Units,
+}
+
+/// A reading as one `--format json` line.
+#[derive(Serialize)]
+struct JsonReading<'a> {
+    time: String,
+    address: String,
+    /// Unit of `temperature` and `dew_point`, when there is a temperature
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature_unit: Option<&'static str>,
+    #[serde(flatten)]
+    data: &'a SensorData,
+}
+
+/// A reading as a JSON object in `units`, as printed with `--format json`.
+pub fn json(reading: &Reading, units: Units) -> String {
+    with_json(reading, units, |json| serde_json::to_string(json)).expect("readings serialize")
+}
+
+/// [`json`] as a value, to embed in a larger document.
+pub fn json_value(reading: &Reading, units: Units) -> serde_json::Value {
+    with_json(reading, units, |json| serde_json::to_value(json)).expect("readings serialize")
+}
+
+fn with_json<T>(reading: &Reading, units: Units, f: impl FnOnce(&JsonReading) -> T) -> T {
+    let data = &*units.convert(&reading.data);
+    f(&JsonReading {
+        time: template::rfc3339(reading.received_at),
+        address: reading.address.to_string(),
+        temperature_unit: data.temperature.map(|_| units.temperature_suffix()),
+        data,
+    })
+}
+
+impl ConsoleExporter {
+    fn line(&self, reading: &Reading) -> String {
+        let (addr, time) = (reading.address, reading.received_at);
+        let data = &*self.units.convert(&reading.data);
+        let suffix = self.units.temperature_suffix();
+        match (self.format, &self.template) {
+            (OutputFormat::Json, _) => json(reading, self.units),
+            (OutputFormat::Text, Some(template)) => template.render(addr, data, time),
+            (OutputFormat::Text, None) => match data.temperature {
+                Some(t) => format!(
+                    "  {} Got sensor reading: {t}{suffix} {:?}",
+                    Icon::Reading,
+                    data
+                ),
+                None => format!("  {} Got sensor reading: {:?}", Icon::Reading, data),
+            },
+        }
+    }
+}
+
+impl Exporter for ConsoleExporter {
+    fn export(&self, reading: &Reading) {
+        println!("{}", self.line(reading));
+    }
+
+    fn export_beacon(&self, _addr: Address, beacon: &Beacon) {
+        status!("  {} Beacon: {beacon}", Icon::Rx);
+    }
+
+    fn export_event(&self, addr: Address, event: &DeviceEvent) {
+        warning!("  {} {addr}: {event}", Icon::Warn);
+    }
+}
+
+/// Hands everything to several exporters in turn.
+pub struct MultiExporter(pub Vec<Box<dyn Exporter + Send>>);
+
+impl Exporter for MultiExporter {
+    fn export(&self, reading: &Reading) {
+        for exporter in &self.0 {
+            exporter.export(reading);
+        }
+    }
+
+    fn export_beacon(&self, addr: Address, beacon: &Beacon) {
+        for exporter in &self.0 {
+            exporter.export_beacon(addr, beacon);
+        }
+    }
+
+    fn export_event(&self, addr: Address, event: &DeviceEvent) {
+        for exporter in &self.0 {
+            exporter.export_event(addr, event);
+        }
+    }
+}
+
+/// Records every reading in memory so tests can assert on the pipeline output.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct MemoryExporter {
+    readings: std::sync::Arc<std::sync::Mutex<Vec<Reading>>>,
+    events: std::sync::Arc<std::sync::Mutex<Vec<(Address, DeviceEvent)>>>,
+}
+
+#[cfg(test)]
+impl MemoryExporter {
+    pub fn readings(&self) -> Vec<(Address, SensorData)> {
+        self.readings
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|r| (r.address, r.data.clone()))
+            .collect()
+    }
+
+    /// The readings with their timestamps.
+    pub fn received(&self) -> Vec<Reading> {
+        self.readings.lock().unwrap().clone()
+    }
+
+    pub fn events(&self) -> Vec<(Address, DeviceEvent)> {
+        self.events.lock().unwrap().clone()
+    }
+
+    pub fn count(&self) -> usize {
+        self.readings.lock().unwrap().len()
+    }
+
+    pub fn assert_count(&self, expected: usize) {
+        assert_eq!(self.count(), expected, "exported readings");
+    }
+
+    /// Asserts the most recent reading for `addr` equals `expected`.
+    pub fn assert_last(&self, addr: Address, expected: &SensorData) {
+        let readings = self.readings();
+        let last = readings
+            .iter()
+            .rev()
+            .find(|(a, _)| *a == addr)
+            .unwrap_or_else(|| panic!("no reading exported for {addr}"));
+        assert_eq!(&last.1, expected);
+    }
+}
+
+#[cfg(test)]
+impl Exporter for MemoryExporter {
+    fn export(&self, reading: &Reading) {
+        self.readings.lock().unwrap().push(reading.clone());
+    }
+
+    fn export_event(&self, addr: Address, event: &DeviceEvent) {
+        self.events.lock().unwrap().push((addr, event.clone()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::Flag;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_json_line() {
+        let console = ConsoleExporter {
+            format: OutputFormat::Json,
+            template: None,
+            units: Units::Metric,
+        };
+        let mut data = SensorData {
+            temperature: Some(22.9),
+            battery: Some(87),
+            device_mac: Some([0xA4, 0xC1, 0x38, 0x00, 0x00, 0x01]),
+            ..Default::default()
+        };
+        data.flags.insert(Flag::Estimated);
+        let reading = Reading {
+            address: Address::new([0xA4, 0xC1, 0x38, 0x00, 0x00, 0x01]),
+            received_at: UNIX_EPOCH + Duration::from_secs(1_714_566_896),
+            data,
+        };
+
+        let json: serde_json::Value = serde_json::from_str(&console.line(&reading)).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "time": "2024-05-01T12:34:56Z",
+                "address": "A4:C1:38:00:00:01",
+                "temperature_unit": "°C",
+                "temperature": 22.9,
+                "humidity": null,
+                "battery": 87,
+                "voltage": null,
+                "device_mac": "A4:C1:38:00:00:01",
+                "flags": ["estimated"],
+                "name": null,
+                "rssi": null,
+            })
+        );
+    }
+}
@@ -0,0 +1,93 @@
+use bluer::Address;
+use std::collections::HashSet;
+
+/// `--allow` / `--deny` address lists.
+#[derive(Debug, Default)]
+pub struct AddressFilter {
+    allow: HashSet<Address>,
+    deny: HashSet<Address>,
+}
+
+impl AddressFilter {
+    pub fn new(allow: &[Address], deny: &[Address]) -> Self {
+        Self {
+            allow: allow.iter().copied().collect(),
+            deny: deny.iter().copied().collect(),
+        }
+    }
+
+    /// Whether events from `addr` should be handled: it's on the allowlist
+    /// (if there is one) and not on the denylist.
+    pub fn permits(&self, addr: Address) -> bool {
+        (self.allow.is_empty() || self.allow.contains(&addr)) && !self.deny.contains(&addr)
+    }
+
+    /// Number of addresses on the allowlist, if there is one.
+    pub fn watched(&self) -> Option<usize> {
+        (!self.allow.is_empty()).then_some(self.allow.len())
+    }
+}
+
+/// `--min-rssi`: drops advertisements from devices too far away.
+#[derive(Debug, Default)]
+pub struct RssiFilter {
+    pub min: Option<i16>,
+    /// Whether advertisements without an RSSI pass
+    pub pass_unknown: bool,
+}
+
+impl RssiFilter {
+    pub fn permits(&self, rssi: Option<i16>) -> bool {
+        match (self.min, rssi) {
+            (None, _) => true,
+            (Some(min), Some(rssi)) => rssi >= min,
+            (Some(_), None) => self.pass_unknown,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(s: &str) -> Address {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_allow_and_deny() {
+        let sensor = addr("A4:C1:38:00:00:01");
+        let other = addr("A4:C1:38:00:00:02");
+
+        let open = AddressFilter::default();
+        assert!(open.permits(sensor));
+        assert_eq!(open.watched(), None);
+
+        let deny = AddressFilter::new(&[], &[addr("a4:c1:38:00:00:02")]);
+        assert!(deny.permits(sensor));
+        assert!(!deny.permits(other));
+
+        let allow = AddressFilter::new(&[addr("a4:c1:38:00:00:01")], &[sensor]);
+        assert!(!allow.permits(sensor), "deny wins over allow");
+        assert!(!allow.permits(other));
+        assert_eq!(allow.watched(), Some(1));
+    }
+
+    #[test]
+    fn test_min_rssi() {
+        let filter = RssiFilter {
+            min: Some(-90),
+            pass_unknown: false,
+        };
+        assert!(filter.permits(Some(-90)));
+        assert!(!filter.permits(Some(-91)));
+        assert!(!filter.permits(None));
+
+        let lenient = RssiFilter {
+            pass_unknown: true,
+            ..filter
+        };
+        assert!(lenient.permits(None));
+        assert!(RssiFilter::default().permits(None));
+    }
+}
@@ -0,0 +1,168 @@
+//! Per-device health: sensors going quiet, or stuck sending the same values.
+
+use bluer::Address;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Temperature and humidity, compared to tell a frozen sensor
+type Values = (Option<f32>, Option<f32>);
+
+struct Device {
+    /// Last data, and whether its silence was reported already
+    seen: Instant,
+    silence_reported: bool,
+    /// Last values, how many readings in a row repeated them since when,
+    /// and whether that was reported already
+    values: Option<Values>,
+    repeats: u32,
+    repeating_since: Instant,
+    stuck_reported: bool,
+}
+
+/// When each sensor last sent data and what, to notice a single one going
+/// quiet while the others (and so the adapter watchdog) keep going, or one
+/// that keeps advertising while its values froze.
+pub struct HealthTracker {
+    silent_after: Duration,
+    /// More than this many repeats over at least this long is stuck
+    stuck: Option<(u32, Duration)>,
+    devices: HashMap<Address, Device>,
+}
+
+impl HealthTracker {
+    pub fn new(silent_after: Duration, stuck: Option<(u32, Duration)>) -> Self {
+        Self {
+            silent_after,
+            stuck,
+            devices: HashMap::new(),
+        }
+    }
+
+    fn device(&mut self, addr: Address, now: Instant) -> &mut Device {
+        self.devices.entry(addr).or_insert(Device {
+            seen: now,
+            silence_reported: false,
+            values: None,
+            repeats: 0,
+            repeating_since: now,
+            stuck_reported: false,
+        })
+    }
+
+    /// `addr` sent data at `now`.
+    pub fn observe(&mut self, addr: Address, now: Instant) {
+        let device = self.device(addr, now);
+        device.seen = now;
+        device.silence_reported = false;
+    }
+
+    /// `addr` sent a new reading with these values at `now`. Returns how
+    /// many readings repeated them over how long, once, when that makes it
+    /// stuck.
+    pub fn observe_values(
+        &mut self,
+        addr: Address,
+        values: Values,
+        now: Instant,
+    ) -> Option<(u32, Duration)> {
+        let (threshold, window) = self.stuck?;
+        if values == (None, None) {
+            return None;
+        }
+        let device = self.device(addr, now);
+        if device.values != Some(values) {
+            device.values = Some(values);
+            device.repeats = 0;
+            device.repeating_since = now;
+            device.stuck_reported = false;
+            return None;
+        }
+        device.repeats += 1;
+        let repeating = now.saturating_duration_since(device.repeating_since);
+        if device.stuck_reported || device.repeats <= threshold || repeating < window {
+            return None;
+        }
+        device.stuck_reported = true;
+        Some((device.repeats, repeating))
+    }
+
+    /// Devices that have been silent longer than the window as of `now`,
+    /// with how long, each reported once until it's heard again.
+    pub fn newly_silent(&mut self, now: Instant) -> Vec<(Address, Duration)> {
+        let mut silent: Vec<_> = self
+            .devices
+            .iter_mut()
+            .filter_map(|(addr, device)| {
+                let quiet = now.saturating_duration_since(device.seen);
+                (!device.silence_reported && quiet > self.silent_after).then(|| {
+                    device.silence_reported = true;
+                    (*addr, quiet)
+                })
+            })
+            .collect();
+        silent.sort();
+        silent
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(last: u8) -> Address {
+        Address::new([0xA4, 0xC1, 0x38, 0x00, 0x00, last])
+    }
+
+    #[test]
+    fn test_silence_reported_once_per_device() {
+        let t0 = Instant::now();
+        let secs = Duration::from_secs;
+        let mut health = HealthTracker::new(secs(20), None);
+
+        health.observe(addr(1), t0);
+        health.observe(addr(2), t0 + secs(15));
+        assert!(health.newly_silent(t0 + secs(20)).is_empty());
+        assert_eq!(health.newly_silent(t0 + secs(21)), [(addr(1), secs(21))]);
+        assert!(health.newly_silent(t0 + secs(30)).is_empty());
+
+        // Heard again, so it can go silent again
+        health.observe(addr(1), t0 + secs(40));
+        assert_eq!(
+            health.newly_silent(t0 + secs(61)),
+            [(addr(1), secs(21)), (addr(2), secs(46))]
+        );
+    }
+
+    #[test]
+    fn test_stuck_needs_repeats_and_window() {
+        let t0 = Instant::now();
+        let secs = Duration::from_secs;
+        let mut health = HealthTracker::new(secs(20), Some((3, secs(600))));
+        let frozen = (Some(21.5), Some(40.0));
+
+        // Fast repeats alone are not enough
+        for i in 0..5 {
+            assert_eq!(health.observe_values(addr(1), frozen, t0 + secs(i)), None);
+        }
+        assert_eq!(
+            health.observe_values(addr(1), frozen, t0 + secs(600)),
+            Some((5, secs(600)))
+        );
+        // Reported once per streak
+        assert_eq!(health.observe_values(addr(1), frozen, t0 + secs(700)), None);
+
+        // A change starts over
+        let moved = (Some(21.6), Some(40.0));
+        assert_eq!(health.observe_values(addr(1), moved, t0 + secs(800)), None);
+        assert_eq!(health.observe_values(addr(1), moved, t0 + secs(1500)), None);
+
+        // Disabled without a threshold
+        let mut off = HealthTracker::new(secs(20), None);
+        for i in 0..10 {
+            assert_eq!(
+                off.observe_values(addr(1), frozen, t0 + secs(i * 600)),
+                None
+            );
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use std::fmt::Write;
+use std::time::Duration;
+
+/// Cumulative latency histogram in the Prometheus style.
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    /// Upper bounds in seconds, ascending
+    bounds: &'static [f64],
+    /// Observations per bucket (not cumulative; `+Inf` is the last one)
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+/// Bucket bounds for per-advertisement processing times.
+pub const LATENCY_BUCKETS: &[f64] = &[0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0];
+
+impl Histogram {
+    pub fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            buckets: vec![0; bounds.len() + 1],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    pub fn observe(&mut self, value: Duration) {
+        let secs = value.as_secs_f64();
+        let bucket = self
+            .bounds
+            .iter()
+            .position(|bound| secs <= *bound)
+            .unwrap_or(self.bounds.len());
+        self.buckets[bucket] += 1;
+        self.sum += secs;
+        self.count += 1;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Mean of all observations, if there are any.
+    pub fn mean(&self) -> Option<Duration> {
+        (self.count > 0).then(|| Duration::from_secs_f64(self.sum / self.count as f64))
+    }
+
+    /// Observations per bucket with its upper bound in seconds, `None` for
+    /// the last, unbounded one.
+    pub fn buckets(&self) -> impl Iterator<Item = (Option<f64>, u64)> + '_ {
+        let bounds = self.bounds.iter().copied().map(Some).chain([None]);
+        bounds.zip(self.buckets.iter().copied())
+    }
+
+    /// Prometheus text exposition of this histogram.
+    pub fn render(&self, name: &str, help: &str) -> String {
+        let mut out = format!("# HELP {name} {help}\n# TYPE {name} histogram\n");
+        let mut cumulative = 0;
+        for (bound, count) in self.bounds.iter().zip(&self.buckets) {
+            cumulative += count;
+            let _ = writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {cumulative}");
+        }
+        let _ = writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {}", self.count);
+        let _ = writeln!(out, "{name}_sum {}", self.sum);
+        let _ = writeln!(out, "{name}_count {}", self.count);
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_is_cumulative() {
+        let mut h = Histogram::new(&[0.01, 0.1]);
+        h.observe(Duration::from_millis(5));
+        h.observe(Duration::from_millis(50));
+        h.observe(Duration::from_millis(60));
+        h.observe(Duration::from_secs(2));
+
+        assert_eq!(
+            h.render("ble_processing_seconds", "Processing time"),
+            "# HELP ble_processing_seconds Processing time\n\
+             # TYPE ble_processing_seconds histogram\n\
+             ble_processing_seconds_bucket{le=\"0.01\"} 1\n\
+             ble_processing_seconds_bucket{le=\"0.1\"} 3\n\
+             ble_processing_seconds_bucket{le=\"+Inf\"} 4\n\
+             ble_processing_seconds_sum 2.115\n\
+             ble_processing_seconds_count 4\n"
+        );
+        assert_eq!(h.count(), 4);
+    }
+
+    #[test]
+    fn test_mean() {
+        let mut h = Histogram::new(LATENCY_BUCKETS);
+        assert_eq!(h.mean(), None);
+
+        h.observe(Duration::from_millis(10));
+        h.observe(Duration::from_millis(30));
+        assert_eq!(h.mean(), Some(Duration::from_millis(20)));
+    }
+}
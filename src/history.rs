@@ -0,0 +1,123 @@
+//! Last readings per device in memory (`--history`), served as JSON on
+//! `/history/<address>` next to `/metrics`.
+
+use crate::export::{self, Exporter, Reading};
+use crate::units::Units;
+use bluer::Address;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+/// The last `capacity` readings of every device, shared between the
+/// pipeline (as an exporter) and the HTTP server.
+#[derive(Clone)]
+pub struct History {
+    devices: Arc<Mutex<HashMap<Address, VecDeque<Reading>>>>,
+    capacity: usize,
+    units: Units,
+}
+
+impl History {
+    pub fn new(capacity: usize, units: Units) -> Self {
+        Self {
+            devices: Arc::default(),
+            capacity: capacity.max(1),
+            units,
+        }
+    }
+
+    /// Readings of `addr` received after `since`, oldest first.
+    pub fn query(&self, addr: Address, since: SystemTime) -> Vec<Reading> {
+        let devices = self.devices.lock().unwrap();
+        devices
+            .get(&addr)
+            .into_iter()
+            .flatten()
+            .filter(|reading| reading.received_at >= since)
+            .cloned()
+            .collect()
+    }
+
+    /// JSON array answering `/history/<address>[?minutes=<n>]`, or `None`
+    /// if the address or window doesn't parse.
+    pub fn render(&self, target: &str, now: SystemTime) -> Option<String> {
+        let target = target.strip_prefix("/history/")?;
+        let (addr, query) = target.split_once('?').unwrap_or((target, ""));
+        let addr: Address = addr.parse().ok()?;
+        let mut since = SystemTime::UNIX_EPOCH;
+        for (key, value) in query.split('&').filter_map(|p| p.split_once('=')) {
+            if key == "minutes" {
+                let minutes: u64 = value.parse().ok()?;
+                since = now
+                    .checked_sub(Duration::from_secs(minutes * 60))
+                    .unwrap_or(SystemTime::UNIX_EPOCH);
+            }
+        }
+        let readings: Vec<_> = self
+            .query(addr, since)
+            .iter()
+            .map(|reading| export::json(reading, self.units))
+            .collect();
+        Some(format!("[{}]", readings.join(",")))
+    }
+}
+
+impl Exporter for History {
+    fn export(&self, reading: &Reading) {
+        let mut devices = self.devices.lock().unwrap();
+        let readings = devices.entry(reading.address).or_default();
+        if readings.len() == self.capacity {
+            readings.pop_front();
+        }
+        readings.push_back(reading.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::SensorData;
+
+    const ADDR: Address = Address::new([0xA4, 0xC1, 0x38, 0x00, 0x00, 0x01]);
+
+    fn reading(minutes_ago: u64, temperature: f32, now: SystemTime) -> Reading {
+        Reading {
+            address: ADDR,
+            received_at: now - Duration::from_secs(minutes_ago * 60),
+            data: SensorData {
+                temperature: Some(temperature),
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_capacity_and_window() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let history = History::new(3, Units::default());
+        for (minutes_ago, temperature) in [(50, 20.0), (40, 21.0), (20, 22.0), (10, 23.0)] {
+            history.export(&reading(minutes_ago, temperature, now));
+        }
+
+        let all = history.query(ADDR, SystemTime::UNIX_EPOCH);
+        let temperatures: Vec<_> = all.iter().map(|r| r.data.temperature).collect();
+        assert_eq!(temperatures, [Some(21.0), Some(22.0), Some(23.0)]);
+
+        let body = history
+            .render("/history/A4:C1:38:00:00:01?minutes=30", now)
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json.as_array().unwrap().len(), 2);
+        assert_eq!(json[0]["temperature"], 22.0);
+
+        assert_eq!(
+            history.render("/history/A4:C1:38:00:00:02", now).as_deref(),
+            Some("[]")
+        );
+        assert_eq!(history.render("/history/nope", now), None);
+        assert_eq!(
+            history.render("/history/A4:C1:38:00:00:01?minutes=x", now),
+            None
+        );
+    }
+}
@@ -0,0 +1,151 @@
+//! Home Assistant MQTT discovery (`--ha-discovery`): a retained config per
+//! reading field, so every sensor shows up as a device without YAML.
+
+use bluer::Address;
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+const DISCOVERY_PREFIX: &str = "homeassistant";
+
+/// Remembers which fields of which device have been announced.
+#[derive(Default)]
+pub struct Discovery {
+    announced: Mutex<HashMap<Address, HashSet<String>>>,
+}
+
+impl Discovery {
+    /// `(topic, config)` for each of `fields` not announced for `addr` yet.
+    /// The state of a field is read from `<state_prefix>/<address>/<field>`.
+    pub fn announce(
+        &self,
+        addr: Address,
+        name: Option<&str>,
+        fields: &[&str],
+        state_prefix: &str,
+    ) -> Vec<(String, String)> {
+        let mut announced = self.announced.lock().unwrap();
+        let announced = announced.entry(addr).or_default();
+        fields
+            .iter()
+            .filter(|field| announced.insert(field.to_string()))
+            .map(|field| config(addr, name, field, state_prefix))
+            .collect()
+    }
+}
+
+/// The discovery topic and config of one field.
+fn config(addr: Address, name: Option<&str>, field: &str, state_prefix: &str) -> (String, String) {
+    let node = addr.to_string().replace(':', "").to_lowercase();
+    let (device_class, unit) = sensor_kind(field);
+    let mut config = json!({
+        "name": title(field),
+        "unique_id": format!("mitempr_{node}_{field}"),
+        "state_topic": format!("{state_prefix}/{addr}/{field}"),
+        "value_template": "{{ value_json.value }}",
+        "state_class": "measurement",
+        "device": {
+            "identifiers": [format!("mitempr_{node}")],
+            "connections": [["mac", addr.to_string()]],
+            "name": name.map_or_else(|| addr.to_string(), str::to_string),
+        },
+    });
+    if let Some(device_class) = device_class {
+        config["device_class"] = device_class.into();
+    }
+    if let Some(unit) = unit {
+        config["unit_of_measurement"] = unit.into();
+    }
+    (
+        format!("{DISCOVERY_PREFIX}/sensor/{node}_{field}/config"),
+        config.to_string(),
+    )
+}
+
+/// Home Assistant device class and unit of a field, where there are ones.
+fn sensor_kind(field: &str) -> (Option<&'static str>, Option<&'static str>) {
+    match field {
+        "temperature" | "dew_point" | "smoothed_temperature" => (Some("temperature"), Some("°C")),
+        "humidity" | "smoothed_humidity" => (Some("humidity"), Some("%")),
+        "battery" => (Some("battery"), Some("%")),
+        "voltage" => (Some("voltage"), Some("V")),
+        "absolute_humidity" => (None, Some("g/m³")),
+        "co2_ppm" => (Some("carbon_dioxide"), Some("ppm")),
+        "pressure_hpa" => (Some("pressure"), Some("hPa")),
+        "illuminance_lux" => (Some("illuminance"), Some("lx")),
+        "moisture_percent" => (Some("moisture"), Some("%")),
+        "pm2_5_ug_m3" => (Some("pm25"), Some("µg/m³")),
+        "pm10_ug_m3" => (Some("pm10"), Some("µg/m³")),
+        "tvoc_ug_m3" => (Some("volatile_organic_compounds"), Some("µg/m³")),
+        "power_w" => (Some("power"), Some("W")),
+        "current_a" => (Some("current"), Some("A")),
+        "conductivity_us_cm" => (None, Some("µS/cm")),
+        "formaldehyde_mg_m3" => (None, Some("mg/m³")),
+        "consumable_percent" => (None, Some("%")),
+        _ => (None, None),
+    }
+}
+
+/// `dew_point` → `Dew point`
+fn title(field: &str) -> String {
+    let words = field.replace('_', " ");
+    let mut chars = words.chars();
+    chars
+        .next()
+        .map(|first| first.to_uppercase().chain(chars).collect())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    const ADDR: Address = Address::new([0xA4, 0xC1, 0x38, 0x00, 0x00, 0x01]);
+
+    #[test]
+    fn test_config() {
+        let (topic, config) = config(ADDR, Some("Bedroom"), "temperature", "mitempr");
+        let config: Value = serde_json::from_str(&config).unwrap();
+
+        assert_eq!(
+            topic,
+            "homeassistant/sensor/a4c138000001_temperature/config"
+        );
+        assert_eq!(config["name"], "Temperature");
+        assert_eq!(config["unique_id"], "mitempr_a4c138000001_temperature");
+        assert_eq!(
+            config["state_topic"],
+            "mitempr/A4:C1:38:00:00:01/temperature"
+        );
+        assert_eq!(config["device_class"], "temperature");
+        assert_eq!(config["unit_of_measurement"], "°C");
+        assert_eq!(config["device"]["name"], "Bedroom");
+    }
+
+    #[test]
+    fn test_unknown_field_has_no_class() {
+        let (_, config) = config(ADDR, None, "door", "mitempr");
+        let config: Value = serde_json::from_str(&config).unwrap();
+
+        assert_eq!(config["name"], "Door");
+        assert!(config.get("device_class").is_none());
+        assert!(config.get("unit_of_measurement").is_none());
+        assert_eq!(config["device"]["name"], "A4:C1:38:00:00:01");
+    }
+
+    #[test]
+    fn test_each_field_announced_once() {
+        let discovery = Discovery::default();
+
+        assert_eq!(
+            discovery
+                .announce(ADDR, None, &["temperature", "humidity"], "mitempr")
+                .len(),
+            2
+        );
+        let later = discovery.announce(ADDR, None, &["temperature", "battery"], "mitempr");
+        assert_eq!(later.len(), 1);
+        assert!(later[0].0.ends_with("_battery/config"));
+    }
+}
@@ -0,0 +1,117 @@
+//! Minimal plain-HTTP client, just enough to talk to local services
+//! without pulling a full HTTP stack onto a Pi Zero.
+
+use std::io;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// GET `url` and return the response body of a 2xx response.
+pub async fn get(url: &str) -> io::Result<String> {
+    request("GET", url, &[], "").await
+}
+
+/// Send a request and return the response body of a 2xx response.
+///
+/// Uses HTTP/1.0 so the server closes the connection and never answers
+/// with chunked encoding. Only `http://` URLs are supported.
+pub async fn request(
+    method: &str,
+    url: &str,
+    headers: &[(&str, &str)],
+    body: &str,
+) -> io::Result<String> {
+    let (host, path) = split_url(url)?;
+    let addr = if host.contains(':') {
+        host.to_string()
+    } else {
+        format!("{host}:80")
+    };
+
+    let mut req = format!("{method} {path} HTTP/1.0\r\nHost: {host}\r\n");
+    for (name, value) in headers {
+        req.push_str(&format!("{name}: {value}\r\n"));
+    }
+    if !body.is_empty() {
+        req.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    }
+    req.push_str("\r\n");
+    req.push_str(body);
+
+    let response = timeout(REQUEST_TIMEOUT, async {
+        let mut stream = TcpStream::connect(&addr).await?;
+        stream.write_all(req.as_bytes()).await?;
+        let mut buf = Vec::new();
+        stream.read_to_end(&mut buf).await?;
+        Ok::<_, io::Error>(buf)
+    })
+    .await
+    .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, format!("{url}: timed out")))??;
+
+    let response = String::from_utf8_lossy(&response);
+    let (head, body) = response.split_once("\r\n\r\n").unwrap_or((&response, ""));
+    let status = head
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse::<u16>().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed HTTP response"))?;
+
+    if !(200..300).contains(&status) {
+        return Err(io::Error::other(format!("{url}: HTTP {status}")));
+    }
+    Ok(body.to_string())
+}
+
+/// Split `http://host[:port]/path` into `("host[:port]", "/path")`.
+fn split_url(url: &str) -> io::Result<(&str, &str)> {
+    let rest = url.strip_prefix("http://").ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{url}: only http:// URLs are supported"),
+        )
+    })?;
+    Ok(match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_split_url() {
+        assert_eq!(
+            split_url("http://inventory:8080/devices?id=1").unwrap(),
+            ("inventory:8080", "/devices?id=1")
+        );
+        assert_eq!(split_url("http://inventory").unwrap(), ("inventory", "/"));
+        assert!(split_url("https://inventory/").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_get_returns_body_and_rejects_errors() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            for response in [
+                "HTTP/1.0 200 OK\r\n\r\nBedroom",
+                "HTTP/1.0 404 Not Found\r\n\r\n",
+            ] {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut buf = [0u8; 1024];
+                let _ = socket.read(&mut buf).await.unwrap();
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        let url = format!("http://{addr}/name");
+        assert_eq!(get(&url).await.unwrap(), "Bedroom");
+        assert!(get(&url).await.is_err());
+    }
+}
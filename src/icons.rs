@@ -0,0 +1,288 @@
+//! Console messages: emoji prefixes on interactive UTF-8 terminals, plain
+//! ASCII tags everywhere else (journald, pipes, `--ascii`), and the
+//! `--log-level` and `RUST_LOG` filter they go through, by the module they
+//! come from. Readings are printed by the console exporter and never
+//! filtered.
+
+use clap::ValueEnum;
+use std::fmt;
+use std::io::IsTerminal;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static ASCII: AtomicBool = AtomicBool::new(false);
+static STATUS_TO_STDERR: AtomicBool = AtomicBool::new(false);
+static FILTER: OnceLock<Filter> = OnceLock::new();
+
+/// `println!` for status lines (level info); they go to stderr instead
+/// when stdout carries machine-readable output (`--format json`).
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if $crate::icons::enabled($crate::icons::LogLevel::Info, module_path!()) {
+            if $crate::icons::status_to_stderr() {
+                eprintln!($($arg)*)
+            } else {
+                println!($($arg)*)
+            }
+        }
+    };
+}
+pub(crate) use status;
+
+/// `eprintln!` for warnings.
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        if $crate::icons::enabled($crate::icons::LogLevel::Warn, module_path!()) {
+            eprintln!($($arg)*)
+        }
+    };
+}
+pub(crate) use warning;
+
+/// `eprintln!` for errors, shown at every level.
+macro_rules! error {
+    ($($arg:tt)*) => {
+        if $crate::icons::enabled($crate::icons::LogLevel::Error, module_path!()) {
+            eprintln!($($arg)*)
+        }
+    };
+}
+pub(crate) use error;
+
+/// Least severe messages shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+}
+
+impl LogLevel {
+    /// A `RUST_LOG` level; `debug` and `trace` mean everything there is,
+    /// and errors are never turned `off`.
+    fn parse(level: &str) -> Option<Self> {
+        match level.trim().to_lowercase().as_str() {
+            "off" | "error" => Some(LogLevel::Error),
+            "warn" => Some(LogLevel::Warn),
+            "info" | "debug" | "trace" => Some(LogLevel::Info),
+            _ => None,
+        }
+    }
+}
+
+/// Which messages are shown, by the module they come from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Filter {
+    default: LogLevel,
+    /// Module paths (`mitempr::pipeline`) and their level, from `RUST_LOG`
+    modules: Vec<(String, LogLevel)>,
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self {
+            default: LogLevel::Info,
+            modules: Vec::new(),
+        }
+    }
+}
+
+impl Filter {
+    /// `RUST_LOG` directives like `warn,mitempr::pipeline=info`: a bare
+    /// level for everything, `<module>=<level>` for a module of this
+    /// program and those below it. Other crates' directives and what
+    /// doesn't parse are ignored, as `env_logger` does.
+    pub fn from_rust_log(value: &str) -> Self {
+        let mut filter = Self::default();
+        for directive in value.split(',') {
+            match directive.split_once('=') {
+                None => {
+                    if let Some(level) = LogLevel::parse(directive) {
+                        filter.default = level;
+                    }
+                }
+                Some((module, level)) => {
+                    let module = module.trim();
+                    if (module == "mitempr" || module.starts_with("mitempr::"))
+                        && let Some(level) = LogLevel::parse(level)
+                    {
+                        filter.modules.push((module.to_string(), level));
+                    }
+                }
+            }
+        }
+        filter
+    }
+
+    /// `--log-level` instead of `RUST_LOG`'s bare level, if given.
+    pub fn with_default(mut self, level: Option<LogLevel>) -> Self {
+        self.default = level.unwrap_or(self.default);
+        self
+    }
+
+    /// The level of the most specific directive for `module`.
+    fn level(&self, module: &str) -> LogLevel {
+        self.modules
+            .iter()
+            .filter(|(prefix, _)| {
+                module
+                    .strip_prefix(prefix.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+            })
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(self.default, |(_, level)| *level)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Icon {
+    Rx,
+    Scan,
+    Reading,
+    Ok,
+    Warn,
+    Error,
+    Watchdog,
+    Removed,
+}
+
+impl Icon {
+    fn emoji(self) -> &'static str {
+        match self {
+            Icon::Rx => "📡",
+            Icon::Scan | Icon::Reading => "🔍",
+            Icon::Ok => "✅",
+            Icon::Warn => "⚠️",
+            Icon::Error | Icon::Removed => "❌",
+            Icon::Watchdog => "⏱",
+        }
+    }
+
+    fn ascii(self) -> &'static str {
+        match self {
+            Icon::Rx => "[RX]",
+            Icon::Scan => "[SCAN]",
+            Icon::Reading => "[DATA]",
+            Icon::Ok => "[OK]",
+            Icon::Warn => "[WARN]",
+            Icon::Error => "[ERR]",
+            Icon::Watchdog => "[WDOG]",
+            Icon::Removed => "[GONE]",
+        }
+    }
+}
+
+impl fmt::Display for Icon {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if ASCII.load(Ordering::Relaxed) {
+            f.write_str(self.ascii())
+        } else {
+            f.write_str(self.emoji())
+        }
+    }
+}
+
+/// Pick emoji or ASCII output, where status lines go and which messages
+/// are shown, for the rest of the process.
+pub fn init(force_ascii: bool, status_to_stderr: bool, filter: Filter) {
+    let emoji = std::io::stdout().is_terminal() && utf8_locale(|var| std::env::var(var).ok());
+    ASCII.store(force_ascii || !emoji, Ordering::Relaxed);
+    STATUS_TO_STDERR.store(status_to_stderr, Ordering::Relaxed);
+    let _ = FILTER.set(filter);
+}
+
+/// Whether a `level` message from `module` is shown; everything up to info
+/// before [`init`].
+pub fn enabled(level: LogLevel, module: &str) -> bool {
+    let filter = FILTER.get_or_init(Filter::default);
+    level <= filter.level(module)
+}
+
+pub fn status_to_stderr() -> bool {
+    STATUS_TO_STDERR.load(Ordering::Relaxed)
+}
+
+/// Whether the effective locale (`LC_ALL` > `LC_CTYPE` > `LANG`) is UTF-8.
+/// An unset locale means POSIX "C", which is not.
+fn utf8_locale(var: impl Fn(&str) -> Option<String>) -> bool {
+    ["LC_ALL", "LC_CTYPE", "LANG"]
+        .into_iter()
+        .filter_map(var)
+        .find(|value| !value.is_empty())
+        .is_some_and(|value| {
+            let value = value.to_lowercase();
+            value.contains("utf-8") || value.contains("utf8")
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env<'a>(vars: &'a [(&'a str, &'a str)]) -> impl Fn(&str) -> Option<String> + 'a {
+        move |name| {
+            vars.iter()
+                .find(|(k, _)| *k == name)
+                .map(|(_, v)| v.to_string())
+        }
+    }
+
+    #[test]
+    fn test_utf8_locale() {
+        assert!(utf8_locale(env(&[("LANG", "de_DE.UTF-8")])));
+        assert!(utf8_locale(env(&[("LC_ALL", "C.utf8"), ("LANG", "C")])));
+        assert!(!utf8_locale(env(&[
+            ("LC_ALL", "C"),
+            ("LANG", "en_US.UTF-8")
+        ])));
+        assert!(utf8_locale(env(&[("LC_ALL", ""), ("LANG", "en_US.UTF-8")])));
+        assert!(!utf8_locale(env(&[])));
+    }
+
+    #[test]
+    fn test_rust_log_directives() {
+        let filter = Filter::from_rust_log("warn");
+        assert_eq!(filter.level("mitempr"), LogLevel::Warn);
+        assert_eq!(filter.level("mitempr::pipeline"), LogLevel::Warn);
+
+        let filter =
+            Filter::from_rust_log("error, mitempr::pipeline=INFO,bluer=debug,mitempr::pipe=warn");
+        assert_eq!(filter.level("mitempr"), LogLevel::Error);
+        assert_eq!(filter.level("mitempr::pipeline"), LogLevel::Info);
+        assert_eq!(filter.level("mitempr::pipeline::inner"), LogLevel::Info);
+        // A module path prefix, not a string prefix
+        assert_eq!(filter.level("mitempr::piper"), LogLevel::Error);
+        assert_eq!(filter.level("mitempr::pipe"), LogLevel::Warn);
+
+        let filter = Filter::from_rust_log("mitempr=warn,mitempr::watchdog=info,loud");
+        assert_eq!(filter.level("mitempr::scan"), LogLevel::Warn);
+        assert_eq!(filter.level("mitempr::watchdog"), LogLevel::Info);
+        assert_eq!(filter, filter.clone().with_default(None));
+
+        // --log-level replaces the bare level, the modules stay
+        let filter =
+            Filter::from_rust_log("info,mitempr::scan=error").with_default(Some(LogLevel::Warn));
+        assert_eq!(filter.level("mitempr"), LogLevel::Warn);
+        assert_eq!(filter.level("mitempr::scan"), LogLevel::Error);
+        assert_eq!(
+            Filter::from_rust_log("off").level("mitempr"),
+            LogLevel::Error
+        );
+    }
+
+    #[test]
+    fn test_ascii_tags_are_ascii() {
+        for icon in [
+            Icon::Rx,
+            Icon::Scan,
+            Icon::Reading,
+            Icon::Ok,
+            Icon::Warn,
+            Icon::Error,
+            Icon::Watchdog,
+            Icon::Removed,
+        ] {
+            assert!(icon.ascii().is_ascii());
+        }
+    }
+}
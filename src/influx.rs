@@ -0,0 +1,329 @@
+use crate::decoder::SensorData;
+use crate::export::{Exporter, Reading};
+use crate::http;
+use crate::icons::{Icon, warning};
+use crate::throttle::LogThrottle;
+use bluer::Address;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// Points per write request.
+const BATCH_SIZE: usize = 50;
+/// How long points may wait for a batch to fill up.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+/// Points held back while InfluxDB is unreachable; the oldest are dropped
+/// beyond this.
+const MAX_PENDING: usize = 10_000;
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Where to write, from `--influx-*`.
+#[derive(Debug, Clone)]
+pub struct Target {
+    /// `http://host:port`
+    pub url: String,
+    pub bucket: String,
+    pub org: Option<String>,
+    pub token: Option<String>,
+}
+
+impl Target {
+    fn write_url(&self) -> String {
+        let mut url = format!(
+            "{}/api/v2/write?bucket={}&precision=ns",
+            self.url.trim_end_matches('/'),
+            self.bucket
+        );
+        if let Some(org) = &self.org {
+            url.push_str(&format!("&org={org}"));
+        }
+        url
+    }
+}
+
+/// Writes every reading as an InfluxDB line protocol point:
+/// `mitempr,address=A4:C1:38:00:00:01 temperature=22.9,battery=87i <ns>`.
+///
+/// A background task batches the points and POSTs them, retrying with
+/// backoff while InfluxDB is unreachable, so writes never hold up scanning.
+pub struct InfluxExporter {
+    points: mpsc::UnboundedSender<String>,
+}
+
+impl InfluxExporter {
+    /// Must be called from within the Tokio runtime. The returned task
+    /// writes what's still pending and ends once the exporter is dropped.
+    pub fn new(target: Target) -> (Self, JoinHandle<()>) {
+        let (points, rx) = mpsc::unbounded_channel();
+        let task = tokio::spawn(write_loop(target, rx));
+        (Self { points }, task)
+    }
+}
+
+impl Exporter for InfluxExporter {
+    fn export(&self, reading: &Reading) {
+        if let Some(point) = point(reading.address, &reading.data, reading.received_at) {
+            // Only fails once the writer task is gone
+            let _ = self.points.send(point);
+        }
+    }
+}
+
+/// The line protocol point of a reading; `None` without any field.
+fn point(addr: Address, data: &SensorData, time: SystemTime) -> Option<String> {
+    // Values keep their own type's formatting, as for StatsD; integers
+    // need the `i` suffix or they'd be written as floats
+    fn float<T: ToString>(name: &str, value: Option<T>) -> Option<(&str, String)> {
+        Some((name, value?.to_string()))
+    }
+    fn integer<T: ToString>(name: &str, value: Option<T>) -> Option<(&str, String)> {
+        Some((name, format!("{}i", value?.to_string())))
+    }
+    let mut fields: Vec<(&str, String)> = [
+        float("temperature", data.temperature),
+        float("humidity", data.humidity),
+        integer("battery", data.battery),
+        float("voltage", data.voltage),
+        integer("rssi", data.rssi),
+        float("dew_point", data.derived.and_then(|d| d.dew_point)),
+        float(
+            "absolute_humidity",
+            data.derived.map(|d| d.absolute_humidity),
+        ),
+        float(
+            "smoothed_temperature",
+            data.smoothed.and_then(|s| s.temperature),
+        ),
+        float("smoothed_humidity", data.smoothed.and_then(|s| s.humidity)),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    fields.extend(
+        data.measurements
+            .iter()
+            .map(|(name, value)| (*name, value.to_string())),
+    );
+    if fields.is_empty() {
+        return None;
+    }
+
+    let fields: Vec<String> = fields
+        .into_iter()
+        .map(|(name, value)| format!("{name}={value}"))
+        .collect();
+    let nanos = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let mut tags = format!("address={addr}");
+    if let Some(name) = &data.name {
+        tags.push_str(&format!(",name={}", escape_tag(name)));
+    }
+    if !data.flags.is_empty() {
+        tags.push_str(&format!(",flags={}", escape_tag(&data.flag_list())));
+    }
+    Some(format!("mitempr,{tags} {} {nanos}", fields.join(",")))
+}
+
+/// Backslash-escape what separates tags in line protocol.
+fn escape_tag(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, ',' | '=' | ' ') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+async fn write_loop(target: Target, mut rx: mpsc::UnboundedReceiver<String>) {
+    let url = target.write_url();
+    let auth = target.token.as_ref().map(|token| format!("Token {token}"));
+    let mut headers = vec![("Content-Type", "text/plain; charset=utf-8")];
+    if let Some(auth) = &auth {
+        headers.push(("Authorization", auth));
+    }
+
+    let mut pending = VecDeque::new();
+    let mut dropped = 0;
+    let mut errors = LogThrottle::new(Duration::from_secs(60));
+    let mut backoff = MIN_BACKOFF;
+    let mut retry_at = Instant::now();
+    let mut flush =
+        tokio::time::interval_at(tokio::time::Instant::now() + FLUSH_INTERVAL, FLUSH_INTERVAL);
+
+    loop {
+        let closed = tokio::select! {
+            point = rx.recv() => match point {
+                Some(point) => {
+                    if pending.len() == MAX_PENDING {
+                        pending.pop_front();
+                        dropped += 1;
+                    }
+                    pending.push_back(point);
+                    if pending.len() % BATCH_SIZE != 0 {
+                        continue;
+                    }
+                    false
+                }
+                // Exporter dropped: write what's left once, then stop
+                None => true,
+            },
+            _ = flush.tick() => false,
+        };
+        if !closed && Instant::now() < retry_at {
+            continue;
+        }
+
+        while !pending.is_empty() {
+            let count = pending.len().min(BATCH_SIZE);
+            let body = pending
+                .range(..count)
+                .cloned()
+                .collect::<Vec<_>>()
+                .join("\n");
+            match http::request("POST", &url, &headers, &body).await {
+                Ok(_) => {
+                    pending.drain(..count);
+                    backoff = MIN_BACKOFF;
+                }
+                Err(e) => {
+                    if errors.allow(Instant::now()) {
+                        warning!(
+                            "{} InfluxDB write failed: {e}, retrying in {}s ({} points pending)",
+                            Icon::Warn,
+                            backoff.as_secs(),
+                            pending.len()
+                        );
+                    }
+                    retry_at = Instant::now() + backoff;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                    break;
+                }
+            }
+        }
+        if dropped > 0 {
+            warning!(
+                "{} InfluxDB unreachable for too long, dropped {dropped} points",
+                Icon::Warn
+            );
+            dropped = 0;
+        }
+        if closed {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::Flag;
+    use crate::derived::Derived;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    const ADDR: Address = Address::new([0xA4, 0xC1, 0x38, 0x00, 0x00, 0x01]);
+
+    #[test]
+    fn test_point_has_only_present_fields() {
+        let data = SensorData {
+            temperature: Some(22.9),
+            battery: Some(87),
+            rssi: Some(-60),
+            name: Some("Living room, north".into()),
+            derived: Some(Derived::compute(25.0, 50.0)),
+            ..Default::default()
+        };
+        let time = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+        let point = point(ADDR, &data, time).unwrap();
+
+        assert!(point.starts_with(
+            r"mitempr,address=A4:C1:38:00:00:01,name=Living\ room\,\ north temperature=22.9,battery=87i,rssi=-60i,dew_point="
+        ));
+        assert!(point.contains(",absolute_humidity="));
+        assert!(!point.contains("humidity=,") && !point.contains("voltage"));
+        assert!(point.ends_with(" 1700000000123000000"));
+    }
+
+    #[test]
+    fn test_flags_tag() {
+        let data = SensorData {
+            battery: Some(50),
+            flags: [Flag::Estimated, Flag::Cached].into(),
+            ..Default::default()
+        };
+
+        let point = point(ADDR, &data, UNIX_EPOCH).unwrap();
+        assert!(
+            point.starts_with(
+                r"mitempr,address=A4:C1:38:00:00:01,flags=estimated\,cached battery=50i"
+            )
+        );
+    }
+
+    #[test]
+    fn test_no_point_without_fields() {
+        assert_eq!(point(ADDR, &SensorData::default(), UNIX_EPOCH), None);
+    }
+
+    /// Whether the body announced by Content-Length has arrived.
+    fn complete(request: &str) -> bool {
+        let Some((head, body)) = request.split_once("\r\n\r\n") else {
+            return false;
+        };
+        let length = head
+            .lines()
+            .find_map(|line| line.strip_prefix("Content-Length: "))
+            .and_then(|length| length.parse::<usize>().ok());
+        length.is_some_and(|length| body.len() >= length)
+    }
+
+    #[tokio::test]
+    async fn test_writes_pending_points_on_shutdown() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = String::new();
+            let mut buf = [0u8; 1024];
+            while !complete(&request) {
+                let n = socket.read(&mut buf).await.unwrap();
+                request.push_str(std::str::from_utf8(&buf[..n]).unwrap());
+            }
+            socket
+                .write_all(b"HTTP/1.0 204 No Content\r\n\r\n")
+                .await
+                .unwrap();
+            request
+        });
+
+        let (exporter, task) = InfluxExporter::new(Target {
+            url: format!("http://{addr}/"),
+            bucket: "sensors".into(),
+            org: Some("home".into()),
+            token: Some("secret".into()),
+        });
+        for temperature in [21.5, 21.6] {
+            let data = SensorData {
+                temperature: Some(temperature),
+                ..Default::default()
+            };
+            exporter.export(&Reading::new(ADDR, data));
+        }
+        drop(exporter);
+        task.await.unwrap();
+
+        let request = server.await.unwrap();
+        assert!(request.starts_with("POST /api/v2/write?bucket=sensors&precision=ns&org=home "));
+        assert!(request.contains("Authorization: Token secret\r\n"));
+        let body = request.split_once("\r\n\r\n").unwrap().1;
+        let lines: Vec<_> = body.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1].starts_with("mitempr,address=A4:C1:38:00:00:01 temperature=21.6 "));
+    }
+}
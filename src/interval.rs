@@ -0,0 +1,76 @@
+use bluer::Address;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Advertisements to observe before characterizing a device's interval.
+const SAMPLES: usize = 5;
+
+/// Measures how often each device advertises.
+#[derive(Default)]
+pub struct IntervalTracker {
+    devices: HashMap<Address, Timing>,
+}
+
+struct Timing {
+    last: Instant,
+    gaps: Vec<Duration>,
+    interval: Option<Duration>,
+}
+
+impl IntervalTracker {
+    /// Record an advertisement from `addr`. Returns the device's typical
+    /// interval (the median gap) once, when enough advertisements were seen.
+    pub fn observe(&mut self, addr: Address, now: Instant) -> Option<Duration> {
+        let Some(timing) = self.devices.get_mut(&addr) else {
+            self.devices.insert(
+                addr,
+                Timing {
+                    last: now,
+                    gaps: Vec::with_capacity(SAMPLES - 1),
+                    interval: None,
+                },
+            );
+            return None;
+        };
+
+        let gap = now.saturating_duration_since(timing.last);
+        timing.last = now;
+        if timing.interval.is_some() {
+            return None;
+        }
+
+        timing.gaps.push(gap);
+        if timing.gaps.len() < SAMPLES - 1 {
+            return None;
+        }
+        timing.gaps.sort();
+        let median = timing.gaps[timing.gaps.len() / 2];
+        timing.interval = Some(median);
+        timing.gaps = Vec::new();
+        Some(median)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDR: Address = Address::new([0xA4, 0xC1, 0x38, 0x00, 0x00, 0x01]);
+
+    #[test]
+    fn test_characterizes_once_after_enough_samples() {
+        let t0 = Instant::now();
+        let mut tracker = IntervalTracker::default();
+
+        // One outlier gap (a missed advertisement) doesn't skew the median
+        let offsets = [0, 1000, 2000, 4000, 5000];
+        let results: Vec<_> = offsets
+            .iter()
+            .map(|ms| tracker.observe(ADDR, t0 + Duration::from_millis(*ms)))
+            .collect();
+
+        assert_eq!(results[..4], [None, None, None, None]);
+        assert_eq!(results[4], Some(Duration::from_millis(1000)));
+        assert_eq!(tracker.observe(ADDR, t0 + Duration::from_secs(6)), None);
+    }
+}
@@ -0,0 +1,278 @@
+use crate::decoder::{BlePacketType, SensorData, ServiceMap};
+use crate::icons::Icon;
+use crate::units::Units;
+use bluer::{Adapter, AdapterEvent, Address};
+use futures::StreamExt;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::Duration;
+
+/// Scan for `duration`, then print every device seen as a table or JSON.
+pub async fn run(
+    adapter: &Adapter,
+    duration: Duration,
+    json: bool,
+    units: Units,
+    services: &ServiceMap,
+) -> bluer::Result<()> {
+    eprintln!(
+        "{} Taking inventory for {}s...",
+        Icon::Scan,
+        duration.as_secs()
+    );
+    let mut inventory = Inventory::default();
+    // Property changes arrive as DeviceAdded too, so RSSI ranges fill in
+    let mut events = adapter.discover_devices_with_changes().await?;
+    let deadline = tokio::time::sleep(duration);
+    tokio::pin!(deadline);
+
+    loop {
+        let addr = tokio::select! {
+            evt = events.next() => match evt {
+                Some(AdapterEvent::DeviceAdded(addr)) => addr,
+                Some(_) => continue,
+                None => break,
+            },
+            _ = &mut deadline => break,
+        };
+
+        let device = adapter.device(addr)?;
+        let name = match device.name().await? {
+            Some(name) => name,
+            None => device.alias().await?,
+        };
+        let service_data = device.service_data().await?.unwrap_or_default();
+        let format = services.packet_type(&service_data);
+        let reading = services.handle_service_data(&service_data).ok();
+        inventory.observe(addr, name, device.rssi().await?, format, reading);
+    }
+
+    if json {
+        println!("{}", inventory.to_json(units));
+    } else {
+        print!("{}", inventory.table(units));
+    }
+    Ok(())
+}
+
+/// Everything learned about the devices around during an inventory run.
+#[derive(Default)]
+pub struct Inventory {
+    devices: BTreeMap<Address, Entry>,
+}
+
+#[derive(Default)]
+struct Entry {
+    name: String,
+    format: Option<BlePacketType>,
+    rssi: Option<(i16, i16)>,
+    reading: Option<SensorData>,
+}
+
+impl Inventory {
+    pub fn observe(
+        &mut self,
+        addr: Address,
+        name: String,
+        rssi: Option<i16>,
+        format: BlePacketType,
+        reading: Option<SensorData>,
+    ) {
+        let entry = self.devices.entry(addr).or_default();
+        entry.name = name;
+        if format != BlePacketType::Other || entry.format.is_none() {
+            entry.format = Some(format);
+        }
+        if let Some(rssi) = rssi {
+            entry.rssi = Some(match entry.rssi {
+                Some((min, max)) => (min.min(rssi), max.max(rssi)),
+                None => (rssi, rssi),
+            });
+        }
+        // Keep the reading with the most values; a newer one wins a tie
+        if let Some(reading) = reading
+            && entry
+                .reading
+                .as_ref()
+                .is_none_or(|best| populated(&reading) >= populated(best))
+        {
+            entry.reading = Some(reading);
+        }
+    }
+
+    pub fn table(&self, units: Units) -> String {
+        let mut out = format!(
+            "{:<17}  {:<7}  {:<9}  {:<20}  READING\n",
+            "ADDRESS", "FORMAT", "RSSI", "NAME"
+        );
+        for (addr, entry) in &self.devices {
+            let _ = writeln!(
+                out,
+                "{addr}  {:<7}  {:<9}  {:<20}  {}",
+                entry.format_name(),
+                entry
+                    .rssi
+                    .map(|(min, max)| format!("{min}..{max}"))
+                    .unwrap_or_default(),
+                entry.name,
+                entry
+                    .reading
+                    .as_ref()
+                    .map(|r| brief(r, units))
+                    .unwrap_or_default()
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{} devices, {} with readings",
+            self.devices.len(),
+            self.devices
+                .values()
+                .filter(|e| e.reading.is_some())
+                .count()
+        );
+        out
+    }
+
+    pub fn to_json(&self, units: Units) -> serde_json::Value {
+        self.devices
+            .iter()
+            .map(|(addr, entry)| {
+                let reading = entry.reading.as_ref().map(|r| {
+                    let r = units.convert(r);
+                    serde_json::json!({
+                        "temperature": r.temperature,
+                        "temperature_unit": r.temperature.map(|_| units.temperature_suffix()),
+                        "humidity": r.humidity,
+                        "battery": r.battery,
+                        "voltage": r.voltage,
+                        "measurements": r.measurements,
+                    })
+                });
+                serde_json::json!({
+                    "address": addr.to_string(),
+                    "name": entry.name,
+                    "format": entry.format_name(),
+                    "rssi_min": entry.rssi.map(|r| r.0),
+                    "rssi_max": entry.rssi.map(|r| r.1),
+                    "reading": reading,
+                })
+            })
+            .collect()
+    }
+}
+
+impl Entry {
+    fn format_name(&self) -> String {
+        match self.format {
+            Some(BlePacketType::Other) | None => "-".to_string(),
+            Some(format) => format!("{format:?}"),
+        }
+    }
+}
+
+fn populated(data: &SensorData) -> usize {
+    [
+        data.temperature.is_some(),
+        data.humidity.is_some(),
+        data.battery.is_some(),
+        data.voltage.is_some(),
+    ]
+    .into_iter()
+    .filter(|present| *present)
+    .count()
+        + data.measurements.len()
+}
+
+/// `21.5°C 45% 87%bat`, only the values that are there.
+fn brief(data: &SensorData, units: Units) -> String {
+    let data = units.convert(data);
+    let mut parts = Vec::new();
+    if let Some(t) = data.temperature {
+        parts.push(format!("{t}{}", units.temperature_suffix()));
+    }
+    if let Some(h) = data.humidity {
+        parts.push(format!("{h}%"));
+    }
+    if let Some(b) = data.battery {
+        parts.push(format!("{b}%bat"));
+    }
+    parts.extend(data.measurements.iter().map(|(k, v)| format!("{k}={v}")));
+    parts.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SENSOR: Address = Address::new([0xA4, 0xC1, 0x38, 0x00, 0x00, 0x01]);
+    const PHONE: Address = Address::new([0x5E, 0x11, 0x22, 0x33, 0x44, 0x55]);
+
+    fn inventory() -> Inventory {
+        let mut inventory = Inventory::default();
+        let partial = SensorData {
+            battery: Some(87),
+            ..Default::default()
+        };
+        let full = SensorData {
+            temperature: Some(21.5),
+            humidity: Some(45.0),
+            battery: Some(86),
+            ..Default::default()
+        };
+
+        inventory.observe(PHONE, "Phone".into(), Some(-90), BlePacketType::Other, None);
+        inventory.observe(
+            SENSOR,
+            "ATC_000001".into(),
+            Some(-62),
+            BlePacketType::BTHome,
+            Some(full),
+        );
+        inventory.observe(
+            SENSOR,
+            "ATC_000001".into(),
+            Some(-80),
+            BlePacketType::BTHome,
+            Some(partial),
+        );
+        inventory
+    }
+
+    #[test]
+    fn test_table_keeps_best_reading_and_rssi_range() {
+        let table = inventory().table(Units::Metric);
+        let lines: Vec<_> = table.lines().collect();
+
+        assert_eq!(lines.len(), 4);
+        assert!(lines[1].starts_with("5E:11:22:33:44:55  -        -90..-90"));
+        assert!(lines[2].starts_with("A4:C1:38:00:00:01  BTHome   -80..-62"));
+        assert!(lines[2].ends_with("21.5°C 45% 86%bat"));
+        assert_eq!(lines[3], "2 devices, 1 with readings");
+    }
+
+    #[test]
+    fn test_json() {
+        let json = inventory().to_json(Units::Metric);
+
+        assert_eq!(json[0]["reading"], serde_json::Value::Null);
+        assert_eq!(json[1]["format"], "BTHome");
+        assert_eq!(json[1]["rssi_min"], -80);
+        assert_eq!(json[1]["reading"]["temperature"], 21.5);
+    }
+
+    #[test]
+    fn test_imperial_units() {
+        let inventory = inventory();
+
+        assert!(
+            inventory
+                .table(Units::Imperial)
+                .contains("70.7°F 45% 86%bat")
+        );
+        let json = inventory.to_json(Units::Imperial);
+        let fahrenheit = json[1]["reading"]["temperature"].as_f64().unwrap();
+        assert!((fahrenheit - 70.7).abs() < 1e-4);
+        assert_eq!(json[1]["reading"]["temperature_unit"], "°F");
+    }
+}
@@ -0,0 +1,22 @@
+//! Decoders for the service data of BLE environmental sensors: BTHome v2,
+//! PVVX custom firmware and Xiaomi MiBeacon.
+//!
+//! ```
+//! use std::collections::HashMap;
+//! use uuid::uuid;
+//!
+//! let bthome = uuid!("0000fcd2-0000-1000-8000-00805f9b34fb");
+//! let data = HashMap::from([(bthome, vec![0x40, 0x02, 0xCA, 0x09])]);
+//! let reading = mitempr::handle_service_data(&data).unwrap();
+//! assert_eq!(reading.temperature, Some(25.06));
+//! ```
+
+pub mod battery;
+pub mod crypto;
+pub mod decoder;
+pub mod derived;
+pub mod rate;
+pub mod rf;
+pub mod smooth;
+
+pub use decoder::{BlePacketType, DecodeError, SensorData, ServiceMap, handle_service_data};
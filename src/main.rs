@@ -1,170 +1,1913 @@
-use bluer::{Adapter, AdapterEvent, Address, Result};
-use clap::Parser;
-use futures::StreamExt;
-use std::collections::HashSet;
+use battery::Chemistry;
+use bluer::monitor::{Monitor, MonitorEvent, Pattern};
+use bluer::{Adapter, AdapterEvent, Address, DeviceEvent, DeviceProperty, DiscoveryFilter, Result};
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{CommandFactory, Parser, Subcommand};
+use clock::{Clock, SystemClock};
+use export::{ConsoleExporter, Exporter, MultiExporter, OutputFormat};
+use futures::{Stream, StreamExt, future};
+use icons::{Icon, error, status, warning};
+use mitempr::{battery, crypto, decoder, derived, rate, rf, smooth};
+use pipeline::Pipeline;
+use resolver::NameResolver;
+use scan::{ScanSettings, ScanType, ScanUuid};
+use seen::SeenDevices;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
-use tokio::sync::{Mutex, mpsc};
+use template::Template;
+use throttle::{Backoff, LogThrottle};
+use tokio::sync::{Mutex, mpsc, watch};
+use tokio::task::JoinHandle;
 use tokio::time::sleep;
-mod decoder;
+mod aliases;
+mod beacon;
+mod calibration;
+mod clock;
+mod coalesce;
+mod config;
+mod csv;
+mod export;
+mod filter;
+mod health;
+mod histogram;
+mod history;
+mod homeassistant;
+mod http;
+mod icons;
+mod influx;
+mod interval;
+mod inventory;
+mod metrics;
+mod mqtt;
+mod packet_id;
+mod pipeline;
+mod resolver;
+mod scan;
+mod seen;
+mod simulate;
+mod snapshot;
+mod sqlite;
+mod statsd;
+mod stdin;
+mod summary;
+mod template;
+mod throttle;
+mod units;
+mod websocket;
+#[cfg(feature = "yaml")]
+mod yaml;
+
+/// How long exporters get to send what's still queued on shutdown.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(2);
 
 /// Simple BLE discovery tool with watchdog restart (Python-style)
-#[derive(Parser, Debug)]
-#[command(author, version, about)]
+#[derive(Parser, Debug, Clone)]
+// Later values win, which lets the command line override --config
+#[command(author, version, about, args_override_self = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Read options from this TOML file: `<option> = <value>` for long
+    /// options, `[[device]]` tables with `address`, `alias`, `bindkey`,
+    /// `calibrate` and `battery_chemistry`, `[adapters.<name>]` tables with
+    /// `scan`, `duplicate_data`, `min_rssi` and `uuids`. Options on the
+    /// command line replace the file's, `--no-<flag>` turns off its flags
+    #[arg(long, value_name = "FILE", global = true)]
+    config: Option<PathBuf>,
+
+    /// Import bindkeys and aliases from a Home Assistant-style YAML file
+    /// (a `devices` list of `mac_address`, `name` and `bindkey`); the
+    /// config file and command line win
+    #[cfg(feature = "yaml")]
+    #[arg(long, value_name = "FILE", global = true)]
+    yaml: Option<PathBuf>,
+
+    /// Print a status line every SECS seconds: devices, readings decoded,
+    /// readings per minute and the time since the last one; a warning if
+    /// nothing was decoded in between
+    #[arg(long, global = true, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    status_interval: Option<u64>,
+
+    /// Scan for SECS seconds without restarting, then print the latest
+    /// reading of every device as a table (or JSON with `--format json`)
+    /// instead of each reading as it comes, and exit
+    #[arg(
+        long,
+        global = true,
+        visible_alias = "scan-duration",
+        value_name = "SECS",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    once: Option<u64>,
+
     /// Watchdog timeout in seconds (restart if no packets seen)
-    #[arg(long, default_value_t = 20)]
+    #[arg(long, global = true, default_value_t = 20)]
     watchdog: u64,
 
+    /// Warn when a single sensor sent nothing for this many seconds while
+    /// others keep going (default: the watchdog timeout). Raise it for
+    /// sensors that advertise rarely
+    #[arg(long, global = true, value_name = "SECS")]
+    silent_after: Option<u64>,
+
+    /// Warn that a sensor may be frozen when more than this many new
+    /// readings in a row carry the same temperature and humidity
+    #[arg(long, global = true, value_name = "N")]
+    stuck_threshold: Option<u32>,
+
+    /// ...and those readings span at least this many seconds
+    #[arg(long, global = true, value_name = "SECS", default_value_t = 3600)]
+    stuck_window: u64,
+
     /// Cooldown pause between restarts in seconds
-    #[arg(long, default_value_t = 5)]
+    #[arg(long, global = true, default_value_t = 5)]
     cooldown: u64,
+
+    /// Longest cooldown in seconds: it doubles with every restart in a row
+    /// until packets arrive again
+    #[arg(long, global = true, value_name = "SECS", default_value_t = 300)]
+    max_cooldown: u64,
+
+    /// Read a device again when it's reported after this many seconds
+    /// since it was last read (0 = every time it's reported)
+    #[arg(long, global = true, value_name = "SECS", default_value_t = 60)]
+    refresh: u64,
+
+    /// Use the Bluetooth controller with this name (e.g. `hci1`) instead
+    /// of the default adapter; repeat to scan with several at once
+    #[arg(
+        long,
+        global = true,
+        value_name = "NAME",
+        conflicts_with = "adapter_address"
+    )]
+    adapter: Vec<String>,
+
+    /// Scan with every Bluetooth controller at once
+    #[arg(long, global = true, conflicts_with_all = ["adapter", "adapter_address"])]
+    all_adapters: bool,
+
+    /// Use the Bluetooth controller with this address instead of the
+    /// default adapter
+    #[arg(long, global = true)]
+    adapter_address: Option<Address>,
+
+    /// Plain ASCII prefixes like `[RX]` instead of emoji (default: emoji
+    /// only on UTF-8 terminals)
+    #[arg(long, global = true, visible_alias = "no-emoji")]
+    ascii: bool,
+
+    /// Least severe messages to show: `error`, `warn` or `info` (default
+    /// `info`, or the level in `RUST_LOG`, which can also set one per
+    /// module: `RUST_LOG=warn,mitempr::pipeline=info`). Readings are always
+    /// printed
+    #[arg(long, global = true, value_enum)]
+    log_level: Option<icons::LogLevel>,
+
+    /// Warn when handling an advertisement takes longer than this many
+    /// milliseconds from reception to export
+    #[arg(long, global = true, default_value_t = 500)]
+    slow_threshold: u64,
+
+    /// Collapse restart log lines within this many seconds into one summary
+    /// (0 = log every restart)
+    #[arg(long, global = true, default_value_t = 60)]
+    restart_log_window: u64,
+
+    /// Seconds to wait before retrying when another process holds discovery
+    /// (0 = exit instead of waiting)
+    #[arg(long, global = true, default_value_t = 30)]
+    busy_retry: u64,
+
+    /// Scan passively through a BlueZ advertisement monitor instead of
+    /// discovery: no scan requests, less power, but needs bluetoothd
+    /// running with `--experimental` and misses scan responses
+    #[arg(long, global = true, conflicts_with_all = ["active", "duplicate_data"])]
+    passive: bool,
+
+    /// Scan actively through BlueZ discovery (the default)
+    #[arg(long, global = true)]
+    active: bool,
+
+    /// Have BlueZ report every advertisement, even when its data didn't
+    /// change, instead of only changes
+    #[arg(long, global = true)]
+    duplicate_data: bool,
+
+    /// Scan type of one adapter, overriding `--passive`/`--active`
+    /// (repeatable)
+    #[arg(long, global = true, value_name = "NAME=active|passive", value_parser = parse_adapter_option::<ScanType>)]
+    adapter_scan: Vec<(String, ScanType)>,
+
+    /// Whether one adapter reports duplicate data, overriding
+    /// `--duplicate-data` (repeatable)
+    #[arg(long, global = true, value_name = "NAME=BOOL", value_parser = parse_adapter_option::<bool>)]
+    adapter_duplicate_data: Vec<(String, bool)>,
+
+    /// `--min-rssi` for one adapter (repeatable)
+    #[arg(long, global = true, value_name = "NAME=DBM", value_parser = parse_adapter_option::<i16>)]
+    adapter_min_rssi: Vec<(String, i16)>,
+
+    /// Limit one adapter's discovery to a service UUID, e.g. `hci0=fcd2`,
+    /// or `hci0=known` for every decoded one (repeatable). Adapters without
+    /// one report every device, e.g. for a survey
+    #[arg(long, global = true, value_name = "NAME=UUID|known", value_parser = parse_adapter_option::<ScanUuid>)]
+    adapter_uuid: Vec<(String, ScanUuid)>,
+
+    /// Only handle advertisements from this address (repeatable)
+    #[arg(long, global = true, value_name = "MAC")]
+    allow: Vec<Address>,
+
+    /// Ignore advertisements from this address (repeatable)
+    #[arg(long, global = true, value_name = "MAC")]
+    deny: Vec<Address>,
+
+    /// Ignore advertisements weaker than this many dBm, e.g. `-90`.
+    /// Ignored sensor advertisements still count as activity for the
+    /// watchdog: the scanner is working, the device is just far away
+    #[arg(long, global = true, value_name = "DBM", allow_negative_numbers = true)]
+    min_rssi: Option<i16>,
+
+    /// Let advertisements without an RSSI pass `--min-rssi` (by default
+    /// they are dropped; BlueZ reports no RSSI for cached devices)
+    #[arg(long, global = true, requires = "min_rssi")]
+    pass_unknown_rssi: bool,
+
+    /// JSON file mapping addresses to friendly names, e.g.
+    /// `{"A4:C1:38:00:00:01": "Bedroom"}`; these win over resolved and
+    /// advertised names. Reloaded on SIGHUP
+    #[arg(long, global = true, value_name = "FILE")]
+    aliases: Option<PathBuf>,
+
+    /// Friendly name of a device: `<MAC>=<name>` (repeatable); wins over
+    /// `--aliases`
+    #[arg(long, global = true, value_name = "MAC=NAME", value_parser = parse_device_option::<String>)]
+    alias: Vec<(Address, String)>,
+
+    /// Inventory URL to resolve friendly names from, e.g.
+    /// `http://inventory/devices/{address}` (plain text or `{"name", "location"}` JSON)
+    #[arg(long, global = true)]
+    name_resolver: Option<String>,
+
+    /// How long resolved names are cached, in seconds
+    #[arg(long, global = true, default_value_t = 3600)]
+    name_resolver_ttl: u64,
+
+    /// Battery type of a device, used to estimate the percentage for
+    /// devices that only send a voltage: `<MAC>=cr2032|alkaline|liion` or a
+    /// custom `<MAC>=3.0:100,2.8:50,2.2:0` volts:percent table
+    /// (repeatable, default cr2032)
+    #[arg(long, global = true, value_parser = parse_device_option::<Chemistry>)]
+    battery_chemistry: Vec<(Address, Chemistry)>,
+
+    /// Offsets added to a device's temperature and humidity before
+    /// anything else: `<MAC>=<°C>[,<%>]`, e.g. `A4:C1:38:00:00:01=-0.7,2.5`
+    /// (repeatable; other devices are left as measured)
+    #[arg(long, global = true, value_name = "MAC=OFFSETS", value_parser = parse_device_option::<calibration::Calibration>)]
+    calibrate: Vec<(Address, calibration::Calibration)>,
+
+    /// Warn once per device when its battery level is at or below this
+    /// many percent (again after it recovered above)
+    #[arg(long, global = true, value_name = "PERCENT", default_value_t = 15, value_parser = clap::value_parser!(u8).range(0..=100))]
+    low_battery: u8,
+
+    /// AES key of a device sending encrypted BTHome v2 or MiBeacon v4/v5
+    /// advertisements: `<MAC>=<32 hex digits>` (repeatable)
+    #[arg(long, global = true, value_name = "MAC=KEY", value_parser = parse_device_option::<crypto::BindKey>)]
+    bindkey: Vec<(Address, crypto::BindKey)>,
+
+    /// Decode but don't output readings for this many seconds after
+    /// startup, while BlueZ replays stale cached devices
+    #[arg(long, global = true, default_value_t = 0)]
+    warmup: u64,
+
+    /// Merge partial readings from the same device arriving within this
+    /// many milliseconds into one (for devices that split temperature and
+    /// humidity across advertisements)
+    #[arg(long, global = true)]
+    coalesce: Option<u64>,
+
+    /// Emit at most one reading per device every this many seconds;
+    /// advertisements in between are dropped without decoding, except
+    /// BTHome frames, which are decoded to let button events through
+    #[arg(long, global = true, value_name = "SECS")]
+    interval: Option<u64>,
+
+    /// Add dew point and absolute humidity computed from temperature and
+    /// humidity
+    #[arg(long, global = true)]
+    derive: bool,
+
+    /// Add the temperature and humidity change per minute since the
+    /// device's previous reading
+    #[arg(long, global = true)]
+    rates: bool,
+
+    /// Minimum seconds between the samples a rate is computed from, so
+    /// bursts of advertisements don't produce huge rates
+    #[arg(long, global = true, default_value_t = 10)]
+    rate_min_spacing: u64,
+
+    /// Add the average temperature and humidity over the device's last N
+    /// readings (fewer until N have arrived) next to the raw values
+    #[arg(long, global = true, value_name = "N", value_parser = clap::value_parser!(u16).range(1..))]
+    smooth: Option<u16>,
+
+    /// Estimate each reading's distance from its RSSI, given the RSSI heard
+    /// at 1 m (default -59 dBm). Approximate at best; combine with --smooth
+    #[arg(
+        long,
+        global = true,
+        value_name = "DBM",
+        num_args = 0..=1,
+        default_missing_value = "-59",
+        allow_negative_numbers = true
+    )]
+    tx_power: Option<i16>,
+
+    /// Attach RF context to each reading: devices heard in the last minute
+    /// and the weakest/strongest RSSI among them, to gauge congestion
+    #[arg(long, global = true)]
+    rf_context: bool,
+
+    /// Identify sensors by the MAC in their payload (PVVX, Mijia) instead
+    /// of the BLE address, so devices with rotating addresses stay one device
+    #[arg(long, global = true)]
+    identity: bool,
+
+    /// Output format of the readings on stdout; with `json`, status lines
+    /// go to stderr
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+
+    /// Units of the temperatures printed and written to `--csv`; readings
+    /// are processed (and sent to StatsD, MQTT and Prometheus) in °C
+    #[arg(long, global = true, value_enum, default_value_t = units::Units::Metric)]
+    units: units::Units,
+
+    /// Print each reading as this line instead, e.g.
+    /// `"{time} {alias} {temperature}°C {humidity}%"` (placeholders: every
+    /// reading field and measurement name; absent values are empty)
+    #[arg(long, global = true, value_parser = Template::parse)]
+    template: Option<Template>,
+
+    /// Also append every reading to this CSV file (header
+    /// `timestamp_iso8601,address,name,rssi,temperature,humidity,battery,voltage`
+    /// when the file is new)
+    #[arg(long, global = true, value_name = "FILE")]
+    csv: Option<PathBuf>,
+
+    /// Also store every reading in this SQLite database, table `readings`
+    /// (needs the `sqlite3` command line shell)
+    #[arg(long, global = true, value_name = "FILE")]
+    sqlite: Option<PathBuf>,
+
+    /// Send readings as StatsD gauges to this `host:port` over UDP
+    #[arg(long, global = true, value_name = "HOST:PORT")]
+    statsd: Option<String>,
+
+    /// Prefix of the StatsD metric names
+    #[arg(long, global = true, default_value = "ble")]
+    statsd_prefix: String,
+
+    /// How StatsD metrics are tagged with the device address
+    #[arg(long, global = true, value_enum, default_value_t = statsd::TagStyle::Graphite)]
+    statsd_tags: statsd::TagStyle,
+
+    /// Publish readings to this MQTT broker (`host:port`), one topic per
+    /// field: `<prefix>/<address>/temperature`
+    #[arg(long, global = true, value_name = "HOST:PORT")]
+    mqtt_broker: Option<String>,
+
+    /// First level of the MQTT topics
+    #[arg(long, global = true, default_value = "mitempr")]
+    mqtt_topic_prefix: String,
+
+    /// MQTT user name
+    #[arg(long, global = true, requires = "mqtt_broker")]
+    mqtt_user: Option<String>,
+
+    /// MQTT password
+    #[arg(long, global = true, requires = "mqtt_user")]
+    mqtt_pass: Option<String>,
+
+    /// Announce every device's sensors to Home Assistant via MQTT discovery
+    /// (retained configs under `homeassistant/sensor/`)
+    #[arg(long, global = true, requires = "mqtt_broker")]
+    ha_discovery: bool,
+
+    /// Write readings to InfluxDB 2 at this URL, e.g.
+    /// `http://influx:8086` (plain HTTP only)
+    #[arg(long, global = true, value_name = "URL", requires = "influx_bucket")]
+    influx_url: Option<String>,
+
+    /// InfluxDB bucket to write to
+    #[arg(long, global = true, requires = "influx_url")]
+    influx_bucket: Option<String>,
+
+    /// InfluxDB organization, if the token doesn't imply one
+    #[arg(long, global = true, requires = "influx_url")]
+    influx_org: Option<String>,
+
+    /// InfluxDB API token
+    #[arg(long, global = true, requires = "influx_url")]
+    influx_token: Option<String>,
+
+    /// Serve Prometheus metrics (last values per device, processing
+    /// times) on `http://<ADDR>/metrics`, e.g. `0.0.0.0:9100`
+    #[arg(long, global = true, value_name = "ADDR")]
+    metrics_addr: Option<std::net::SocketAddr>,
+
+    /// Drop a device's metrics after this many seconds without a reading
+    #[arg(long, global = true, default_value_t = 300)]
+    metrics_stale: u64,
+
+    /// Keep each device's last N readings in memory and serve them as JSON
+    /// on `http://<--metrics-addr>/history/<MAC>?minutes=<M>`
+    #[arg(long, global = true, value_name = "N", requires = "metrics_addr", value_parser = clap::value_parser!(u32).range(1..))]
+    history: Option<u32>,
+
+    /// Stream readings as JSON to WebSocket clients of `ws://<ADDR>/`,
+    /// e.g. `0.0.0.0:8080`
+    #[arg(long, global = true, value_name = "ADDR")]
+    ws_addr: Option<std::net::SocketAddr>,
+
+    /// Also report iBeacon and Eddystone-UID beacons (for inventory)
+    #[arg(long, global = true)]
+    beacons: bool,
+
+    /// Also decode service data under this UUID as a format, for firmware
+    /// using a custom UUID: `<uuid>=bthome|pvvx|mijia`, the UUID in full or
+    /// 16-bit short form like `fcd9` (repeatable)
+    #[arg(long, global = true, value_name = "UUID=FORMAT", value_parser = parse_extra_uuid)]
+    extra_uuid: Vec<(uuid::Uuid, stdin::PayloadFormat)>,
+
+    /// Export service data of unknown formats as raw `<uuid>:<hex>` readings
+    /// instead of dropping it (feed them back through `--decode-only`)
+    #[arg(long, global = true)]
+    passthrough_unknown: bool,
+
+    /// Report every advertisement that can't be fully decoded as an error
+    #[arg(long, global = true)]
+    strict: bool,
+
+    /// Print an end-of-run summary (advertisements per format, decode
+    /// errors, last reading per device) on Ctrl-C, or write it to a file
+    #[arg(long, global = true, value_name = "FILE", num_args = 0..=1, default_missing_value = "-")]
+    summary: Option<PathBuf>,
+
+    /// Run without Bluetooth, feeding this many simulated BTHome/PVVX/Mijia
+    /// devices through the pipeline
+    #[arg(long, global = true, value_name = "N", conflicts_with = "decode_only")]
+    simulate: Option<usize>,
+
+    /// Decode `uuid:hex` lines from stdin and exit at EOF (no Bluetooth
+    /// needed). Readings are printed per `--format`/`--template` and written
+    /// to `--csv`, errors go to stderr; with `--strict` any error exits 1
+    #[arg(long, global = true)]
+    decode_only: bool,
+
+    /// Format of bare hex lines in `--decode-only` mode
+    #[arg(long, global = true, value_enum, requires = "decode_only")]
+    payload_format: Option<stdin::PayloadFormat>,
+}
+
+impl Args {
+    /// The service data formats to decode: the standard UUIDs and
+    /// `--extra-uuid`.
+    fn services(&self) -> decoder::ServiceMap {
+        let mut services = decoder::ServiceMap::default();
+        for (uuid, format) in &self.extra_uuid {
+            services.add(*uuid, (*format).into());
+        }
+        services
+    }
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum Command {
+    /// Scan for a while, then list every device seen with its format, name,
+    /// RSSI range and best reading
+    Inventory {
+        /// How long to scan, in seconds
+        #[arg(long, default_value_t = 30)]
+        duration: u64,
+
+        /// Print JSON instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+    /// Validate the options and `--config` file, print the arguments the
+    /// file becomes and exit without scanning
+    CheckConfig,
 }
 
 #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
 async fn main() -> Result<()> {
-    let args = Args::parse();
-
-    let session = bluer::Session::new().await?;
-    let adapter = session.default_adapter().await?;
-    adapter.set_powered(true).await?;
-    println!(
-        "Starting robust continuous BLE discovery (watchdog={}s, cooldown={}s)...",
-        args.watchdog, args.cooldown
+    let args = parse_args();
+    if let Err(e) = validate(&args) {
+        Args::command().error(ErrorKind::ArgumentConflict, e).exit();
+    }
+    let log_filter = icons::Filter::from_rust_log(&std::env::var("RUST_LOG").unwrap_or_default())
+        .with_default(args.log_level);
+    // With --once, stdout is for the final table only
+    icons::init(
+        args.ascii,
+        args.format == OutputFormat::Json || args.once.is_some(),
+        log_filter,
+    );
+
+    if let Some(Command::CheckConfig) = args.command {
+        check_config(&args);
+        return Ok(());
+    }
+
+    if args.decode_only {
+        decode_only(&args)?;
+        return Ok(());
+    }
+
+    if let Some(path) = &args.config {
+        status!("{} Read options from {}", Icon::Ok, path.display());
+    }
+
+    let fixed_aliases: HashMap<Address, String> = args.alias.iter().cloned().collect();
+    let aliases =
+        (args.aliases.is_some() || !fixed_aliases.is_empty()).then(
+            || match aliases::Aliases::load(args.aliases.clone(), fixed_aliases) {
+                Ok(aliases) => {
+                    status!("{} Loaded {} aliases", Icon::Ok, aliases.len());
+                    if aliases.has_file() {
+                        tokio::spawn(aliases::reload_on_hangup(aliases.clone()));
+                    }
+                    aliases
+                }
+                Err(e) => {
+                    error!("{} Cannot load aliases from {e}", Icon::Error);
+                    std::process::exit(1);
+                }
+            },
+        );
+
+    let snapshot = args.once.map(|_| snapshot::Snapshot::new(args.units));
+    let mut exporters: Vec<Box<dyn Exporter + Send>> = match &snapshot {
+        Some(snapshot) => vec![Box::new(snapshot.clone())],
+        None => vec![Box::new(ConsoleExporter {
+            format: args.format,
+            template: args.template.clone(),
+            units: args.units,
+        })],
+    };
+    if let Some(path) = &args.csv {
+        match csv::CsvExporter::open(path, args.units) {
+            Ok(exporter) => exporters.push(Box::new(exporter)),
+            Err(e) => {
+                error!("{} Cannot open {}: {e}", Icon::Error, path.display());
+                std::process::exit(1);
+            }
+        }
+    }
+    // Background senders, waited for on shutdown
+    let mut senders = Vec::new();
+    if let Some(path) = &args.sqlite {
+        match sqlite::SqliteExporter::open(path) {
+            Ok((exporter, task)) => {
+                exporters.push(Box::new(exporter));
+                senders.push(task);
+            }
+            Err(e) => {
+                error!("{} Cannot open {}: {e}", Icon::Error, path.display());
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(target) = &args.statsd {
+        let (exporter, task) = statsd::StatsdExporter::new(
+            target.clone(),
+            args.statsd_prefix.clone(),
+            args.statsd_tags,
+        );
+        exporters.push(Box::new(exporter));
+        senders.push(task);
+    }
+    if let Some(address) = &args.mqtt_broker {
+        let broker = mqtt::Broker {
+            address: address.clone(),
+            user: args.mqtt_user.clone(),
+            pass: args.mqtt_pass.clone(),
+        };
+        let (exporter, task) =
+            mqtt::MqttExporter::new(broker, args.mqtt_topic_prefix.clone(), args.ha_discovery);
+        exporters.push(Box::new(exporter));
+        senders.push(task);
+    }
+    if let (Some(url), Some(bucket)) = (&args.influx_url, &args.influx_bucket) {
+        let (exporter, task) = influx::InfluxExporter::new(influx::Target {
+            url: url.clone(),
+            bucket: bucket.clone(),
+            org: args.influx_org.clone(),
+            token: args.influx_token.clone(),
+        });
+        exporters.push(Box::new(exporter));
+        senders.push(task);
+    }
+    let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+    let metrics = match args.metrics_addr {
+        Some(addr) => {
+            let listener = match tokio::net::TcpListener::bind(addr).await {
+                Ok(listener) => listener,
+                Err(e) => {
+                    error!("{} Cannot serve metrics on {addr}: {e}", Icon::Error);
+                    std::process::exit(1);
+                }
+            };
+            let metrics =
+                metrics::Metrics::new(Duration::from_secs(args.metrics_stale), clock.clone());
+            let history = args
+                .history
+                .map(|n| history::History::new(n as usize, args.units));
+            if let Some(history) = &history {
+                exporters.push(Box::new(history.clone()));
+            }
+            tokio::spawn(metrics::serve(listener, metrics.clone(), history));
+            exporters.push(Box::new(metrics.clone()));
+            Some(metrics)
+        }
+        None => None,
+    };
+    if let Some(addr) = args.ws_addr {
+        let listener = match tokio::net::TcpListener::bind(addr).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                error!("{} Cannot serve WebSocket on {addr}: {e}", Icon::Error);
+                std::process::exit(1);
+            }
+        };
+        let exporter = websocket::WebSocketExporter::new(args.units);
+        tokio::spawn(websocket::serve(listener, exporter.clone()));
+        exporters.push(Box::new(exporter));
+    }
+    let mut pipeline = Pipeline::new(args.clone(), Box::new(MultiExporter(exporters)), clock);
+
+    if let Some(count) = args.simulate {
+        simulate::run(count, &mut pipeline).await;
+        shut_down(&args, pipeline, senders).await;
+        return Ok(());
+    }
+
+    let session = match bluer::Session::new().await {
+        Ok(session) => session,
+        Err(e) => exit_unavailable("Cannot connect to BlueZ", &e, None),
+    };
+    let adapters = match select_adapters(&session, &args).await {
+        Ok(adapters) => adapters,
+        Err(e) => exit_unavailable("Cannot use a Bluetooth adapter", &e, None),
+    };
+    let settings = match adapter_settings(&args, &adapters) {
+        Ok(settings) => settings,
+        Err(e) => {
+            error!("{} Invalid adapter settings: {e}", Icon::Error);
+            std::process::exit(1);
+        }
+    };
+    let multiple = adapters.len() > 1;
+    for adapter in &adapters {
+        if let Err(e) = adapter.set_powered(true).await {
+            let context = format!(
+                "Adapter {} is off and could not be powered on",
+                adapter.name()
+            );
+            exit_unavailable(&context, &e, Some(POWER_HINT));
+        }
+    }
+
+    if let Some(Command::Inventory { duration, json }) = args.command {
+        return inventory::run(
+            &adapters[0],
+            Duration::from_secs(duration),
+            json,
+            args.units,
+            &args.services(),
+        )
+        .await;
+    }
+
+    status!(
+        "Starting robust continuous BLE discovery (watchdog={}s, cooldown={}-{}s)...",
+        args.watchdog,
+        args.cooldown,
+        args.max_cooldown.max(args.cooldown)
     );
+    let filter = filter::AddressFilter::new(&args.allow, &args.deny);
+    if multiple {
+        let names: Vec<_> = adapters.iter().map(Adapter::name).collect();
+        status!("{} Scanning with {}", Icon::Scan, names.join(", "));
+    }
+    if let Some(watched) = filter.watched() {
+        status!("{} Watching {watched} allowed addresses", Icon::Scan);
+    }
 
-    let seen_devices = Arc::new(Mutex::new(HashSet::<Address>::new()));
-    let last_ble_packet = Arc::new(Mutex::new(Instant::now()));
-    let (tx, mut rx) = mpsc::unbounded_channel::<AdapterEvent>();
+    let resolver = args
+        .name_resolver
+        .clone()
+        .map(|url| NameResolver::new(url, Duration::from_secs(args.name_resolver_ttl)));
 
+    let mut seen_devices = SeenDevices::new(Duration::from_secs(args.refresh));
+    // One task per device and adapter forwarding its service data updates
+    let mut watchers = HashMap::<(usize, Address), JoinHandle<()>>::new();
+    let scanners: Vec<_> = adapters
+        .into_iter()
+        .zip(settings)
+        .map(|(adapter, settings)| Scanner::new(adapter, settings, multiple))
+        .collect();
+    // Events carry the time the discovery task received them, so queueing
+    // delays count towards the processing latency
+    let (tx, mut rx) = mpsc::unbounded_channel::<Event>();
+    let (stop, stopped) = watch::channel(false);
+
+    //
+    // 🔄 Discovery + watchdog task per adapter
     //
-    // 🔄 Discovery + watchdog task
+    let discovery: Vec<_> = scanners
+        .iter()
+        .enumerate()
+        .map(|(index, scanner)| {
+            spawn_discovery(scanner.clone(), index, tx.clone(), &args, stopped.clone())
+        })
+        .collect();
+
+    //
+    // 📡 Event processing loop
     //
+    let slow_threshold = Duration::from_millis(args.slow_threshold);
+    let mut slow_log = LogThrottle::new(Duration::from_secs(args.restart_log_window));
+    let mut tick = tokio::time::interval(pipeline.tick_interval());
+    let status_period = Duration::from_secs(args.status_interval.unwrap_or(0).max(1));
+    let mut status_tick =
+        tokio::time::interval_at(tokio::time::Instant::now() + status_period, status_period);
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+    let deadline = sleep(Duration::from_secs(args.once.unwrap_or(0)));
+    tokio::pin!(deadline);
+    loop {
+        let (received, index, evt) = tokio::select! {
+            evt = rx.recv() => match evt {
+                Some(evt) => evt,
+                None => break,
+            },
+            _ = &mut ctrl_c => {
+                status!("{} Stopping...", Icon::Scan);
+                break;
+            }
+            _ = &mut deadline, if args.once.is_some() => break,
+            _ = tick.tick() => {
+                pipeline.tick();
+                seen_devices.prune(Instant::now());
+                continue;
+            }
+            _ = status_tick.tick(), if args.status_interval.is_some() => {
+                match pipeline.status() {
+                    (line, true) => status!("{} {line}", Icon::Watchdog),
+                    (line, false) => warning!("{} {line}", Icon::Watchdog),
+                }
+                continue;
+            }
+        };
+
+        let addr = match evt {
+            ScanEvent::Adapter(AdapterEvent::DeviceAdded(addr)) if filter.permits(addr) => {
+                // BlueZ may report a device again after discovery restarts
+                if watchers
+                    .get(&(index, addr))
+                    .is_none_or(JoinHandle::is_finished)
+                {
+                    let watcher = watch_device(&scanners[index].adapter, index, addr, tx.clone());
+                    watchers.insert((index, addr), watcher);
+                }
+                if !seen_devices.should_handle(addr, Instant::now()) {
+                    continue;
+                }
+                addr
+            }
+            ScanEvent::ServiceData(addr) => addr,
+            ScanEvent::Adapter(AdapterEvent::DeviceRemoved(addr)) => {
+                status!("{} Device removed: {addr}", Icon::Removed);
+                seen_devices.remove(addr);
+                if let Some(watcher) = watchers.remove(&(index, addr)) {
+                    watcher.abort();
+                }
+                continue;
+            }
+            ScanEvent::Adapter(_) => continue,
+        };
+
+        if let Err(e) = handle_device(
+            &scanners,
+            index,
+            addr,
+            &mut pipeline,
+            aliases.as_ref(),
+            resolver.as_ref(),
+        )
+        .await
+        {
+            error!("Error handling device {addr}: {e}");
+        }
+
+        let elapsed = received.elapsed();
+        pipeline.observe_latency(elapsed);
+        if let Some(metrics) = &metrics {
+            metrics.observe_processing(elapsed);
+        }
+        if elapsed > slow_threshold && slow_log.allow(Instant::now()) {
+            let latency = pipeline.summary().latency();
+            warning!(
+                "{} Handling {addr} took {elapsed:?} (mean {:?} over {} advertisements)",
+                Icon::Watchdog,
+                latency.mean().unwrap_or_default(),
+                latency.count()
+            );
+        }
+    }
+
+    for watcher in watchers.into_values() {
+        watcher.abort();
+    }
+    let _ = stop.send(true);
+    futures::future::join_all(discovery).await;
+    // BlueZ is told to stop discovery in the background once the stream
+    // is gone; give it a moment so no adapter is left scanning
+    let _ = tokio::time::timeout(SHUTDOWN_GRACE, async {
+        for scanner in &scanners {
+            while scanner.adapter.is_discovering().await.unwrap_or(false) {
+                sleep(Duration::from_millis(100)).await;
+            }
+        }
+    })
+    .await;
+
+    shut_down(&args, pipeline, senders).await;
+    if let Some(snapshot) = snapshot {
+        match args.format {
+            OutputFormat::Text => print!("{}", snapshot.table()),
+            OutputFormat::Json => println!("{}", snapshot.to_json()),
+        }
+    }
+    Ok(())
+}
+
+/// Emit what's still held back, let the exporters send their queues and
+/// report what the run saw.
+async fn shut_down(args: &Args, mut pipeline: Pipeline, senders: Vec<JoinHandle<()>>) {
+    pipeline.flush();
+    status!(
+        "{} {}",
+        Icon::Ok,
+        pipeline.summary().headline(pipeline.now())
+    );
+    write_summary(args, &pipeline);
+
+    // Closes the exporters' queues, so their senders finish
+    drop(pipeline);
+    if tokio::time::timeout(SHUTDOWN_GRACE, futures::future::join_all(senders))
+        .await
+        .is_err()
     {
-        let adapter = adapter.clone();
-        let tx = tx.clone();
-        let last_ble_packet = last_ble_packet.clone();
-        let watchdog = args.watchdog;
-        let cooldown = args.cooldown;
+        warning!("{} Exporters didn't finish sending in time", Icon::Warn);
+    }
+}
 
-        tokio::spawn(async move {
-            let mut restart_counter: u64 = 1;
+/// `--decode-only`: readings from stdin go to stdout as `--format` and
+/// `--template` say, and to `--csv`; with `--strict`, a line that doesn't
+/// decode makes the exit code 1.
+fn decode_only(args: &Args) -> Result<()> {
+    let mut exporters: Vec<Box<dyn Exporter + Send>> = vec![Box::new(ConsoleExporter {
+        format: args.format,
+        template: args.template.clone(),
+        units: args.units,
+    })];
+    if let Some(path) = &args.csv {
+        match csv::CsvExporter::open(path, args.units) {
+            Ok(exporter) => exporters.push(Box::new(exporter)),
+            Err(e) => {
+                error!("{} Cannot open {}: {e}", Icon::Error, path.display());
+                std::process::exit(1);
+            }
+        }
+    }
+    let errors = stdin::run(
+        args.payload_format,
+        args.strict,
+        &args.services(),
+        &MultiExporter(exporters),
+    )?;
+    if errors > 0 && args.strict {
+        error!("{} {errors} lines did not decode", Icon::Error);
+        std::process::exit(1);
+    }
+    Ok(())
+}
 
-            loop {
-                println!("🔍 (Re)starting discovery...");
-                let mut events = match adapter.discover_devices().await {
-                    Ok(ev) => ev,
-                    Err(e) => {
-                        eprintln!("❌ Failed to start discovery: {e}");
-                        sleep(Duration::from_secs(cooldown)).await;
-                        continue;
+/// Print or write the `--summary` report, if requested.
+fn write_summary(args: &Args, pipeline: &Pipeline) {
+    let Some(path) = &args.summary else {
+        return;
+    };
+    let summary = pipeline.summary();
+    let report = match args.format {
+        OutputFormat::Text => summary.render(pipeline.now(), args.units),
+        OutputFormat::Json => summary.to_json(pipeline.now(), args.units) + "\n",
+    };
+    if path.as_os_str() == "-" {
+        // Like the readings, JSON goes to stdout
+        match args.format {
+            OutputFormat::Text => status!("{}", report.trim_end()),
+            OutputFormat::Json => print!("{report}"),
+        }
+    } else if let Err(e) = std::fs::write(path, report) {
+        error!(
+            "{} Could not write summary to {}: {e}",
+            Icon::Error,
+            path.display()
+        );
+    }
+}
+
+/// The command line, filled in with the options from `--config` if given.
+fn parse_args() -> Args {
+    let cli: Vec<OsString> = std::env::args_os().collect();
+    let file = match config::path(&cli, "--config") {
+        Some(path) => config_args(&path).unwrap_or_else(|e| {
+            Args::command()
+                .error(
+                    ErrorKind::InvalidValue,
+                    format!("--config {}: {e}", path.display()),
+                )
+                .exit()
+        }),
+        None => config::FileArgs::default(),
+    };
+    #[cfg_attr(not(feature = "yaml"), allow(unused_mut))]
+    let mut args = merge_args(&cli, &file);
+    // The import goes first so the config file and command line win; its
+    // path may come from either
+    #[cfg(feature = "yaml")]
+    if let Some(path) = config::path(&args, "--yaml") {
+        let imported = yaml::load(&path).unwrap_or_else(|e| {
+            Args::command()
+                .error(
+                    ErrorKind::InvalidValue,
+                    format!("--yaml {}: {e}", path.display()),
+                )
+                .exit()
+        });
+        args.splice(1..1, imported.into_iter().map(OsString::from));
+    }
+    Args::parse_from(args)
+}
+
+/// The arguments to parse: the config file's options except those the
+/// command line gives, turns off with `--no-<flag>` or conflicts with, then
+/// the file's per-device and per-adapter settings, then the command line,
+/// whose own per-device settings come last and win.
+fn merge_args(cli: &[OsString], file: &config::FileArgs) -> Vec<OsString> {
+    let command = Args::command();
+    let find = |long: &str| {
+        command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(long))
+    };
+
+    let mut off = Vec::new();
+    let cli: Vec<OsString> = cli
+        .iter()
+        .filter(|arg| {
+            let negated = arg
+                .to_str()
+                .and_then(|arg| arg.strip_prefix("--no-"))
+                .and_then(find)
+                .filter(|arg| matches!(arg.get_action(), clap::ArgAction::SetTrue));
+            if let Some(arg) = negated {
+                off.push(arg.get_id());
+            }
+            negated.is_none()
+        })
+        .cloned()
+        .collect();
+
+    // Only to see what's given, so errors wait for the real parse
+    let matches = command
+        .clone()
+        .ignore_errors(true)
+        .try_get_matches_from(&cli)
+        .ok();
+    let given: Vec<&clap::Arg> = command
+        .get_arguments()
+        .filter(|arg| {
+            matches.as_ref().is_some_and(|matches| {
+                matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine)
+            })
+        })
+        .collect();
+    let conflict = |a: &clap::Arg, b: &clap::Arg| {
+        command
+            .get_arg_conflicts_with(a)
+            .iter()
+            .any(|arg| arg.get_id() == b.get_id())
+    };
+    let replaced = |option: &String| {
+        let long = option.trim_start_matches("--");
+        let long = long.split_once('=').map_or(long, |(long, _)| long);
+        find(long).is_some_and(|arg| {
+            off.contains(&arg.get_id())
+                || given.iter().any(|given| {
+                    given.get_id() == arg.get_id() || conflict(given, arg) || conflict(arg, given)
+                })
+        })
+    };
+
+    let mut args = cli[..1].to_vec();
+    let options = file.options.iter().filter(|option| !replaced(option));
+    args.extend(options.chain(&file.tables).map(OsString::from));
+    args.extend(cli[1..].iter().cloned());
+    args
+}
+
+/// The arguments the config file at `path` becomes.
+fn config_args(path: &Path) -> std::result::Result<config::FileArgs, String> {
+    let command = Args::command();
+    let known: Vec<&str> = command
+        .get_arguments()
+        .filter_map(|arg| arg.get_long())
+        .collect();
+    config::Config::load(path).and_then(|config| config.args(&known))
+}
+
+/// Checks across options that clap can't express. Nothing here needs
+/// Bluetooth, files or the network, so `check-config` runs them all.
+fn validate(args: &Args) -> std::result::Result<(), String> {
+    if args.format == OutputFormat::Json && args.template.is_some() {
+        return Err("--template only applies to --format text".into());
+    }
+    if let Some(url) = &args.influx_url
+        && !url.starts_with("http://")
+    {
+        return Err(format!("--influx-url {url}: only http:// is supported"));
+    }
+    for adapter in scan::configured_adapters(args) {
+        ScanSettings::for_adapter(args, adapter)?;
+    }
+    Ok(())
+}
+
+/// `check-config`: the options got past clap and `validate`, so only the
+/// file's arguments are left to show.
+fn check_config(args: &Args) {
+    match &args.config {
+        Some(path) => {
+            // parse_args already loaded it, so this can't fail
+            let file_args = config_args(path).unwrap_or_default();
+            status!(
+                "{} {} is valid, it sets {} arguments:",
+                Icon::Ok,
+                path.display(),
+                file_args.iter().count()
+            );
+            for arg in file_args.iter() {
+                println!("  {arg}");
+            }
+        }
+        None => status!("{} The options are valid (no --config file)", Icon::Ok),
+    }
+    #[cfg(feature = "yaml")]
+    if let Some(path) = &args.yaml {
+        let imported = yaml::load(path).unwrap_or_default();
+        status!(
+            "{} {} is valid, it imports {} arguments:",
+            Icon::Ok,
+            path.display(),
+            imported.len()
+        );
+        for arg in imported {
+            println!("  {arg}");
+        }
+    }
+}
+
+/// The scan settings of each selected adapter, in order. Settings for an
+/// adapter that isn't scanning are a mistake worth stopping for.
+fn adapter_settings(
+    args: &Args,
+    adapters: &[Adapter],
+) -> std::result::Result<Vec<ScanSettings>, String> {
+    let names: Vec<&str> = adapters.iter().map(|adapter| adapter.name()).collect();
+    if let Some(unknown) = scan::configured_adapters(args)
+        .into_iter()
+        .find(|name| !names.contains(name))
+    {
+        return Err(format!(
+            "settings given for adapter {unknown}, which isn't scanning (scanning: {})",
+            names.join(", ")
+        ));
+    }
+    names
+        .iter()
+        .map(|name| ScanSettings::for_adapter(args, name))
+        .collect()
+}
+
+/// Parse a per-adapter `<name>=<value>` option.
+fn parse_adapter_option<T>(s: &str) -> std::result::Result<(String, T), String>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    let (name, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected <adapter>=<value>, got {s:?}"))?;
+    let value = value.trim().parse().map_err(|e| format!("{e}"))?;
+    Ok((name.trim().to_string(), value))
+}
+
+/// Parse a per-device `<MAC>=<value>` option.
+fn parse_device_option<T>(s: &str) -> std::result::Result<(Address, T), String>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    let (addr, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected <MAC>=<value>, got {s:?}"))?;
+    let addr = addr
+        .trim()
+        .parse()
+        .map_err(|e| format!("invalid address {addr:?}: {e}"))?;
+    Ok((addr, value.trim().parse().map_err(|e| format!("{e}"))?))
+}
+
+fn parse_extra_uuid(s: &str) -> std::result::Result<(uuid::Uuid, stdin::PayloadFormat), String> {
+    let (uuid, format) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected <uuid>=<format>, got {s:?}"))?;
+    let format = <stdin::PayloadFormat as clap::ValueEnum>::from_str(format.trim(), true)?;
+    Ok((stdin::parse_uuid(uuid.trim())?, format))
+}
+
+/// The adapters requested on the command line, or the default one.
+async fn select_adapters(session: &bluer::Session, args: &Args) -> Result<Vec<Adapter>> {
+    if args.all_adapters {
+        let names = session.adapter_names().await?;
+        if names.is_empty() {
+            return Err(bluer::Error {
+                kind: bluer::ErrorKind::NotFound,
+                message: "no Bluetooth adapters found".into(),
+            });
+        }
+        return names.iter().map(|name| session.adapter(name)).collect();
+    }
+    let mut adapters: Vec<Adapter> = Vec::new();
+    for wanted in &args.adapter {
+        if adapters.iter().all(|a| a.name() != wanted) {
+            adapters.push(select_adapter(session, Some(wanted), None).await?);
+        }
+    }
+    if adapters.is_empty() {
+        adapters.push(select_adapter(session, None, args.adapter_address).await?);
+    }
+    Ok(adapters)
+}
+
+/// The adapter with the given name or address, or the default one.
+async fn select_adapter(
+    session: &bluer::Session,
+    name: Option<&String>,
+    address: Option<Address>,
+) -> Result<Adapter> {
+    if let Some(wanted) = name {
+        let names = session.adapter_names().await?;
+        if names.contains(wanted) {
+            return session.adapter(wanted);
+        }
+        let available = if names.is_empty() {
+            "none".to_string()
+        } else {
+            names.join(", ")
+        };
+        return Err(bluer::Error {
+            kind: bluer::ErrorKind::NotFound,
+            message: format!("no adapter named {wanted}; available: {available}"),
+        });
+    }
+    let Some(wanted) = address else {
+        return session.default_adapter().await;
+    };
+
+    let mut available = Vec::new();
+    for name in session.adapter_names().await? {
+        let adapter = session.adapter(&name)?;
+        let addr = adapter.address().await?;
+        if addr == wanted {
+            return Ok(adapter);
+        }
+        available.push(format!("{name} ({addr})"));
+    }
+
+    let available = if available.is_empty() {
+        "none".to_string()
+    } else {
+        available.join(", ")
+    };
+    Err(bluer::Error {
+        kind: bluer::ErrorKind::NotFound,
+        message: format!("no adapter with address {wanted}; available: {available}"),
+    })
+}
+
+/// Run discovery on one adapter until `stopped` fires, restarting it when
+/// the adapter's watchdog sees no sensor advertisements. Events are tagged
+/// with `index` so the event loop knows which adapter reported them.
+fn spawn_discovery(
+    scanner: Scanner,
+    index: usize,
+    tx: mpsc::UnboundedSender<Event>,
+    args: &Args,
+    mut stopped: watch::Receiver<bool>,
+) -> JoinHandle<()> {
+    let Scanner {
+        adapter,
+        last_ble_packet,
+        label,
+        settings,
+    } = scanner;
+    // A --once scan is over before restarting would help
+    let watchdog = match args.once {
+        Some(_) => u64::MAX,
+        None => args.watchdog,
+    };
+    let busy_retry = args.busy_retry;
+    let ScanSettings {
+        passive,
+        duplicate_data,
+        uuids,
+        ..
+    } = settings;
+    // Passive scanning matches the same UUIDs discovery is limited to
+    let patterns = if uuids.is_empty() {
+        passive_patterns(&args.services())
+    } else {
+        uuids.iter().copied().map(service_data_pattern).collect()
+    };
+    let mut restart_log = LogThrottle::new(Duration::from_secs(args.restart_log_window));
+    let mut backoff = Backoff::new(
+        Duration::from_secs(args.cooldown),
+        Duration::from_secs(args.max_cooldown),
+    );
+
+    let discovery_loop = async move {
+        let mut restart_counter: u64 = 1;
+        let mut adapter_busy = false;
+        // Whether the restart that led here was logged
+        let mut log_restart = true;
+
+        // Kept by the adapter for every later start of discovery
+        if duplicate_data || !uuids.is_empty() {
+            let filter = DiscoveryFilter {
+                uuids: uuids.into_iter().collect(),
+                duplicate_data,
+                ..Default::default()
+            };
+            if let Err(e) = adapter.set_discovery_filter(filter).await {
+                warning!("{} {label}Failed to set discovery filter: {e}", Icon::Warn);
+            }
+        }
+
+        loop {
+            if log_restart {
+                status!("{} {label}(Re)starting discovery...", Icon::Scan);
+            }
+            let mut events = match start_scan(&adapter, passive.then_some(&patterns[..])).await {
+                Ok(ev) => {
+                    if adapter_busy {
+                        status!(
+                            "{} {label}Adapter no longer busy, discovery running",
+                            Icon::Ok
+                        );
+                        adapter_busy = false;
                     }
-                };
-
-                loop {
-                    tokio::select! {
-                        evt = events.next() => {
-                            match evt {
-                                Some(AdapterEvent::DeviceAdded(addr)) => {
-                                    // ❌ no timestamp update here anymore
-                                    let _ = tx.send(AdapterEvent::DeviceAdded(addr));
-                                }
-                                Some(AdapterEvent::DeviceRemoved(addr)) => {
-                                    let _ = tx.send(AdapterEvent::DeviceRemoved(addr));
-                                }
-                                Some(_) => {}
-                                None => {
-                                    println!("⚠️ Discovery stream ended — restarting...");
-                                    break;
+                    ev
+                }
+                Err(e) if is_adapter_busy(&e) => {
+                    if busy_retry == 0 {
+                        error!(
+                            "{} {label}Adapter busy ({e}): another process holds discovery",
+                            Icon::Error
+                        );
+                        std::process::exit(1);
+                    }
+                    // Warn once per busy streak instead of on every retry
+                    if !adapter_busy {
+                        warning!(
+                            "{} {label}Adapter busy ({e}): another process (bluetoothctl, another mitempr?) holds discovery, retrying every {busy_retry}s...",
+                            Icon::Warn
+                        );
+                        adapter_busy = true;
+                    }
+                    sleep(Duration::from_secs(busy_retry)).await;
+                    continue;
+                }
+                Err(e) => {
+                    log_restart = restart_log.allow(Instant::now());
+                    let delay = backoff.next_delay();
+                    if log_restart {
+                        let hint = if passive {
+                            " (passive scanning needs bluetoothd --experimental)"
+                        } else {
+                            ""
+                        };
+                        error!(
+                            "{} {label}Failed to start discovery: {e}{hint}, retrying in {}s",
+                            Icon::Error,
+                            delay.as_secs()
+                        );
+                    }
+                    sleep(delay).await;
+                    continue;
+                }
+            };
+            // To tell whether packets came in since this start
+            let started = Instant::now();
+
+            loop {
+                tokio::select! {
+                    evt = events.next() => {
+                        match evt {
+                            Some(AdapterEvent::DeviceAdded(addr)) => {
+                                // ❌ no timestamp update here anymore
+                                let _ = tx.send((Instant::now(), index, ScanEvent::Adapter(AdapterEvent::DeviceAdded(addr))));
+                            }
+                            Some(AdapterEvent::DeviceRemoved(addr)) => {
+                                let _ = tx.send((Instant::now(), index, ScanEvent::Adapter(AdapterEvent::DeviceRemoved(addr))));
+                            }
+                            Some(_) => {}
+                            None => {
+                                log_restart = restart_log.allow(Instant::now());
+                                if log_restart {
+                                    warning!("{} {label}Discovery stream ended - restarting...", Icon::Warn);
                                 }
+                                break;
                             }
                         }
+                    }
 
-                        _ = sleep(Duration::from_secs(5)) => {
-                            let elapsed = last_ble_packet.lock().await.elapsed();
-                            if elapsed > Duration::from_secs(watchdog) {
-                                println!(
-                                    "⏱ Watchdog: no BLE packets for {:?}, restarting discovery (count {})...",
-                                    elapsed, restart_counter
+                    _ = sleep(Duration::from_secs(5)) => {
+                        if let Some(n) = restart_log.flush(Instant::now()) {
+                            status!(
+                                "{} {label}{n} more restarts in the last {}s",
+                                Icon::Watchdog,
+                                restart_log.window().as_secs()
+                            );
+                        }
+
+                        let last_packet = *last_ble_packet.lock().await;
+                        if last_packet > started {
+                            backoff.reset();
+                        }
+                        let elapsed = last_packet.elapsed();
+                        if elapsed > Duration::from_secs(watchdog) {
+                            log_restart = restart_log.allow(Instant::now());
+                            let delay = backoff.next_delay();
+                            if log_restart {
+                                warning!(
+                                    "{} {label}Watchdog: no BLE packets for {:?}, restarting discovery in {}s (count {})...",
+                                    Icon::Watchdog,
+                                    elapsed, delay.as_secs(), restart_counter
                                 );
-                                restart_counter += 1;
+                            }
+                            restart_counter += 1;
 
-                                // Drop the current stream (equivalent to disable_le_scan)
-                                drop(events);
+                            // Drop the current stream (equivalent to disable_le_scan)
+                            drop(events);
 
-                                // Wait before restarting (equivalent to Python’s 5s delay)
-                                sleep(Duration::from_secs(cooldown)).await;
+                            // Wait before restarting, longer the more restarts
+                            // in a row didn't help
+                            sleep(delay).await;
 
-                                break;
-                            }
+                            break;
                         }
                     }
                 }
-
-                // Small delay before reinitializing discovery
-                sleep(Duration::from_secs(2)).await;
             }
-        });
+
+            // Small delay before reinitializing discovery
+            sleep(Duration::from_secs(2)).await;
+        }
+    };
+    // Dropping the loop mid-wait drops the discovery stream with it,
+    // which stops discovery
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = discovery_loop => {}
+            _ = stopped.changed() => {}
+        }
+    })
+}
+
+type AdapterEvents = Pin<Box<dyn Stream<Item = AdapterEvent> + Send>>;
+
+/// Start finding devices: BlueZ discovery, which always scans actively, or
+/// with `monitor` patterns an advertisement monitor, which the controller
+/// matches while scanning passively.
+async fn start_scan(adapter: &Adapter, monitor: Option<&[Pattern]>) -> Result<AdapterEvents> {
+    let Some(patterns) = monitor else {
+        return Ok(Box::pin(adapter.discover_devices().await?));
+    };
+    let manager = adapter.monitor().await?;
+    let monitor = manager
+        .register(Monitor {
+            patterns: Some(patterns.to_vec()),
+            ..Default::default()
+        })
+        .await?;
+    Ok(Box::pin(monitor.filter_map(move |event| {
+        // The monitor is unregistered along with its manager
+        let _ = &manager;
+        future::ready(match event {
+            MonitorEvent::DeviceFound(id) => Some(AdapterEvent::DeviceAdded(id.device)),
+            MonitorEvent::DeviceLost(id) => Some(AdapterEvent::DeviceRemoved(id.device)),
+            _ => None,
+        })
+    })))
+}
+
+// AD types of service data by UUID size, and of manufacturer data
+const SERVICE_DATA_16_BIT_UUID: u8 = 0x16;
+const SERVICE_DATA_32_BIT_UUID: u8 = 0x20;
+const SERVICE_DATA_128_BIT_UUID: u8 = 0x21;
+const MANUFACTURER_DATA: u8 = 0xFF;
+/// The Bluetooth base UUID, which 16 and 32-bit UUIDs are short for
+const BASE_UUID: u128 = 0x00000000_0000_1000_8000_00805F9B34FB;
+
+/// Service data starting with each UUID `services` decodes, in its
+/// shortest form, and Xiaomi manufacturer data, the same advertisements
+/// active scanning decodes. UUIDs and company IDs go over the air
+/// little-endian.
+fn passive_patterns(services: &decoder::ServiceMap) -> Vec<Pattern> {
+    let mut patterns = Vec::new();
+    for uuid in services.uuids() {
+        let pattern = service_data_pattern(uuid);
+        if !patterns.contains(&pattern) {
+            patterns.push(pattern);
+        }
     }
+    patterns.push(Pattern::new(
+        MANUFACTURER_DATA,
+        0,
+        &decoder::XIAOMI_COMPANY_ID.to_le_bytes(),
+    ));
+    patterns
+}
 
-    //
-    // 📡 Event processing loop
-    //
-    while let Some(evt) = rx.recv().await {
-        match evt {
-            AdapterEvent::DeviceAdded(addr) => {
-                let mut seen = seen_devices.lock().await;
-                if !seen.contains(&addr) {
-                    seen.insert(addr);
-                    if let Err(e) = handle_device(&adapter, addr, last_ble_packet.clone()).await {
-                        eprintln!("Error handling device {addr}: {e}");
-                    }
-                }
-            }
-            AdapterEvent::DeviceRemoved(addr) => {
-                println!("❌ Device removed: {addr}");
-                let mut seen = seen_devices.lock().await;
-                seen.remove(&addr);
+/// Service data under `uuid`, in its shortest form.
+fn service_data_pattern(uuid: uuid::Uuid) -> Pattern {
+    let uuid = uuid.as_u128();
+    let short = (uuid >> 96) as u32;
+    if uuid & !(u128::from(u32::MAX) << 96) != BASE_UUID {
+        Pattern::new(SERVICE_DATA_128_BIT_UUID, 0, &uuid.to_le_bytes())
+    } else if let Ok(short) = u16::try_from(short) {
+        Pattern::new(SERVICE_DATA_16_BIT_UUID, 0, &short.to_le_bytes())
+    } else {
+        Pattern::new(SERVICE_DATA_32_BIT_UUID, 0, &short.to_le_bytes())
+    }
+}
+
+const PERMISSION_HINT: &str = "Permission denied: run as a user in the `bluetooth` group, or give the binary CAP_NET_ADMIN (`sudo setcap cap_net_admin+ep mitempr`)";
+const POWER_HINT: &str = "Is it blocked? Check `rfkill list`, then `rfkill unblock bluetooth` or `bluetoothctl power on`";
+
+/// What to do about a Bluetooth startup error, if it's a common one.
+fn unavailable_hint(e: &bluer::Error) -> Option<&'static str> {
+    match &e.kind {
+        bluer::ErrorKind::Internal(bluer::InternalErrorKind::DBus(name)) => {
+            match name.rsplit('.').next()? {
+                "ServiceUnknown" | "NameHasNoOwner" => Some(
+                    "bluetoothd is not running: start it with `sudo systemctl start bluetooth`",
+                ),
+                "AccessDenied" => Some(PERMISSION_HINT),
+                "FileNotFound" | "NoServer" | "Disconnected" => Some(
+                    "The D-Bus system bus is not reachable: is dbus running (in a container, is /run/dbus mounted)?",
+                ),
+                _ => None,
             }
-            _ => {}
         }
+        bluer::ErrorKind::Internal(bluer::InternalErrorKind::DBusConnectionLost) => {
+            Some("Lost the connection to D-Bus: did dbus or bluetoothd restart?")
+        }
+        bluer::ErrorKind::NotAuthorized | bluer::ErrorKind::NotPermitted => Some(PERMISSION_HINT),
+        // Adapter names and addresses that don't exist explain themselves
+        bluer::ErrorKind::NotFound if e.message.is_empty() => {
+            Some("No Bluetooth adapter found: is one plugged in and not blocked (`rfkill list`)?")
+        }
+        _ => None,
     }
+}
 
-    Ok(())
+/// Report a Bluetooth startup error with what to do about it, and exit.
+fn exit_unavailable(context: &str, e: &bluer::Error, fallback: Option<&str>) -> ! {
+    error!("{} {context}: {e}", Icon::Error);
+    if let Some(hint) = unavailable_hint(e).or(fallback) {
+        error!("  {hint}");
+    }
+    std::process::exit(1);
 }
 
-async fn handle_device(
-    adapter: &Adapter,
-    addr: Address,
+/// Whether a discovery error means someone else is using the adapter.
+fn is_adapter_busy(e: &bluer::Error) -> bool {
+    match e.kind {
+        bluer::ErrorKind::InProgress => true,
+        bluer::ErrorKind::Failed | bluer::ErrorKind::NotReady => {
+            let message = e.message.to_lowercase();
+            message.contains("in progress") || message.contains("busy")
+        }
+        _ => false,
+    }
+}
+
+/// What the event loop acts on.
+enum ScanEvent {
+    Adapter(AdapterEvent),
+    /// A watched device advertised new service data
+    ServiceData(Address),
+}
+
+/// A scan event with when it was received and the index of the adapter
+/// that reported it.
+type Event = (Instant, usize, ScanEvent);
+
+/// An adapter being scanned, with its own discovery watchdog.
+#[derive(Clone)]
+struct Scanner {
+    adapter: Adapter,
+    /// When a sensor advertisement last arrived through this adapter
     last_ble_packet: Arc<Mutex<Instant>>,
-) -> Result<()> {
-    let device = adapter.device(addr)?;
-    let name = device.name().await?.unwrap_or_else(|| "<unknown>".into());
-    let rssi = device.rssi().await?.unwrap_or(0);
+    /// Log line prefix naming the adapter, empty when scanning with one
+    label: String,
+    settings: ScanSettings,
+}
 
-    println!("📡 {addr} ({name}), RSSI={rssi}");
+impl Scanner {
+    fn new(adapter: Adapter, settings: ScanSettings, multiple: bool) -> Self {
+        let label = if multiple {
+            format!("[{}] ", adapter.name())
+        } else {
+            String::new()
+        };
+        Self {
+            adapter,
+            last_ble_packet: Arc::new(Mutex::new(Instant::now())),
+            label,
+            settings,
+        }
+    }
+}
 
-    if let Some(data_map) = device.service_data().await? {
-        for (uuid, data) in &data_map {
-            println!("  Service {uuid}: {:02X?}", data);
+/// Forward service data changes of `addr` to the event loop, so readings
+/// are live instead of only taken when BlueZ reports the device.
+fn watch_device(
+    adapter: &Adapter,
+    index: usize,
+    addr: Address,
+    tx: mpsc::UnboundedSender<Event>,
+) -> JoinHandle<()> {
+    let adapter = adapter.clone();
+    tokio::spawn(async move {
+        let events = match adapter.device(addr) {
+            Ok(device) => device.events().await,
+            Err(e) => Err(e),
+        };
+        let events = match events {
+            Ok(events) => events,
+            Err(e) => {
+                warning!("{} Cannot watch {addr} for updates: {e}", Icon::Warn);
+                return;
+            }
+        };
+        let mut events = std::pin::pin!(events);
+        while let Some(DeviceEvent::PropertyChanged(property)) = events.next().await {
+            if matches!(property, DeviceProperty::ServiceData(_))
+                && tx
+                    .send((Instant::now(), index, ScanEvent::ServiceData(addr)))
+                    .is_err()
+            {
+                break;
+            }
         }
+    })
+}
 
-        if let Some(decoded) = decoder::handle_service_data(&data_map) {
-            println!("  🔍 Got sensor reading: {:?}", decoded);
+/// The scanner that hears `addr` best, falling back to `index` (the one
+/// that reported it) when no other adapter knows the device.
+async fn best_scanner(scanners: &[Scanner], index: usize, addr: Address) -> &Scanner {
+    let mut best = &scanners[index];
+    let mut best_rssi = None;
+    for scanner in scanners {
+        let Ok(device) = scanner.adapter.device(addr) else {
+            continue;
+        };
+        if let Ok(Some(rssi)) = device.rssi().await
+            && best_rssi.is_none_or(|best| rssi > best)
+        {
+            best = scanner;
+            best_rssi = Some(rssi);
+        }
+    }
+    best
+}
 
-            // ✅ Reset watchdog timer only on actual service data
+async fn handle_device(
+    scanners: &[Scanner],
+    index: usize,
+    addr: Address,
+    pipeline: &mut Pipeline,
+    aliases: Option<&aliases::Aliases>,
+    resolver: Option<&NameResolver>,
+) -> Result<()> {
+    // The adapter that reported the advertisement has seen a packet, even
+    // when another one is read because it hears the device better
+    let last_ble_packet = &scanners[index].last_ble_packet;
+    let (scanner, adapter_name) = if scanners.len() > 1 {
+        let scanner = best_scanner(scanners, index, addr).await;
+        (scanner, Some(scanner.adapter.name()))
+    } else {
+        (&scanners[index], None)
+    };
+    let device = scanner.adapter.device(addr)?;
+    let rssi = device.rssi().await?;
+    if !scanner.settings.rssi_filter().permits(rssi) {
+        let service_data = device.service_data().await?.unwrap_or_default();
+        if pipeline.services().packet_type(&service_data) != decoder::BlePacketType::Other {
             *last_ble_packet.lock().await = Instant::now();
         }
+        return Ok(());
+    }
+
+    let resolved = match (aliases.and_then(|a| a.get(addr)), resolver) {
+        (Some(alias), _) => Some(alias),
+        (None, Some(resolver)) => resolver.lookup(addr).await,
+        (None, None) => None,
+    };
+    // Configured alias first, then the inventory name, then the advertised
+    // name, then BlueZ's alias (which itself falls back to the address)
+    let name = match resolved.or(device.name().await?) {
+        Some(name) => name,
+        None => device.alias().await?,
+    };
+
+    match adapter_name {
+        Some(adapter) => status!(
+            "{} {addr} ({name}), RSSI={} via {adapter}",
+            Icon::Rx,
+            rssi.unwrap_or(0)
+        ),
+        None => status!("{} {addr} ({name}), RSSI={}", Icon::Rx, rssi.unwrap_or(0)),
     }
 
-    // Uncomment this if you also want manufacturer data
-    /*
-    if let Some(mdata) = device.manufacturer_data().await? {
-        for (id, data) in mdata {
-            println!("  Manufacturer {id:#06X}: {:02X?}", data);
+    let service_data = device.service_data().await?;
+    let manufacturer_data = device.manufacturer_data().await?;
+    if service_data.is_some() || manufacturer_data.is_some() {
+        let service_data = service_data.unwrap_or_default();
+        let manufacturer_data = manufacturer_data.unwrap_or_default();
+        for (uuid, data) in &service_data {
+            status!("  Service {uuid}: {:02X?}", data);
+        }
+        for (id, data) in &manufacturer_data {
+            status!("  Manufacturer {id:#06X}: {:02X?}", data);
         }
+
+        if pipeline.process_advertisement(
+            addr,
+            Some(&name),
+            rssi,
+            adapter_name,
+            &service_data,
+            &manufacturer_data,
+        ) {
+            // ✅ Reset watchdog timer only on actual sensor data
+            *last_ble_packet.lock().await = Instant::now();
+        }
+        pipeline.process_beacon(addr, &manufacturer_data, &service_data);
     }
-    */
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_extra_uuid() {
+        let (uuid, format) = parse_extra_uuid("fcd9=BTHome").unwrap();
+        assert_eq!(uuid, uuid::uuid!("0000fcd9-0000-1000-8000-00805f9b34fb"));
+        assert_eq!(
+            decoder::BlePacketType::from(format),
+            decoder::BlePacketType::BTHome
+        );
+        assert!(parse_extra_uuid("fcd9").is_err());
+        assert!(parse_extra_uuid("fcd9=atc").is_err());
+        assert!(parse_extra_uuid("nope=pvvx").is_err());
+    }
+
+    #[test]
+    fn test_parse_device_option_rejects_bad_input() {
+        assert!(parse_device_option::<Chemistry>("A4:C1:38:00:00:01").is_err());
+        assert!(parse_device_option::<Chemistry>("nope=cr2032").is_err());
+        assert!(parse_device_option::<Chemistry>("A4:C1:38:00:00:01=nimh").is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        let validate = |cli: &[&str]| {
+            let args = Args::parse_from(std::iter::once("mitempr").chain(cli.iter().copied()));
+            validate(&args)
+        };
+
+        assert!(validate(&["--template={alias}", "--adapter-min-rssi=hci0=-70"]).is_ok());
+        assert!(validate(&["--format=json", "--template={alias}"]).is_err());
+        assert!(validate(&["--influx-url=https://db:8086", "--influx-bucket=b"]).is_err());
+        assert!(validate(&["--passive", "--adapter-duplicate-data=hci0=true"]).is_err());
+        assert!(matches!(
+            Args::parse_from(["mitempr", "check-config", "--config", "a.toml"]).command,
+            Some(Command::CheckConfig)
+        ));
+        // Every option goes after the subcommand too
+        assert!(
+            validate(&[
+                "check-config",
+                "--passive",
+                "--adapter-duplicate-data=hci0=true"
+            ])
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_passive_patterns_cover_extra_uuids_and_manufacturer_data() {
+        let args = Args::parse_from([
+            "mitempr",
+            "--extra-uuid=fcd9=bthome",
+            "--extra-uuid=fcd2=bthome",
+            "--extra-uuid=12345678-9abc-def0-1234-56789abcdef0=pvvx",
+        ]);
+        let patterns = passive_patterns(&args.services());
+
+        let custom = 0x12345678_9abc_def0_1234_56789abcdef0_u128.to_le_bytes();
+        assert_eq!(
+            patterns,
+            [
+                Pattern::new(0x16, 0, &[0x95, 0xFE]),
+                Pattern::new(0x16, 0, &[0xD2, 0xFC]),
+                Pattern::new(0x16, 0, &[0x1A, 0x18]),
+                Pattern::new(0x16, 0, &[0xD9, 0xFC]),
+                Pattern::new(0x21, 0, &custom),
+                Pattern::new(0xFF, 0, &[0x8F, 0x03]),
+            ]
+        );
+        // Without --extra-uuid only the standard formats
+        assert_eq!(passive_patterns(&decoder::ServiceMap::default()).len(), 4);
+    }
+
+    #[test]
+    fn test_tx_power_defaults_when_given_without_value() {
+        let args = Args::parse_from(["mitempr", "--tx-power"]);
+        assert_eq!(args.tx_power, Some(rf::DEFAULT_TX_POWER));
+        let args = Args::parse_from(["mitempr", "--tx-power", "-65"]);
+        assert_eq!(args.tx_power, Some(-65));
+        assert_eq!(Args::parse_from(["mitempr"]).tx_power, None);
+    }
+
+    #[test]
+    fn test_unavailable_hints() {
+        let error = |kind| bluer::Error {
+            kind,
+            message: String::new(),
+        };
+        let dbus = |name: &str| {
+            error(bluer::ErrorKind::Internal(bluer::InternalErrorKind::DBus(
+                name.into(),
+            )))
+        };
+        let hint = |e| unavailable_hint(&e).unwrap_or_default();
+
+        assert!(hint(dbus("org.freedesktop.DBus.Error.ServiceUnknown")).contains("bluetoothd"));
+        assert!(hint(dbus("org.freedesktop.DBus.Error.AccessDenied")).contains("bluetooth` group"));
+        assert!(hint(dbus("org.freedesktop.DBus.Error.FileNotFound")).contains("system bus"));
+        assert!(hint(error(bluer::ErrorKind::NotFound)).contains("No Bluetooth adapter"));
+        // Our own "no adapter named ..." errors need no hint
+        let named = bluer::Error {
+            kind: bluer::ErrorKind::NotFound,
+            message: "no adapter named hci7; available: hci0".into(),
+        };
+        assert_eq!(unavailable_hint(&named), None);
+        assert_eq!(unavailable_hint(&error(bluer::ErrorKind::Failed)), None);
+    }
+
+    #[test]
+    fn test_min_rssi_accepts_negative_values() {
+        let args = Args::parse_from(["mitempr", "--min-rssi", "-90"]);
+        assert_eq!(args.min_rssi, Some(-90));
+    }
+
+    #[test]
+    fn test_adapter_name_and_address_conflict() {
+        let args = Args::parse_from(["mitempr", "--adapter", "hci1", "--adapter", "hci2"]);
+        assert_eq!(args.adapter, ["hci1", "hci2"]);
+
+        let both = Args::try_parse_from([
+            "mitempr",
+            "--adapter",
+            "hci1",
+            "--adapter-address",
+            "00:1A:7D:DA:71:13",
+        ]);
+        assert_eq!(both.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+
+        let all = Args::try_parse_from(["mitempr", "--all-adapters", "--adapter", "hci1"]);
+        assert_eq!(all.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn test_scan_mode_flags() {
+        let args = Args::parse_from(["mitempr"]);
+        assert!(!args.passive && !args.duplicate_data);
+
+        let passive = Args::try_parse_from(["mitempr", "--passive", "--active"]);
+        assert_eq!(passive.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+        let passive = Args::try_parse_from(["mitempr", "--passive", "--duplicate-data"]);
+        assert_eq!(passive.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+        assert!(Args::try_parse_from(["mitempr", "--active", "--duplicate-data"]).is_ok());
+    }
+
+    #[test]
+    fn test_once_duration() {
+        assert_eq!(Args::parse_from(["mitempr", "--once", "30"]).once, Some(30));
+        let alias = Args::parse_from(["mitempr", "--scan-duration", "10"]);
+        assert_eq!(alias.once, Some(10));
+        assert!(Args::try_parse_from(["mitempr", "--once", "0"]).is_err());
+    }
+
+    #[test]
+    fn test_command_line_overrides_config_file() {
+        let file = config::FileArgs {
+            options: [
+                "--watchdog=60",
+                "--passive",
+                "--derive",
+                "--deny=A4:C1:38:00:00:09",
+            ]
+            .map(String::from)
+            .into(),
+            tables: vec!["--alias=A4:C1:38:00:00:01=Bedroom".into()],
+        };
+        let merged = |cli: &[&str]| {
+            let cli: Vec<OsString> = std::iter::once("mitempr")
+                .chain(cli.iter().copied())
+                .map(OsString::from)
+                .collect();
+            Args::try_parse_from(merge_args(&cli, &file))
+        };
+
+        let args = merged(&[]).unwrap();
+        assert_eq!(args.watchdog, 60);
+        assert!(args.passive && args.derive);
+
+        // Values and lists are replaced, conflicting flags dropped and
+        // flags turned off, while per-device settings merge
+        let args = merged(&[
+            "--watchdog",
+            "30",
+            "--active",
+            "--no-derive",
+            "--deny=A4:C1:38:00:00:0A",
+            "--alias=A4:C1:38:00:00:02=Attic",
+        ])
+        .unwrap();
+        assert_eq!(args.watchdog, 30);
+        assert!(args.active && !args.passive && !args.derive);
+        assert_eq!(args.deny.len(), 1);
+        assert_eq!(args.alias.len(), 2);
+        assert_eq!(args.alias[1].1, "Attic");
+
+        // Also after a subcommand
+        let args = merged(&["check-config", "--active", "--watchdog=30"]).unwrap();
+        assert!(args.active && !args.passive);
+        assert_eq!(args.watchdog, 30);
+
+        // Errors are the real parse's
+        assert!(merged(&["--no-watchdog"]).is_err());
+    }
+}
@@ -0,0 +1,274 @@
+//! Prometheus `/metrics` endpoint (`--metrics-addr`), plus
+//! `/history/<address>` with `--history`.
+
+use crate::clock::Clock;
+use crate::export::{Exporter, Reading};
+use crate::histogram::{Histogram, LATENCY_BUCKETS};
+use crate::history::History;
+use crate::icons::{Icon, warning};
+use bluer::Address;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// Requests are only a request line and a few headers.
+const MAX_REQUEST: usize = 8192;
+
+/// Latest values per device plus processing times, shared between the
+/// pipeline (as an exporter) and the HTTP server.
+#[derive(Clone)]
+pub struct Metrics {
+    inner: Arc<Mutex<Inner>>,
+    clock: Arc<dyn Clock>,
+    /// Devices not heard from for this long are dropped from the output
+    stale_after: Duration,
+}
+
+struct Inner {
+    devices: BTreeMap<Address, Gauges>,
+    processing: Histogram,
+}
+
+/// Picks one metric's value out of a device's gauges.
+type GaugeValue = fn(&Gauges) -> Option<String>;
+
+struct Gauges {
+    last_seen: Instant,
+    temperature: Option<f32>,
+    humidity: Option<f32>,
+    battery: Option<u8>,
+    rssi: Option<i16>,
+}
+
+impl Metrics {
+    pub fn new(stale_after: Duration, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                devices: BTreeMap::new(),
+                processing: Histogram::new(LATENCY_BUCKETS),
+            })),
+            clock,
+            stale_after,
+        }
+    }
+
+    /// Record how long handling one advertisement took.
+    pub fn observe_processing(&self, elapsed: Duration) {
+        self.inner.lock().unwrap().processing.observe(elapsed);
+    }
+
+    /// Prometheus text exposition of everything not stale.
+    pub fn render(&self) -> String {
+        let now = self.clock.now();
+        let mut inner = self.inner.lock().unwrap();
+        inner
+            .devices
+            .retain(|_, gauges| now.saturating_duration_since(gauges.last_seen) < self.stale_after);
+
+        let mut out = String::new();
+        let families: [(&str, &str, GaugeValue); 4] = [
+            (
+                "mitempr_temperature_celsius",
+                "Last temperature reading",
+                |g| g.temperature.map(|v| v.to_string()),
+            ),
+            (
+                "mitempr_humidity_percent",
+                "Last relative humidity reading",
+                |g| g.humidity.map(|v| v.to_string()),
+            ),
+            ("mitempr_battery_percent", "Last battery level", |g| {
+                g.battery.map(|v| v.to_string())
+            }),
+            (
+                "mitempr_rssi_dbm",
+                "Signal strength of the last reading",
+                |g| g.rssi.map(|v| v.to_string()),
+            ),
+        ];
+        for (name, help, value) in families {
+            let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} gauge");
+            for (addr, gauges) in &inner.devices {
+                if let Some(value) = value(gauges) {
+                    let _ = writeln!(out, "{name}{{address=\"{addr}\"}} {value}");
+                }
+            }
+        }
+        out.push_str(&inner.processing.render(
+            "mitempr_processing_seconds",
+            "Time from receiving an advertisement to exporting it",
+        ));
+        out
+    }
+}
+
+impl Exporter for Metrics {
+    fn export(&self, reading: &Reading) {
+        let (addr, data) = (reading.address, &reading.data);
+        let now = self.clock.now();
+        let mut inner = self.inner.lock().unwrap();
+        let gauges = inner.devices.entry(addr).or_insert(Gauges {
+            last_seen: now,
+            temperature: None,
+            humidity: None,
+            battery: None,
+            rssi: None,
+        });
+        // Devices that split their values across advertisements keep the
+        // ones the current reading lacks
+        gauges.last_seen = now;
+        gauges.temperature = data.temperature.or(gauges.temperature);
+        gauges.humidity = data.humidity.or(gauges.humidity);
+        gauges.battery = data.battery.or(gauges.battery);
+        gauges.rssi = data.rssi.or(gauges.rssi);
+    }
+}
+
+/// Answer `GET /metrics` (and `GET /history/...` with a `history`) on
+/// `listener` until the process exits.
+pub async fn serve(listener: TcpListener, metrics: Metrics, history: Option<History>) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                warning!("{} Metrics endpoint accept failed: {e}", Icon::Warn);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+        let metrics = metrics.clone();
+        let history = history.clone();
+        tokio::spawn(async move {
+            // Scrapers that hang up early aren't worth a log line
+            let request = respond(stream, &metrics, history.as_ref());
+            let _ = tokio::time::timeout(REQUEST_TIMEOUT, request).await;
+        });
+    }
+}
+
+async fn respond(
+    mut stream: TcpStream,
+    metrics: &Metrics,
+    history: Option<&History>,
+) -> io::Result<()> {
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < MAX_REQUEST {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        request.extend(&buf[..n]);
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let mut words = request.split_whitespace();
+    let response = match (words.next(), words.next()) {
+        (Some("GET"), Some("/metrics")) => {
+            let body = metrics.render();
+            format!(
+                "HTTP/1.0 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\n\r\n{body}",
+                body.len()
+            )
+        }
+        (Some("GET"), Some(target)) if target.starts_with("/history/") && history.is_some() => {
+            match history.and_then(|h| h.render(target, SystemTime::now())) {
+                Some(body) => format!(
+                    "HTTP/1.0 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+                    body.len()
+                ),
+                None => "HTTP/1.0 400 Bad Request\r\nContent-Length: 0\r\n\r\n".to_string(),
+            }
+        }
+        _ => "HTTP/1.0 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_string(),
+    };
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::decoder::SensorData;
+
+    const ADDR: Address = Address::new([0xA4, 0xC1, 0x38, 0x00, 0x00, 0x01]);
+
+    fn reading() -> SensorData {
+        SensorData {
+            temperature: Some(22.9),
+            battery: Some(87),
+            rssi: Some(-60),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_gauges_and_stale_devices() {
+        let clock = MockClock::new();
+        let metrics = Metrics::new(Duration::from_secs(300), Arc::new(clock.clone()));
+
+        metrics.export(&Reading::new(ADDR, reading()));
+        metrics.export(&Reading::new(
+            ADDR,
+            SensorData {
+                humidity: Some(45.5),
+                ..Default::default()
+            },
+        ));
+        let out = metrics.render();
+        assert!(out.contains("mitempr_temperature_celsius{address=\"A4:C1:38:00:00:01\"} 22.9\n"));
+        assert!(out.contains("mitempr_humidity_percent{address=\"A4:C1:38:00:00:01\"} 45.5\n"));
+        assert!(out.contains("mitempr_battery_percent{address=\"A4:C1:38:00:00:01\"} 87\n"));
+        assert!(out.contains("mitempr_rssi_dbm{address=\"A4:C1:38:00:00:01\"} -60\n"));
+
+        clock.advance(Duration::from_secs(300));
+        let out = metrics.render();
+        assert!(!out.contains("A4:C1:38:00:00:01"));
+        assert!(out.contains("# TYPE mitempr_temperature_celsius gauge\n"));
+    }
+
+    #[tokio::test]
+    async fn test_serves_metrics() {
+        let metrics = Metrics::new(Duration::from_secs(300), Arc::new(MockClock::new()));
+        metrics.export(&Reading::new(ADDR, reading()));
+        metrics.observe_processing(Duration::from_millis(3));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(serve(listener, metrics, None));
+
+        let body = crate::http::get(&format!("{url}/metrics")).await.unwrap();
+        assert!(body.contains("mitempr_temperature_celsius{address=\"A4:C1:38:00:00:01\"} 22.9\n"));
+        assert!(body.contains("mitempr_processing_seconds_count 1\n"));
+
+        assert!(crate::http::get(&format!("{url}/")).await.is_err());
+        let history = format!("{url}/history/A4:C1:38:00:00:01");
+        assert!(crate::http::get(&history).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_serves_history() {
+        let metrics = Metrics::new(Duration::from_secs(300), Arc::new(MockClock::new()));
+        let history = History::new(10, crate::units::Units::default());
+        history.export(&Reading::new(ADDR, reading()));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(serve(listener, metrics, Some(history)));
+
+        let body = crate::http::get(&format!("{url}/history/A4:C1:38:00:00:01?minutes=30"))
+            .await
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json[0]["temperature"], 22.9);
+        assert!(
+            crate::http::get(&format!("{url}/history/nope"))
+                .await
+                .is_err()
+        );
+    }
+}
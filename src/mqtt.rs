@@ -0,0 +1,396 @@
+use crate::decoder::SensorData;
+use crate::export::{Exporter, Reading};
+use crate::homeassistant::Discovery;
+use crate::icons::{Icon, status, warning};
+use bluer::Address;
+use std::io;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// Messages held back while the broker is unreachable; newer ones are
+/// dropped once this many are waiting.
+const QUEUE_SIZE: usize = 1024;
+/// Seconds between pings, announced to the broker in CONNECT.
+const KEEP_ALIVE: u16 = 30;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Broker address and credentials from `--mqtt-*`.
+#[derive(Debug, Clone)]
+pub struct Broker {
+    /// `host:port`
+    pub address: String,
+    pub user: Option<String>,
+    pub pass: Option<String>,
+}
+
+/// Publishes every reading field to `<prefix>/<address>/<field>` as
+/// `{"value":22.9,"address":"A4:C1:38:00:00:01","rssi":-60}`, QoS 0, with
+/// `"flags":["smoothed"]` if the reading has any.
+///
+/// A background task owns the connection and reconnects with backoff, so
+/// an unreachable broker never holds up scanning.
+pub struct MqttExporter {
+    prefix: String,
+    messages: mpsc::Sender<Message>,
+    /// With `--ha-discovery`
+    discovery: Option<Discovery>,
+}
+
+struct Message {
+    topic: String,
+    payload: String,
+    /// Kept by the broker for later subscribers
+    retain: bool,
+}
+
+impl MqttExporter {
+    /// Must be called from within the Tokio runtime. The returned task
+    /// publishes what's still queued and disconnects once the exporter is
+    /// dropped. With `ha_discovery`, every new field of a device is
+    /// announced to Home Assistant first.
+    pub fn new(broker: Broker, prefix: String, ha_discovery: bool) -> (Self, JoinHandle<()>) {
+        let (messages, rx) = mpsc::channel(QUEUE_SIZE);
+        let task = tokio::spawn(publish_loop(broker, rx));
+        let exporter = Self {
+            prefix: prefix.trim_end_matches('/').to_string(),
+            messages,
+            discovery: ha_discovery.then(Discovery::default),
+        };
+        (exporter, task)
+    }
+
+    fn messages(&self, addr: Address, data: &SensorData) -> Vec<(String, String)> {
+        let rssi = data.rssi.map_or("null".to_string(), |r| r.to_string());
+        let flags = if data.flags.is_empty() {
+            String::new()
+        } else {
+            format!(r#","flags":{}"#, serde_json::json!(data.flags))
+        };
+        fields(data)
+            .into_iter()
+            .map(|(name, value)| {
+                (
+                    format!("{}/{addr}/{name}", self.prefix),
+                    format!(r#"{{"value":{value},"address":"{addr}","rssi":{rssi}{flags}}}"#),
+                )
+            })
+            .collect()
+    }
+}
+
+/// The fields a reading has, with their values.
+fn fields(data: &SensorData) -> Vec<(&'static str, String)> {
+    // Values keep their own type's formatting, as for StatsD
+    fn field<T: ToString>(name: &'static str, value: Option<T>) -> Option<(&'static str, String)> {
+        Some((name, value?.to_string()))
+    }
+    let mut fields: Vec<(&str, String)> = [
+        field("temperature", data.temperature),
+        field("humidity", data.humidity),
+        field("battery", data.battery),
+        field("voltage", data.voltage),
+        field("dew_point", data.derived.and_then(|d| d.dew_point)),
+        field(
+            "absolute_humidity",
+            data.derived.map(|d| d.absolute_humidity),
+        ),
+        field(
+            "smoothed_temperature",
+            data.smoothed.and_then(|s| s.temperature),
+        ),
+        field("smoothed_humidity", data.smoothed.and_then(|s| s.humidity)),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    fields.extend(
+        data.measurements
+            .iter()
+            .map(|(name, value)| (*name, value.to_string())),
+    );
+    fields
+}
+
+impl Exporter for MqttExporter {
+    fn export(&self, reading: &Reading) {
+        let (addr, data) = (reading.address, &reading.data);
+        if let Some(discovery) = &self.discovery {
+            let names: Vec<_> = fields(data).into_iter().map(|(name, _)| name).collect();
+            let configs = discovery.announce(addr, data.name.as_deref(), &names, &self.prefix);
+            for (topic, payload) in configs {
+                let _ = self.messages.try_send(Message {
+                    topic,
+                    payload,
+                    retain: true,
+                });
+            }
+        }
+        for (topic, payload) in self.messages(addr, data) {
+            // Full queue: the broker has been away for a while, drop
+            let _ = self.messages.try_send(Message {
+                topic,
+                payload,
+                retain: false,
+            });
+        }
+    }
+}
+
+async fn publish_loop(broker: Broker, mut rx: mpsc::Receiver<Message>) {
+    let mut backoff = MIN_BACKOFF;
+    loop {
+        match connect(&broker).await {
+            Ok(stream) => {
+                status!("{} Connected to MQTT broker {}", Icon::Ok, broker.address);
+                backoff = MIN_BACKOFF;
+                match publish(stream, &mut rx).await {
+                    Ok(()) => return,
+                    Err(e) => warning!("{} MQTT connection lost: {e}", Icon::Warn),
+                }
+            }
+            Err(e) => warning!(
+                "{} MQTT broker {} unreachable: {e}, retrying in {}s",
+                Icon::Warn,
+                broker.address,
+                backoff.as_secs()
+            ),
+        }
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+async fn connect(broker: &Broker) -> io::Result<TcpStream> {
+    let handshake = async {
+        let mut stream = TcpStream::connect(&broker.address).await?;
+        stream.write_all(&connect_packet(broker)).await?;
+        let mut connack = [0; 4];
+        stream.read_exact(&mut connack).await?;
+        match connack {
+            [0x20, 0x02, _, 0] => Ok(stream),
+            [0x20, 0x02, _, code] => Err(io::Error::other(format!(
+                "connection refused (code {code})"
+            ))),
+            _ => Err(io::Error::other("unexpected reply to CONNECT")),
+        }
+    };
+    tokio::time::timeout(CONNECT_TIMEOUT, handshake)
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "no CONNACK"))?
+}
+
+/// Publish until the exporter is dropped (`Ok`) or the connection fails.
+async fn publish(stream: TcpStream, rx: &mut mpsc::Receiver<Message>) -> io::Result<()> {
+    let (mut reader, mut writer) = stream.into_split();
+    let mut ping = tokio::time::interval(Duration::from_secs(KEEP_ALIVE.into()));
+    ping.tick().await;
+    let mut incoming = [0; 256];
+
+    loop {
+        tokio::select! {
+            message = rx.recv() => match message {
+                Some(message) => {
+                    let packet = publish_packet(&message.topic, &message.payload, message.retain);
+                    writer.write_all(&packet).await?
+                }
+                None => {
+                    // DISCONNECT
+                    let _ = writer.write_all(&[0xE0, 0x00]).await;
+                    return Ok(());
+                }
+            },
+            _ = ping.tick() => writer.write_all(&[0xC0, 0x00]).await?,
+            // Only PINGRESPs are expected; reading just detects a closed connection
+            read = reader.read(&mut incoming) => if read? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            },
+        }
+    }
+}
+
+fn connect_packet(broker: &Broker) -> Vec<u8> {
+    let mut flags = 0x02; // clean session
+    let mut payload = Vec::new();
+    put_string(&mut payload, &format!("mitempr-{}", std::process::id()));
+    if let Some(user) = &broker.user {
+        flags |= 0x80;
+        put_string(&mut payload, user);
+        if let Some(pass) = &broker.pass {
+            flags |= 0x40;
+            put_string(&mut payload, pass);
+        }
+    }
+
+    let mut body = Vec::new();
+    put_string(&mut body, "MQTT");
+    body.push(4); // protocol level 3.1.1
+    body.push(flags);
+    body.extend(KEEP_ALIVE.to_be_bytes());
+    body.extend(payload);
+    packet(0x10, body)
+}
+
+fn publish_packet(topic: &str, payload: &str, retain: bool) -> Vec<u8> {
+    let mut body = Vec::new();
+    put_string(&mut body, topic);
+    body.extend(payload.as_bytes());
+    packet(if retain { 0x31 } else { 0x30 }, body)
+}
+
+fn packet(header: u8, body: Vec<u8>) -> Vec<u8> {
+    let mut packet = vec![header];
+    // Remaining length: 7 bits per byte, high bit set while more follow
+    let mut len = body.len();
+    loop {
+        let byte = (len % 128) as u8;
+        len /= 128;
+        packet.push(if len > 0 { byte | 0x80 } else { byte });
+        if len == 0 {
+            break;
+        }
+    }
+    packet.extend(body);
+    packet
+}
+
+fn put_string(buf: &mut Vec<u8>, s: &str) {
+    buf.extend((s.len() as u16).to_be_bytes());
+    buf.extend(s.as_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::Flag;
+    use tokio::net::TcpListener;
+
+    const ADDR: Address = Address::new([0xA4, 0xC1, 0x38, 0x00, 0x00, 0x01]);
+
+    fn broker(address: String) -> Broker {
+        Broker {
+            address,
+            user: Some("sensors".into()),
+            pass: Some("secret".into()),
+        }
+    }
+
+    #[test]
+    fn test_only_present_fields_with_rssi() {
+        let (tx, _rx) = mpsc::channel(1);
+        let exporter = MqttExporter {
+            prefix: "home".into(),
+            messages: tx,
+            discovery: None,
+        };
+        let data = SensorData {
+            temperature: Some(22.9),
+            rssi: Some(-60),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            exporter.messages(ADDR, &data),
+            [(
+                "home/A4:C1:38:00:00:01/temperature".to_string(),
+                r#"{"value":22.9,"address":"A4:C1:38:00:00:01","rssi":-60}"#.to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_flags_in_payload() {
+        let (tx, _rx) = mpsc::channel(1);
+        let exporter = MqttExporter {
+            prefix: "home".into(),
+            messages: tx,
+            discovery: None,
+        };
+        let data = SensorData {
+            battery: Some(50),
+            flags: [Flag::Estimated].into(),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            exporter.messages(ADDR, &data)[0].1,
+            r#"{"value":50,"address":"A4:C1:38:00:00:01","rssi":null,"flags":["estimated"]}"#
+        );
+    }
+
+    #[test]
+    fn test_remaining_length_spans_bytes() {
+        assert_eq!(packet(0x30, vec![0; 127])[..2], [0x30, 0x7F]);
+        assert_eq!(packet(0x30, vec![0; 321])[..3], [0x30, 0xC1, 0x02]);
+    }
+
+    #[test]
+    fn test_discovery_configs_are_retained_and_sent_once() {
+        let (tx, mut rx) = mpsc::channel(16);
+        let exporter = MqttExporter {
+            prefix: "home".into(),
+            messages: tx,
+            discovery: Some(Discovery::default()),
+        };
+        let data = SensorData {
+            temperature: Some(22.9),
+            ..Default::default()
+        };
+
+        exporter.export(&Reading::new(ADDR, data.clone()));
+        exporter.export(&Reading::new(ADDR, data));
+        let mut messages = Vec::new();
+        while let Ok(message) = rx.try_recv() {
+            messages.push((message.topic, message.retain));
+        }
+        assert_eq!(
+            messages,
+            [
+                (
+                    "homeassistant/sensor/a4c138000001_temperature/config".to_string(),
+                    true
+                ),
+                ("home/A4:C1:38:00:00:01/temperature".to_string(), false),
+                ("home/A4:C1:38:00:00:01/temperature".to_string(), false),
+            ]
+        );
+        assert_eq!(publish_packet("t", "", true)[0], 0x31);
+    }
+
+    #[tokio::test]
+    async fn test_publishes_to_broker() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let (exporter, _task) = MqttExporter::new(broker(address.clone()), "home/".into(), false);
+
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let expected = connect_packet(&broker(address));
+        let mut connect = vec![0; expected.len()];
+        socket.read_exact(&mut connect).await.unwrap();
+        assert_eq!(connect, expected);
+        socket.write_all(&[0x20, 0x02, 0x00, 0x00]).await.unwrap();
+
+        exporter.export(&Reading::new(
+            ADDR,
+            SensorData {
+                battery: Some(87),
+                ..Default::default()
+            },
+        ));
+        let expected = publish_packet(
+            "home/A4:C1:38:00:00:01/battery",
+            r#"{"value":87,"address":"A4:C1:38:00:00:01","rssi":null}"#,
+            false,
+        );
+        let mut publish = vec![0; expected.len()];
+        tokio::time::timeout(Duration::from_secs(5), socket.read_exact(&mut publish))
+            .await
+            .expect("no PUBLISH")
+            .unwrap();
+        assert_eq!(publish, expected);
+    }
+}
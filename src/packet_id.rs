@@ -0,0 +1,175 @@
+use bluer::Address;
+use std::collections::HashMap;
+
+/// Packet IDs below this after a backward jump mean the device restarted
+/// counting rather than a late, reordered frame.
+const REBOOT_MAX_ID: u8 = 8;
+
+/// Distinct stale IDs counting up among themselves that mean the device
+/// restarted, e.g. one that rebooted again before passing `REBOOT_MAX_ID`.
+const REBOOT_STALE_RUN: u8 = 3;
+
+/// How a BTHome packet ID relates to the previous one from the device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sequence {
+    /// First packet seen from the device
+    First,
+    /// Moved forward, including wrapping from 255 to 0
+    New,
+    /// Same ID as last time: the device repeated its advertisement
+    Duplicate,
+    /// Small step backwards: an old frame arriving late
+    Stale,
+    /// Large step backwards to a low ID, or a run of stale IDs counting up:
+    /// the device rebooted
+    ///
+    /// Only a step back is seen as one: IDs up to 127 ahead are
+    /// [`Sequence::New`], wrapping included. A device rebooting from an ID of
+    /// 129 or more to a low one looks exactly like it counted on past 255
+    /// while out of range (200 to 0 is 56 ahead), so that reboot goes
+    /// unreported.
+    Reboot { previous: u8 },
+}
+
+/// Tracks the last BTHome packet ID per device.
+#[derive(Default)]
+pub struct PacketIds {
+    last: HashMap<Address, u8>,
+    /// The newest stale ID since the last new one, and how many distinct
+    /// stale IDs led up to it
+    stale: HashMap<Address, (u8, u8)>,
+}
+
+impl PacketIds {
+    pub fn observe(&mut self, addr: Address, packet_id: u8) -> Sequence {
+        let Some(previous) = self.last.insert(addr, packet_id) else {
+            return Sequence::First;
+        };
+
+        let forward = packet_id.wrapping_sub(previous);
+        let sequence = match forward {
+            0 => Sequence::Duplicate,
+            1..=127 => Sequence::New,
+            _ if packet_id < REBOOT_MAX_ID && previous.wrapping_sub(packet_id) > REBOOT_MAX_ID => {
+                Sequence::Reboot { previous }
+            }
+            _ => Sequence::Stale,
+        };
+        if sequence != Sequence::Stale {
+            self.stale.remove(&addr);
+            return sequence;
+        }
+        let run = match self.stale.get(&addr) {
+            Some(&(id, run)) if id == packet_id => run,
+            Some(&(id, run)) if (1..=127).contains(&packet_id.wrapping_sub(id)) => run + 1,
+            _ => 1,
+        };
+        if run >= REBOOT_STALE_RUN {
+            self.stale.remove(&addr);
+            return Sequence::Reboot { previous };
+        }
+        self.stale.insert(addr, (packet_id, run));
+        // Keep comparing against the newest ID
+        self.last.insert(addr, previous);
+        Sequence::Stale
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDR: Address = Address::new([0xA4, 0xC1, 0x38, 0x00, 0x00, 0x01]);
+
+    fn sequence(ids: &[u8]) -> Vec<Sequence> {
+        let mut tracker = PacketIds::default();
+        ids.iter().map(|id| tracker.observe(ADDR, *id)).collect()
+    }
+
+    #[test]
+    fn test_increment_and_duplicates() {
+        assert_eq!(
+            sequence(&[10, 11, 11, 13]),
+            [
+                Sequence::First,
+                Sequence::New,
+                Sequence::Duplicate,
+                Sequence::New
+            ]
+        );
+    }
+
+    #[test]
+    fn test_wraparound_is_not_a_reboot() {
+        assert_eq!(
+            sequence(&[254, 255, 0, 1]),
+            [Sequence::First, Sequence::New, Sequence::New, Sequence::New]
+        );
+    }
+
+    #[test]
+    fn test_reset_to_zero_is_a_reboot() {
+        assert_eq!(
+            sequence(&[120, 0, 1]),
+            [
+                Sequence::First,
+                Sequence::Reboot { previous: 120 },
+                Sequence::New
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reboot_at_a_low_previous_id() {
+        // Rebooted again at 5: the repeated 0 doesn't count twice
+        assert_eq!(
+            sequence(&[5, 0, 0, 1, 2, 3]),
+            [
+                Sequence::First,
+                Sequence::Stale,
+                Sequence::Stale,
+                Sequence::Stale,
+                Sequence::Reboot { previous: 5 },
+                Sequence::New
+            ]
+        );
+        // Late frames that don't count up are no reboot
+        assert_eq!(
+            sequence(&[60, 58, 57, 59, 61])[1..],
+            [
+                Sequence::Stale,
+                Sequence::Stale,
+                Sequence::Stale,
+                Sequence::New
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reboot_from_a_high_id_looks_like_wrapping() {
+        // 128 to 0 is as far back as ahead, and counts as a reboot
+        assert_eq!(
+            sequence(&[128, 0]),
+            [Sequence::First, Sequence::Reboot { previous: 128 }]
+        );
+        // From 129 on, the restarted count is ahead: a known blind spot
+        assert_eq!(
+            sequence(&[129, 0, 1]),
+            [Sequence::First, Sequence::New, Sequence::New]
+        );
+        assert_eq!(sequence(&[200, 0])[1], Sequence::New);
+    }
+
+    #[test]
+    fn test_late_frame_is_stale() {
+        assert_eq!(
+            sequence(&[50, 52, 51, 53]),
+            [
+                Sequence::First,
+                Sequence::New,
+                Sequence::Stale,
+                Sequence::New
+            ]
+        );
+    }
+}
@@ -0,0 +1,1169 @@
+use crate::Args;
+use crate::battery::LowBattery;
+use crate::beacon;
+use crate::calibration::Calibration;
+use crate::clock::Clock;
+use crate::coalesce::Coalescer;
+use crate::decoder::{self, BindKeys, BlePacketType, DecodeError, Flag, SensorData, ServiceMap};
+use crate::derived::Derived;
+use crate::export::{DeviceEvent, Exporter, Reading};
+use crate::health::HealthTracker;
+use crate::icons::{Icon, error, status, warning};
+use crate::interval::IntervalTracker;
+use crate::packet_id::{PacketIds, Sequence};
+use crate::rate::RateTracker;
+use crate::rf::{self, RfTracker};
+use crate::smooth::Smoother;
+use crate::summary::Summary;
+use bluer::Address;
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// Everything that happens to an advertisement between receiving it and
+/// handing the reading to the exporter, plus the per-device state that
+/// needs.
+pub struct Pipeline {
+    args: Args,
+    clock: Arc<dyn Clock>,
+    exporter: Box<dyn Exporter + Send>,
+    coalescer: Option<Coalescer>,
+    rates: Option<RateTracker>,
+    smoother: Option<Smoother>,
+    intervals: IntervalTracker,
+    rf: RfTracker,
+    packet_ids: PacketIds,
+    /// Payload fingerprint of the last advertisement with a packet ID
+    last_payloads: HashMap<Address, u64>,
+    low_battery: LowBattery,
+    health: HealthTracker,
+    summary: Summary,
+    /// The service data formats to decode, with `--extra-uuid`
+    services: ServiceMap,
+    bindkeys: BindKeys,
+    calibration: HashMap<Address, Calibration>,
+    /// When each device's last reading was let through, for `--interval`
+    last_emitted: HashMap<Address, Instant>,
+    /// Readings are decoded but not exported before this
+    warmup_until: Instant,
+}
+
+impl Pipeline {
+    pub fn new(args: Args, exporter: Box<dyn Exporter + Send>, clock: Arc<dyn Clock>) -> Self {
+        let coalescer = args
+            .coalesce
+            .map(|ms| Coalescer::new(Duration::from_millis(ms)));
+        let rates = args
+            .rates
+            .then(|| RateTracker::new(Duration::from_secs(args.rate_min_spacing)));
+        let smoother = args.smooth.map(|n| Smoother::new(n.into()));
+        let low_battery = LowBattery::new(args.low_battery);
+        let stuck = args
+            .stuck_threshold
+            .map(|n| (n, Duration::from_secs(args.stuck_window)));
+        let health = HealthTracker::new(
+            Duration::from_secs(args.silent_after.unwrap_or(args.watchdog)),
+            stuck,
+        );
+        let services = args.services();
+        let bindkeys = args.bindkey.iter().map(|(a, key)| (a.0, *key)).collect();
+        let calibration = args.calibrate.iter().copied().collect();
+        let started = clock.now();
+        let warmup_until = started + Duration::from_secs(args.warmup);
+        Self {
+            args,
+            clock,
+            exporter,
+            coalescer,
+            rates,
+            smoother,
+            intervals: IntervalTracker::default(),
+            rf: RfTracker::default(),
+            packet_ids: PacketIds::default(),
+            last_payloads: HashMap::new(),
+            low_battery,
+            health,
+            summary: Summary::new(started),
+            services,
+            bindkeys,
+            calibration,
+            last_emitted: HashMap::new(),
+            warmup_until,
+        }
+    }
+
+    pub fn services(&self) -> &ServiceMap {
+        &self.services
+    }
+
+    /// How often [`Pipeline::tick`] needs to run to flush pending readings
+    /// in time.
+    pub fn tick_interval(&self) -> Duration {
+        match self.args.coalesce {
+            Some(ms) => Duration::from_millis(ms / 4)
+                .clamp(Duration::from_millis(50), Duration::from_secs(1)),
+            None => Duration::from_secs(1),
+        }
+    }
+
+    /// Decode one advertisement's service data and export the reading.
+    ///
+    /// Returns `true` if the data decoded, even if coalescing holds the
+    /// reading back for now.
+    pub fn process(
+        &mut self,
+        addr: Address,
+        name: Option<&str>,
+        rssi: Option<i16>,
+        data_map: &HashMap<Uuid, Vec<u8>>,
+    ) -> bool {
+        self.process_advertisement(addr, name, rssi, None, data_map, &HashMap::new())
+    }
+
+    /// Like [`Pipeline::process`], with the manufacturer data decoded as
+    /// well and merged into the reading; the service data wins fields both
+    /// have. `adapter` tags the reading with the adapter it was heard on.
+    pub fn process_advertisement(
+        &mut self,
+        addr: Address,
+        name: Option<&str>,
+        rssi: Option<i16>,
+        adapter: Option<&str>,
+        data_map: &HashMap<Uuid, Vec<u8>>,
+        manufacturer_data: &HashMap<u16, Vec<u8>>,
+    ) -> bool {
+        let now = self.clock.now();
+        self.rf.observe(addr.0, rssi, now);
+        if let Some(interval) = self.intervals.observe(addr, now) {
+            status!(
+                "{} {addr} advertises every ~{} ms",
+                Icon::Rx,
+                interval.as_millis()
+            );
+        }
+
+        let service_type = self.services.packet_type(data_map);
+        let manufacturer_type = decoder::manufacturer_packet_type(manufacturer_data);
+        // Service data isn't worth an "unknown" line when the manufacturer
+        // data is what the device speaks
+        let skip_service =
+            service_type == BlePacketType::Other && manufacturer_type != BlePacketType::Other;
+        let known =
+            service_type != BlePacketType::Other || manufacturer_type != BlePacketType::Other;
+        let limited = matches!(
+            (self.args.interval, self.last_emitted.get(&addr)),
+            (Some(secs), Some(last)) if now.duration_since(*last) < Duration::from_secs(secs)
+        );
+        // Only BTHome frames carry button presses, which aren't limited, so
+        // anything else within the interval isn't even decoded
+        if limited && service_type != BlePacketType::BTHome {
+            return self.skip_limited(addr, known, now);
+        }
+        let decoded = match self
+            .services
+            .decrypt_service_data(data_map, addr.0, &self.bindkeys)
+        {
+            Ok(_) if skip_service => None,
+            Ok(decrypted) => {
+                let mut decoded = self.decode(addr, decrypted.as_ref().unwrap_or(data_map));
+                if let (Some(decoded), Some(_)) = (&mut decoded, decrypted) {
+                    decoded.flags.insert(Flag::Decrypted);
+                }
+                decoded
+            }
+            Err(e) => {
+                warning!("  {} {addr}: {e}", Icon::Warn);
+                None
+            }
+        };
+        // Some sensors split their values between the two, so both are
+        // decoded and merged, the service data winning where both have a value
+        let from_manufacturer = (manufacturer_type != BlePacketType::Other)
+            .then(|| decoder::handle_manufacturer_data(manufacturer_data));
+        let decoded = match (decoded, from_manufacturer) {
+            (Some(from_service), Some(Ok(mut merged))) => {
+                merged.merge_from(from_service);
+                Some(merged)
+            }
+            (None, Some(Err(e))) => {
+                warning!(
+                    "  {} Could not decode {manufacturer_type:?} manufacturer data: {e}",
+                    Icon::Warn
+                );
+                None
+            }
+            (from_service, from_manufacturer) => {
+                from_service.or(from_manufacturer.and_then(Result::ok))
+            }
+        };
+        // Button presses are over once advertised, so they aren't limited
+        if limited && decoded.as_ref().is_none_or(|d| d.events.is_empty()) {
+            return self.skip_limited(addr, known, now);
+        }
+
+        // With --identity, rotating-address sensors are tracked and exported
+        // under the MAC in their payload
+        let device = match decoded.as_ref().and_then(|d| d.device_mac) {
+            Some(mac) if self.args.identity => Address::new(mac),
+            _ => addr,
+        };
+        let packet_type = if service_type == BlePacketType::Other {
+            manufacturer_type
+        } else {
+            service_type
+        };
+        let last = decoded.as_ref().map(|data| Reading {
+            address: device,
+            received_at: self.clock.system_now(),
+            data: SensorData {
+                name: name.map(str::to_string),
+                rssi,
+                ..data.clone()
+            },
+        });
+        self.summary.record(device, packet_type, last, now);
+
+        let Some(mut decoded) = decoded else {
+            if self.args.passthrough_unknown && packet_type == BlePacketType::Other {
+                let reading = SensorData {
+                    raw: passthrough(data_map),
+                    name: name.map(str::to_string),
+                    ..Default::default()
+                };
+                self.emit(addr, reading, now);
+            }
+            return false;
+        };
+        self.health.observe(device, now);
+        if device != addr {
+            decoded.ble_address = Some(addr.to_string());
+        }
+        decoded.name = name.map(str::to_string);
+        decoded.rssi = rssi;
+        if rssi.is_none() {
+            decoded.flags.insert(Flag::Cached);
+        }
+        decoded.adapter = adapter.map(str::to_string);
+
+        if let Some(packet_id) = decoded.packet_id {
+            let sequence = self.packet_ids.observe(device, packet_id);
+            // An older measurement arriving late. A button pressed back then
+            // was still pressed, so its events get through
+            if sequence == Sequence::Stale && decoded.events.is_empty() {
+                return true;
+            }
+            let payload = fingerprint(data_map, manufacturer_data);
+            let repeated = self.last_payloads.insert(device, payload) == Some(payload);
+            match sequence {
+                // The same advertisement broadcast again. Devices that never
+                // increment the ID send changed payloads and still get through
+                Sequence::Duplicate | Sequence::Stale if repeated => return true,
+                Sequence::Reboot { previous } if now >= self.warmup_until => {
+                    let event = DeviceEvent::Rebooted {
+                        previous_packet_id: previous,
+                        packet_id,
+                    };
+                    self.exporter.export_event(device, &event);
+                }
+                _ => {}
+            }
+        }
+
+        if let Some((readings, over)) =
+            self.health
+                .observe_values(device, (decoded.temperature, decoded.humidity), now)
+            && now >= self.warmup_until
+        {
+            let event = DeviceEvent::Stuck {
+                readings,
+                seconds: over.as_secs(),
+            };
+            self.exporter.export_event(device, &event);
+        }
+
+        // The decoder estimates voltage-only batteries as CR2032
+        if decoded.flags.contains(&Flag::Estimated)
+            && let Some(voltage) = decoded.voltage
+            && let Some((_, chemistry)) = self
+                .args
+                .battery_chemistry
+                .iter()
+                .find(|(a, _)| *a == device)
+        {
+            decoded.battery = Some(chemistry.percent(voltage));
+        }
+        // Before anything is derived from the values or exported
+        if let Some(calibration) = self.calibration.get(&device) {
+            calibration.apply(&mut decoded);
+        }
+
+        if let Some(percent) = decoded.battery
+            && self.low_battery.observe(device.0, percent)
+            && now >= self.warmup_until
+        {
+            let event = DeviceEvent::LowBattery {
+                percent,
+                threshold: self.low_battery.threshold(),
+            };
+            self.exporter.export_event(device, &event);
+        }
+
+        if self.args.rf_context {
+            decoded.rf_context = Some(self.rf.context());
+        }
+        if let (Some(tx_power), Some(rssi)) = (self.args.tx_power, rssi) {
+            decoded.distance_m = Some(rf::distance(rssi, tx_power));
+        }
+        self.last_emitted.insert(addr, now);
+
+        let ready = match &mut self.coalescer {
+            Some(coalescer) => coalescer.push(device, decoded, now),
+            None => vec![decoded],
+        };
+        for reading in ready {
+            self.finish(device, reading, now);
+        }
+        true
+    }
+
+    fn decode(&self, addr: Address, data_map: &HashMap<Uuid, Vec<u8>>) -> Option<SensorData> {
+        if self.args.strict {
+            self.services
+                .handle_service_data_strict(data_map)
+                .map_err(|e| error!("  {} Strict decode failed for {addr}: {e}", Icon::Error))
+                .ok()
+        } else {
+            match self.services.handle_service_data(data_map) {
+                Ok(decoded) => Some(decoded),
+                Err(DecodeError::UnknownService(_)) => {
+                    status!("  -> Unknown BLE packet");
+                    None
+                }
+                Err(e) => {
+                    let format = self.services.packet_type(data_map);
+                    warning!("  {} Could not decode {format:?} payload: {e}", Icon::Warn);
+                    None
+                }
+            }
+        }
+    }
+
+    /// Totals since startup, for the end-of-run report.
+    pub fn summary(&self) -> &Summary {
+        &self.summary
+    }
+
+    /// Handling an advertisement took `elapsed` from its reception, for the
+    /// end-of-run report.
+    pub fn observe_latency(&mut self, elapsed: Duration) {
+        self.summary.observe_latency(elapsed);
+    }
+
+    /// The `--status-interval` line about the time since the last one, and
+    /// whether any reading was decoded in it.
+    pub fn status(&mut self) -> (String, bool) {
+        let now = self.clock.now();
+        self.summary.status(now)
+    }
+
+    /// The time according to the pipeline's clock.
+    pub fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    /// Report an iBeacon/Eddystone-UID beacon found in the advertisement.
+    pub fn process_beacon(
+        &mut self,
+        addr: Address,
+        manufacturer_data: &HashMap<u16, Vec<u8>>,
+        service_data: &HashMap<Uuid, Vec<u8>>,
+    ) {
+        if !self.args.beacons || self.clock.now() < self.warmup_until {
+            return;
+        }
+        if let Some(beacon) = beacon::decode_beacon(manufacturer_data, service_data) {
+            self.exporter.export_beacon(addr, &beacon);
+        }
+    }
+
+    /// Drop an advertisement within `--interval` of the last reading.
+    fn skip_limited(&mut self, addr: Address, known: bool, now: Instant) -> bool {
+        // Still a sensor talking, as far as the watchdog is concerned
+        if known {
+            self.health.observe(addr, now);
+        }
+        known
+    }
+
+    /// Emit readings that have waited for their coalescing window to end,
+    /// and report sensors that went silent.
+    pub fn tick(&mut self) {
+        let now = self.clock.now();
+        for (addr, silent) in self.health.newly_silent(now) {
+            let event = DeviceEvent::Silent {
+                seconds: silent.as_secs(),
+            };
+            self.exporter.export_event(addr, &event);
+        }
+        if let Some(coalescer) = &mut self.coalescer {
+            for (addr, reading) in coalescer.flush_expired(now) {
+                self.finish(addr, reading, now);
+            }
+        }
+    }
+
+    /// Emit every reading still waiting for its coalescing window, for
+    /// shutdown.
+    pub fn flush(&mut self) {
+        let now = self.clock.now();
+        if let Some(coalescer) = &mut self.coalescer {
+            for (addr, reading) in coalescer.drain() {
+                self.finish(addr, reading, now);
+            }
+        }
+    }
+
+    /// Add what's computed from complete readings, then emit.
+    fn finish(&mut self, addr: Address, mut reading: SensorData, now: Instant) {
+        if self.args.derive {
+            reading.derived = derive(&reading);
+        }
+        if let Some(rates) = &mut self.rates {
+            reading.rates = rates.observe(addr.0, &reading, now);
+        }
+        if let Some(smoother) = &mut self.smoother {
+            reading.smoothed = smoother.observe(addr.0, &reading);
+            if reading.smoothed.is_some() {
+                reading.flags.insert(Flag::Smoothed);
+            }
+        }
+        self.emit(addr, reading, now);
+    }
+
+    fn emit(&self, addr: Address, data: SensorData, now: Instant) {
+        // BlueZ replays cached devices with stale data right after startup
+        if now >= self.warmup_until {
+            self.exporter.export(&Reading {
+                address: addr,
+                received_at: self.clock.system_now(),
+                data,
+            });
+        }
+    }
+}
+
+/// Hash of the service and manufacturer data, independent of the maps'
+/// order.
+fn fingerprint(
+    data_map: &HashMap<Uuid, Vec<u8>>,
+    manufacturer_data: &HashMap<u16, Vec<u8>>,
+) -> u64 {
+    let mut services: Vec<_> = data_map.iter().collect();
+    services.sort();
+    let mut manufacturers: Vec<_> = manufacturer_data.iter().collect();
+    manufacturers.sort();
+    let mut hasher = DefaultHasher::new();
+    services.hash(&mut hasher);
+    manufacturers.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Service data as sorted `<uuid>:<hex>` entries, the `--decode-only`
+/// input format.
+fn passthrough(data_map: &HashMap<Uuid, Vec<u8>>) -> Vec<String> {
+    let mut raw: Vec<String> = data_map
+        .iter()
+        .map(|(uuid, bytes)| format!("{uuid}:{}", hex::encode(bytes)))
+        .collect();
+    raw.sort();
+    raw
+}
+
+fn derive(data: &SensorData) -> Option<Derived> {
+    Some(Derived::compute(data.temperature?, data.humidity?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::decoder::{BtHomeEvent, ButtonPress};
+    use crate::export::MemoryExporter;
+    use crate::units::Units;
+    use clap::Parser;
+    use std::collections::{BTreeMap, BTreeSet};
+    use uuid::uuid;
+
+    const ADDR: Address = Address::new([0xA4, 0xC1, 0x38, 0x00, 0x00, 0x01]);
+    const BTHOME: Uuid = uuid!("0000fcd2-0000-1000-8000-00805f9b34fb");
+
+    fn pipeline(extra: &[&str]) -> (Pipeline, MemoryExporter) {
+        let (pipeline, exporter, _) = clocked(extra);
+        (pipeline, exporter)
+    }
+
+    /// A pipeline whose time only moves with the returned clock.
+    fn clocked(extra: &[&str]) -> (Pipeline, MemoryExporter, MockClock) {
+        let args = Args::parse_from(std::iter::once("mitempr").chain(extra.iter().copied()));
+        let exporter = MemoryExporter::default();
+        let clock = MockClock::new();
+        let pipeline = Pipeline::new(args, Box::new(exporter.clone()), Arc::new(clock.clone()));
+        (pipeline, exporter, clock)
+    }
+
+    fn bthome(payload: &[u8]) -> HashMap<Uuid, Vec<u8>> {
+        HashMap::from([(BTHOME, payload.to_vec())])
+    }
+
+    fn pvvx_frame() -> HashMap<Uuid, Vec<u8>> {
+        HashMap::from([(
+            uuid!("0000181A-0000-1000-8000-00805F9B34FB"),
+            vec![
+                0x03, 0x7B, 0xA0, 0x38, 0xC1, 0xA4, 0xF2, 0x08, 0x19, 0x19, 0x1D, 0x09, 0x10, 0x4A,
+                0x05,
+            ],
+        )])
+    }
+
+    #[test]
+    fn test_pipeline_exports_decoded_reading() {
+        let (mut pipeline, exporter) = pipeline(&[]);
+
+        assert!(pipeline.process(ADDR, None, None, &pvvx_frame()));
+
+        exporter.assert_count(1);
+        exporter.assert_last(
+            ADDR,
+            &SensorData {
+                temperature: Some(22.9),
+                humidity: Some(64.25),
+                battery: Some(16),
+                voltage: Some(2.333),
+                measurements: BTreeMap::from([
+                    ("reed_switch", 1.0),
+                    ("gpio_trigger_output", 0.0),
+                    ("temperature_trigger", 0.0),
+                    ("humidity_trigger", 0.0),
+                ]),
+                device_mac: Some([0xA4, 0xC1, 0x38, 0xA0, 0x7B, 0x03]),
+                // Without an RSSI
+                flags: BTreeSet::from([Flag::Cached]),
+                ..Default::default()
+            },
+        );
+    }
+
+    fn xiaomi_manufacturer_data() -> HashMap<u16, Vec<u8>> {
+        // MiBeacon frame with temperature 23.4 °C and humidity 60.9 %
+        HashMap::from([(
+            decoder::XIAOMI_COMPANY_ID,
+            vec![
+                0x50, 0x20, 0xAA, 0x01, 0xF5, 0x40, 0x71, 0xD5, 0xA8, 0x65, 0x4C, 0x0D, 0x10, 0x04,
+                0xEA, 0x00, 0x61, 0x02,
+            ],
+        )])
+    }
+
+    #[test]
+    fn test_manufacturer_data_fallback() {
+        let (mut pipeline, exporter) = pipeline(&[]);
+
+        assert!(pipeline.process_advertisement(
+            ADDR,
+            None,
+            None,
+            None,
+            &HashMap::new(),
+            &xiaomi_manufacturer_data()
+        ));
+        let readings = exporter.readings();
+        assert_eq!(readings[0].1.temperature, Some(23.4));
+        assert_eq!(readings[0].1.humidity, Some(60.9));
+        let report = pipeline.summary().render(pipeline.now(), Units::Metric);
+        assert!(report.contains("  Decoded: Mijia 1\n"));
+    }
+
+    #[test]
+    fn test_service_data_preferred_over_manufacturer_data() {
+        let (mut pipeline, exporter) = pipeline(&[]);
+
+        assert!(pipeline.process_advertisement(
+            ADDR,
+            None,
+            None,
+            None,
+            &pvvx_frame(),
+            &xiaomi_manufacturer_data()
+        ));
+        exporter.assert_count(1);
+        let reading = &exporter.readings()[0].1;
+        assert_eq!(reading.temperature, Some(22.9));
+        // Only the manufacturer data has a MiBeacon header
+        assert_eq!(reading.mijia.unwrap().product_id, 0x01AA);
+    }
+
+    #[test]
+    fn test_service_and_manufacturer_data_merged() {
+        let (mut pipeline, exporter) = pipeline(&[]);
+
+        // Battery in the service data, the rest in the manufacturer data
+        assert!(pipeline.process_advertisement(
+            ADDR,
+            None,
+            None,
+            None,
+            &bthome(&[0x40, 0x01, 0x57]),
+            &xiaomi_manufacturer_data()
+        ));
+        exporter.assert_count(1);
+        let reading = &exporter.readings()[0].1;
+        assert_eq!(reading.battery, Some(87));
+        assert_eq!(reading.temperature, Some(23.4));
+        assert_eq!(reading.humidity, Some(60.9));
+    }
+
+    #[test]
+    fn test_reading_tagged_with_adapter() {
+        let (mut pipeline, exporter) = pipeline(&[]);
+
+        assert!(pipeline.process_advertisement(
+            ADDR,
+            None,
+            None,
+            Some("hci1"),
+            &pvvx_frame(),
+            &HashMap::new()
+        ));
+        assert!(pipeline.process(ADDR, None, None, &pvvx_frame()));
+        let readings = exporter.readings();
+        assert_eq!(readings[0].1.adapter.as_deref(), Some("hci1"));
+        assert_eq!(readings[1].1.adapter, None);
+    }
+
+    #[test]
+    fn test_interval_limits_readings_per_device() {
+        let (mut pipeline, exporter, clock) = clocked(&["--interval", "10"]);
+        let other = Address::new([0xA4, 0xC1, 0x38, 0x00, 0x00, 0x02]);
+
+        assert!(pipeline.process(ADDR, None, None, &pvvx_frame()));
+        clock.advance(Duration::from_secs(5));
+        assert!(pipeline.process(ADDR, None, None, &pvvx_frame()));
+        assert!(pipeline.process(other, None, None, &pvvx_frame()));
+        exporter.assert_count(2);
+
+        clock.advance(Duration::from_secs(5));
+        assert!(pipeline.process(ADDR, None, None, &pvvx_frame()));
+        exporter.assert_count(3);
+        // The dropped advertisement isn't counted
+        let headline = pipeline.summary().headline(pipeline.now());
+        assert!(headline.starts_with("Summary: 3 advertisements,"));
+    }
+
+    #[test]
+    fn test_button_events_pass_interval_but_not_retransmissions() {
+        let (mut pipeline, exporter, clock) = clocked(&["--interval", "60"]);
+        let press = |packet_id| bthome(&[0x44, 0x00, packet_id, 0x3A, 0x01]);
+
+        assert!(pipeline.process(ADDR, None, None, &press(1)));
+        clock.advance(Duration::from_secs(1));
+        // The device repeats each event advertisement a few times
+        assert!(pipeline.process(ADDR, None, None, &press(1)));
+        assert!(pipeline.process(ADDR, None, None, &press(2)));
+        exporter.assert_count(2);
+        // A plain measurement is still limited
+        let temperature = bthome(&[0x40, 0x00, 3, 0x02, 0xCA, 0x09]);
+        assert!(pipeline.process(ADDR, None, None, &temperature));
+        exporter.assert_count(2);
+        let events: Vec<_> = exporter
+            .readings()
+            .into_iter()
+            .flat_map(|(_, data)| data.events)
+            .collect();
+        assert_eq!(
+            events,
+            [BtHomeEvent::Button {
+                button: 0,
+                press: ButtonPress::Press
+            }; 2]
+        );
+    }
+
+    #[test]
+    fn test_late_button_press_still_exported() {
+        let (mut pipeline, exporter) = pipeline(&[]);
+        let press = |packet_id| bthome(&[0x44, 0x00, packet_id, 0x3A, 0x01]);
+        let temperature = |packet_id| bthome(&[0x40, 0x00, packet_id, 0x02, 0xCA, 0x09]);
+
+        pipeline.process(ADDR, None, None, &temperature(10));
+        // Overtaken by the measurement after it
+        assert!(pipeline.process(ADDR, None, None, &press(9)));
+        assert!(pipeline.process(ADDR, None, None, &press(9)));
+        // A late measurement is still dropped
+        pipeline.process(ADDR, None, None, &temperature(8));
+        exporter.assert_count(2);
+        assert_eq!(
+            exporter.readings()[1].1.events,
+            [BtHomeEvent::Button {
+                button: 0,
+                press: ButtonPress::Press
+            }]
+        );
+    }
+
+    #[test]
+    fn test_pipeline_skips_unknown_service() {
+        let data = HashMap::from([(uuid!("0000feaa-0000-1000-8000-00805f9b34fb"), vec![0x00])]);
+
+        for flags in [&[][..], &["--strict"][..]] {
+            let (mut pipeline, exporter) = pipeline(flags);
+            assert!(!pipeline.process(ADDR, None, None, &data));
+            exporter.assert_count(0);
+        }
+    }
+
+    #[test]
+    fn test_battery_estimated_from_voltage_per_chemistry() {
+        // BTHome frame with only a voltage object: 2.8 V
+        let data = bthome(&[0x40, 0x0C, 0xF0, 0x0A]);
+        let other = Address::new([0xA4, 0xC1, 0x38, 0x00, 0x00, 0x02]);
+        let (mut pipeline, exporter) =
+            pipeline(&["--battery-chemistry", "a4:c1:38:00:00:02=3.0:100,2.6:0"]);
+
+        pipeline.process(ADDR, None, None, &data);
+        pipeline.process(other, None, None, &data);
+
+        let readings = exporter.readings();
+        assert_eq!(readings[0].1.battery, Some(60)); // CR2032 default
+        assert_eq!(readings[1].1.battery, Some(50));
+        assert!(readings[1].1.flags.contains(&Flag::Estimated));
+    }
+
+    #[test]
+    fn test_coalesce_merges_split_frames() {
+        let (mut pipeline, exporter, clock) = clocked(&["--coalesce", "2000"]);
+
+        // Temperature and battery first, humidity 1.5 s later
+        pipeline.process(
+            ADDR,
+            None,
+            None,
+            &bthome(&[0x40, 0x02, 0xCA, 0x09, 0x01, 0x64]),
+        );
+        exporter.assert_count(0);
+        clock.advance(Duration::from_millis(1500));
+        pipeline.process(ADDR, None, None, &bthome(&[0x40, 0x03, 0xBF, 0x13]));
+
+        exporter.assert_count(1);
+        exporter.assert_last(
+            ADDR,
+            &SensorData {
+                temperature: Some(25.06),
+                humidity: Some(50.55),
+                battery: Some(100),
+                flags: BTreeSet::from([Flag::Cached]),
+                ..Default::default()
+            },
+        );
+    }
+
+    #[test]
+    fn test_coalesce_flushes_partial_reading_on_timeout() {
+        let (mut pipeline, exporter, clock) = clocked(&["--coalesce", "2000"]);
+
+        pipeline.process(ADDR, None, None, &bthome(&[0x40, 0x02, 0xCA, 0x09]));
+        clock.advance(Duration::from_millis(1999));
+        pipeline.tick();
+        exporter.assert_count(0);
+
+        clock.advance(Duration::from_millis(1));
+        pipeline.tick();
+        exporter.assert_count(1);
+        assert_eq!(exporter.readings()[0].1.humidity, None);
+    }
+
+    #[test]
+    fn test_flush_emits_pending_readings() {
+        let (mut pipeline, exporter) = pipeline(&["--coalesce", "2000"]);
+
+        pipeline.process(ADDR, None, None, &bthome(&[0x40, 0x02, 0xCA, 0x09]));
+        exporter.assert_count(0);
+
+        pipeline.flush();
+        exporter.assert_count(1);
+        assert_eq!(exporter.readings()[0].1.temperature, Some(25.06));
+    }
+
+    #[test]
+    fn test_coalesce_does_not_merge_across_windows() {
+        let (mut pipeline, exporter, clock) = clocked(&["--coalesce", "1000"]);
+
+        pipeline.process(ADDR, None, None, &bthome(&[0x40, 0x02, 0xCA, 0x09]));
+        clock.advance(Duration::from_secs(5));
+        pipeline.process(ADDR, None, None, &bthome(&[0x40, 0x03, 0xBF, 0x13]));
+
+        // The stale temperature-only reading goes out alone; humidity waits
+        exporter.assert_count(1);
+        assert_eq!(exporter.readings()[0].1.humidity, None);
+    }
+
+    #[test]
+    fn test_warmup_suppresses_export_but_still_decodes() {
+        let (mut pipeline, exporter, clock) = clocked(&["--warmup", "5"]);
+
+        clock.advance(Duration::from_secs(1));
+        assert!(pipeline.process(ADDR, None, None, &pvvx_frame()));
+        exporter.assert_count(0);
+
+        clock.advance(Duration::from_secs(5));
+        assert!(pipeline.process(ADDR, None, None, &pvvx_frame()));
+        exporter.assert_count(1);
+    }
+
+    #[test]
+    fn test_derive_only_when_enabled() {
+        let (mut plain, plain_exporter) = pipeline(&[]);
+        let (mut derived, derived_exporter) = pipeline(&["--derive"]);
+
+        plain.process(ADDR, None, None, &pvvx_frame());
+        derived.process(ADDR, None, None, &pvvx_frame());
+
+        assert_eq!(plain_exporter.readings()[0].1.derived, None);
+        let values = derived_exporter.readings()[0].1.derived.unwrap();
+        assert!((values.dew_point.unwrap() - 15.8).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_reading_carries_address_and_time() {
+        let (mut pipeline, exporter, clock) = clocked(&[]);
+        clock.advance(Duration::from_secs(5));
+
+        pipeline.process(ADDR, None, None, &pvvx_frame());
+
+        let reading = &exporter.received()[0];
+        assert_eq!(reading.address, ADDR);
+        assert_eq!(reading.received_at, clock.system_now());
+    }
+
+    #[test]
+    fn test_derive_needs_temperature_and_humidity() {
+        let (mut pipeline, exporter) = pipeline(&["--derive"]);
+
+        // Temperature and battery, no humidity
+        pipeline.process(
+            ADDR,
+            None,
+            None,
+            &bthome(&[0x40, 0x02, 0xCA, 0x09, 0x01, 0x64]),
+        );
+
+        let reading = &exporter.readings()[0].1;
+        assert!(reading.temperature.is_some());
+        assert_eq!(reading.derived, None);
+    }
+
+    #[test]
+    fn test_smooth_keeps_raw_values() {
+        let (mut pipeline, exporter) = pipeline(&["--smooth", "4"]);
+
+        pipeline.process(ADDR, None, None, &bthome(&[0x40, 0x02, 0xCA, 0x09]));
+        pipeline.process(ADDR, None, None, &bthome(&[0x40, 0x02, 0xF2, 0x09]));
+
+        let reading = &exporter.readings()[1].1;
+        assert_eq!(reading.temperature, Some(25.46));
+        assert_eq!(reading.smoothed.unwrap().temperature, Some(25.26));
+    }
+
+    #[test]
+    fn test_rf_context_only_when_enabled() {
+        let other = Address::new([0xA4, 0xC1, 0x38, 0x00, 0x00, 0x02]);
+        let (mut with_context, exporter) = pipeline(&["--rf-context"]);
+
+        with_context.process(other, None, Some(-88), &pvvx_frame());
+        with_context.process(ADDR, None, Some(-52), &pvvx_frame());
+
+        let context = exporter.readings()[1].1.rf_context.unwrap();
+        assert_eq!(context.tracked_devices, 2);
+        assert_eq!(context.weakest_rssi, Some(-88));
+        assert_eq!(context.strongest_rssi, Some(-52));
+
+        let (mut plain, plain_exporter) = pipeline(&[]);
+        plain.process(ADDR, None, Some(-52), &pvvx_frame());
+        assert_eq!(plain_exporter.readings()[0].1.rf_context, None);
+    }
+
+    #[test]
+    fn test_summary_counts_every_advertisement() {
+        let (mut pipeline, _) = pipeline(&["--warmup", "60"]);
+        let unknown = HashMap::from([(uuid!("0000feaa-0000-1000-8000-00805f9b34fb"), vec![0x00])]);
+
+        pipeline.process(ADDR, None, None, &pvvx_frame());
+        pipeline.process(ADDR, None, None, &unknown);
+
+        let report = pipeline.summary().render(pipeline.now(), Units::Metric);
+        assert!(report.starts_with("Summary: 2 advertisements, 1 devices"));
+        assert!(report.contains("Decoded: Pvvx 1"));
+        assert!(report.contains("Errors: unknown service 1"));
+    }
+
+    #[test]
+    fn test_identity_merges_rotating_addresses() {
+        let rotated = Address::new([0x5E, 0x11, 0x22, 0x33, 0x44, 0x55]);
+        let logical = Address::new([0xA4, 0xC1, 0x38, 0xA0, 0x7B, 0x03]);
+        let (mut plain, plain_exporter) = pipeline(&[]);
+        let (mut resolved, resolved_exporter) = pipeline(&["--identity"]);
+
+        for pipeline in [&mut plain, &mut resolved] {
+            pipeline.process(ADDR, None, None, &pvvx_frame());
+            pipeline.process(rotated, None, None, &pvvx_frame());
+        }
+
+        let addrs: Vec<_> = plain_exporter.readings().iter().map(|r| r.0).collect();
+        assert_eq!(addrs, [ADDR, rotated]);
+        assert_eq!(plain_exporter.readings()[0].1.ble_address, None);
+
+        let readings = resolved_exporter.readings();
+        assert!(readings.iter().all(|(addr, _)| *addr == logical));
+        assert_eq!(
+            readings[1].1.ble_address.as_deref(),
+            Some("5E:11:22:33:44:55")
+        );
+    }
+
+    #[test]
+    fn test_passthrough_unknown_service_data() {
+        let data = HashMap::from([(
+            uuid!("0000feaa-0000-1000-8000-00805f9b34fb"),
+            vec![0x10, 0xF4],
+        )]);
+        let (mut plain, plain_exporter) = pipeline(&[]);
+        let (mut passthrough, exporter) = pipeline(&["--passthrough-unknown"]);
+
+        assert!(!plain.process(ADDR, None, None, &data));
+        assert!(!passthrough.process(ADDR, Some("Tag"), None, &data));
+
+        plain_exporter.assert_count(0);
+        exporter.assert_last(
+            ADDR,
+            &SensorData {
+                raw: vec!["0000feaa-0000-1000-8000-00805f9b34fb:10f4".into()],
+                name: Some("Tag".into()),
+                ..Default::default()
+            },
+        );
+    }
+
+    #[test]
+    fn test_rates_only_when_enabled() {
+        let (mut plain, plain_exporter, plain_clock) = clocked(&[]);
+        let (mut rates, exporter, clock) = clocked(&["--rates"]);
+
+        for (pipeline, clock) in [(&mut plain, plain_clock), (&mut rates, clock)] {
+            pipeline.process(ADDR, None, None, &bthome(&[0x40, 0x02, 0xCA, 0x09]));
+            clock.advance(Duration::from_secs(30));
+            pipeline.process(ADDR, None, None, &bthome(&[0x40, 0x02, 0x92, 0x09]));
+        }
+
+        assert_eq!(plain_exporter.readings()[1].1.rates, None);
+        assert_eq!(exporter.readings()[0].1.rates, None);
+        let rate = exporter.readings()[1].1.rates.unwrap();
+        assert!((rate.temperature_delta_per_min.unwrap() + 1.12).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_reboot_event_and_reading_after_packet_id_reset() {
+        let (mut pipeline, exporter) = pipeline(&[]);
+        let frame = |packet_id| bthome(&[0x40, 0x00, packet_id, 0x02, 0xCA, 0x09]);
+
+        pipeline.process(ADDR, None, None, &frame(120));
+        pipeline.process(ADDR, None, None, &frame(121));
+        pipeline.process(ADDR, None, None, &frame(0));
+
+        exporter.assert_count(3);
+        assert_eq!(
+            exporter.events(),
+            [(
+                ADDR,
+                DeviceEvent::Rebooted {
+                    previous_packet_id: 121,
+                    packet_id: 0
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn test_repeated_packet_id_skipped() {
+        let (mut pipeline, exporter) = pipeline(&[]);
+        let frame = |packet_id, temp| bthome(&[0x40, 0x00, packet_id, 0x02, temp, 0x09]);
+
+        assert!(pipeline.process(ADDR, None, None, &frame(254, 0xCA)));
+        assert!(pipeline.process(ADDR, None, None, &frame(254, 0xCA)));
+        exporter.assert_count(1);
+
+        // Wrapped around
+        pipeline.process(ADDR, None, None, &frame(255, 0xCA));
+        pipeline.process(ADDR, None, None, &frame(0, 0xCA));
+        // Arrived late
+        pipeline.process(ADDR, None, None, &frame(253, 0xCA));
+        exporter.assert_count(3);
+
+        // Never incremented, but the measurement changed
+        pipeline.process(ADDR, None, None, &frame(0, 0xD4));
+        exporter.assert_count(4);
+        assert!(exporter.events().is_empty());
+    }
+
+    #[test]
+    fn test_single_silent_sensor_reported() {
+        let (mut pipeline, exporter, clock) = clocked(&["--silent-after", "60"]);
+        let other = Address::new([0xA4, 0xC1, 0x38, 0x00, 0x00, 0x02]);
+
+        pipeline.process(ADDR, None, None, &pvvx_frame());
+        for _ in 0..7 {
+            clock.advance(Duration::from_secs(10));
+            pipeline.process(other, None, None, &pvvx_frame());
+            pipeline.tick();
+        }
+        pipeline.tick();
+        assert_eq!(
+            exporter.events(),
+            [(ADDR, DeviceEvent::Silent { seconds: 70 })]
+        );
+    }
+
+    #[test]
+    fn test_stuck_values_reported_once() {
+        let (mut pipeline, exporter, clock) =
+            clocked(&["--stuck-threshold", "3", "--stuck-window", "60"]);
+        let frame = |packet_id| bthome(&[0x40, 0x00, packet_id, 0x02, 0xCA, 0x09]);
+
+        for packet_id in 0..6 {
+            // Retransmissions don't count as readings
+            pipeline.process(ADDR, None, None, &frame(packet_id));
+            pipeline.process(ADDR, None, None, &frame(packet_id));
+            clock.advance(Duration::from_secs(15));
+        }
+        assert_eq!(
+            exporter.events(),
+            [(
+                ADDR,
+                DeviceEvent::Stuck {
+                    readings: 4,
+                    seconds: 60
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn test_smoothed_and_cached_flags() {
+        let (mut smoothing, exporter) = pipeline(&["--smooth", "3"]);
+        let frame = |packet_id| bthome(&[0x40, 0x00, packet_id, 0x02, 0xCA, 0x09]);
+
+        smoothing.process(ADDR, None, Some(-60), &frame(1));
+        smoothing.process(ADDR, None, None, &frame(2));
+
+        let readings = exporter.readings();
+        assert_eq!(readings[0].1.flags, BTreeSet::from([Flag::Smoothed]));
+        assert_eq!(
+            readings[1].1.flags,
+            BTreeSet::from([Flag::Smoothed, Flag::Cached])
+        );
+
+        let (mut pipeline, exporter) = pipeline(&[]);
+        pipeline.process(ADDR, None, Some(-60), &frame(1));
+        assert!(exporter.readings()[0].1.flags.is_empty());
+    }
+
+    #[test]
+    fn test_calibration_applies_before_derived_values() {
+        let other = Address::new([0xA4, 0xC1, 0x38, 0x00, 0x00, 0x02]);
+        let (mut pipeline, exporter) =
+            pipeline(&["--derive", "--calibrate", "A4:C1:38:00:00:01=-0.5,2"]);
+        // 25.06 °C, 50.55 %
+        let frame = bthome(&[0x40, 0x02, 0xCA, 0x09, 0x03, 0xBF, 0x13]);
+
+        pipeline.process(ADDR, None, None, &frame);
+        pipeline.process(other, None, Some(-60), &frame);
+
+        let calibrated = &exporter.readings()[0].1;
+        assert_eq!(calibrated.temperature, Some(24.56));
+        assert_eq!(calibrated.humidity, Some(52.55));
+        assert!(calibrated.flags.contains(&Flag::Calibrated));
+        assert_eq!(calibrated.derived, Some(Derived::compute(24.56, 52.55)));
+        let untouched = &exporter.readings()[1].1;
+        assert_eq!(untouched.temperature, Some(25.06));
+        assert!(untouched.flags.is_empty());
+    }
+
+    #[test]
+    fn test_low_battery_event_once() {
+        let (mut pipeline, exporter) = pipeline(&["--low-battery", "20"]);
+        let frame = |battery| bthome(&[0x40, 0x01, battery]);
+
+        for battery in [25, 20, 18, 80, 10] {
+            pipeline.process(ADDR, None, None, &frame(battery));
+        }
+
+        let low = |percent| {
+            (
+                ADDR,
+                DeviceEvent::LowBattery {
+                    percent,
+                    threshold: 20,
+                },
+            )
+        };
+        assert_eq!(exporter.events(), [low(20), low(10)]);
+    }
+
+    #[test]
+    fn test_bindkey_decrypts_bthome() {
+        const KEY: &str = "231d39c1d7cc1ab1aee224cd096db932";
+        let key: crate::crypto::BindKey = KEY.parse().unwrap();
+        let counter = [0x01, 0x00, 0x00, 0x00];
+        let mut nonce = ADDR.0.to_vec();
+        nonce.extend([0xD2, 0xFC, 0x41]);
+        nonce.extend(counter);
+        let (ciphertext, mic) =
+            crate::crypto::ccm_encrypt(&key, &nonce, &[], &[0x02, 0xCA, 0x09], 4);
+        let frame = bthome(&[&[0x41][..], &ciphertext, &counter, &mic].concat());
+
+        let (mut locked, exporter) = pipeline(&[]);
+        assert!(!locked.process(ADDR, None, None, &frame));
+        exporter.assert_count(0);
+
+        let bindkey = format!("{ADDR}={KEY}");
+        let (mut pipeline, exporter) = pipeline(&["--bindkey", &bindkey]);
+        assert!(pipeline.process(ADDR, None, None, &frame));
+        let (_, reading) = exporter.readings().pop().unwrap();
+        assert_eq!(reading.temperature, Some(25.06));
+        assert!(reading.flags.contains(&Flag::Decrypted));
+    }
+
+    #[test]
+    fn test_bindkey_decrypts_mibeacon() {
+        // Captured from an XMWSDJ04MMC, whose frames carry no MAC
+        let addr = Address::new([0x2C, 0x11, 0x65, 0x25, 0x70, 0x04]);
+        let frame = HashMap::from([(
+            uuid!("0000fe95-0000-1000-8000-00805f9b34fb"),
+            vec![
+                0x48, 0x59, 0x03, 0x12, 0xA4, 0x1B, 0x77, 0x6E, 0x7C, 0x96, 0xAD, 0xD7, 0x00, 0x00,
+                0x00, 0xF2, 0xBF, 0x54, 0x5B,
+            ],
+        )]);
+
+        let (mut locked, exporter) = pipeline(&[]);
+        assert!(!locked.process(addr, None, None, &frame));
+        exporter.assert_count(0);
+
+        let bindkey = format!("{addr}=b2cf9a553d53571b5657defd582d676e");
+        let (mut pipeline, exporter) = pipeline(&["--bindkey", &bindkey]);
+        assert!(pipeline.process(addr, None, None, &frame));
+        let (_, reading) = exporter.readings().pop().unwrap();
+        assert_eq!(reading.mijia.unwrap().product_id, 0x1203);
+        assert!(reading.flags.contains(&Flag::Decrypted));
+    }
+}
@@ -0,0 +1,134 @@
+use crate::decoder::{Mac, SensorData};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Change per minute since an earlier reading of the same device.
+#[derive(Debug, Clone, Copy, PartialEq, Default, serde::Serialize)]
+pub struct Rates {
+    pub temperature_delta_per_min: Option<f32>,
+    pub humidity_delta_per_min: Option<f32>,
+}
+
+/// Computes per-device rates of change between readings.
+///
+/// Readings closer than `min_spacing` to the previous sample don't replace
+/// it, so bursts of advertisements don't divide by a few milliseconds.
+pub struct RateTracker {
+    min_spacing: Duration,
+    devices: HashMap<Mac, Samples>,
+}
+
+#[derive(Default)]
+struct Samples {
+    temperature: Option<(Instant, f32)>,
+    humidity: Option<(Instant, f32)>,
+}
+
+impl RateTracker {
+    pub fn new(min_spacing: Duration) -> Self {
+        Self {
+            min_spacing,
+            devices: HashMap::new(),
+        }
+    }
+
+    /// Record `data` from `addr`; returns the rates once there is history
+    /// for at least one field.
+    pub fn observe(&mut self, addr: Mac, data: &SensorData, now: Instant) -> Option<Rates> {
+        let samples = self.devices.entry(addr).or_default();
+        let rates = Rates {
+            temperature_delta_per_min: rate(
+                &mut samples.temperature,
+                data.temperature,
+                now,
+                self.min_spacing,
+            ),
+            humidity_delta_per_min: rate(
+                &mut samples.humidity,
+                data.humidity,
+                now,
+                self.min_spacing,
+            ),
+        };
+        (rates != Rates::default()).then_some(rates)
+    }
+}
+
+fn rate(
+    sample: &mut Option<(Instant, f32)>,
+    value: Option<f32>,
+    now: Instant,
+    min_spacing: Duration,
+) -> Option<f32> {
+    let value = value?;
+    match *sample {
+        Some((then, _)) if now.saturating_duration_since(then) < min_spacing => None,
+        Some((then, previous)) => {
+            *sample = Some((now, value));
+            let minutes = now.duration_since(then).as_secs_f32() / 60.0;
+            Some((value - previous) / minutes)
+        }
+        None => {
+            *sample = Some((now, value));
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDR: Mac = [0xA4, 0xC1, 0x38, 0x00, 0x00, 0x01];
+
+    fn reading(temperature: f32, humidity: Option<f32>) -> SensorData {
+        SensorData {
+            temperature: Some(temperature),
+            humidity,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_rates_per_minute() {
+        let t0 = Instant::now();
+        let mut rates = RateTracker::new(Duration::from_secs(10));
+
+        assert_eq!(rates.observe(ADDR, &reading(21.0, Some(40.0)), t0), None);
+        // Door opened: -3 °C and +6 % in 30 s
+        let after = rates
+            .observe(
+                ADDR,
+                &reading(18.0, Some(46.0)),
+                t0 + Duration::from_secs(30),
+            )
+            .unwrap();
+        assert_eq!(after.temperature_delta_per_min, Some(-6.0));
+        assert_eq!(after.humidity_delta_per_min, Some(12.0));
+
+        // Temperature-only frame: no humidity rate
+        let after = rates
+            .observe(ADDR, &reading(18.5, None), t0 + Duration::from_secs(90))
+            .unwrap();
+        assert_eq!(after.temperature_delta_per_min, Some(0.5));
+        assert_eq!(after.humidity_delta_per_min, None);
+    }
+
+    #[test]
+    fn test_bursts_keep_the_older_sample() {
+        let t0 = Instant::now();
+        let mut rates = RateTracker::new(Duration::from_secs(10));
+
+        rates.observe(ADDR, &reading(20.0, None), t0);
+        // 0.1 °C within 100 ms would be 60 °C/min
+        assert_eq!(
+            rates.observe(ADDR, &reading(20.1, None), t0 + Duration::from_millis(100)),
+            None
+        );
+        // Measured against the first sample, not the burst
+        let after = rates
+            .observe(ADDR, &reading(21.0, None), t0 + Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(after.temperature_delta_per_min, Some(1.0));
+    }
+}
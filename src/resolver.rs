@@ -0,0 +1,130 @@
+use crate::http;
+use crate::icons::{Icon, warning};
+use bluer::Address;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+/// Resolves friendly device names from an external inventory service.
+///
+/// Lookups run in the background and are cached with a TTL, so callers
+/// never wait on the network: the first sight of a device falls back to
+/// its BLE name and later readings pick up the resolved one.
+#[derive(Clone)]
+pub struct NameResolver {
+    url: String,
+    ttl: Duration,
+    cache: Arc<Mutex<HashMap<Address, CacheEntry>>>,
+}
+
+struct CacheEntry {
+    name: Option<String>,
+    fetched: Instant,
+}
+
+impl NameResolver {
+    /// `url` may contain an `{address}` placeholder; otherwise the address
+    /// is appended as the last path segment.
+    pub fn new(url: String, ttl: Duration) -> Self {
+        Self {
+            url,
+            ttl,
+            cache: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Cached name for `addr`, starting a background lookup if it's
+    /// missing or older than the TTL.
+    pub async fn lookup(&self, addr: Address) -> Option<String> {
+        let mut cache = self.cache.lock().await;
+        let (cached, expired) = match cache.get(&addr) {
+            Some(entry) => (entry.name.clone(), entry.fetched.elapsed() > self.ttl),
+            None => (None, true),
+        };
+
+        if expired {
+            // Mark as fresh right away so only one lookup per device is in flight
+            cache.insert(
+                addr,
+                CacheEntry {
+                    name: cached.clone(),
+                    fetched: Instant::now(),
+                },
+            );
+            let resolver = self.clone();
+            tokio::spawn(async move {
+                match http::get(&resolver.url_for(addr)).await {
+                    Ok(body) => {
+                        let name = parse_response(&body);
+                        if let Some(entry) = resolver.cache.lock().await.get_mut(&addr) {
+                            entry.name = name;
+                        }
+                    }
+                    // Keep the previous name and retry once the TTL expires
+                    Err(e) => warning!("{} Name lookup for {addr} failed: {e}", Icon::Warn),
+                }
+            });
+        }
+
+        cached
+    }
+
+    fn url_for(&self, addr: Address) -> String {
+        if self.url.contains("{address}") {
+            self.url.replace("{address}", &addr.to_string())
+        } else {
+            format!("{}/{addr}", self.url.trim_end_matches('/'))
+        }
+    }
+}
+
+/// Accepts `{"name": "...", "location": "..."}` JSON or a plain-text name.
+fn parse_response(body: &str) -> Option<String> {
+    let body = body.trim();
+    if let Ok(serde_json::Value::Object(obj)) = serde_json::from_str(body) {
+        let name = obj.get("name")?.as_str()?;
+        return Some(match obj.get("location").and_then(|l| l.as_str()) {
+            Some(location) => format!("{name} @ {location}"),
+            None => name.to_string(),
+        });
+    }
+    (!body.is_empty()).then(|| body.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDR: Address = Address::new([0xA4, 0xC1, 0x38, 0x00, 0x00, 0x01]);
+
+    #[test]
+    fn test_url_for() {
+        let templated = NameResolver::new("http://inv/d/{address}/name".into(), Duration::ZERO);
+        let appended = NameResolver::new("http://inv/devices/".into(), Duration::ZERO);
+
+        assert_eq!(
+            templated.url_for(ADDR),
+            "http://inv/d/A4:C1:38:00:00:01/name"
+        );
+        assert_eq!(
+            appended.url_for(ADDR),
+            "http://inv/devices/A4:C1:38:00:00:01"
+        );
+    }
+
+    #[test]
+    fn test_parse_response() {
+        assert_eq!(
+            parse_response(r#"{"name": "Fridge", "location": "Kitchen"}"#).as_deref(),
+            Some("Fridge @ Kitchen")
+        );
+        assert_eq!(
+            parse_response(r#"{"name": "Fridge"}"#).as_deref(),
+            Some("Fridge")
+        );
+        assert_eq!(parse_response("Bedroom\n").as_deref(), Some("Bedroom"));
+        assert_eq!(parse_response(r#"{"id": 3}"#), None);
+        assert_eq!(parse_response(""), None);
+    }
+}
@@ -0,0 +1,100 @@
+use crate::decoder::Mac;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// How far back "recently" reaches for the RF context.
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Snapshot of the radio environment when a reading arrived.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+pub struct RfContext {
+    /// Devices heard in the last minute, including this one
+    pub tracked_devices: usize,
+    /// Weakest RSSI (dBm) of any device heard in the last minute
+    pub weakest_rssi: Option<i16>,
+    /// Strongest RSSI (dBm) of any device heard in the last minute
+    pub strongest_rssi: Option<i16>,
+}
+
+/// RSSI (dBm) a typical sensor is heard with at 1 m.
+pub const DEFAULT_TX_POWER: i16 = -59;
+
+/// How fast the signal fades with distance: 2 in free space, more indoors.
+const PATH_LOSS_EXPONENT: f32 = 2.0;
+
+/// Rough distance in metres at which a device that's heard with `tx_power`
+/// dBm at 1 m arrives with `rssi` dBm, after the log-distance path-loss
+/// model. Walls, bodies and antenna orientation easily throw it off by a
+/// factor of two, so it's a hint for presence, not a measurement.
+pub fn distance(rssi: i16, tx_power: i16) -> f32 {
+    let metres = 10f32.powf(f32::from(tx_power - rssi) / (10.0 * PATH_LOSS_EXPONENT));
+    (metres * 100.0).round() / 100.0
+}
+
+/// Keeps the last RSSI of every device heard recently.
+#[derive(Default)]
+pub struct RfTracker {
+    heard: HashMap<Mac, (Instant, Option<i16>)>,
+}
+
+impl RfTracker {
+    pub fn observe(&mut self, addr: Mac, rssi: Option<i16>, now: Instant) {
+        self.heard.insert(addr, (now, rssi));
+        self.heard
+            .retain(|_, (seen, _)| now.saturating_duration_since(*seen) <= WINDOW);
+    }
+
+    pub fn context(&self) -> RfContext {
+        let rssis = self.heard.values().filter_map(|(_, rssi)| *rssi);
+        RfContext {
+            tracked_devices: self.heard.len(),
+            weakest_rssi: rssis.clone().min(),
+            strongest_rssi: rssis.max(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(last: u8) -> Mac {
+        [0xA4, 0xC1, 0x38, 0x00, 0x00, last]
+    }
+
+    #[test]
+    fn test_distance() {
+        assert_eq!(distance(-59, -59), 1.0);
+        assert_eq!(distance(-79, -59), 10.0);
+        assert_eq!(distance(-53, -59), 0.5);
+    }
+
+    #[test]
+    fn test_context_covers_recent_devices_only() {
+        let t0 = Instant::now();
+        let mut rf = RfTracker::default();
+
+        rf.observe(addr(1), Some(-95), t0);
+        rf.observe(addr(2), Some(-60), t0 + Duration::from_secs(30));
+        rf.observe(addr(3), None, t0 + Duration::from_secs(40));
+        assert_eq!(
+            rf.context(),
+            RfContext {
+                tracked_devices: 3,
+                weakest_rssi: Some(-95),
+                strongest_rssi: Some(-60),
+            }
+        );
+
+        // addr(1) falls out of the window
+        rf.observe(addr(2), Some(-70), t0 + Duration::from_secs(61));
+        assert_eq!(
+            rf.context(),
+            RfContext {
+                tracked_devices: 2,
+                weakest_rssi: Some(-70),
+                strongest_rssi: Some(-70),
+            }
+        );
+    }
+}
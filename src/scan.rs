@@ -0,0 +1,189 @@
+//! How each adapter scans: `--passive`, `--duplicate-data` and `--min-rssi`
+//! for all of them, overridden per adapter with `--adapter-scan`,
+//! `--adapter-duplicate-data` and `--adapter-min-rssi` (or the
+//! `[adapters.<name>]` tables of a config file). `--adapter-uuid` narrows
+//! one adapter's discovery to some service UUIDs; without it an adapter
+//! reports everything around.
+
+use crate::Args;
+use crate::filter::RssiFilter;
+use crate::stdin;
+use std::collections::BTreeSet;
+use std::str::FromStr;
+use uuid::Uuid;
+
+/// Whether an adapter sends scan requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScanType {
+    Active,
+    Passive,
+}
+
+impl FromStr for ScanType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "active" => Ok(Self::Active),
+            "passive" => Ok(Self::Passive),
+            _ => Err(format!("expected active or passive, got {s:?}")),
+        }
+    }
+}
+
+/// A `--adapter-uuid` value: one service UUID, or `known` for every one
+/// that's decoded (the standard ones and `--extra-uuid`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScanUuid {
+    Known,
+    Uuid(Uuid),
+}
+
+impl FromStr for ScanUuid {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "known" => Ok(Self::Known),
+            s => stdin::parse_uuid(s).map(Self::Uuid),
+        }
+    }
+}
+
+/// What one adapter's discovery is set up with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanSettings {
+    pub passive: bool,
+    pub duplicate_data: bool,
+    pub min_rssi: Option<i16>,
+    pub pass_unknown_rssi: bool,
+    /// Service UUIDs discovery is limited to; empty for no limit
+    pub uuids: Vec<Uuid>,
+}
+
+impl ScanSettings {
+    /// The settings of `adapter`: its own where given, the global ones
+    /// otherwise. The last of several settings for one adapter wins, except
+    /// UUIDs, which add up.
+    pub fn for_adapter(args: &Args, adapter: &str) -> Result<Self, String> {
+        fn own<T: Copy>(settings: &[(String, T)], adapter: &str) -> Option<T> {
+            settings
+                .iter()
+                .rev()
+                .find(|(name, _)| name == adapter)
+                .map(|(_, value)| *value)
+        }
+        let mut settings = Self {
+            passive: own(&args.adapter_scan, adapter)
+                .map_or(args.passive, |scan| scan == ScanType::Passive),
+            duplicate_data: own(&args.adapter_duplicate_data, adapter)
+                .unwrap_or(args.duplicate_data),
+            min_rssi: own(&args.adapter_min_rssi, adapter).or(args.min_rssi),
+            pass_unknown_rssi: args.pass_unknown_rssi,
+            uuids: Vec::new(),
+        };
+        let known = args.services();
+        for (_, uuid) in args.adapter_uuid.iter().filter(|(name, _)| name == adapter) {
+            let uuids: Vec<Uuid> = match uuid {
+                ScanUuid::Known => known.uuids().collect(),
+                ScanUuid::Uuid(uuid) => vec![*uuid],
+            };
+            for uuid in uuids {
+                if !settings.uuids.contains(&uuid) {
+                    settings.uuids.push(uuid);
+                }
+            }
+        }
+        if settings.passive && settings.duplicate_data {
+            return Err(format!(
+                "{adapter}: passive scanning can't be combined with duplicate data"
+            ));
+        }
+        Ok(settings)
+    }
+
+    pub fn rssi_filter(&self) -> RssiFilter {
+        RssiFilter {
+            min: self.min_rssi,
+            pass_unknown: self.pass_unknown_rssi,
+        }
+    }
+}
+
+/// Every adapter name a per-adapter setting refers to.
+pub fn configured_adapters(args: &Args) -> BTreeSet<&str> {
+    let names = args.adapter_scan.iter().map(|(name, _)| name);
+    let names = names.chain(args.adapter_duplicate_data.iter().map(|(name, _)| name));
+    let names = names.chain(args.adapter_min_rssi.iter().map(|(name, _)| name));
+    let names = names.chain(args.adapter_uuid.iter().map(|(name, _)| name));
+    names.map(String::as_str).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn settings(cli: &[&str], adapter: &str) -> Result<ScanSettings, String> {
+        let args = Args::parse_from(std::iter::once("mitempr").chain(cli.iter().copied()));
+        ScanSettings::for_adapter(&args, adapter)
+    }
+
+    #[test]
+    fn test_adapter_settings_override_global_ones() {
+        let cli = [
+            "--min-rssi=-90",
+            "--adapter-scan=hci1=passive",
+            "--adapter-min-rssi=hci1=-70",
+            "--adapter-duplicate-data=hci0=true",
+        ];
+
+        let hci0 = settings(&cli, "hci0").unwrap();
+        assert!(!hci0.passive && hci0.duplicate_data);
+        assert_eq!(hci0.min_rssi, Some(-90));
+        let hci1 = settings(&cli, "hci1").unwrap();
+        assert!(hci1.passive && !hci1.duplicate_data);
+        assert_eq!(hci1.min_rssi, Some(-70));
+
+        // An adapter can go back to active scanning
+        let hci2 = settings(&["--passive", "--adapter-scan=hci2=active"], "hci2").unwrap();
+        assert!(!hci2.passive);
+
+        let args = Args::parse_from(["mitempr"].iter().chain(&cli));
+        assert_eq!(
+            configured_adapters(&args).into_iter().collect::<Vec<_>>(),
+            ["hci0", "hci1"]
+        );
+    }
+
+    #[test]
+    fn test_adapter_uuids_add_up() {
+        let cli = [
+            "--extra-uuid=fcd9=bthome",
+            "--adapter-uuid=hci0=known",
+            "--adapter-uuid=hci0=fcd2",
+            "--adapter-uuid=hci0=fe2c",
+        ];
+        let short = |short: u128| Uuid::from_u128((short << 96) | 0x1000_8000_0080_5F9B_34FB);
+
+        assert_eq!(
+            settings(&cli, "hci0").unwrap().uuids,
+            [0xFE95, 0xFCD2, 0x181A, 0xFCD9, 0xFE2C].map(short)
+        );
+        // The survey adapter sees everything
+        assert!(settings(&cli, "hci1").unwrap().uuids.is_empty());
+        assert!("nope".parse::<ScanUuid>().is_err());
+    }
+
+    #[test]
+    fn test_passive_with_duplicate_data_is_an_error() {
+        let cli = ["--duplicate-data", "--adapter-scan=hci1=passive"];
+
+        assert!(settings(&cli, "hci0").is_ok());
+        assert_eq!(
+            settings(&cli, "hci1"),
+            Err("hci1: passive scanning can't be combined with duplicate data".into())
+        );
+        assert!(Args::try_parse_from(["mitempr", "--adapter-scan=hci1=sideways"]).is_err());
+    }
+}
@@ -0,0 +1,95 @@
+use bluer::Address;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// When each device was last handled, so a device's `DeviceAdded` is acted
+/// on again once its values may have changed.
+///
+/// Without expiry a device would only be read once, until BlueZ removed it.
+pub struct SeenDevices {
+    refresh: Duration,
+    handled: HashMap<Address, Instant>,
+}
+
+impl SeenDevices {
+    /// A zero `refresh` handles every event.
+    pub fn new(refresh: Duration) -> Self {
+        Self {
+            refresh,
+            handled: HashMap::new(),
+        }
+    }
+
+    /// Whether `addr` should be handled at `now`: it's new, or was last
+    /// handled at least `refresh` ago. Records `now` if so.
+    pub fn should_handle(&mut self, addr: Address, now: Instant) -> bool {
+        match self.handled.get(&addr) {
+            Some(last) if now.duration_since(*last) < self.refresh => false,
+            _ => {
+                self.handled.insert(addr, now);
+                true
+            }
+        }
+    }
+
+    /// Forget `addr`, e.g. when BlueZ removed it.
+    pub fn remove(&mut self, addr: Address) {
+        self.handled.remove(&addr);
+    }
+
+    /// Drop entries due for a refresh; they'd be handled anyway.
+    pub fn prune(&mut self, now: Instant) {
+        self.handled
+            .retain(|_, last| now.duration_since(*last) < self.refresh);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDR: Address = Address::new([0xA4, 0xC1, 0x38, 0x00, 0x00, 0x01]);
+
+    #[test]
+    fn test_handled_again_after_refresh() {
+        let mut seen = SeenDevices::new(Duration::from_secs(30));
+        let start = Instant::now();
+
+        assert!(seen.should_handle(ADDR, start));
+        assert!(!seen.should_handle(ADDR, start + Duration::from_secs(29)));
+        assert!(seen.should_handle(ADDR, start + Duration::from_secs(30)));
+        assert!(!seen.should_handle(ADDR, start + Duration::from_secs(31)));
+    }
+
+    #[test]
+    fn test_removed_device_is_new() {
+        let mut seen = SeenDevices::new(Duration::from_secs(30));
+        let start = Instant::now();
+
+        seen.should_handle(ADDR, start);
+        seen.remove(ADDR);
+        assert!(seen.should_handle(ADDR, start));
+    }
+
+    #[test]
+    fn test_prune_drops_expired() {
+        let other = Address::new([0xA4, 0xC1, 0x38, 0x00, 0x00, 0x02]);
+        let mut seen = SeenDevices::new(Duration::from_secs(30));
+        let start = Instant::now();
+
+        seen.should_handle(ADDR, start);
+        seen.should_handle(other, start + Duration::from_secs(20));
+        seen.prune(start + Duration::from_secs(40));
+        assert_eq!(seen.handled.len(), 1);
+        assert!(seen.should_handle(ADDR, start + Duration::from_secs(40)));
+    }
+
+    #[test]
+    fn test_zero_refresh_handles_everything() {
+        let mut seen = SeenDevices::new(Duration::ZERO);
+        let start = Instant::now();
+
+        assert!(seen.should_handle(ADDR, start));
+        assert!(seen.should_handle(ADDR, start));
+    }
+}
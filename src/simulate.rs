@@ -0,0 +1,210 @@
+use crate::decoder::{self, BlePacketType};
+use crate::icons::{Icon, status};
+use crate::pipeline::Pipeline;
+use bluer::Address;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+/// How often each simulated device advertises.
+const ADVERTISING_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Feed `count` simulated devices through `pipeline` until Ctrl-C.
+pub async fn run(count: usize, pipeline: &mut Pipeline) {
+    let seed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(1, |since| since.as_nanos() as u64);
+    let mut simulator = Simulator::new(count, seed);
+    let mut interval = tokio::time::interval(
+        (ADVERTISING_INTERVAL / count.max(1) as u32).max(Duration::from_millis(10)),
+    );
+    status!("{} Simulating {count} devices", Icon::Scan);
+
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = &mut ctrl_c => break,
+        }
+        let Some(ad) = simulator.next_advertisement() else {
+            continue;
+        };
+        status!("{} {} ({}), RSSI={}", Icon::Rx, ad.addr, ad.name, ad.rssi);
+        pipeline.process(ad.addr, Some(&ad.name), Some(ad.rssi), &ad.service_data);
+        pipeline.tick();
+    }
+}
+
+/// Fake sensors for `--simulate`, emitting BTHome, PVVX and Mijia service
+/// data whose values follow slow random walks.
+pub struct Simulator {
+    devices: Vec<SimulatedDevice>,
+    next: usize,
+    rng: XorShift,
+}
+
+struct SimulatedDevice {
+    addr: Address,
+    format: BlePacketType,
+    temperature: f32,
+    humidity: f32,
+    battery: u8,
+    counter: u8,
+}
+
+/// One fake advertisement.
+pub struct Advertisement {
+    pub addr: Address,
+    pub name: String,
+    pub rssi: i16,
+    pub service_data: HashMap<Uuid, Vec<u8>>,
+}
+
+impl Simulator {
+    pub fn new(count: usize, seed: u64) -> Self {
+        let mut rng = XorShift(seed.max(1));
+        let devices = (0..count)
+            .map(|i| SimulatedDevice {
+                // Locally administered addresses, so they can't clash with real devices
+                addr: Address::new([0x02, 0x51, 0x4D, 0x00, (i >> 8) as u8, i as u8]),
+                format: [
+                    BlePacketType::BTHome,
+                    BlePacketType::Pvvx,
+                    BlePacketType::Mijia,
+                ][i % 3],
+                temperature: 15.0 + rng.unit() * 10.0,
+                humidity: 35.0 + rng.unit() * 30.0,
+                battery: 60 + (rng.unit() * 40.0) as u8,
+                counter: 0,
+            })
+            .collect();
+        Self {
+            devices,
+            next: 0,
+            rng,
+        }
+    }
+
+    /// The next device's advertisement, round robin.
+    pub fn next_advertisement(&mut self) -> Option<Advertisement> {
+        let index = self.next;
+        self.next = (self.next + 1) % self.devices.len().max(1);
+        let rng = &mut self.rng;
+        let device = self.devices.get_mut(index)?;
+
+        device.temperature = (device.temperature + rng.step(0.1)).clamp(-20.0, 40.0);
+        device.humidity = (device.humidity + rng.step(0.3)).clamp(5.0, 95.0);
+        device.counter = device.counter.wrapping_add(1);
+
+        let payload = match device.format {
+            BlePacketType::BTHome => bthome_frame(device),
+            BlePacketType::Pvvx => pvvx_frame(device),
+            _ => mijia_frame(device),
+        };
+        let uuid = decoder::service_uuid(device.format).expect("simulated formats have a UUID");
+        Some(Advertisement {
+            addr: device.addr,
+            name: format!("sim-{:?}-{index}", device.format).to_lowercase(),
+            rssi: -50 - (rng.unit() * 40.0) as i16,
+            service_data: HashMap::from([(uuid, payload)]),
+        })
+    }
+}
+
+fn bthome_frame(device: &SimulatedDevice) -> Vec<u8> {
+    let temperature = ((device.temperature * 100.0).round() as i16).to_le_bytes();
+    let humidity = ((device.humidity * 100.0).round() as u16).to_le_bytes();
+    vec![
+        0x40,
+        0x00,
+        device.counter,
+        0x01,
+        device.battery,
+        0x02,
+        temperature[0],
+        temperature[1],
+        0x03,
+        humidity[0],
+        humidity[1],
+    ]
+}
+
+fn pvvx_frame(device: &SimulatedDevice) -> Vec<u8> {
+    let mut frame: Vec<u8> = device.addr.0.iter().rev().copied().collect();
+    frame.extend(((device.temperature * 100.0).round() as i16).to_le_bytes());
+    frame.extend(((device.humidity * 100.0).round() as u16).to_le_bytes());
+    frame.extend((2500 + device.battery as u16 * 5).to_le_bytes());
+    frame.extend([device.battery, device.counter, 0x04]);
+    frame
+}
+
+fn mijia_frame(device: &SimulatedDevice) -> Vec<u8> {
+    // LYWSDCGQ (product ID 0x01AA), combined temperature + humidity object
+    let mut frame = vec![0x50, 0x20, 0xAA, 0x01, device.counter];
+    frame.extend(device.addr.0.iter().rev());
+    frame.extend([0x0D, 0x10, 0x04]);
+    frame.extend(((device.temperature * 10.0).round() as i16).to_le_bytes());
+    frame.extend(((device.humidity * 10.0).round() as u16).to_le_bytes());
+    frame
+}
+
+/// Small PRNG; simulated data doesn't need more.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Uniform in `0.0..1.0`.
+    fn unit(&mut self) -> f32 {
+        (self.next() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// Uniform in `-max..max`.
+    fn step(&mut self, max: f32) -> f32 {
+        (self.unit() * 2.0 - 1.0) * max
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_simulated_frames_decode_strictly() {
+        let mut simulator = Simulator::new(3, 42);
+
+        for _ in 0..30 {
+            let ad = simulator.next_advertisement().unwrap();
+            let data = decoder::handle_service_data_strict(&ad.service_data)
+                .unwrap_or_else(|e| panic!("{}: {e}", ad.name));
+            assert!(data.temperature.is_some() && data.humidity.is_some());
+        }
+    }
+
+    #[test]
+    fn test_random_walk_is_smooth() {
+        let mut simulator = Simulator::new(1, 7);
+        let mut previous: Option<f32> = None;
+
+        for _ in 0..100 {
+            let ad = simulator.next_advertisement().unwrap();
+            let data = decoder::handle_service_data(&ad.service_data).unwrap();
+            let temperature = data.temperature.unwrap();
+            if let Some(previous) = previous {
+                assert!((temperature - previous).abs() <= 0.11);
+            }
+            previous = Some(temperature);
+        }
+    }
+
+    #[test]
+    fn test_no_devices() {
+        assert!(Simulator::new(0, 1).next_advertisement().is_none());
+    }
+}
@@ -0,0 +1,114 @@
+use crate::decoder::{Mac, SensorData};
+use std::collections::{HashMap, VecDeque};
+
+/// Moving averages over a device's last readings, with `--smooth`.
+#[derive(Debug, Clone, Copy, PartialEq, Default, serde::Serialize)]
+pub struct Smoothed {
+    pub temperature: Option<f32>,
+    pub humidity: Option<f32>,
+    /// The RSSI distance estimate jumps around far more than the sensors
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub distance_m: Option<f32>,
+}
+
+/// Keeps the last `size` temperature, humidity and distance values per
+/// device.
+///
+/// Only the jittery measurements are averaged; the battery level stays as
+/// reported.
+pub struct Smoother {
+    size: usize,
+    devices: HashMap<Mac, Window>,
+}
+
+#[derive(Default)]
+struct Window {
+    temperature: VecDeque<f32>,
+    humidity: VecDeque<f32>,
+    distance_m: VecDeque<f32>,
+}
+
+impl Smoother {
+    pub fn new(size: usize) -> Self {
+        Self {
+            size: size.max(1),
+            devices: HashMap::new(),
+        }
+    }
+
+    /// Add `data` from `addr`; returns the averages over what's in the
+    /// windows so far, including this reading.
+    pub fn observe(&mut self, addr: Mac, data: &SensorData) -> Option<Smoothed> {
+        let window = self.devices.entry(addr).or_default();
+        let smoothed = Smoothed {
+            temperature: average(&mut window.temperature, data.temperature, self.size),
+            humidity: average(&mut window.humidity, data.humidity, self.size),
+            distance_m: average(&mut window.distance_m, data.distance_m, self.size),
+        };
+        (smoothed != Smoothed::default()).then_some(smoothed)
+    }
+}
+
+/// Push `value` (if any) and return the mean, rounded to the sensors'
+/// 0.01 resolution. A reading without the value leaves its average out.
+fn average(window: &mut VecDeque<f32>, value: Option<f32>, size: usize) -> Option<f32> {
+    let value = value?;
+    if window.len() == size {
+        window.pop_front();
+    }
+    window.push_back(value);
+    let mean = window.iter().sum::<f32>() / window.len() as f32;
+    Some((mean * 100.0).round() / 100.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDR: Mac = [0xA4, 0xC1, 0x38, 0x00, 0x00, 0x01];
+
+    fn reading(temperature: f32, humidity: Option<f32>) -> SensorData {
+        SensorData {
+            temperature: Some(temperature),
+            humidity,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_average_over_available_then_full_window() {
+        let mut smoother = Smoother::new(3);
+
+        let first = smoother.observe(ADDR, &reading(21.0, Some(40.0))).unwrap();
+        assert_eq!(first.temperature, Some(21.0));
+        let second = smoother.observe(ADDR, &reading(21.3, None)).unwrap();
+        assert_eq!(second.temperature, Some(21.15));
+        assert_eq!(second.humidity, None);
+        smoother.observe(ADDR, &reading(21.6, Some(42.0)));
+        let full = smoother.observe(ADDR, &reading(22.2, Some(44.0))).unwrap();
+        // 21.3, 21.6 and 22.2; humidity 40, 42 and 44
+        assert_eq!(full.temperature, Some(21.7));
+        assert_eq!(full.humidity, Some(42.0));
+    }
+
+    #[test]
+    fn test_devices_are_separate() {
+        let other = [0xA4, 0xC1, 0x38, 0x00, 0x00, 0x02];
+        let mut smoother = Smoother::new(5);
+
+        smoother.observe(ADDR, &reading(30.0, None));
+        let smoothed = smoother.observe(other, &reading(10.0, None)).unwrap();
+        assert_eq!(smoothed.temperature, Some(10.0));
+    }
+
+    #[test]
+    fn test_nothing_to_average() {
+        let mut smoother = Smoother::new(5);
+        let battery_only = SensorData {
+            battery: Some(87),
+            ..Default::default()
+        };
+
+        assert_eq!(smoother.observe(ADDR, &battery_only), None);
+    }
+}
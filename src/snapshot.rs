@@ -0,0 +1,134 @@
+//! Latest reading per device for `--once`, printed as a table when the
+//! scan is over.
+
+use crate::export::{self, Exporter, Reading};
+use crate::units::Units;
+use bluer::Address;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+
+/// The most recent reading of every device, shared between the pipeline
+/// (as an exporter) and `main`, which prints it at the end.
+#[derive(Clone)]
+pub struct Snapshot {
+    devices: Arc<Mutex<BTreeMap<Address, Reading>>>,
+    units: Units,
+}
+
+impl Snapshot {
+    pub fn new(units: Units) -> Self {
+        Self {
+            devices: Arc::default(),
+            units,
+        }
+    }
+
+    /// One line per device, sorted by address.
+    pub fn table(&self) -> String {
+        let devices = self.devices.lock().unwrap();
+        let mut out = table(devices.values(), self.units);
+        let _ = writeln!(out, "{} devices", devices.len());
+        out
+    }
+
+    /// The readings as a JSON array, sorted by address.
+    pub fn to_json(&self) -> String {
+        let devices = self.devices.lock().unwrap();
+        let readings: Vec<_> = devices
+            .values()
+            .map(|reading| export::json(reading, self.units))
+            .collect();
+        format!("[{}]", readings.join(","))
+    }
+}
+
+/// A header and one line per reading with its name, temperature, humidity,
+/// battery and RSSI in `units`, as `--once` prints them.
+pub fn table<'a>(readings: impl Iterator<Item = &'a Reading>, units: Units) -> String {
+    let mut out = format!(
+        "{:<17}  {:<20}  {:>8}  {:>8}  {:>7}  {:>4}\n",
+        "ADDRESS", "NAME", "TEMP", "HUMIDITY", "BATTERY", "RSSI"
+    );
+    for reading in readings {
+        let data = units.convert(&reading.data);
+        let _ = writeln!(
+            out,
+            "{}  {:<20}  {:>8}  {:>8}  {:>7}  {:>4}",
+            reading.address,
+            data.name.as_deref().unwrap_or("-"),
+            data.temperature
+                .map(|t| format!("{t}{}", units.temperature_suffix()))
+                .unwrap_or_else(|| "-".into()),
+            data.humidity
+                .map(|h| format!("{h}%"))
+                .unwrap_or_else(|| "-".into()),
+            data.battery
+                .map(|b| format!("{b}%"))
+                .unwrap_or_else(|| "-".into()),
+            data.rssi
+                .map(|r| r.to_string())
+                .unwrap_or_else(|| "-".into()),
+        );
+    }
+    out
+}
+
+impl Exporter for Snapshot {
+    fn export(&self, reading: &Reading) {
+        let mut devices = self.devices.lock().unwrap();
+        devices.insert(reading.address, reading.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::SensorData;
+    use std::time::SystemTime;
+
+    fn reading(last: u8, data: SensorData) -> Reading {
+        Reading {
+            address: Address::new([0xA4, 0xC1, 0x38, 0x00, 0x00, last]),
+            received_at: SystemTime::UNIX_EPOCH,
+            data,
+        }
+    }
+
+    #[test]
+    fn test_latest_reading_per_device_sorted() {
+        let snapshot = Snapshot::new(Units::Metric);
+        snapshot.export(&reading(
+            2,
+            SensorData {
+                battery: Some(90),
+                ..Default::default()
+            },
+        ));
+        for temperature in [21.5, 22.0] {
+            snapshot.export(&reading(
+                1,
+                SensorData {
+                    name: Some("Bedroom".into()),
+                    temperature: Some(temperature),
+                    humidity: Some(45.0),
+                    rssi: Some(-70),
+                    ..Default::default()
+                },
+            ));
+        }
+
+        let table = snapshot.table();
+        let lines: Vec<_> = table.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[1].starts_with("A4:C1:38:00:00:01  Bedroom"));
+        assert!(lines[1].ends_with("22°C       45%        -   -70"));
+        assert!(lines[2].starts_with("A4:C1:38:00:00:02  -"));
+        assert!(lines[2].contains("90%"));
+        assert_eq!(lines[3], "2 devices");
+
+        let json: serde_json::Value = serde_json::from_str(&snapshot.to_json()).unwrap();
+        assert_eq!(json[0]["temperature"], 22.0);
+        assert_eq!(json[1]["battery"], 90);
+    }
+}
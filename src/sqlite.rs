@@ -0,0 +1,269 @@
+//! Reading history in an SQLite database (`--sqlite`).
+//!
+//! Rows are written by the `sqlite3` command line shell fed over a pipe,
+//! which keeps a full SQLite build (and its C toolchain requirements for
+//! cross compiling) out of the binary.
+
+use crate::decoder::SensorData;
+use crate::export::{Exporter, Reading};
+use crate::icons::{Icon, error};
+use crate::template;
+use bluer::Address;
+use std::io;
+use std::path::Path;
+use std::process::Stdio;
+use std::time::{Duration, SystemTime};
+use tokio::io::AsyncWriteExt;
+use tokio::process::{Child, ChildStdin, Command};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// How long rows are collected before they are written in one transaction.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// WAL lets other processes query the database while rows are written.
+const SCHEMA: &str = "PRAGMA journal_mode=WAL;
+CREATE TABLE IF NOT EXISTS readings (
+    ts TEXT NOT NULL,
+    address TEXT NOT NULL,
+    name TEXT,
+    temperature REAL,
+    humidity REAL,
+    battery INTEGER,
+    voltage REAL,
+    rssi INTEGER,
+    flags TEXT
+);
+";
+
+/// Inserts a row per reading into the `readings` table.
+///
+/// Exporting only queues the row; a background task writes the queue in a
+/// transaction every few seconds, so database I/O never holds up scanning.
+pub struct SqliteExporter {
+    rows: mpsc::UnboundedSender<String>,
+}
+
+impl SqliteExporter {
+    /// Open (or create) the database at `path`. Must be called from within
+    /// the Tokio runtime. The returned task writes what's still queued and
+    /// closes the database once the exporter is dropped.
+    pub fn open(path: &Path) -> io::Result<(Self, JoinHandle<()>)> {
+        add_flags_column(path)?;
+        let mut shell = Command::new("sqlite3")
+            .arg("-batch")
+            .arg(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| io::Error::new(e.kind(), format!("cannot run sqlite3: {e}")))?;
+        let stdin = shell.stdin.take().expect("stdin is piped");
+        let (rows, rx) = mpsc::unbounded_channel();
+        let task = tokio::spawn(write_loop(shell, stdin, rx));
+        Ok((Self { rows }, task))
+    }
+}
+
+impl Exporter for SqliteExporter {
+    fn export(&self, reading: &Reading) {
+        // Only fails once the writer task is gone
+        let _ = self
+            .rows
+            .send(insert(reading.address, &reading.data, reading.received_at));
+    }
+}
+
+/// Databases written before readings had flags get the column added; a
+/// new database gets it from [`SCHEMA`].
+fn add_flags_column(path: &Path) -> io::Result<()> {
+    let run = |sql: &str| {
+        std::process::Command::new("sqlite3")
+            .arg("-batch")
+            .arg(path)
+            .arg(sql)
+            .output()
+            .map_err(|e| io::Error::new(e.kind(), format!("cannot run sqlite3: {e}")))
+    };
+    let columns = run("SELECT name FROM pragma_table_info('readings');")?;
+    let columns = String::from_utf8_lossy(&columns.stdout);
+    if !columns.is_empty() && !columns.lines().any(|column| column == "flags") {
+        run("ALTER TABLE readings ADD COLUMN flags TEXT;")?;
+    }
+    Ok(())
+}
+
+/// The INSERT statement of one reading; absent values are NULL, and so are
+/// infinite and NaN ones, which SQL has no literal for.
+fn insert(addr: Address, data: &SensorData, time: SystemTime) -> String {
+    fn value<T: ToString>(value: Option<T>) -> String {
+        value.map_or("NULL".to_string(), |v| v.to_string())
+    }
+    let real = |real: Option<f32>| value(real.filter(|v| v.is_finite()));
+    format!(
+        "INSERT INTO readings VALUES ('{}', '{addr}', {}, {}, {}, {}, {}, {}, {});",
+        template::rfc3339(time),
+        data.name.as_deref().map_or("NULL".to_string(), quote),
+        real(data.temperature),
+        real(data.humidity),
+        value(data.battery),
+        real(data.voltage),
+        value(data.rssi),
+        match data.flag_list().as_str() {
+            "" => "NULL".to_string(),
+            flags => quote(flags),
+        },
+    )
+}
+
+/// An SQL string literal.
+fn quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "''"))
+}
+
+async fn write_loop(
+    mut shell: Child,
+    mut stdin: ChildStdin,
+    mut rx: mpsc::UnboundedReceiver<String>,
+) {
+    if let Err(e) = stdin.write_all(SCHEMA.as_bytes()).await {
+        error!("{} Cannot set up the SQLite database: {e}", Icon::Error);
+        return;
+    }
+    let mut batch = String::new();
+    let mut flush = tokio::time::interval(FLUSH_INTERVAL);
+    let mut closed = false;
+    while !closed {
+        tokio::select! {
+            row = rx.recv() => match row {
+                Some(row) => {
+                    if batch.is_empty() {
+                        batch.push_str("BEGIN;\n");
+                    }
+                    batch.push_str(&row);
+                    batch.push('\n');
+                    continue;
+                }
+                // Exporter dropped: write what's left, then stop
+                None => closed = true,
+            },
+            _ = flush.tick() => {}
+        }
+        if batch.is_empty() {
+            continue;
+        }
+        batch.push_str("COMMIT;\n");
+        let written = async {
+            stdin.write_all(batch.as_bytes()).await?;
+            stdin.flush().await
+        };
+        if let Err(e) = written.await {
+            error!(
+                "{} SQLite writer stopped, readings are no longer stored: {e}",
+                Icon::Error
+            );
+            return;
+        }
+        batch.clear();
+    }
+
+    // End of input makes the shell close the database and exit
+    drop(stdin);
+    let _ = shell.wait().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::Flag;
+    use std::time::UNIX_EPOCH;
+
+    const ADDR: Address = Address::new([0xA4, 0xC1, 0x38, 0x00, 0x00, 0x01]);
+
+    #[test]
+    fn test_insert_with_nulls_and_quotes() {
+        let data = SensorData {
+            temperature: Some(22.9),
+            battery: Some(87),
+            name: Some("Kid's room".into()),
+            ..Default::default()
+        };
+        let time = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        assert_eq!(
+            insert(ADDR, &data, time),
+            "INSERT INTO readings VALUES ('2023-11-14T22:13:20Z', 'A4:C1:38:00:00:01', 'Kid''s room', 22.9, NULL, 87, NULL, NULL, NULL);"
+        );
+        let broken = SensorData {
+            temperature: Some(f32::NAN),
+            voltage: Some(f32::INFINITY),
+            ..Default::default()
+        };
+        assert!(
+            insert(ADDR, &broken, time).ends_with("NULL, NULL, NULL, NULL, NULL, NULL, NULL);")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rows_are_stored() {
+        let path = std::env::temp_dir().join(format!("mitempr-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let (exporter, task) = SqliteExporter::open(&path).unwrap();
+        for temperature in [21.5, 21.6] {
+            let data = SensorData {
+                temperature: Some(temperature),
+                ..Default::default()
+            };
+            exporter.export(&Reading::new(ADDR, data));
+        }
+        drop(exporter);
+        task.await.unwrap();
+
+        let output = std::process::Command::new("sqlite3")
+            .arg(&path)
+            .arg(
+                "SELECT address, temperature, humidity IS NULL FROM readings; PRAGMA journal_mode;",
+            )
+            .output()
+            .unwrap();
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{suffix}", path.display()));
+        }
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "A4:C1:38:00:00:01|21.5|1\nA4:C1:38:00:00:01|21.6|1\nwal\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_old_database_gets_flags_column() {
+        let path = std::env::temp_dir().join(format!("mitempr-old-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        std::process::Command::new("sqlite3")
+            .arg(&path)
+            .arg("CREATE TABLE readings (ts TEXT NOT NULL, address TEXT NOT NULL, name TEXT, temperature REAL, humidity REAL, battery INTEGER, voltage REAL, rssi INTEGER);")
+            .output()
+            .unwrap();
+
+        let (exporter, task) = SqliteExporter::open(&path).unwrap();
+        let data = SensorData {
+            battery: Some(50),
+            flags: [Flag::Estimated].into(),
+            ..Default::default()
+        };
+        exporter.export(&Reading::new(ADDR, data));
+        drop(exporter);
+        task.await.unwrap();
+
+        let output = std::process::Command::new("sqlite3")
+            .arg(&path)
+            .arg("SELECT battery, flags FROM readings;")
+            .output()
+            .unwrap();
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{suffix}", path.display()));
+        }
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "50|estimated\n");
+    }
+}
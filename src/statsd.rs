@@ -0,0 +1,249 @@
+use crate::decoder::SensorData;
+use crate::export::{Exporter, Reading};
+use crate::icons::{Icon, error, warning};
+use crate::throttle::LogThrottle;
+use bluer::Address;
+use clap::ValueEnum;
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// Stay below a typical MTU so datagrams aren't fragmented.
+const MAX_DATAGRAM: usize = 1400;
+/// How long metric lines may wait for a datagram to fill up.
+const FLUSH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How the device address is attached to metrics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TagStyle {
+    /// `ble.temperature;address=A4-C1-38-00-00-01:22.9|g`
+    Graphite,
+    /// `ble.temperature:21.5|g|#address:A4-C1-38-00-00-01`
+    Datadog,
+}
+
+/// Sends every reading as StatsD gauges over UDP.
+///
+/// Exporting only queues the lines; a background task batches them into
+/// datagrams, so a slow or missing StatsD server never holds up scanning.
+pub struct StatsdExporter {
+    prefix: String,
+    tags: TagStyle,
+    lines: mpsc::UnboundedSender<String>,
+}
+
+impl StatsdExporter {
+    /// Must be called from within the Tokio runtime. The returned task
+    /// sends the last batch and ends once the exporter is dropped.
+    pub fn new(target: String, prefix: String, tags: TagStyle) -> (Self, JoinHandle<()>) {
+        let (lines, rx) = mpsc::unbounded_channel();
+        let task = tokio::spawn(send_batches(target, rx));
+        let exporter = Self {
+            prefix,
+            tags,
+            lines,
+        };
+        (exporter, task)
+    }
+
+    fn lines(&self, addr: Address, data: &SensorData) -> Vec<String> {
+        // Values keep their own type's formatting (an f32 widened to f64
+        // would print 22.899999618530273 instead of 22.9)
+        fn gauge<T: ToString>(name: &str, value: Option<T>) -> Option<(&str, String)> {
+            Some((name, value?.to_string()))
+        }
+        let mut gauges: Vec<(&str, String)> = [
+            gauge("temperature", data.temperature),
+            gauge("humidity", data.humidity),
+            gauge("battery", data.battery),
+            gauge("voltage", data.voltage),
+            gauge("dew_point", data.derived.and_then(|d| d.dew_point)),
+            gauge(
+                "absolute_humidity",
+                data.derived.map(|d| d.absolute_humidity),
+            ),
+            gauge(
+                "smoothed_temperature",
+                data.smoothed.and_then(|s| s.temperature),
+            ),
+            gauge("smoothed_humidity", data.smoothed.and_then(|s| s.humidity)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        gauges.extend(
+            data.measurements
+                .iter()
+                .map(|(name, value)| (*name, value.to_string())),
+        );
+
+        let address = addr.to_string().replace(':', "-");
+        // Datadog takes a key several times, Graphite one value per key
+        let flags: String = match self.tags {
+            _ if data.flags.is_empty() => String::new(),
+            TagStyle::Graphite => format!(";flags={}", data.flag_list()),
+            TagStyle::Datadog => data.flags.iter().map(|f| format!(",flag:{f}")).collect(),
+        };
+        gauges
+            .into_iter()
+            .map(|(name, value)| match self.tags {
+                TagStyle::Graphite => {
+                    format!("{}.{name};address={address}{flags}:{value}|g", self.prefix)
+                }
+                TagStyle::Datadog => {
+                    format!("{}.{name}:{value}|g|#address:{address}{flags}", self.prefix)
+                }
+            })
+            .collect()
+    }
+}
+
+impl Exporter for StatsdExporter {
+    fn export(&self, reading: &Reading) {
+        let (addr, data) = (reading.address, &reading.data);
+        for line in self.lines(addr, data) {
+            // Only fails once the sender task is gone
+            let _ = self.lines.send(line);
+        }
+    }
+}
+
+async fn send_batches(target: String, mut rx: mpsc::UnboundedReceiver<String>) {
+    let socket = match UdpSocket::bind("0.0.0.0:0").await {
+        Ok(socket) => socket,
+        Err(e) => {
+            error!("{} StatsD disabled, no UDP socket: {e}", Icon::Error);
+            return;
+        }
+    };
+    let mut errors = LogThrottle::new(Duration::from_secs(60));
+    let mut batch = String::new();
+    let mut flush = tokio::time::interval(FLUSH_INTERVAL);
+
+    let mut closed = false;
+    while !closed {
+        let line = tokio::select! {
+            line = rx.recv() => match line {
+                Some(line) => Some(line),
+                // Exporter dropped: send what's left, then stop
+                None => {
+                    closed = true;
+                    None
+                }
+            },
+            _ = flush.tick() => None,
+        };
+
+        let full = line
+            .as_ref()
+            .is_some_and(|line| batch.len() + line.len() + 1 > MAX_DATAGRAM);
+        if (line.is_none() || full) && !batch.is_empty() {
+            if let Err(e) = socket.send_to(batch.as_bytes(), &target).await
+                && errors.allow(Instant::now())
+            {
+                warning!("{} StatsD send to {target} failed: {e}", Icon::Warn);
+            }
+            batch.clear();
+        }
+        if let Some(line) = line {
+            if !batch.is_empty() {
+                batch.push('\n');
+            }
+            batch.push_str(&line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::Flag;
+
+    const ADDR: Address = Address::new([0xA4, 0xC1, 0x38, 0x00, 0x00, 0x01]);
+
+    fn reading() -> SensorData {
+        let mut data = SensorData {
+            temperature: Some(22.9),
+            battery: Some(87),
+            ..Default::default()
+        };
+        data.measurements.insert("power_w", 3.25);
+        data
+    }
+
+    #[tokio::test]
+    async fn test_gauges_reach_udp_listener() {
+        let listener = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let target = listener.local_addr().unwrap().to_string();
+        let (exporter, _task) = StatsdExporter::new(target, "ble".into(), TagStyle::Graphite);
+
+        exporter.export(&Reading::new(ADDR, reading()));
+
+        let mut buf = [0; MAX_DATAGRAM];
+        let len = tokio::time::timeout(Duration::from_secs(5), listener.recv(&mut buf))
+            .await
+            .expect("no datagram")
+            .unwrap();
+        assert_eq!(
+            std::str::from_utf8(&buf[..len]).unwrap(),
+            "ble.temperature;address=A4-C1-38-00-00-01:22.9|g\n\
+             ble.battery;address=A4-C1-38-00-00-01:87|g\n\
+             ble.power_w;address=A4-C1-38-00-00-01:3.25|g"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_last_batch_sent_when_dropped() {
+        let listener = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let target = listener.local_addr().unwrap().to_string();
+        let (exporter, task) = StatsdExporter::new(target, "ble".into(), TagStyle::Graphite);
+        // Let the flush timer's immediate first tick pass
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        exporter.export(&Reading::new(ADDR, reading()));
+        drop(exporter);
+        tokio::time::timeout(Duration::from_secs(5), task)
+            .await
+            .expect("sender task kept running")
+            .unwrap();
+
+        let mut buf = [0; MAX_DATAGRAM];
+        let len = listener.try_recv(&mut buf).expect("last batch not sent");
+        assert!(
+            std::str::from_utf8(&buf[..len])
+                .unwrap()
+                .ends_with("power_w;address=A4-C1-38-00-00-01:3.25|g")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_datadog_tags() {
+        let (exporter, _task) =
+            StatsdExporter::new("127.0.0.1:9".into(), "home".into(), TagStyle::Datadog);
+
+        assert_eq!(
+            exporter.lines(ADDR, &reading())[0],
+            "home.temperature:22.9|g|#address:A4-C1-38-00-00-01"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_flags_as_tags() {
+        let mut data = reading();
+        data.flags = [Flag::Estimated, Flag::Smoothed].into();
+        let (graphite, _task) =
+            StatsdExporter::new("127.0.0.1:9".into(), "home".into(), TagStyle::Graphite);
+        let (datadog, _task) =
+            StatsdExporter::new("127.0.0.1:9".into(), "home".into(), TagStyle::Datadog);
+
+        assert_eq!(
+            graphite.lines(ADDR, &data)[0],
+            "home.temperature;address=A4-C1-38-00-00-01;flags=estimated,smoothed:22.9|g"
+        );
+        assert_eq!(
+            datadog.lines(ADDR, &data)[0],
+            "home.temperature:22.9|g|#address:A4-C1-38-00-00-01,flag:estimated,flag:smoothed"
+        );
+    }
+}
@@ -0,0 +1,175 @@
+use crate::decoder::{self, BlePacketType, ServiceMap};
+use crate::export::{Exporter, Reading};
+use bluer::Address;
+use clap::ValueEnum;
+use std::collections::HashMap;
+use std::io::{self, BufRead};
+use std::time::SystemTime;
+use uuid::Uuid;
+
+/// Payload format hint for `--decode-only` lines that carry no UUID.
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum PayloadFormat {
+    Mijia,
+    Bthome,
+    Pvvx,
+}
+
+impl From<PayloadFormat> for BlePacketType {
+    fn from(format: PayloadFormat) -> Self {
+        match format {
+            PayloadFormat::Mijia => BlePacketType::Mijia,
+            PayloadFormat::Bthome => BlePacketType::BTHome,
+            PayloadFormat::Pvvx => BlePacketType::Pvvx,
+        }
+    }
+}
+
+/// Read `uuid:hex` (or bare hex with a format hint) lines from stdin until
+/// EOF and hand what each decodes to to `exporter`, like readings from a
+/// scan. Lines that don't parse or decode are reported on stderr; returns
+/// how many there were.
+pub fn run(
+    format: Option<PayloadFormat>,
+    strict: bool,
+    services: &ServiceMap,
+    exporter: &dyn Exporter,
+) -> io::Result<usize> {
+    decode_lines(io::stdin().lock(), format, strict, services, exporter)
+}
+
+fn decode_lines(
+    input: impl BufRead,
+    format: Option<PayloadFormat>,
+    strict: bool,
+    services: &ServiceMap,
+    exporter: &dyn Exporter,
+) -> io::Result<usize> {
+    let mut errors = 0;
+    for line in input.lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let decoded = parse_line(line, format).and_then(|service_data| {
+            if strict {
+                services
+                    .handle_service_data_strict(&service_data)
+                    .map_err(|e| e.to_string())
+            } else {
+                services
+                    .handle_service_data(&service_data)
+                    .map_err(|e| e.to_string())
+            }
+        });
+        match decoded {
+            // There is no BLE address, only the one in the payload if any
+            Ok(data) => exporter.export(&Reading {
+                address: data.device_mac.map_or(Address::any(), Address::new),
+                received_at: SystemTime::now(),
+                data,
+            }),
+            Err(e) => {
+                eprintln!("{line}: {e}");
+                errors += 1;
+            }
+        }
+    }
+
+    Ok(errors)
+}
+
+/// Parse one input line into a service data map.
+///
+/// Accepts `<uuid>:<hex>` where the UUID is either the full 128-bit form or
+/// the 16-bit short form (`fcd2`), or bare hex when `format` is given. Hex may
+/// contain whitespace, `:`/`-` separators and a `0x` prefix, as copied from
+/// nRF Connect.
+fn parse_line(line: &str, format: Option<PayloadFormat>) -> Result<HashMap<Uuid, Vec<u8>>, String> {
+    let (uuid, hex_part) = match format {
+        Some(format) => {
+            let uuid = decoder::service_uuid(format.into()).expect("hint maps to a known format");
+            (uuid, line)
+        }
+        None => {
+            let (uuid, hex_part) = line
+                .split_once(':')
+                .ok_or("expected <uuid>:<hex> (or pass --payload-format)")?;
+            (parse_uuid(uuid.trim())?, hex_part)
+        }
+    };
+
+    let cleaned: String = hex_part
+        .trim()
+        .trim_start_matches("0x")
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != ':' && *c != '-')
+        .collect();
+    let bytes = hex::decode(&cleaned).map_err(|e| format!("invalid hex: {e}"))?;
+
+    Ok(HashMap::from([(uuid, bytes)]))
+}
+
+pub fn parse_uuid(s: &str) -> Result<Uuid, String> {
+    let s = s.trim_start_matches("0x");
+    if s.len() == 4 {
+        let short = u16::from_str_radix(s, 16).map_err(|e| format!("invalid UUID {s}: {e}"))?;
+        return Ok(Uuid::from_u128(
+            ((short as u128) << 96) | 0x0000_0000_0000_1000_8000_0080_5F9B_34FB,
+        ));
+    }
+    Uuid::parse_str(s).map_err(|e| format!("invalid UUID {s}: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::MemoryExporter;
+    use uuid::uuid;
+
+    const BTHOME: Uuid = uuid!("0000fcd2-0000-1000-8000-00805f9b34fb");
+
+    #[test]
+    fn test_parse_full_and_short_uuid() {
+        let full = parse_line("0000fcd2-0000-1000-8000-00805f9b34fb:40001201", None).unwrap();
+        let short = parse_line("FCD2: 40 00 12 01", None).unwrap();
+
+        assert_eq!(full, short);
+        assert_eq!(full[&BTHOME], vec![0x40, 0x00, 0x12, 0x01]);
+    }
+
+    #[test]
+    fn test_parse_bare_hex_with_format_hint() {
+        let data = parse_line("0x40-00-12-01", Some(PayloadFormat::Bthome)).unwrap();
+
+        assert_eq!(data[&BTHOME], vec![0x40, 0x00, 0x12, 0x01]);
+    }
+
+    #[test]
+    fn test_parse_bare_hex_without_hint_fails() {
+        assert!(parse_line("40001201", None).is_err());
+    }
+
+    #[test]
+    fn test_readings_are_exported_and_errors_counted() {
+        let input = "# capture\nfcd2:4002ca09\nnot hex\nfcd2:40ff00\n\nfcd2:4003bf13\n";
+        let services = ServiceMap::default();
+
+        let exporter = MemoryExporter::default();
+        let errors = decode_lines(input.as_bytes(), None, false, &services, &exporter).unwrap();
+        let readings = exporter.readings();
+        // The unknown object 0xFF only stops lenient decoding
+        assert_eq!(errors, 1);
+        assert_eq!(readings.len(), 3);
+        assert_eq!(readings[0].0, Address::any());
+        assert_eq!(readings[0].1.temperature, Some(25.06));
+        assert_eq!(readings[2].1.humidity, Some(50.55));
+
+        let exporter = MemoryExporter::default();
+        let errors = decode_lines(input.as_bytes(), None, true, &services, &exporter).unwrap();
+        assert_eq!(errors, 2);
+        assert_eq!(exporter.readings().len(), 2);
+    }
+}
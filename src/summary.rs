@@ -0,0 +1,376 @@
+use crate::decoder::BlePacketType;
+use crate::export::{self, Reading};
+use crate::histogram::{Histogram, LATENCY_BUCKETS};
+use crate::snapshot;
+use crate::units::Units;
+use bluer::Address;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+/// Tallies for the end-of-run report (`--summary`) and the periodic
+/// status line (`--status-interval`).
+pub struct Summary {
+    started: Instant,
+    advertisements: u64,
+    decoded: BTreeMap<BlePacketType, u64>,
+    errors: BTreeMap<BlePacketType, u64>,
+    last_readings: BTreeMap<Address, Reading>,
+    last_decoded: Option<Instant>,
+    /// When the last status line was made and the decoded total then
+    last_status: (Instant, u64),
+    /// From receiving an advertisement to having exported it
+    latency: Histogram,
+}
+
+impl Summary {
+    pub fn new(started: Instant) -> Self {
+        Self {
+            started,
+            advertisements: 0,
+            decoded: BTreeMap::new(),
+            errors: BTreeMap::new(),
+            last_readings: BTreeMap::new(),
+            last_decoded: None,
+            last_status: (started, 0),
+            latency: Histogram::new(LATENCY_BUCKETS),
+        }
+    }
+
+    /// Count one advertisement in `format` received at `now` and what it
+    /// decoded to, if anything.
+    pub fn record(
+        &mut self,
+        addr: Address,
+        format: BlePacketType,
+        decoded: Option<Reading>,
+        now: Instant,
+    ) {
+        self.advertisements += 1;
+        match decoded {
+            Some(reading) => {
+                *self.decoded.entry(format).or_default() += 1;
+                self.last_readings.insert(addr, reading);
+                self.last_decoded = Some(now);
+            }
+            None => *self.errors.entry(format).or_default() += 1,
+        }
+    }
+
+    /// Handling one advertisement took `elapsed`, counted from when it was
+    /// received.
+    pub fn observe_latency(&mut self, elapsed: Duration) {
+        self.latency.observe(elapsed);
+    }
+
+    pub fn latency(&self) -> &Histogram {
+        &self.latency
+    }
+
+    /// `Summary: 12 advertisements, 3 devices with readings in 60s`
+    pub fn headline(&self, now: Instant) -> String {
+        let runtime = Duration::from_secs(now.saturating_duration_since(self.started).as_secs());
+        format!(
+            "Summary: {} advertisements, {} devices with readings in {runtime:?}",
+            self.advertisements,
+            self.last_readings.len()
+        )
+    }
+
+    /// `Status: 3 devices, 120 readings decoded, 12.0/min, last 5s ago`
+    /// about the time since the previous status line, and whether anything
+    /// was decoded in it.
+    pub fn status(&mut self, now: Instant) -> (String, bool) {
+        let total: u64 = self.decoded.values().sum();
+        let (since, previous) = std::mem::replace(&mut self.last_status, (now, total));
+        let recent = total - previous;
+        let window = Duration::from_secs(now.saturating_duration_since(since).as_secs());
+        let last = match self.last_decoded {
+            Some(at) => {
+                let ago = now.saturating_duration_since(at).as_secs();
+                format!("last {:?} ago", Duration::from_secs(ago))
+            }
+            None => "none yet".to_string(),
+        };
+        let devices = self.last_readings.len();
+        if recent == 0 {
+            let line = format!(
+                "Status: no readings in the last {window:?} ({devices} devices, {total} readings decoded, {last}); check the sensors and the adapter"
+            );
+            return (line, false);
+        }
+        let per_minute = recent as f64 * 60.0 / window.as_secs_f64().max(1.0);
+        let line = format!(
+            "Status: {devices} devices, {total} readings decoded, {per_minute:.1}/min over {window:?}, {last}"
+        );
+        (line, true)
+    }
+
+    /// Human-readable report of the run up to `now`, with the last
+    /// readings as the `--once` table in `units`.
+    pub fn render(&self, now: Instant, units: Units) -> String {
+        let mut out = format!("{}\n", self.headline(now));
+        let _ = writeln!(out, "  Decoded: {}", tally(&self.decoded));
+        let _ = writeln!(out, "  Errors: {}", tally(&self.errors));
+        let _ = writeln!(out, "  Processing: {}", latency(&self.latency));
+        if !self.last_readings.is_empty() {
+            let table = snapshot::table(self.last_readings.values(), units);
+            for line in table.lines() {
+                let _ = writeln!(out, "  {line}");
+            }
+        }
+        out
+    }
+
+    /// The report as one JSON object, for `--format json`; the last
+    /// readings are `--format json` readings in `units`.
+    pub fn to_json(&self, now: Instant, units: Units) -> String {
+        let counts = |counts: &BTreeMap<BlePacketType, u64>| {
+            counts
+                .iter()
+                .map(|(format, count)| (format_name(*format), *count))
+                .collect()
+        };
+        let summary = JsonSummary {
+            runtime_seconds: now.saturating_duration_since(self.started).as_secs(),
+            advertisements: self.advertisements,
+            decoded: counts(&self.decoded),
+            errors: counts(&self.errors),
+            processing: JsonLatency {
+                count: self.latency.count(),
+                mean_seconds: self.latency.mean().map(|mean| mean.as_secs_f64()),
+                buckets: self
+                    .latency
+                    .buckets()
+                    .filter(|(_, count)| *count > 0)
+                    .map(|(le, count)| JsonBucket { le, count })
+                    .collect(),
+            },
+            readings: self
+                .last_readings
+                .values()
+                .map(|reading| export::json_value(reading, units))
+                .collect(),
+        };
+        serde_json::to_string(&summary).expect("summaries serialize")
+    }
+}
+
+/// The report as printed with `--format json`.
+#[derive(Serialize)]
+struct JsonSummary {
+    runtime_seconds: u64,
+    advertisements: u64,
+    /// Decoded advertisements per format
+    decoded: BTreeMap<&'static str, u64>,
+    /// Undecodable advertisements per format, `unknown` for other services
+    errors: BTreeMap<&'static str, u64>,
+    processing: JsonLatency,
+    /// The last reading of every device, sorted by address
+    readings: Vec<serde_json::Value>,
+}
+
+#[derive(Serialize)]
+struct JsonLatency {
+    count: u64,
+    mean_seconds: Option<f64>,
+    /// Only the buckets with observations
+    buckets: Vec<JsonBucket>,
+}
+
+/// Observations above the previous bucket's bound and up to `le` seconds
+/// (not cumulative, unlike Prometheus); `null` is past the last bound.
+#[derive(Serialize)]
+struct JsonBucket {
+    le: Option<f64>,
+    count: u64,
+}
+
+/// A format's name in the JSON report.
+fn format_name(format: BlePacketType) -> &'static str {
+    match format {
+        BlePacketType::Mijia => "Mijia",
+        BlePacketType::BTHome => "BTHome",
+        BlePacketType::Pvvx => "Pvvx",
+        BlePacketType::Other => "unknown",
+    }
+}
+
+/// `mean 1.2ms over 12 advertisements (<=1ms 10, <=5ms 2)`, only the
+/// buckets with observations.
+fn latency(histogram: &Histogram) -> String {
+    let Some(mean) = histogram.mean() else {
+        return "none".to_string();
+    };
+    let buckets: Vec<_> = histogram
+        .buckets()
+        .filter(|(_, count)| *count > 0)
+        .map(|(bound, count)| match bound {
+            Some(bound) => format!("<={:?} {count}", Duration::from_secs_f64(bound)),
+            None => {
+                let slowest = LATENCY_BUCKETS.last().copied().unwrap_or_default();
+                format!(">{:?} {count}", Duration::from_secs_f64(slowest))
+            }
+        })
+        .collect();
+    format!(
+        "mean {mean:?} over {} advertisements ({})",
+        histogram.count(),
+        buckets.join(", ")
+    )
+}
+
+/// `BTHome 12, Pvvx 3`, with unknown services named as such.
+fn tally(counts: &BTreeMap<BlePacketType, u64>) -> String {
+    if counts.is_empty() {
+        return "none".to_string();
+    }
+    counts
+        .iter()
+        .map(|(format, count)| match format {
+            BlePacketType::Other => format!("unknown service {count}"),
+            format => format!("{format:?} {count}"),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::SensorData;
+    use std::time::SystemTime;
+
+    const ADDR: Address = Address::new([0xA4, 0xC1, 0x38, 0x00, 0x00, 0x01]);
+
+    fn reading(temperature: f32) -> Reading {
+        Reading {
+            address: ADDR,
+            received_at: SystemTime::UNIX_EPOCH,
+            data: SensorData {
+                temperature: Some(temperature),
+                humidity: Some(45.0),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Two BTHome readings, two errors and three latencies in 90.5s.
+    fn summary() -> (Summary, Instant) {
+        let t0 = Instant::now();
+        let mut summary = Summary::new(t0);
+        summary.record(ADDR, BlePacketType::BTHome, Some(reading(21.0)), t0);
+        summary.record(ADDR, BlePacketType::BTHome, Some(reading(21.5)), t0);
+        summary.record(ADDR, BlePacketType::Mijia, None, t0);
+        summary.record(ADDR, BlePacketType::Other, None, t0);
+        for ms in [1, 3, 8] {
+            summary.observe_latency(Duration::from_millis(ms));
+        }
+        (summary, t0 + Duration::from_millis(90_500))
+    }
+
+    #[test]
+    fn test_render_counts_formats_and_errors() {
+        let (summary, now) = summary();
+
+        let report = summary.render(now, Units::Metric);
+        assert!(report.starts_with("Summary: 4 advertisements, 1 devices with readings in 90s\n"));
+        assert!(report.contains("  Decoded: BTHome 2\n"));
+        assert!(report.contains("  Errors: Mijia 1, unknown service 1\n"));
+        assert!(report.contains(
+            "  Processing: mean 4ms over 3 advertisements (<=1ms 1, <=5ms 1, <=10ms 1)\n"
+        ));
+        // The last reading, in the --once table
+        assert!(report.contains("  ADDRESS            NAME"));
+        assert!(report.contains("  A4:C1:38:00:00:01  -                       21.5°C       45%"));
+        assert!(!report.contains("SensorData"));
+    }
+
+    #[test]
+    fn test_json_shape() {
+        let (summary, now) = summary();
+
+        let json: serde_json::Value =
+            serde_json::from_str(&summary.to_json(now, Units::Metric)).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "runtime_seconds": 90,
+                "advertisements": 4,
+                "decoded": {"BTHome": 2},
+                "errors": {"Mijia": 1, "unknown": 1},
+                "processing": {
+                    "count": 3,
+                    "mean_seconds": 0.004,
+                    "buckets": [
+                        {"le": 0.001, "count": 1},
+                        {"le": 0.005, "count": 1},
+                        {"le": 0.01, "count": 1},
+                    ],
+                },
+                "readings": [{
+                    "time": "1970-01-01T00:00:00Z",
+                    "address": "A4:C1:38:00:00:01",
+                    "temperature_unit": "°C",
+                    "temperature": 21.5,
+                    "humidity": 45.0,
+                    "battery": null,
+                    "voltage": null,
+                    "name": null,
+                    "rssi": null,
+                }],
+            })
+        );
+    }
+
+    #[test]
+    fn test_status_rate_and_quiet_intervals() {
+        let t0 = Instant::now();
+        let secs = Duration::from_secs;
+        let mut summary = Summary::new(t0);
+        let reading = Reading {
+            address: ADDR,
+            received_at: SystemTime::UNIX_EPOCH,
+            data: SensorData::default(),
+        };
+
+        let (line, active) = summary.status(t0 + secs(60));
+        assert!(!active);
+        assert!(line.starts_with(
+            "Status: no readings in the last 60s (0 devices, 0 readings decoded, none yet)"
+        ));
+
+        for s in [70, 80, 90] {
+            summary.record(
+                ADDR,
+                BlePacketType::BTHome,
+                Some(reading.clone()),
+                t0 + secs(s),
+            );
+        }
+        summary.record(ADDR, BlePacketType::Other, None, t0 + secs(100));
+        let (line, active) = summary.status(t0 + secs(120));
+        assert!(active);
+        assert_eq!(
+            line,
+            "Status: 1 devices, 3 readings decoded, 3.0/min over 60s, last 30s ago"
+        );
+
+        let (line, active) = summary.status(t0 + secs(180));
+        assert!(!active);
+        assert!(
+            line.contains(
+                "no readings in the last 60s (1 devices, 3 readings decoded, last 90s ago)"
+            )
+        );
+    }
+
+    #[test]
+    fn test_render_empty_run() {
+        let t0 = Instant::now();
+        let report = Summary::new(t0).render(t0, Units::Metric);
+
+        assert!(report.contains("  Decoded: none\n  Errors: none\n  Processing: none\n"));
+    }
+}
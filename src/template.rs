@@ -0,0 +1,236 @@
+use crate::decoder::{MEASUREMENT_NAMES, SensorData};
+use bluer::Address;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Placeholders besides the measurement names.
+const FIELDS: &[&str] = &[
+    "time",
+    "address",
+    "name",
+    "alias",
+    "rssi",
+    "temperature",
+    "humidity",
+    "battery",
+    "voltage",
+    "dew_point",
+    "absolute_humidity",
+    "temperature_delta_per_min",
+    "humidity_delta_per_min",
+    "smoothed_temperature",
+    "smoothed_humidity",
+    "smoothed_distance_m",
+    "tracked_devices",
+    "weakest_rssi",
+    "strongest_rssi",
+    "distance_m",
+    "device_mac",
+    "ble_address",
+    "adapter",
+    "model",
+    "note",
+    "flags",
+    "raw",
+];
+
+/// Console line format from `--template`, e.g.
+/// `"{time} {alias} {temperature}°C {humidity}%"`.
+///
+/// Placeholders are validated when the template is parsed; absent values
+/// render as empty strings. `{{` and `}}` are literal braces.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    Field(&'static str),
+}
+
+impl Template {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = s.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(format!("unclosed placeholder {{{name}")),
+                        }
+                    }
+                    let field = FIELDS
+                        .iter()
+                        .chain(MEASUREMENT_NAMES)
+                        .find(|field| **field == name)
+                        .ok_or_else(|| {
+                            format!(
+                                "unknown placeholder {{{name}}} (known: {})",
+                                FIELDS
+                                    .iter()
+                                    .chain(MEASUREMENT_NAMES)
+                                    .copied()
+                                    .collect::<Vec<_>>()
+                                    .join(", ")
+                            )
+                        })?;
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(Segment::Field(field));
+                }
+                '}' => return Err("unmatched '}' (use '}}' for a literal brace)".into()),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+        Ok(Self { segments })
+    }
+
+    /// The line for a reading from `addr` exported at `time`.
+    pub fn render(&self, addr: Address, data: &SensorData, time: SystemTime) -> String {
+        self.segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Literal(text) => text.clone(),
+                Segment::Field(field) => value(field, addr, data, time).unwrap_or_default(),
+            })
+            .collect()
+    }
+}
+
+fn value(field: &str, addr: Address, data: &SensorData, time: SystemTime) -> Option<String> {
+    fn show<T: ToString>(value: Option<T>) -> Option<String> {
+        value.map(|v| v.to_string())
+    }
+
+    match field {
+        "time" => Some(rfc3339(time)),
+        "address" => Some(addr.to_string()),
+        "name" | "alias" => data.name.clone(),
+        "rssi" => show(data.rssi),
+        "temperature" => show(data.temperature),
+        "humidity" => show(data.humidity),
+        "battery" => show(data.battery),
+        "voltage" => show(data.voltage),
+        "dew_point" => show(data.derived.and_then(|d| d.dew_point)),
+        "absolute_humidity" => show(data.derived.map(|d| d.absolute_humidity)),
+        "temperature_delta_per_min" => show(data.rates.and_then(|r| r.temperature_delta_per_min)),
+        "humidity_delta_per_min" => show(data.rates.and_then(|r| r.humidity_delta_per_min)),
+        "smoothed_temperature" => show(data.smoothed.and_then(|s| s.temperature)),
+        "smoothed_humidity" => show(data.smoothed.and_then(|s| s.humidity)),
+        "smoothed_distance_m" => show(data.smoothed.and_then(|s| s.distance_m)),
+        "tracked_devices" => show(data.rf_context.map(|c| c.tracked_devices)),
+        "weakest_rssi" => show(data.rf_context.and_then(|c| c.weakest_rssi)),
+        "strongest_rssi" => show(data.rf_context.and_then(|c| c.strongest_rssi)),
+        "distance_m" => show(data.distance_m),
+        "device_mac" => show(data.device_mac.map(Address::new)),
+        "ble_address" => data.ble_address.clone(),
+        "adapter" => data.adapter.clone(),
+        "model" => data.model.map(str::to_string),
+        "note" => data.note.clone(),
+        "flags" => Some(data.flag_list()),
+        "raw" => Some(data.raw.join(" ")),
+        measurement => show(data.measurements.get(measurement)),
+    }
+}
+
+/// `2024-05-01T12:34:56Z`; times before 1970 render as the epoch.
+pub fn rfc3339(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const ADDR: Address = Address::new([0xA4, 0xC1, 0x38, 0x00, 0x00, 0x01]);
+
+    #[test]
+    fn test_render_fields_and_missing_values() {
+        let template =
+            Template::parse("{time} {name}: {temperature}°C {humidity}% {{{battery}}}").unwrap();
+        let data = SensorData {
+            temperature: Some(-4.5),
+            name: Some("Freezer".into()),
+            ..Default::default()
+        };
+        let time = UNIX_EPOCH + Duration::from_secs(1_714_566_896);
+
+        assert_eq!(
+            template.render(ADDR, &data, time),
+            "2024-05-01T12:34:56Z Freezer: -4.5°C % {}"
+        );
+    }
+
+    #[test]
+    fn test_render_measurements_and_address() {
+        let template = Template::parse("{address} {illuminance_lux} lx").unwrap();
+        let mut data = SensorData::default();
+        data.measurements.insert("illuminance_lux", 310.0);
+
+        assert_eq!(
+            template.render(ADDR, &data, UNIX_EPOCH),
+            "A4:C1:38:00:00:01 310 lx"
+        );
+    }
+
+    #[test]
+    fn test_parse_rejects_unknown_placeholders() {
+        assert!(
+            Template::parse("{temprature}")
+                .unwrap_err()
+                .contains("{temprature}")
+        );
+        assert!(Template::parse("{temperature").is_err());
+        assert!(Template::parse("50}").is_err());
+    }
+
+    #[test]
+    fn test_rfc3339() {
+        assert_eq!(rfc3339(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        assert_eq!(
+            rfc3339(UNIX_EPOCH + Duration::from_secs(951_782_400)),
+            "2000-02-29T00:00:00Z"
+        );
+    }
+}
@@ -0,0 +1,132 @@
+use std::time::{Duration, Instant};
+
+/// Collapses bursts of repeated log lines into a periodic summary.
+///
+/// The first event in a window is logged; the rest are only counted and
+/// reported by [`LogThrottle::flush`] once the window is over.
+pub struct LogThrottle {
+    window: Duration,
+    window_start: Option<Instant>,
+    suppressed: u64,
+}
+
+impl LogThrottle {
+    /// A zero `window` disables throttling.
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            window_start: None,
+            suppressed: 0,
+        }
+    }
+
+    /// Whether an event at `now` should be logged.
+    pub fn allow(&mut self, now: Instant) -> bool {
+        if self.window.is_zero() {
+            return true;
+        }
+        match self.window_start {
+            Some(start) if now.duration_since(start) < self.window => {
+                self.suppressed += 1;
+                false
+            }
+            _ => {
+                self.window_start = Some(now);
+                true
+            }
+        }
+    }
+
+    /// Number of events suppressed in a window that ended before `now`,
+    /// if there were any. Each suppressed event is reported only once.
+    pub fn flush(&mut self, now: Instant) -> Option<u64> {
+        let start = self.window_start?;
+        if self.suppressed == 0 || now.duration_since(start) < self.window {
+            return None;
+        }
+        self.window_start = None;
+        Some(std::mem::take(&mut self.suppressed))
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+}
+
+/// Pause before restarting discovery, doubling with every restart in a row
+/// from `base` up to `max` so a flapping adapter isn't hammered.
+pub struct Backoff {
+    base: Duration,
+    max: Duration,
+    next: Duration,
+}
+
+impl Backoff {
+    /// A `max` below `base` means no backoff: always `base`.
+    pub fn new(base: Duration, max: Duration) -> Self {
+        Self {
+            base,
+            max: max.max(base),
+            next: base,
+        }
+    }
+
+    /// The pause before this restart; the next one is twice as long.
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.next;
+        self.next = (delay * 2).min(self.max);
+        delay
+    }
+
+    /// Discovery works again: the next restart waits `base`.
+    pub fn reset(&mut self) {
+        self.next = self.base;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_burst_collapses_into_summary() {
+        let t0 = Instant::now();
+        let mut throttle = LogThrottle::new(Duration::from_secs(60));
+
+        assert!(throttle.allow(t0));
+        for s in 1..=4 {
+            assert!(!throttle.allow(t0 + Duration::from_secs(s)));
+        }
+        assert_eq!(throttle.flush(t0 + Duration::from_secs(30)), None);
+        assert_eq!(throttle.flush(t0 + Duration::from_secs(60)), Some(4));
+        assert_eq!(throttle.flush(t0 + Duration::from_secs(120)), None);
+
+        // Next event after the window is logged again
+        assert!(throttle.allow(t0 + Duration::from_secs(61)));
+    }
+
+    #[test]
+    fn test_backoff_doubles_up_to_max_and_resets() {
+        let secs = Duration::from_secs;
+        let mut backoff = Backoff::new(secs(5), secs(30));
+
+        let delays: Vec<_> = (0..5).map(|_| backoff.next_delay()).collect();
+        assert_eq!(delays, [secs(5), secs(10), secs(20), secs(30), secs(30)]);
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), secs(5));
+
+        let mut fixed = Backoff::new(secs(5), secs(1));
+        assert_eq!(fixed.next_delay(), secs(5));
+        assert_eq!(fixed.next_delay(), secs(5));
+    }
+
+    #[test]
+    fn test_zero_window_logs_everything() {
+        let t0 = Instant::now();
+        let mut throttle = LogThrottle::new(Duration::ZERO);
+
+        assert!(throttle.allow(t0));
+        assert!(throttle.allow(t0));
+        assert_eq!(throttle.flush(t0 + Duration::from_secs(60)), None);
+    }
+}
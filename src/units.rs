@@ -0,0 +1,95 @@
+//! Display units (`--units`). Readings are decoded and processed in °C;
+//! only what is shown or written out is converted.
+
+use crate::decoder::SensorData;
+use clap::ValueEnum;
+use std::borrow::Cow;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Units {
+    /// °C
+    #[default]
+    Metric,
+    /// °F
+    Imperial,
+}
+
+impl Units {
+    pub fn temperature_suffix(self) -> &'static str {
+        match self {
+            Units::Metric => "°C",
+            Units::Imperial => "°F",
+        }
+    }
+
+    /// `data` with every temperature in these units; percentages, voltages
+    /// and everything else are left alone.
+    pub fn convert(self, data: &SensorData) -> Cow<'_, SensorData> {
+        if self == Units::Metric {
+            return Cow::Borrowed(data);
+        }
+        let mut data = data.clone();
+        data.temperature = data.temperature.map(fahrenheit);
+        if let Some(derived) = &mut data.derived {
+            derived.dew_point = derived.dew_point.map(fahrenheit);
+        }
+        if let Some(smoothed) = &mut data.smoothed {
+            smoothed.temperature = smoothed.temperature.map(fahrenheit);
+        }
+        if let Some(rates) = &mut data.rates {
+            // A difference, so no offset
+            rates.temperature_delta_per_min =
+                rates.temperature_delta_per_min.map(|d| round(d * 1.8));
+        }
+        Cow::Owned(data)
+    }
+}
+
+fn fahrenheit(celsius: f32) -> f32 {
+    round(celsius * 1.8 + 32.0)
+}
+
+/// Two decimals, the resolution of the sensors' °C values, instead of
+/// conversion noise like 73.21999.
+fn round(value: f32) -> f32 {
+    (value * 100.0).round() / 100.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::derived::Derived;
+
+    #[test]
+    fn test_imperial_converts_only_temperatures() {
+        let data = SensorData {
+            temperature: Some(22.9),
+            humidity: Some(45.5),
+            battery: Some(87),
+            voltage: Some(2.95),
+            derived: Some(Derived::compute(25.0, 50.0)),
+            ..Default::default()
+        };
+
+        let converted = Units::Imperial.convert(&data);
+        assert_eq!(converted.temperature, Some(73.22));
+        assert_eq!(converted.humidity, Some(45.5));
+        assert_eq!(converted.battery, Some(87));
+        assert_eq!(converted.voltage, Some(2.95));
+        let dew_point = converted.derived.unwrap().dew_point.unwrap();
+        assert!((dew_point - 57.0).abs() < 0.2);
+        assert_eq!(
+            converted.derived.unwrap().absolute_humidity,
+            data.derived.unwrap().absolute_humidity
+        );
+
+        assert_eq!(*Units::Metric.convert(&data), data);
+    }
+
+    #[test]
+    fn test_freezing_and_negative() {
+        assert_eq!(fahrenheit(0.0), 32.0);
+        assert_eq!(fahrenheit(-40.0), -40.0);
+        assert_eq!(fahrenheit(-12.34), 9.79);
+    }
+}
@@ -0,0 +1,381 @@
+//! Live readings over WebSocket (`--ws-addr`), for browser dashboards.
+//!
+//! Just enough of RFC 6455 for a server that only talks: the handshake,
+//! unfragmented text frames out, and close/ping handling for what clients
+//! send.
+
+use crate::export::{self, Exporter, Reading};
+use crate::icons::{Icon, warning};
+use crate::units::Units;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+/// The handshake is only a request line and a few headers.
+const MAX_REQUEST: usize = 8192;
+/// Clients only send control frames; anything bigger is dropped.
+const MAX_MESSAGE: usize = 64 * 1024;
+/// Readings a slow client may fall behind before it skips some.
+const BACKLOG: usize = 256;
+/// Appended to the client's key for `Sec-WebSocket-Accept`.
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xA;
+
+/// Broadcasts every reading as a `--format json` object to all connected
+/// clients. Clients start with the next reading; there's no backfill.
+#[derive(Clone)]
+pub struct WebSocketExporter {
+    readings: broadcast::Sender<Arc<str>>,
+    units: Units,
+}
+
+impl WebSocketExporter {
+    pub fn new(units: Units) -> Self {
+        Self {
+            readings: broadcast::channel(BACKLOG).0,
+            units,
+        }
+    }
+}
+
+impl Exporter for WebSocketExporter {
+    fn export(&self, reading: &Reading) {
+        // Only fails while nobody is connected
+        let _ = self.readings.send(export::json(reading, self.units).into());
+    }
+}
+
+/// Accept WebSocket clients on `listener` until the process exits.
+pub async fn serve(listener: TcpListener, exporter: WebSocketExporter) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                warning!("{} WebSocket accept failed: {e}", Icon::Warn);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+        // Subscribing before the handshake completes means a client gets
+        // every reading exported after it saw the 101 response
+        let readings = exporter.readings.subscribe();
+        tokio::spawn(async move {
+            // Clients that hang up are simply gone; nothing to log
+            if let Ok(Ok(Some(stream))) =
+                tokio::time::timeout(HANDSHAKE_TIMEOUT, handshake(stream)).await
+            {
+                let _ = stream_readings(stream, readings).await;
+            }
+        });
+    }
+}
+
+/// Answer the upgrade request. `None` if it wasn't one.
+async fn handshake(mut stream: TcpStream) -> io::Result<Option<TcpStream>> {
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < MAX_REQUEST {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            return Ok(None);
+        }
+        request.extend(&buf[..n]);
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let key = request.lines().skip(1).find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.trim()
+            .eq_ignore_ascii_case("Sec-WebSocket-Key")
+            .then(|| value.trim())
+    });
+    let Some(key) = key.filter(|_| request.starts_with("GET ")) else {
+        stream
+            .write_all(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n")
+            .await?;
+        return Ok(None);
+    };
+    let response = format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key(key)
+    );
+    stream.write_all(response.as_bytes()).await?;
+    Ok(Some(stream))
+}
+
+/// Send readings until the client closes the connection or goes away.
+async fn stream_readings(
+    mut stream: TcpStream,
+    mut readings: broadcast::Receiver<Arc<str>>,
+) -> io::Result<()> {
+    let mut received = Vec::new();
+    let mut buf = [0; 1024];
+    loop {
+        tokio::select! {
+            reading = readings.recv() => match reading {
+                Ok(json) => stream.write_all(&frame(OPCODE_TEXT, json.as_bytes())).await?,
+                // A slow client misses readings rather than holding up others
+                Err(broadcast::error::RecvError::Lagged(_)) => {}
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            n = stream.read(&mut buf) => {
+                let n = n?;
+                if n == 0 {
+                    return Ok(());
+                }
+                received.extend(&buf[..n]);
+                while let Some((opcode, payload, len)) = parse_frame(&received) {
+                    received.drain(..len);
+                    match opcode {
+                        OPCODE_CLOSE => {
+                            stream.write_all(&frame(OPCODE_CLOSE, &[])).await?;
+                            return Ok(());
+                        }
+                        OPCODE_PING => stream.write_all(&frame(OPCODE_PONG, &payload)).await?,
+                        _ => {}
+                    }
+                }
+                if received.len() > MAX_MESSAGE {
+                    break;
+                }
+            }
+        }
+    }
+    stream.write_all(&frame(OPCODE_CLOSE, &[])).await
+}
+
+/// An unmasked, unfragmented server frame.
+fn frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        len @ 0..=125 => frame.push(len as u8),
+        len @ 126..=0xFFFF => {
+            frame.push(126);
+            frame.extend((len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend((len as u64).to_be_bytes());
+        }
+    }
+    frame.extend(payload);
+    frame
+}
+
+/// `(opcode, unmasked payload, frame length)` of the frame at the start of
+/// `buf`, `None` until it has fully arrived.
+fn parse_frame(buf: &[u8]) -> Option<(u8, Vec<u8>, usize)> {
+    let opcode = buf.first()? & 0x0F;
+    let second = *buf.get(1)?;
+    let (len, mut i) = match second & 0x7F {
+        126 => (
+            u16::from_be_bytes(buf.get(2..4)?.try_into().ok()?) as usize,
+            4,
+        ),
+        127 => (
+            u64::from_be_bytes(buf.get(2..10)?.try_into().ok()?) as usize,
+            10,
+        ),
+        len => (len as usize, 2),
+    };
+    let mask = if second & 0x80 != 0 {
+        i += 4;
+        Some(buf.get(i - 4..i)?)
+    } else {
+        None
+    };
+    let mut payload = buf.get(i..i.checked_add(len)?)?.to_vec();
+    if let Some(mask) = mask {
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+    }
+    Some((opcode, payload, i + len))
+}
+
+/// `Sec-WebSocket-Accept` for the client's `Sec-WebSocket-Key`.
+fn accept_key(key: &str) -> String {
+    base64(&sha1(format!("{key}{ACCEPT_GUID}").as_bytes()))
+}
+
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend((data.len() as u64 * 8).to_be_bytes());
+
+    for block in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for (word, bytes) in w.iter_mut().zip(block.chunks(4)) {
+            *word = u32::from_be_bytes(bytes.try_into().unwrap());
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let t = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            (e, d, c, b, a) = (d, c, b.rotate_left(30), a, t);
+        }
+        for (h, v) in h.iter_mut().zip([a, b, c, d, e]) {
+            *h = h.wrapping_add(v);
+        }
+    }
+
+    let mut digest = [0; 20];
+    for (bytes, h) in digest.chunks_mut(4).zip(h) {
+        bytes.copy_from_slice(&h.to_be_bytes());
+    }
+    digest
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let n = u32::from_be_bytes([
+            0,
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i)) as usize & 63] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::SensorData;
+    use bluer::Address;
+
+    const ADDR: Address = Address::new([0xA4, 0xC1, 0x38, 0x00, 0x00, 0x01]);
+
+    #[test]
+    fn test_accept_key() {
+        // The example from RFC 6455, section 1.3
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+        assert_eq!(base64(b"ab"), "YWI=");
+        assert_eq!(base64(b"a"), "YQ==");
+    }
+
+    #[test]
+    fn test_frames() {
+        let long = frame(OPCODE_TEXT, &[b'x'; 300]);
+        assert_eq!(long[..4], [0x81, 126, 0x01, 0x2C]);
+        assert_eq!(
+            parse_frame(&long),
+            Some((OPCODE_TEXT, vec![b'x'; 300], 304))
+        );
+        assert_eq!(parse_frame(&long[..200]), None);
+
+        // A masked client frame: "Hello" from RFC 6455, section 5.7
+        let masked = [
+            0x81, 0x85, 0x37, 0xFA, 0x21, 0x3D, 0x7F, 0x9F, 0x4D, 0x51, 0x58,
+        ];
+        assert_eq!(
+            parse_frame(&masked),
+            Some((OPCODE_TEXT, b"Hello".to_vec(), 11))
+        );
+    }
+
+    async fn read_frame(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+        let mut received = Vec::new();
+        let mut buf = [0; 1024];
+        loop {
+            if let Some((opcode, payload, _)) = parse_frame(&received) {
+                return (opcode, payload);
+            }
+            let n = stream.read(&mut buf).await.unwrap();
+            assert!(n > 0, "connection closed");
+            received.extend(&buf[..n]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_streams_readings_to_clients() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let exporter = WebSocketExporter::new(Units::default());
+        tokio::spawn(serve(listener, exporter.clone()));
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(
+                b"GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+                  sec-websocket-key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        let mut response = Vec::new();
+        let mut buf = [0; 1];
+        while !response.ends_with(b"\r\n\r\n") {
+            client.read_exact(&mut buf).await.unwrap();
+            response.push(buf[0]);
+        }
+        let response = String::from_utf8(response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 101 "));
+        assert!(response.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+
+        exporter.export(&Reading::new(
+            ADDR,
+            SensorData {
+                temperature: Some(22.9),
+                ..Default::default()
+            },
+        ));
+        let (opcode, payload) = read_frame(&mut client).await;
+        assert_eq!(opcode, OPCODE_TEXT);
+        let json: serde_json::Value = serde_json::from_slice(&payload).unwrap();
+        assert_eq!(json["address"], "A4:C1:38:00:00:01");
+        assert_eq!(json["temperature"], 22.9);
+
+        // A masked, empty close frame is answered and ends the stream
+        client
+            .write_all(&[0x88, 0x80, 0x01, 0x02, 0x03, 0x04])
+            .await
+            .unwrap();
+        assert_eq!(read_frame(&mut client).await, (OPCODE_CLOSE, vec![]));
+    }
+
+    #[tokio::test]
+    async fn test_rejects_plain_http() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(serve(listener, WebSocketExporter::new(Units::default())));
+
+        assert!(crate::http::get(&url).await.is_err());
+    }
+}
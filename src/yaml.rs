@@ -0,0 +1,231 @@
+//! Bindkeys and aliases from a Home Assistant/ESPHome-style YAML file
+//! (`--yaml`, with the `yaml` feature):
+//!
+//! ```yaml
+//! devices:
+//!   - mac_address: "A4:C1:38:00:00:01"
+//!     name: Bedroom
+//!     bindkey: 231d39c1d7cc1ab1aee224cd096db932
+//!   - mac_address: A4:C1:38:00:00:02  # no key, plain PVVX firmware
+//!     name: "Kid's room"
+//! ```
+//!
+//! Each entry becomes `--alias` and `--bindkey` arguments placed before
+//! the `--config` file's and the command line's, so both win over the
+//! import. Like the TOML in [`crate::config`], only this much YAML is
+//! understood: one `devices` list of flat mappings with plain, single or
+//! double quoted scalars.
+
+use crate::crypto::BindKey;
+use bluer::Address;
+use std::path::Path;
+use std::str::FromStr;
+
+/// One `devices` entry.
+#[derive(Debug, Default)]
+struct Device {
+    /// Line the entry starts on
+    line: usize,
+    address: Option<Address>,
+    name: Option<String>,
+    bindkey: Option<String>,
+}
+
+/// The `--alias` and `--bindkey` arguments the file at `path` becomes.
+pub fn load(path: &Path) -> Result<Vec<String>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    args(&text)
+}
+
+fn args(text: &str) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    for device in devices(text)? {
+        let addr = device
+            .address
+            .ok_or_else(|| format!("line {}: entry without a mac_address", device.line))?;
+        if let Some(name) = device.name {
+            args.push(format!("--alias={addr}={name}"));
+        }
+        if let Some(bindkey) = device.bindkey {
+            args.push(format!("--bindkey={addr}={bindkey}"));
+        }
+    }
+    Ok(args)
+}
+
+fn devices(text: &str) -> Result<Vec<Device>, String> {
+    let mut devices: Vec<Device> = Vec::new();
+    let mut in_devices = false;
+    // Indentation of the keys of the current entry
+    let mut entry_indent = None;
+    for (index, line) in text.lines().enumerate() {
+        let number = index + 1;
+        let error = |message: String| format!("line {number}: {message}");
+        let line = strip_comment(line).trim_end();
+        let content = line.trim_start_matches(' ');
+        if content.is_empty() {
+            continue;
+        }
+        if content.starts_with('\t') {
+            return Err(error("tabs can't indent YAML".into()));
+        }
+        let indent = line.len() - content.len();
+
+        if indent == 0 {
+            match content {
+                "devices:" => in_devices = true,
+                "devices: []" => in_devices = false,
+                _ => return Err(error(format!("expected devices:, got {content:?}"))),
+            }
+            entry_indent = None;
+            continue;
+        }
+        if !in_devices {
+            return Err(error("expected devices:".into()));
+        }
+        let pair = if let Some(rest) = content.strip_prefix("- ") {
+            devices.push(Device {
+                line: number,
+                ..Default::default()
+            });
+            entry_indent = Some(indent + 2);
+            rest.trim_start()
+        } else if entry_indent == Some(indent) {
+            content
+        } else {
+            return Err(error(
+                "expected a \"- \" entry or a key of the entry above".into(),
+            ));
+        };
+
+        let (key, value) = key_value(pair).map_err(error)?;
+        let device = devices.last_mut().expect("an entry was started");
+        let invalid = |e: String| error(format!("{key}: {e}"));
+        let duplicate = || error(format!("{key} is set twice"));
+        match key {
+            "mac_address" | "mac" => {
+                let addr = Address::from_str(&value).map_err(|e| invalid(e.to_string()))?;
+                if device.address.replace(addr).is_some() {
+                    return Err(duplicate());
+                }
+            }
+            "name" => {
+                if device.name.replace(value).is_some() {
+                    return Err(duplicate());
+                }
+            }
+            "bindkey" => {
+                BindKey::from_str(&value).map_err(invalid)?;
+                if device.bindkey.replace(value).is_some() {
+                    return Err(duplicate());
+                }
+            }
+            _ => return Err(error(format!("unknown key {key:?}"))),
+        }
+    }
+    Ok(devices)
+}
+
+/// `key: value`, with the value unquoted.
+fn key_value(pair: &str) -> Result<(&str, String), String> {
+    let (key, value) = pair
+        .split_once(": ")
+        .ok_or_else(|| format!("expected key: value, got {pair:?}"))?;
+    let value = value.trim();
+    let value = if let Some(quoted) = value.strip_prefix('"') {
+        let inner = quoted
+            .strip_suffix('"')
+            .ok_or_else(|| format!("{key}: unterminated string"))?;
+        inner.replace("\\\"", "\"").replace("\\\\", "\\")
+    } else if let Some(quoted) = value.strip_prefix('\'') {
+        let inner = quoted
+            .strip_suffix('\'')
+            .ok_or_else(|| format!("{key}: unterminated string"))?;
+        inner.replace("''", "'")
+    } else {
+        value.to_string()
+    };
+    if value.is_empty() {
+        return Err(format!("{key}: expected a value"));
+    }
+    Ok((key.trim(), value))
+}
+
+/// The line up to a `#` that starts a comment, i.e. one at the start or
+/// after a space and outside quoted scalars.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut previous = ' ';
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            // Reopening right after the closing quote is a '' escape
+            (None, '"' | '\'') if previous == ' ' || previous == c => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            (None, '#') if previous == ' ' => return &line[..i],
+            _ => {}
+        }
+        previous = c;
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entries_become_arguments() {
+        let text = r#"
+# exported from Home Assistant
+devices:
+  - mac_address: "A4:C1:38:00:00:01"
+    name: Bedroom   # upstairs
+    bindkey: 231d39c1d7cc1ab1aee224cd096db932
+
+  - mac: A4:C1:38:00:00:02
+    name: 'Kid''s room #2'
+"#;
+        assert_eq!(
+            args(text).unwrap(),
+            [
+                "--alias=A4:C1:38:00:00:01=Bedroom",
+                "--bindkey=A4:C1:38:00:00:01=231d39c1d7cc1ab1aee224cd096db932",
+                "--alias=A4:C1:38:00:00:02=Kid's room #2",
+            ]
+        );
+        assert_eq!(args("devices: []\n"), Ok(vec![]));
+    }
+
+    #[test]
+    fn test_errors_name_line_and_key() {
+        assert_eq!(
+            args("devices:\n  - mac_address: A4:C1:38:00:00:01\n    bindkey: 1234"),
+            Err("line 3: bindkey: bind key must be 16 bytes, got 2".into())
+        );
+        assert!(
+            args("devices:\n  - mac_address: A4:C1:38:00:00:0Z")
+                .unwrap_err()
+                .starts_with("line 2: mac_address: ")
+        );
+        assert_eq!(
+            args("devices:\n  - name: Bedroom"),
+            Err("line 2: entry without a mac_address".into())
+        );
+        assert_eq!(
+            args("devices:\n  - mac: A4:C1:38:00:00:01\n    room: attic"),
+            Err("line 3: unknown key \"room\"".into())
+        );
+        assert_eq!(
+            args("devices:\n  - name: a\n    name: b"),
+            Err("line 3: name is set twice".into())
+        );
+        assert_eq!(
+            args("sensor:\n  - platform: pvvx"),
+            Err("line 1: expected devices:, got \"sensor:\"".into())
+        );
+        assert_eq!(
+            args("devices:\n  - name: \"Bedroom"),
+            Err("line 2: name: unterminated string".into())
+        );
+    }
+}
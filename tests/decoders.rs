@@ -0,0 +1,121 @@
+//! Raw advertisements through the public decoding API.
+
+use mitempr::decoder::{self, Flag};
+use mitempr::{BlePacketType, DecodeError, handle_service_data};
+use std::collections::HashMap;
+use uuid::{Uuid, uuid};
+
+const BTHOME: Uuid = uuid!("0000fcd2-0000-1000-8000-00805f9b34fb");
+const PVVX: Uuid = uuid!("0000181a-0000-1000-8000-00805f9b34fb");
+const MIJIA: Uuid = uuid!("0000fe95-0000-1000-8000-00805f9b34fb");
+
+fn service_data(uuid: Uuid, payload: &[u8]) -> HashMap<Uuid, Vec<u8>> {
+    HashMap::from([(uuid, payload.to_vec())])
+}
+
+#[test]
+fn bthome() {
+    // Packet ID 7, battery 87 %, 25.06 °C, 50.55 %
+    let data = service_data(
+        BTHOME,
+        &[
+            0x40, 0x00, 0x07, 0x01, 0x57, 0x02, 0xCA, 0x09, 0x03, 0xBF, 0x13,
+        ],
+    );
+
+    assert_eq!(decoder::packet_type(&data), BlePacketType::BTHome);
+    let reading = handle_service_data(&data).unwrap();
+    assert_eq!(reading.packet_id, Some(7));
+    assert_eq!(reading.battery, Some(87));
+    assert_eq!(reading.temperature, Some(25.06));
+    assert_eq!(reading.humidity, Some(50.55));
+    assert!(reading.flags.is_empty());
+}
+
+#[test]
+fn pvvx() {
+    let data = service_data(
+        PVVX,
+        &[
+            0x03, 0x7B, 0xA0, 0x38, 0xC1, 0xA4, 0xF2, 0x08, 0x19, 0x19, 0x1D, 0x09, 0x10, 0x4A,
+            0x05,
+        ],
+    );
+
+    let reading = handle_service_data(&data).unwrap();
+    assert_eq!(reading.temperature, Some(22.9));
+    assert_eq!(reading.humidity, Some(64.25));
+    assert_eq!(reading.voltage, Some(2.333));
+    assert_eq!(reading.battery, Some(16));
+    assert_eq!(
+        reading.device_mac,
+        Some([0xA4, 0xC1, 0x38, 0xA0, 0x7B, 0x03])
+    );
+}
+
+#[test]
+fn atc1441() {
+    // The sensor above switched to the original atc1441 format, showing
+    // 22.9 °C on its display
+    let data = service_data(
+        PVVX,
+        &[
+            0xA4, 0xC1, 0x38, 0xA0, 0x7B, 0x03, 0x00, 0xE5, 0x40, 0x10, 0x09, 0x1D, 0x05,
+        ],
+    );
+
+    let reading = handle_service_data(&data).unwrap();
+    assert_eq!(reading.temperature, Some(22.9));
+    assert_eq!(reading.humidity, Some(64.0));
+    assert_eq!(reading.voltage, Some(2.333));
+    assert_eq!(reading.battery, Some(16));
+    assert_eq!(
+        reading.device_mac,
+        Some([0xA4, 0xC1, 0x38, 0xA0, 0x7B, 0x03])
+    );
+    assert!(reading.flags.is_empty());
+}
+
+#[test]
+fn mijia() {
+    // Temperature and humidity object: 23.4 °C, 61.0 %
+    let data = service_data(
+        MIJIA,
+        &[
+            0x50, 0x20, 0xAA, 0x01, 0xF5, 0x40, 0x71, 0xD5, 0xA8, 0x65, 0x4C, 0x0D, 0x10, 0x04,
+            0xEA, 0x00, 0x62, 0x02,
+        ],
+    );
+
+    let reading = handle_service_data(&data).unwrap();
+    assert_eq!(reading.temperature, Some(23.4));
+    assert_eq!(reading.humidity, Some(61.0));
+    assert_eq!(reading.mijia.unwrap().product_id, 0x01AA);
+    assert_eq!(reading.model, Some("LYWSDCGQ"));
+}
+
+#[test]
+fn voltage_only_battery_is_estimated() {
+    let reading = handle_service_data(&service_data(BTHOME, &[0x40, 0x0C, 0xB8, 0x0B])).unwrap();
+
+    assert_eq!(reading.voltage, Some(3.0));
+    assert_eq!(reading.battery, Some(100));
+    assert!(reading.flags.contains(&Flag::Estimated));
+}
+
+#[test]
+fn errors() {
+    assert_eq!(
+        handle_service_data(&service_data(PVVX, &[0x03, 0x7B])),
+        Err(DecodeError::TooShort { got: 2, need: 15 })
+    );
+    assert_eq!(
+        handle_service_data(&service_data(BTHOME, &[0x41, 0xA4, 0x72])),
+        Err(DecodeError::Encrypted)
+    );
+    let unknown = uuid!("0000feaa-0000-1000-8000-00805f9b34fb");
+    assert_eq!(
+        handle_service_data(&service_data(unknown, &[0x00])),
+        Err(DecodeError::UnknownService(vec![unknown]))
+    );
+}